@@ -1,5 +1,4 @@
 use std::{
-    num::NonZeroU32,
     sync::Arc,
     time::Instant,
 };
@@ -64,20 +63,15 @@ impl ApplicationHandler for App {
 
         self.backend = Some(
             Terminal::new(
-                futures_lite::future::block_on(
-                    Builder::from_font(
-                        Font::new(include_bytes!(concat!(
-                            env!("CARGO_MANIFEST_DIR"),
-                            "/src/backend/fonts/CascadiaMono-Regular.ttf"
-                        )))
-                        .unwrap(),
-                    )
-                    .with_width_and_height(Dimensions {
-                        width: NonZeroU32::new(size.width).unwrap(),
-                        height: NonZeroU32::new(size.height).unwrap(),
-                    })
-                    .build_with_target(self.window.as_ref().unwrap().clone()),
+                Builder::from_font(
+                    Font::new(include_bytes!(concat!(
+                        env!("CARGO_MANIFEST_DIR"),
+                        "/src/backend/fonts/CascadiaMono-Regular.ttf"
+                    )))
+                    .unwrap(),
                 )
+                .with_width_and_height(Dimensions::from_physical(size.width, size.height))
+                .build_with_target_blocking(self.window.as_ref().unwrap().clone())
                 .unwrap(),
             )
             .unwrap(),
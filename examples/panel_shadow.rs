@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use ratatui::{
+    prelude::*,
+    widgets::*,
+};
+use ratatui_wgpu::{
+    shaders::{
+        PanelShadowConfig,
+        PanelShadowPostProcessor,
+    },
+    Builder,
+    Dimensions,
+    Font,
+    WgpuBackend,
+};
+use winit::{
+    application::ApplicationHandler,
+    event::WindowEvent,
+    event_loop::EventLoop,
+    window::{
+        Window,
+        WindowAttributes,
+    },
+};
+
+pub struct App {
+    window: Option<Arc<Window>>,
+    backend: Option<Terminal<WgpuBackend<'static, 'static, PanelShadowPostProcessor>>>,
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let event_loop = EventLoop::builder().build()?;
+
+    let mut app = App {
+        window: None,
+        backend: None,
+    };
+    event_loop.run_app(&mut app).unwrap();
+
+    Ok(())
+}
+
+impl ApplicationHandler for App {
+    fn resumed(&mut self, event_loop: &winit::event_loop::ActiveEventLoop) {
+        self.window = Some(Arc::new(
+            event_loop
+                .create_window(WindowAttributes::default())
+                .unwrap(),
+        ));
+
+        let size = self.window.as_ref().unwrap().inner_size();
+
+        self.backend = Some(
+            Terminal::new(
+                Builder::from_font_and_user_data(
+                    Font::new(include_bytes!(concat!(
+                        env!("CARGO_MANIFEST_DIR"),
+                        "/src/backend/fonts/CascadiaMono-Regular.ttf"
+                    )))
+                    .unwrap(),
+                    PanelShadowConfig {
+                        radius: 8,
+                        intensity: 0.5,
+                    },
+                )
+                .with_width_and_height(Dimensions::from_physical(size.width, size.height))
+                .build_with_target_blocking(self.window.as_ref().unwrap().clone())
+                .unwrap(),
+            )
+            .unwrap(),
+        );
+
+        self.window.as_ref().unwrap().request_redraw();
+    }
+
+    fn window_event(
+        &mut self,
+        event_loop: &winit::event_loop::ActiveEventLoop,
+        _window_id: winit::window::WindowId,
+        event: winit::event::WindowEvent,
+    ) {
+        if let WindowEvent::CloseRequested = event {
+            event_loop.exit();
+            return;
+        }
+
+        let Some(terminal) = self.backend.as_mut() else {
+            return;
+        };
+
+        if let WindowEvent::Resized(size) = event {
+            terminal.backend_mut().resize(size.width, size.height);
+        }
+
+        let area = Rect::from((Position::ORIGIN, terminal.size().unwrap()));
+        let [left, right] = Layout::horizontal([Constraint::Fill(1); 2])
+            .margin(2)
+            .spacing(4)
+            .areas(area);
+        let [top, bottom] = Layout::vertical([Constraint::Fill(1); 2])
+            .spacing(2)
+            .areas(right);
+
+        let backend = terminal.backend_mut();
+        let cell_size = backend.cell_size();
+        backend
+            .post_processor_mut()
+            .set_panels(cell_size, &[left, top, bottom]);
+
+        terminal
+            .draw(|f| {
+                // Shadows darken the background, so they need a background
+                // which isn't already black to be visible.
+                f.render_widget(
+                    Block::new().style(Style::new().bg(Color::Rgb(96, 104, 120))),
+                    f.area(),
+                );
+
+                f.render_widget(
+                    Paragraph::new("Panels cast a soft shadow down and to the right.")
+                        .wrap(Wrap { trim: false })
+                        .block(Block::bordered().title("Shadows"))
+                        .style(Style::new().fg(Color::Black).bg(Color::Rgb(220, 220, 210))),
+                    left,
+                );
+                f.render_widget(
+                    Paragraph::new("The effect is computed entirely in a post-processor.")
+                        .wrap(Wrap { trim: false })
+                        .block(Block::bordered().title("Post Processing"))
+                        .style(Style::new().fg(Color::White).bg(Color::Rgb(40, 80, 140))),
+                    top,
+                );
+                f.render_widget(
+                    Paragraph::new("Borders and text are left crisp.")
+                        .wrap(Wrap { trim: false })
+                        .block(Block::bordered().title("Edges"))
+                        .style(Style::new().fg(Color::Black).bg(Color::Rgb(200, 160, 90))),
+                    bottom,
+                );
+            })
+            .unwrap();
+
+        self.window.as_ref().unwrap().request_redraw();
+    }
+}
@@ -1,13 +1,23 @@
 use std::{
+    collections::{
+        HashMap,
+        HashSet,
+    },
+    hash::BuildHasher,
     marker::PhantomData,
     num::{
         NonZeroU32,
         NonZeroU64,
     },
+    path::PathBuf,
+    sync::Arc,
 };
 
 use bitvec::vec::BitVec;
-use ratatui::style::Color;
+use ratatui::style::{
+    Color,
+    Modifier,
+};
 use rustybuzz::UnicodeBuffer;
 use web_time::{
     Duration,
@@ -20,14 +30,17 @@ use wgpu::{
         DeviceExt,
     },
     vertex_attr_array,
+    AdapterInfo,
     AddressMode,
     Backends,
     BindGroupDescriptor,
     BindGroupEntry,
     BindGroupLayoutDescriptor,
     BindGroupLayoutEntry,
-    BindingResource,
     BindingType,
+    BlendComponent,
+    BlendFactor,
+    BlendOperation,
     BlendState,
     Buffer,
     BufferBindingType,
@@ -35,8 +48,8 @@ use wgpu::{
     BufferUsages,
     ColorTargetState,
     ColorWrites,
+    CompositeAlphaMode,
     Device,
-    Extent3d,
     FilterMode,
     FragmentState,
     Instance,
@@ -46,23 +59,22 @@ use wgpu::{
     MultisampleState,
     PipelineCompilationOptions,
     PipelineLayoutDescriptor,
+    PowerPreference,
     PresentMode,
     PrimitiveState,
     PrimitiveTopology,
+    Queue,
     RenderPipelineDescriptor,
     Sampler,
     SamplerBindingType,
     SamplerDescriptor,
     ShaderStages,
     Surface,
+    SurfaceConfiguration,
     SurfaceTarget,
-    TextureDescriptor,
-    TextureDimension,
+    Texture,
     TextureFormat,
     TextureSampleType,
-    TextureUsages,
-    TextureView,
-    TextureViewDescriptor,
     TextureViewDimension,
     VertexBufferLayout,
     VertexState,
@@ -71,17 +83,35 @@ use wgpu::{
 
 use crate::{
     backend::{
+        build_atlas_bindings,
+        build_overrender_target,
+        build_text_atlas,
+        build_vertical_compositor,
         build_wgpu_state,
         c2c,
         private::Token,
         wgpu_backend::WgpuBackend,
+        whole_cell_size,
+        BlendMode,
+        CellShader,
+        CursorStyle,
+        DimMode,
         Dimensions,
+        EmojiMode,
+        FrameBuffers,
+        FrameStats,
+        HintingMode,
+        LogicalMetrics,
         PostProcessor,
         RenderSurface,
+        ResizePolicy,
+        Rounding,
         TextBgVertexMember,
         TextCacheBgPipeline,
         TextCacheFgPipeline,
         TextVertexMember,
+        TextureTarget,
+        UnderlineStyle,
         Viewport,
     },
     colors::{
@@ -89,7 +119,7 @@ use crate::{
             BLACK,
             WHITE,
         },
-        Rgb,
+        Palette,
     },
     fonts::{
         Font,
@@ -97,10 +127,12 @@ use crate::{
     },
     shaders::DefaultPostProcessor,
     utils::{
+        glyph_cache::GlyphDiskCache,
         plan_cache::PlanCache,
         text_atlas::Atlas,
     },
     Error,
+    RandomState,
     Result,
 };
 
@@ -112,19 +144,56 @@ const CACHE_HEIGHT: u32 = 1200;
 /// Height and width will default to 1x1, so don't forget to call
 /// [`Builder::with_dimensions`] to configure the backend presentation
 /// dimensions.
-pub struct Builder<'a, P: PostProcessor = DefaultPostProcessor> {
+pub struct Builder<'a, P: PostProcessor = DefaultPostProcessor, H = RandomState> {
     user_data: P::UserData,
+    hasher: H,
     fonts: Fonts<'a>,
     instance: Option<Instance>,
+    atlas_size: (u32, u32),
+    max_atlas_pages: u32,
+    device: Option<(Arc<Device>, Arc<Queue>)>,
+    instance_descriptor: Option<InstanceDescriptor>,
     limits: Option<Limits>,
+    power_preference: PowerPreference,
+    force_fallback_adapter: bool,
     present_mode: Option<PresentMode>,
     width: NonZeroU32,
     height: NonZeroU32,
     viewport: Viewport,
-    reset_fg: Rgb,
-    reset_bg: Rgb,
+    grid_rounding: Rounding,
+    integer_scaling: bool,
+    scale_factor: f32,
+    resize_policy: ResizePolicy,
+    reset_fg: Color,
+    reset_bg: Color,
+    palette: Palette,
+    transparent_background: bool,
+    alpha_mode: Option<CompositeAlphaMode>,
+    blink: bool,
     fast_blink: Duration,
     slow_blink: Duration,
+    min_frame_interval: Duration,
+    prealloc_grid: Option<(u16, u16)>,
+    glyph_antialiasing: bool,
+    subpixel_aa: bool,
+    text_gamma: f32,
+    text_blend: BlendMode,
+    hinting: HintingMode,
+    fake_italic_skew: f32,
+    emoji_mode: EmojiMode,
+    missing_glyph: Option<char>,
+    required_coverage: Vec<char>,
+    bidi: bool,
+    min_contrast: f32,
+    dim_mode: DimMode,
+    cell_shader: Option<CellShader>,
+    overrender_margin: u16,
+    vertical_text: bool,
+    glyph_disk_cache: Option<PathBuf>,
+    cursor_style: CursorStyle,
+    cursor_blink: Option<Duration>,
+    underline_style: UnderlineStyle,
+    padding_color: Option<Color>,
 }
 
 impl<'a, P: PostProcessor> Builder<'a, P>
@@ -136,17 +205,54 @@ where
     pub fn from_font(font: Font<'a>) -> Self {
         Self {
             user_data: Default::default(),
+            hasher: RandomState::default(),
             instance: None,
+            atlas_size: (CACHE_WIDTH, CACHE_HEIGHT),
+            max_atlas_pages: 4,
+            device: None,
+            instance_descriptor: None,
             fonts: Fonts::new(font, 24),
             limits: None,
+            power_preference: PowerPreference::default(),
+            force_fallback_adapter: false,
             present_mode: None,
             width: NonZeroU32::new(1).unwrap(),
             height: NonZeroU32::new(1).unwrap(),
             viewport: Viewport::Full,
-            reset_fg: BLACK,
-            reset_bg: WHITE,
+            grid_rounding: Rounding::Floor,
+            integer_scaling: false,
+            scale_factor: 1.0,
+            resize_policy: ResizePolicy::Reflow,
+            reset_fg: Color::Reset,
+            reset_bg: Color::Reset,
+            palette: Palette::default(),
+            transparent_background: false,
+            alpha_mode: None,
+            blink: true,
             fast_blink: Duration::from_millis(200),
             slow_blink: Duration::from_millis(1000),
+            min_frame_interval: Duration::ZERO,
+            prealloc_grid: None,
+            glyph_antialiasing: true,
+            subpixel_aa: false,
+            text_gamma: 1.0,
+            text_blend: BlendMode::SourceOver,
+            hinting: HintingMode::None,
+            fake_italic_skew: 0.25,
+            emoji_mode: EmojiMode::Color,
+            missing_glyph: None,
+            required_coverage: vec![],
+            bidi: true,
+            min_contrast: 1.0,
+            dim_mode: DimMode::Alpha,
+            cell_shader: None,
+            overrender_margin: 0,
+            vertical_text: false,
+            glyph_disk_cache: None,
+            cursor_style: CursorStyle::Block,
+            cursor_blink: None,
+            underline_style: UnderlineStyle::Single,
+            padding_color: None,
         }
     }
 }
@@ -157,17 +263,167 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
     pub fn from_font_and_user_data(font: Font<'a>, user_data: P::UserData) -> Self {
         Self {
             user_data,
+            hasher: RandomState::default(),
             instance: None,
+            atlas_size: (CACHE_WIDTH, CACHE_HEIGHT),
+            max_atlas_pages: 4,
+            device: None,
+            instance_descriptor: None,
             fonts: Fonts::new(font, 24),
             limits: None,
+            power_preference: PowerPreference::default(),
+            force_fallback_adapter: false,
             present_mode: None,
             width: NonZeroU32::new(1).unwrap(),
             height: NonZeroU32::new(1).unwrap(),
             viewport: Viewport::Full,
-            reset_fg: BLACK,
-            reset_bg: WHITE,
+            grid_rounding: Rounding::Floor,
+            integer_scaling: false,
+            scale_factor: 1.0,
+            resize_policy: ResizePolicy::Reflow,
+            reset_fg: Color::Reset,
+            reset_bg: Color::Reset,
+            palette: Palette::default(),
+            transparent_background: false,
+            alpha_mode: None,
+            blink: true,
             fast_blink: Duration::from_millis(200),
             slow_blink: Duration::from_millis(1000),
+            min_frame_interval: Duration::ZERO,
+            prealloc_grid: None,
+            glyph_antialiasing: true,
+            subpixel_aa: false,
+            text_gamma: 1.0,
+            text_blend: BlendMode::SourceOver,
+            hinting: HintingMode::None,
+            fake_italic_skew: 0.25,
+            emoji_mode: EmojiMode::Color,
+            missing_glyph: None,
+            required_coverage: vec![],
+            bidi: true,
+            min_contrast: 1.0,
+            dim_mode: DimMode::Alpha,
+            cell_shader: None,
+            overrender_margin: 0,
+            vertical_text: false,
+            glyph_disk_cache: None,
+            cursor_style: CursorStyle::Block,
+            cursor_blink: None,
+            underline_style: UnderlineStyle::Single,
+            padding_color: None,
+        }
+    }
+}
+
+impl<'a, P: PostProcessor, H: BuildHasher + Clone> Builder<'a, P, H> {
+    /// Use the supplied hasher for the backend's internal glyph and shaping
+    /// caches. This is useful when hashing needs to be deterministic, e.g. for
+    /// reproducible golden tests. Defaults to `ahash::RandomState` when the
+    /// `ahash` feature is enabled, and `std::hash::RandomState` otherwise.
+    #[must_use]
+    pub fn with_hasher<H2: BuildHasher + Clone>(self, hasher: H2) -> Builder<'a, P, H2> {
+        let Builder {
+            hasher: _,
+            user_data,
+            fonts,
+            instance,
+            atlas_size,
+            max_atlas_pages,
+            device,
+            instance_descriptor,
+            limits,
+            power_preference,
+            force_fallback_adapter,
+            present_mode,
+            width,
+            height,
+            viewport,
+            grid_rounding,
+            integer_scaling,
+            scale_factor,
+            resize_policy,
+            reset_fg,
+            reset_bg,
+            palette,
+            transparent_background,
+            alpha_mode,
+            blink,
+            fast_blink,
+            slow_blink,
+            min_frame_interval,
+            prealloc_grid,
+            glyph_antialiasing,
+            subpixel_aa,
+            text_gamma,
+            text_blend,
+            hinting,
+            fake_italic_skew,
+            emoji_mode,
+            missing_glyph,
+            required_coverage,
+            bidi,
+            min_contrast,
+            dim_mode,
+            cell_shader,
+            overrender_margin,
+            vertical_text,
+            glyph_disk_cache,
+            cursor_style,
+            cursor_blink,
+            underline_style,
+            padding_color,
+        } = self;
+
+        Builder {
+            hasher,
+            user_data,
+            fonts,
+            instance,
+            atlas_size,
+            max_atlas_pages,
+            device,
+            instance_descriptor,
+            limits,
+            power_preference,
+            force_fallback_adapter,
+            present_mode,
+            width,
+            height,
+            viewport,
+            grid_rounding,
+            integer_scaling,
+            scale_factor,
+            resize_policy,
+            reset_fg,
+            reset_bg,
+            palette,
+            transparent_background,
+            alpha_mode,
+            blink,
+            fast_blink,
+            slow_blink,
+            min_frame_interval,
+            prealloc_grid,
+            glyph_antialiasing,
+            subpixel_aa,
+            text_gamma,
+            text_blend,
+            hinting,
+            fake_italic_skew,
+            emoji_mode,
+            missing_glyph,
+            required_coverage,
+            bidi,
+            min_contrast,
+            dim_mode,
+            cell_shader,
+            overrender_margin,
+            vertical_text,
+            glyph_disk_cache,
+            cursor_style,
+            cursor_blink,
+            underline_style,
+            padding_color,
         }
     }
 
@@ -178,6 +434,30 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
         self
     }
 
+    /// Use the supplied [`wgpu::Device`] and [`wgpu::Queue`] when building the
+    /// backend instead of requesting a new device. An adapter is still
+    /// requested from the builder's instance to query the surface's
+    /// capabilities, so the instance the device was created from should be
+    /// supplied with [`Builder::with_instance`]. Limits set with
+    /// [`Builder::with_limits`] are ignored in favor of the device's limits.
+    #[must_use]
+    pub fn with_device(mut self, device: Arc<Device>, queue: Arc<Queue>) -> Self {
+        self.device = Some((device, queue));
+        self
+    }
+
+    /// Use the supplied [`wgpu::InstanceDescriptor`] when the builder needs to
+    /// create its own [`wgpu::Instance`]. This lets you select the backends,
+    /// instance flags, DX12 shader compiler, or GLES minor version. Any field
+    /// may be overridden. This is ignored if an instance is supplied with
+    /// [`Builder::with_instance`]. Defaults to [`Backends::default`] and
+    /// [`InstanceFlags::default`] with the remaining fields defaulted.
+    #[must_use]
+    pub fn with_instance_descriptor(mut self, descriptor: InstanceDescriptor) -> Self {
+        self.instance_descriptor = Some(descriptor);
+        self
+    }
+
     /// Use the supplied [`Viewport`] for rendering. Defaults to
     /// [`Viewport::Full`].
     #[must_use]
@@ -186,6 +466,39 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
         self
     }
 
+    /// Use the specified [`Rounding`] when computing the number of columns and
+    /// rows which fit in the drawable area. Defaults to [`Rounding::Floor`].
+    #[must_use]
+    pub fn with_grid_rounding(mut self, rounding: Rounding) -> Self {
+        self.grid_rounding = rounding;
+        self
+    }
+
+    /// Size the surface to a whole number of cells, rounding the requested size
+    /// down, so the grid is drawn at exactly its size instead of being
+    /// stretched over a partial cell's worth of slack. Any inset from
+    /// [`Viewport::Shrink`] is kept. This overrides
+    /// [`Builder::with_grid_rounding`].
+    ///
+    /// The surface no longer covers the whole window, and what fills the rest
+    /// of it is up to the platform. Some window managers leave the remainder
+    /// blank, while others scale the surface to the window anyway. Prefer a
+    /// padding color or [`Viewport::Centered`] if the window must be covered.
+    /// Defaults to disabled.
+    #[must_use]
+    pub fn with_integer_scaling(mut self, enabled: bool) -> Self {
+        self.integer_scaling = enabled;
+        self
+    }
+
+    /// Use the specified [`ResizePolicy`] when a resize changes the number of
+    /// columns or rows in the grid. Defaults to [`ResizePolicy::Reflow`].
+    #[must_use]
+    pub fn with_resize_policy(mut self, policy: ResizePolicy) -> Self {
+        self.resize_policy = policy;
+        self
+    }
+
     /// Use the specified font size in pixels. Defaults to 24px.
     #[must_use]
     pub fn with_font_size_px(mut self, size: u32) -> Self {
@@ -193,6 +506,54 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
         self
     }
 
+    /// Multiply the font size, line height, and cell padding by `scale`, so
+    /// that they're given in logical pixels while the surface is sized in
+    /// physical pixels. Pass the scale factor of the window, e.g. from
+    /// winit's `Window::scale_factor`, and update it with
+    /// [`WgpuBackend::set_scale_factor`] when it changes. Non-positive values
+    /// are ignored. Defaults to 1.0.
+    #[must_use]
+    pub fn with_scale_factor(mut self, scale: f32) -> Self {
+        if scale > 0.0 && scale.is_finite() {
+            self.scale_factor = scale;
+        }
+        self
+    }
+
+    /// Use cells of the specified height in pixels, which spaces rows further
+    /// apart for fonts which look cramped at their natural height. Glyphs are
+    /// centered vertically within the taller cells and backgrounds fill the
+    /// entire cell. Heights smaller than the font size are ignored. Defaults
+    /// to the font size.
+    #[must_use]
+    pub fn with_line_height_px(mut self, height: u32) -> Self {
+        self.fonts.set_line_height_px(Some(height));
+        self
+    }
+
+    /// Widen every cell by the specified number of pixels, which loosens the
+    /// spacing between characters without switching fonts. Glyphs are
+    /// centered horizontally within the wider cells and backgrounds fill the
+    /// entire cell. Defaults to 0.
+    #[must_use]
+    pub fn with_cell_width_padding_px(mut self, padding: u32) -> Self {
+        self.fonts.set_width_padding_px(padding);
+        self
+    }
+
+    /// Use the specified font as the last-resort fallback in place of the
+    /// font the builder was created with. It renders any character which none
+    /// of the other fonts can, so a font with broad coverage, such as Noto, is
+    /// a good choice. Characters which it can't render either are drawn with
+    /// its missing glyph, see [`Builder::with_missing_glyph`].
+    ///
+    /// See also [`Fonts::set_last_resort`].
+    #[must_use]
+    pub fn with_last_resort_font(mut self, font: Font<'a>) -> Self {
+        self.fonts.set_last_resort(font);
+        self
+    }
+
     /// Use the specified list of fonts for rendering. You may call this
     /// multiple times to extend the list of fallback fonts. Note that this will
     /// automatically organize fonts by relative width in order to optimize
@@ -253,6 +614,24 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
         self
     }
 
+    /// Request an adapter with the specified [`wgpu::PowerPreference`], e.g.
+    /// [`PowerPreference::LowPower`] to avoid waking a laptop's discrete GPU.
+    /// Defaults to [`PowerPreference::None`].
+    #[must_use]
+    pub fn with_power_preference(mut self, preference: PowerPreference) -> Self {
+        self.power_preference = preference;
+        self
+    }
+
+    /// Only request a fallback adapter, which is usually a software renderer.
+    /// This is useful for rendering consistently in CI. Building fails if no
+    /// fallback adapter is available. Defaults to false.
+    #[must_use]
+    pub fn with_force_fallback_adapter(mut self, force: bool) -> Self {
+        self.force_fallback_adapter = force;
+        self
+    }
+
     /// Use the specified [`wgpu::PresentMode`].
     #[must_use]
     pub fn with_present_mode(mut self, mode: PresentMode) -> Self {
@@ -271,6 +650,50 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
         self
     }
 
+    /// Use the specified height and width for the text atlas which caches
+    /// rasterized glyphs. Larger fonts and scripts with many distinct glyphs
+    /// (e.g. CJK) may need a larger atlas to avoid glyphs being evicted and
+    /// rasterized again every frame. Building the backend fails with
+    /// [`Error::AtlasTooLarge`] if either dimension exceeds the device's
+    /// `max_texture_dimension_2d`. Defaults to 1800x1200.
+    #[must_use]
+    pub fn with_atlas_size(mut self, dimensions: Dimensions) -> Self {
+        self.atlas_size = (dimensions.width.get(), dimensions.height.get());
+        self
+    }
+
+    /// Use up to the specified number of pages for the text atlas. A page is
+    /// added when every glyph in the atlas is needed for the frame being
+    /// drawn, rather than evicting glyphs which are about to be drawn. Each
+    /// page is the size set with [`Builder::with_atlas_size`], and the number
+    /// of pages is limited to the device's `max_texture_array_layers`.
+    /// Defaults to 4.
+    #[must_use]
+    pub fn with_max_atlas_pages(mut self, pages: NonZeroU32) -> Self {
+        self.max_atlas_pages = pages.get();
+        self
+    }
+
+    /// Cache rasterized glyphs in files in `dir`, so that later runs can load
+    /// them instead of rasterizing them again. The directory is created if it
+    /// doesn't exist. Glyphs are cached separately for each font size and
+    /// rasterization setting, and are keyed by a hash of the font's data, so
+    /// changing a font's bytes invalidates its glyphs. Glyphs from fonts the
+    /// backend no longer uses are dropped from the cache when it's loaded.
+    ///
+    /// Cached glyphs are loaded into the text atlas when the backend is built
+    /// and whenever the font size or rasterization settings change, and newly
+    /// rasterized glyphs are written out when the backend is flushed. The
+    /// glyphs for the current settings are also kept in memory, up to 8192 of
+    /// them, and only the files for the four most recently written other
+    /// settings are kept alongside the current one. Failing to read or write
+    /// the cache is logged, and otherwise ignored. Defaults to no cache.
+    #[must_use]
+    pub fn with_glyph_disk_cache(mut self, dir: PathBuf) -> Self {
+        self.glyph_disk_cache = Some(dir);
+        self
+    }
+
     /// Use the specified height and width when creating the surface. Defaults
     /// to 1x1.
     #[must_use]
@@ -284,7 +707,7 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
     /// color. Defaults to Black.
     #[must_use]
     pub fn with_fg_color(mut self, fg: Color) -> Self {
-        self.reset_fg = c2c(fg, self.reset_fg);
+        self.reset_fg = fg;
         self
     }
 
@@ -292,7 +715,289 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
     /// color. Defaults to White.
     #[must_use]
     pub fn with_bg_color(mut self, bg: Color) -> Self {
-        self.reset_bg = c2c(bg, self.reset_bg);
+        self.reset_bg = bg;
+        self
+    }
+
+    /// Use the colors in `palette` for named and indexed colors, including the
+    /// default foreground and background colors if they are named or indexed.
+    /// Defaults to [`Palette::default`].
+    #[must_use]
+    pub fn with_palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    /// Reserve space for a grid of the specified number of columns and rows
+    /// when building the backend. If you know the size of your grid up front,
+    /// this avoids reallocating internal buffers during the first few frames.
+    /// This only reserves capacity and does not otherwise change rendering
+    /// behavior.
+    #[must_use]
+    pub fn with_prealloc_grid(mut self, cols: u16, rows: u16) -> Self {
+        self.prealloc_grid = Some((cols, rows));
+        self
+    }
+
+    /// Enable or disable antialiasing of outline glyphs. Disabling
+    /// antialiasing produces hard-edged glyphs, which preserves the crisp look
+    /// of pixel fonts. Color and bitmap glyphs are unaffected. Defaults to
+    /// enabled.
+    #[must_use]
+    pub fn with_glyph_antialiasing(mut self, enabled: bool) -> Self {
+        self.glyph_antialiasing = enabled;
+        self
+    }
+
+    /// Enable or disable subpixel antialiasing of outline glyphs. Each glyph is
+    /// rasterized with separate coverage for the red, green, and blue
+    /// subpixels of a pixel, which makes text look sharper on LCD screens
+    /// with a horizontal RGB subpixel layout. Text may show color fringes on
+    /// other screens, or if the output is scaled. Has no effect if glyph
+    /// antialiasing is disabled or [`Builder::with_text_blend`] is set to
+    /// anything but [`BlendMode::SourceOver`]. Color and bitmap glyphs are
+    /// unaffected. Defaults to disabled.
+    #[must_use]
+    pub fn with_subpixel_aa(mut self, enabled: bool) -> Self {
+        self.subpixel_aa = enabled;
+        self
+    }
+
+    /// Set the gamma applied to the coverage of outline and bitmap glyphs
+    /// before they're blended over the background. Values above 1.0 make text
+    /// heavier and values below 1.0 make it lighter, which can compensate for
+    /// strokes looking too thin or too heavy after blending. Color glyphs are
+    /// unaffected. Non-positive values are ignored. Defaults to 1.0, which
+    /// leaves coverage unchanged.
+    #[must_use]
+    pub fn with_text_gamma(mut self, gamma: f32) -> Self {
+        if gamma > 0.0 {
+            self.text_gamma = gamma;
+        }
+        self
+    }
+
+    /// Use the specified [`BlendMode`] when blending glyphs with the cell
+    /// background, e.g. [`BlendMode::Multiply`] for highlighter-like text.
+    /// Subpixel antialiasing is only used with [`BlendMode::SourceOver`], and
+    /// is disabled by any other mode. Defaults to [`BlendMode::SourceOver`].
+    #[must_use]
+    pub fn with_text_blend(mut self, mode: BlendMode) -> Self {
+        self.text_blend = mode;
+        self
+    }
+
+    /// Use the specified [`HintingMode`] when rasterizing outline glyphs.
+    /// Defaults to [`HintingMode::None`].
+    #[must_use]
+    pub fn with_hinting(mut self, hinting: HintingMode) -> Self {
+        self.hinting = hinting;
+        self
+    }
+
+    /// Set the slant of synthesized italics for fonts which lack an italic
+    /// face, as the horizontal shift per unit of glyph height. Positive values
+    /// lean glyphs to the right, and e.g. `0.2` is roughly an 11° slant.
+    /// Defaults to `0.25`.
+    #[must_use]
+    pub fn with_fake_italic_skew(mut self, skew: f32) -> Self {
+        self.fake_italic_skew = skew;
+        self
+    }
+
+    /// Use the specified [`EmojiMode`] when rendering glyphs with color data.
+    /// Defaults to [`EmojiMode::Color`].
+    #[must_use]
+    pub fn with_emoji_mode(mut self, mode: EmojiMode) -> Self {
+        self.emoji_mode = mode;
+        self
+    }
+
+    /// Draw the specified character from the last-resort font in place of
+    /// characters which none of the fonts can render, e.g. `'□'` or `'�'`.
+    /// The placeholder is ignored if the last-resort font can't render it
+    /// either. Defaults to drawing whatever the font that was chosen for the
+    /// character uses for missing glyphs, which is often nothing at all.
+    #[must_use]
+    pub fn with_missing_glyph(mut self, placeholder: char) -> Self {
+        self.missing_glyph = Some(placeholder);
+        self
+    }
+
+    /// Fail to build with [`Error::InsufficientFontCoverage`] if any of the
+    /// specified characters can't be rendered by any of the fonts, e.g. to
+    /// catch loading the wrong font file before it renders a blank screen.
+    /// Characters are looked up following the same fallback order as unstyled
+    /// text, and each one only has to be covered by a single font, so a set
+    /// split between the regular font and its fallbacks passes. The check runs
+    /// before an adapter or device is requested. Defaults to not checking any
+    /// characters.
+    #[must_use]
+    pub fn with_required_coverage(mut self, chars: &[char]) -> Self {
+        self.required_coverage = chars.to_vec();
+        self
+    }
+
+    /// Enable or disable reordering right-to-left text, such as Arabic or
+    /// Hebrew, into visual order using the Unicode bidirectional algorithm.
+    /// When disabled, every row is laid out left-to-right in the order its
+    /// cells were written, which saves work for applications which only
+    /// display left-to-right text. Defaults to enabled.
+    #[must_use]
+    pub fn with_bidi(mut self, enabled: bool) -> Self {
+        self.bidi = enabled;
+        self
+    }
+
+    /// Adjust the foreground color of each cell as needed so that it has at
+    /// least the specified WCAG 2 contrast ratio against the cell's background,
+    /// from 1.0 to 21.0. Foregrounds which fall short are lightened or darkened
+    /// just enough to meet the ratio, which keeps text legible with arbitrary
+    /// color schemes. WCAG recommends a ratio of 4.5 for body text. Defaults to
+    /// 1.0, which never adjusts colors.
+    #[must_use]
+    pub fn with_min_contrast(mut self, ratio: f32) -> Self {
+        self.min_contrast = ratio.clamp(1.0, 21.0);
+        self
+    }
+
+    /// Use the specified [`DimMode`] for text with
+    /// [`Modifier::DIM`](ratatui::style::Modifier::DIM). Defaults to
+    /// [`DimMode::Alpha`].
+    #[must_use]
+    pub fn with_dim_mode(mut self, mode: DimMode) -> Self {
+        self.dim_mode = mode;
+        self
+    }
+
+    /// Run `shader` on every cell as it's drawn, which can override the colors
+    /// and opacity the cell is drawn with, e.g. to add a shimmer to selected
+    /// text. Overrides from the shader take precedence over those from
+    /// [`WgpuBackend::print_rgba`] and are combined with any opacity from
+    /// [`WgpuBackend::set_cell_opacity`]. The foreground is adjusted for
+    /// [`Builder::with_min_contrast`] after the shader runs.
+    ///
+    /// The shader only runs for cells which are redrawn during a flush, so it
+    /// should return the same result for a cell until that cell changes.
+    /// By default, no shader runs and cells are drawn as is.
+    #[must_use]
+    pub fn with_cell_shader(mut self, shader: CellShader) -> Self {
+        self.cell_shader = Some(shader);
+        self
+    }
+
+    /// Render text into an intermediate target which is `cells` columns and
+    /// rows larger than the grid, copying the region covered by the grid out of
+    /// it each frame. When a resize leaves the grid within the bounds of this
+    /// target, its contents survive the resize and are presented immediately,
+    /// rather than the screen being blank until the next draw. This reduces
+    /// flicker while a window is being dragged to a new size, particularly
+    /// when combined with [`ResizePolicy::Reflow`]. Newly exposed cells show
+    /// stale contents until they are drawn.
+    ///
+    /// The intermediate target costs 4 bytes per pixel, covering
+    /// `(columns + cells) * (rows + cells)` cells, on top of the regular target
+    /// for the grid. It also adds a copy of the grid each frame. Defaults to 0,
+    /// which disables the intermediate target.
+    #[must_use]
+    pub fn with_overrender_margin(mut self, cells: u16) -> Self {
+        self.overrender_margin = cells;
+        self
+    }
+
+    /// Lay the grid out for vertical writing, as is common for CJK text. Each
+    /// row of the grid is presented as a column of the screen, with the first
+    /// row on the right and later rows to its left, and its cells are stacked
+    /// top to bottom down that column one slot each. Glyphs stay upright, so
+    /// columns are two cells wide: narrow characters are centered across their
+    /// slot, and wide characters are centered down the two slots they cover.
+    ///
+    /// This is experimental. The grid is still drawn as rows of cells, so
+    /// [`Backend::size`](ratatui::backend::Backend::size) reports one column
+    /// for each slot down the screen. Text is rendered into an intermediate
+    /// target as for [`Builder::with_overrender_margin`], and the slots of
+    /// changed cells are laid out onto the surface in a single pass. Pixel
+    /// positions reported by the backend, such as by
+    /// [`WgpuBackend::capture_region`], are those of that horizontal layout,
+    /// except for [`WgpuBackend::take_damage`]. Defaults to false.
+    #[must_use]
+    pub fn with_vertical_text(mut self, vertical: bool) -> Self {
+        self.vertical_text = vertical;
+        self
+    }
+
+    /// Use the specified [`CursorStyle`] when the cursor is shown. Defaults to
+    /// [`CursorStyle::Block`].
+    #[must_use]
+    pub fn with_cursor_style(mut self, style: CursorStyle) -> Self {
+        self.cursor_style = style;
+        self
+    }
+
+    /// Decorate underlined text using the specified [`UnderlineStyle`].
+    /// Defaults to [`UnderlineStyle::Single`].
+    #[must_use]
+    pub fn with_underline_style(mut self, style: UnderlineStyle) -> Self {
+        self.underline_style = style;
+        self
+    }
+
+    /// Leave the background of cells using [`Color::Reset`] transparent instead
+    /// of filling it with the default background color, e.g. for overlays and
+    /// HUDs drawn over other content. The area outside of the grid is also left
+    /// transparent unless a padding color is set.
+    ///
+    /// The composited text uses premultiplied alpha, so the surface's
+    /// [`CompositeAlphaMode`] is set to `PreMultiplied` if the surface supports
+    /// it and no other mode was chosen with [`Builder::with_alpha_mode`].
+    /// Otherwise, whether the transparency is visible depends on how the
+    /// platform composites the window. Defaults to false.
+    #[must_use]
+    pub fn with_transparent_background(mut self, transparent: bool) -> Self {
+        self.transparent_background = transparent;
+        self
+    }
+
+    /// Use the specified [`CompositeAlphaMode`] for the surface, which controls
+    /// how the platform blends the window with what's behind it. If the surface
+    /// doesn't support the mode, a warning is logged and the default mode is
+    /// used instead. Defaults to the surface's default mode, or
+    /// `PreMultiplied` with [`Builder::with_transparent_background`].
+    #[must_use]
+    pub fn with_alpha_mode(mut self, mode: CompositeAlphaMode) -> Self {
+        self.alpha_mode = Some(mode);
+        self
+    }
+
+    /// Fill the area of the surface which isn't covered by whole cells with the
+    /// specified color, instead of stretching the text to cover the whole
+    /// surface. Defaults to stretching.
+    #[must_use]
+    pub fn with_padding_color(mut self, color: Color) -> Self {
+        self.padding_color = Some(color);
+        self
+    }
+
+    /// Blink the cursor, toggling it at the specified interval in milliseconds.
+    /// Like blinking text, this requires `flush` to be called frequently
+    /// enough. Defaults to a solid, non-blinking cursor.
+    #[must_use]
+    pub fn with_cursor_blink_millis(mut self, millis: u64) -> Self {
+        self.cursor_blink = Some(Duration::from_millis(millis));
+        self
+    }
+
+    /// Whether text with [`Modifier::RAPID_BLINK`] or [`Modifier::SLOW_BLINK`]
+    /// blinks. When disabled, blinking text is always shown, and `flush` no
+    /// longer tracks which cells blink or toggles them, so it only renders
+    /// when something has actually changed. This doesn't affect the cursor,
+    /// see [`Builder::with_cursor_blink_millis`]. Defaults to true.
+    ///
+    /// [`Modifier::RAPID_BLINK`]: ratatui::style::Modifier::RAPID_BLINK
+    /// [`Modifier::SLOW_BLINK`]: ratatui::style::Modifier::SLOW_BLINK
+    #[must_use]
+    pub fn with_blink(mut self, blink: bool) -> Self {
+        self.blink = blink;
         self
     }
 
@@ -315,21 +1020,34 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
         self.slow_blink = Duration::from_millis(millis);
         self
     }
+
+    /// Skip calls to `flush` made less than `interval` after the last flush
+    /// which rendered. Changes made in the meantime aren't lost, they are
+    /// rendered by the next flush once the interval has elapsed. This limits
+    /// how much time is spent rendering when `flush` is called in a tight
+    /// loop, e.g. to keep text blinking. Defaults to zero, which never skips a
+    /// flush.
+    #[must_use]
+    pub fn with_min_frame_interval(mut self, interval: Duration) -> Self {
+        self.min_frame_interval = interval;
+        self
+    }
 }
 
-impl<'a, P: PostProcessor> Builder<'a, P> {
+impl<'a, P: PostProcessor, H: BuildHasher + Clone> Builder<'a, P, H> {
     /// Build a new backend with the provided surface target - e.g. a winit
     /// `Window`.
     pub async fn build_with_target<'s>(
         mut self,
         target: impl Into<SurfaceTarget<'s>>,
-    ) -> Result<WgpuBackend<'a, 's, P>> {
+    ) -> Result<WgpuBackend<'a, 's, P, Surface<'s>, H>> {
+        let descriptor = self.instance_descriptor.take();
         let instance = self.instance.get_or_insert_with(|| {
-            wgpu::Instance::new(InstanceDescriptor {
+            wgpu::Instance::new(descriptor.unwrap_or(InstanceDescriptor {
                 backends: Backends::default(),
                 flags: InstanceFlags::default(),
                 ..Default::default()
-            })
+            }))
         });
         let surface = instance
             .create_surface(target)
@@ -345,63 +1063,121 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
     pub async fn build_with_surface<'s>(
         self,
         surface: Surface<'s>,
-    ) -> Result<WgpuBackend<'a, 's, P>> {
+    ) -> Result<WgpuBackend<'a, 's, P, Surface<'s>, H>> {
         self.build_with_render_surface(surface).await
     }
 
-    #[cfg(test)]
-    pub(crate) async fn build_headless(
+    /// Build a new backend with the provided surface target, blocking the
+    /// current thread until it is ready. See [`Builder::build_with_target`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn build_with_target_blocking<'s>(
         self,
-    ) -> Result<WgpuBackend<'a, 'static, P, super::HeadlessSurface>> {
+        target: impl Into<SurfaceTarget<'s>>,
+    ) -> Result<WgpuBackend<'a, 's, P, Surface<'s>, H>> {
+        futures_lite::future::block_on(self.build_with_target(target))
+    }
+
+    /// Build a new backend from this builder with the supplied surface,
+    /// blocking the current thread until it is ready. See
+    /// [`Builder::build_with_surface`].
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn build_with_surface_blocking<'s>(
+        self,
+        surface: Surface<'s>,
+    ) -> Result<WgpuBackend<'a, 's, P, Surface<'s>, H>> {
+        futures_lite::future::block_on(self.build_with_surface(surface))
+    }
+
+    /// Build a new backend from this builder which renders to an offscreen
+    /// texture instead of a window. The rendered result can be read back with
+    /// [`WgpuBackend::read_rgba`].
+    pub async fn build_headless(
+        self,
+    ) -> Result<WgpuBackend<'a, 'static, P, super::HeadlessSurface, H>> {
         self.build_with_render_surface(super::HeadlessSurface::default())
             .await
     }
 
+    /// Build a new backend from this builder which renders into a texture
+    /// owned by the caller, using the caller's device and queue. This is
+    /// useful for embedding the terminal in a larger wgpu scene, e.g. a game
+    /// or editor. The texture must have been created on `device` with
+    /// [`TextureUsages::RENDER_ATTACHMENT`], and its size takes the place of
+    /// the size set with [`Builder::with_width_and_height`]. Use
+    /// [`WgpuBackend::set_texture`] rather than [`WgpuBackend::resize`] to
+    /// change the size of the terminal.
+    ///
+    /// Rendered frames are submitted to `queue` at the end of each call to
+    /// flush, so the texture can be sampled by any work submitted afterwards.
+    pub fn build_with_texture(
+        self,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        texture: Texture,
+    ) -> Result<WgpuBackend<'a, 'static, P, TextureTarget, H>> {
+        self.check_coverage()?;
+
+        let surface = TextureTarget::new(texture);
+        let surface_config = surface.config();
+        self.build_with_device(surface, surface_config, vec![], None, device, queue)
+    }
+
     #[cfg(test)]
     pub(crate) async fn build_headless_with_format(
         self,
         format: TextureFormat,
-    ) -> Result<WgpuBackend<'a, 'static, P, super::HeadlessSurface>> {
+    ) -> Result<WgpuBackend<'a, 'static, P, super::HeadlessSurface, H>> {
         self.build_with_render_surface(super::HeadlessSurface::new(format))
             .await
     }
 
     async fn build_with_render_surface<'s, S: RenderSurface<'s> + 's>(
         mut self,
-        mut surface: S,
-    ) -> Result<WgpuBackend<'a, 's, P, S>> {
+        surface: S,
+    ) -> Result<WgpuBackend<'a, 's, P, S, H>> {
+        self.check_coverage()?;
+
+        let descriptor = self.instance_descriptor.take();
         let instance = self.instance.get_or_insert_with(|| {
-            wgpu::Instance::new(InstanceDescriptor {
+            wgpu::Instance::new(descriptor.unwrap_or(InstanceDescriptor {
                 backends: Backends::default(),
                 flags: InstanceFlags::default(),
                 ..Default::default()
-            })
+            }))
         });
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: self.power_preference,
+                force_fallback_adapter: self.force_fallback_adapter,
                 compatible_surface: surface.wgpu_surface(Token),
-                ..Default::default()
             })
             .await
             .ok_or(Error::AdapterRequestFailed)?;
 
-        let limits = if let Some(limits) = self.limits {
-            min_limits(&adapter, limits)
+        let (device, queue) = if let Some((device, queue)) = self.device.take() {
+            (device, queue)
         } else {
-            adapter.limits()
+            let limits = if let Some(limits) = self.limits.take() {
+                min_limits(&adapter, limits)
+            } else {
+                adapter.limits()
+            };
+
+            let (device, queue) = adapter
+                .request_device(
+                    &wgpu::DeviceDescriptor {
+                        required_limits: limits,
+                        ..Default::default()
+                    },
+                    None,
+                )
+                .await
+                .map_err(Error::DeviceRequestFailed)?;
+
+            (Arc::new(device), Arc::new(queue))
         };
-
-        let (device, queue) = adapter
-            .request_device(
-                &wgpu::DeviceDescriptor {
-                    required_limits: limits.clone(),
-                    ..Default::default()
-                },
-                None,
-            )
-            .await
-            .map_err(Error::DeviceRequestFailed)?;
+        let limits = device.limits();
 
         let mut surface_config = surface
             .get_default_config(
@@ -416,55 +1192,116 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
             surface_config.present_mode = mode;
         }
 
-        surface.configure(&device, &surface_config, Token);
+        let alpha_mode = self.alpha_mode.or(self
+            .transparent_background
+            .then_some(CompositeAlphaMode::PreMultiplied));
+        if let Some(mode) = alpha_mode {
+            match surface.wgpu_surface(Token) {
+                Some(wgpu_surface)
+                    if !wgpu_surface
+                        .get_capabilities(&adapter)
+                        .alpha_modes
+                        .contains(&mode) =>
+                {
+                    warn!(
+                        "Surface doesn't support the {mode:?} alpha mode, falling back to {:?}",
+                        surface_config.alpha_mode
+                    );
+                }
+                _ => surface_config.alpha_mode = mode,
+            }
+        }
+
+        let present_modes = surface
+            .wgpu_surface(Token)
+            .map(|wgpu_surface| wgpu_surface.get_capabilities(&adapter).present_modes)
+            .unwrap_or_default();
+
+        let adapter_info = adapter.get_info();
+        info!(
+            "Using {} ({:?} backend)",
+            adapter_info.name, adapter_info.backend
+        );
+
+        self.build_with_device(
+            surface,
+            surface_config,
+            present_modes,
+            Some(adapter_info),
+            device,
+            queue,
+        )
+    }
+
+    fn check_coverage(&self) -> Result<()> {
+        let missing = self
+            .required_coverage
+            .iter()
+            .copied()
+            .filter(|ch| self.fonts.resolve(*ch, Modifier::empty()).is_none())
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Error::InsufficientFontCoverage { missing })
+        }
+    }
+
+    fn build_with_device<'s, S: RenderSurface<'s> + 's>(
+        mut self,
+        mut surface: S,
+        surface_config: SurfaceConfiguration,
+        present_modes: Vec<PresentMode>,
+        adapter_info: Option<AdapterInfo>,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+    ) -> Result<WgpuBackend<'a, 's, P, S, H>> {
+        let (atlas_width, atlas_height) = self.atlas_size;
+        let max_dimension = device.limits().max_texture_dimension_2d;
+        if atlas_width > max_dimension || atlas_height > max_dimension {
+            return Err(Error::AtlasTooLarge {
+                width: atlas_width,
+                height: atlas_height,
+                max: max_dimension,
+            });
+        }
+
+        let logical_metrics = LogicalMetrics::of(&self.fonts);
+        logical_metrics.apply(&mut self.fonts, self.scale_factor);
 
         let (inset_width, inset_height) = match self.viewport {
-            Viewport::Full => (0, 0),
+            Viewport::Full | Viewport::Centered => (0, 0),
             Viewport::Shrink { width, height } => (width, height),
         };
 
-        let drawable_width = surface_config.width - inset_width;
-        let drawable_height = surface_config.height - inset_height;
+        // With vertical text, each column of the surface is two cells wide.
+        let (slot_width, slot_height) = if self.vertical_text {
+            (self.fonts.cell_width_px() * 2, self.fonts.cell_height_px())
+        } else {
+            (self.fonts.cell_width_px(), self.fonts.cell_height_px())
+        };
 
-        info!(
-            "char width x height: {}x{}",
-            self.fonts.min_width_px(),
-            self.fonts.height_px()
-        );
+        let mut surface_config = surface_config;
+        if self.integer_scaling {
+            (surface_config.width, surface_config.height) = whole_cell_size(
+                (surface_config.width, surface_config.height),
+                (inset_width, inset_height),
+                (slot_width, slot_height),
+            );
+        }
 
-        let text_cache = device.create_texture(&TextureDescriptor {
-            label: Some("Text Atlas"),
-            size: Extent3d {
-                width: CACHE_WIDTH,
-                height: CACHE_HEIGHT,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
+        surface.configure(&device, &surface_config, Token);
 
-        let text_cache_view = text_cache.create_view(&TextureViewDescriptor::default());
+        let drawable_width = surface_config.width.saturating_sub(inset_width);
+        let drawable_height = surface_config.height.saturating_sub(inset_height);
 
-        let text_mask = device.create_texture(&TextureDescriptor {
-            label: Some("Text Mask"),
-            size: Extent3d {
-                width: CACHE_WIDTH,
-                height: CACHE_HEIGHT,
-                depth_or_array_layers: 1,
-            },
-            mip_level_count: 1,
-            sample_count: 1,
-            dimension: TextureDimension::D2,
-            format: TextureFormat::R8Unorm,
-            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
-            view_formats: &[],
-        });
+        info!(
+            "char width x height: {}x{}",
+            self.fonts.cell_width_px(),
+            self.fonts.cell_height_px()
+        );
 
-        let text_mask_view = text_mask.create_view(&TextureViewDescriptor::default());
+        let (text_cache, text_mask) = build_text_atlas(&device, atlas_width, atlas_height, 1);
 
         let sampler = device.create_sampler(&SamplerDescriptor {
             address_mode_u: AddressMode::ClampToEdge,
@@ -485,53 +1322,144 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
 
         let atlas_size_buffer = device.create_buffer_init(&BufferInitDescriptor {
             label: Some("Atlas Size buffer"),
-            contents: bytemuck::cast_slice(&[CACHE_WIDTH as f32, CACHE_HEIGHT as f32, 0.0, 0.0]),
+            contents: bytemuck::cast_slice(&[
+                atlas_width as f32,
+                atlas_height as f32,
+                self.text_gamma,
+                0.0,
+            ]),
             usage: BufferUsages::UNIFORM,
         });
 
         let text_bg_compositor = build_text_bg_compositor(&device, &text_screen_size_buffer);
 
+        let subpixel_aa =
+            self.subpixel_aa && self.glyph_antialiasing && self.text_blend == BlendMode::SourceOver;
         let text_fg_compositor = build_text_fg_compositor(
             &device,
             &text_screen_size_buffer,
-            &atlas_size_buffer,
-            &text_cache_view,
-            &text_mask_view,
-            &sampler,
+            atlas_size_buffer,
+            sampler,
+            &text_cache,
+            &text_mask,
+            subpixel_aa,
+            self.text_blend,
         );
 
-        let wgpu_state = build_wgpu_state(
-            &device,
-            (drawable_width / self.fonts.min_width_px()) * self.fonts.min_width_px(),
-            (drawable_height / self.fonts.height_px()) * self.fonts.height_px(),
+        let text_width = self.grid_rounding.cells(drawable_width, slot_width) * slot_width;
+        let text_height = self.grid_rounding.cells(drawable_height, slot_height) * slot_height;
+        let mut wgpu_state =
+            if self.padding_color.is_some() || matches!(self.viewport, Viewport::Centered) {
+                build_wgpu_state(
+                    &device,
+                    text_width.max(surface_config.width),
+                    text_height.max(surface_config.height),
+                )
+            } else {
+                build_wgpu_state(&device, text_width, text_height)
+            };
+        if self.overrender_margin != 0 || self.vertical_text {
+            // Text is always rendered in rows, so the rows of a vertical grid are laid
+            // out across the intermediate target.
+            let (grid_width, grid_height) = if self.vertical_text {
+                (
+                    text_height / slot_height * self.fonts.cell_width_px(),
+                    text_width / slot_width * slot_height,
+                )
+            } else {
+                (text_width, text_height)
+            };
+            wgpu_state.overrender = Some(build_overrender_target(
+                &device,
+                grid_width + self.overrender_margin as u32 * self.fonts.cell_width_px(),
+                grid_height + self.overrender_margin as u32 * self.fonts.cell_height_px(),
+            ));
+        }
+
+        let (prealloc_cells, prealloc_rows) = self
+            .prealloc_grid
+            .map(|(cols, rows)| (cols as usize * rows as usize, rows as usize))
+            .unwrap_or_default();
+
+        let mut cached = Atlas::new(
+            &self.fonts,
+            atlas_width,
+            atlas_height,
+            self.max_atlas_pages
+                .min(device.limits().max_texture_array_layers),
         );
+        cached.reserve(prealloc_cells);
 
-        Ok(WgpuBackend {
+        let mut backend = WgpuBackend {
             post_process: P::compile(
                 &device,
                 &wgpu_state.text_dest_view,
                 &surface_config,
                 self.user_data,
             ),
-            cells: vec![],
-            dirty_rows: vec![],
-            dirty_cells: BitVec::new(),
-            rendered: vec![],
-            sourced: vec![],
-            fast_blinking: BitVec::new(),
-            slow_blinking: BitVec::new(),
+            post_process_enabled: true,
+            blitter: None,
+            pointer: None,
+            paused: false,
+            batching: false,
+            batch_pending: false,
+            metric_overlay: None,
+            focus_ring: None,
+            panes: vec![],
+            region_gradients: vec![],
+            images: vec![],
+            image_compositor: None,
+            image_draws: vec![],
+            image_vertices: vec![],
+            vertical_compositor: self
+                .vertical_text
+                .then(|| build_vertical_compositor(&device)),
+            vertical_vertices: vec![],
+            #[cfg(any(feature = "kitty-graphics", feature = "sixel"))]
+            escapes: Default::default(),
+            #[cfg(any(feature = "kitty-graphics", feature = "sixel"))]
+            escape_placements: HashMap::default(),
+            overlay_quads: 0,
+            cursor_quads: 0,
+            cells: Vec::with_capacity(prealloc_cells),
+            dirty_rows: Vec::with_capacity(prealloc_rows),
+            dirty_cells: BitVec::with_capacity(prealloc_cells),
+            flushed_cells: BitVec::with_capacity(prealloc_cells),
+            damage: None,
+            needs_present: false,
+            missing_glyphs: HashSet::new(),
+            glyphs_rasterized: 0,
+            last_frame_stats: FrameStats::default(),
+            hasher: self.hasher.clone(),
+            rendered: Vec::with_capacity(prealloc_cells),
+            sourced: Vec::with_capacity(prealloc_cells),
+            fast_blinking: BitVec::with_capacity(prealloc_cells),
+            slow_blinking: BitVec::with_capacity(prealloc_cells),
+            cell_opacity: HashMap::default(),
+            color_overrides: HashMap::default(),
             cursor: (0, 0),
+            saved_screen: None,
             surface,
             _surface: PhantomData,
             surface_config,
+            present_modes,
+            adapter_info,
             device,
             queue,
-            plan_cache: PlanCache::new(self.fonts.count().max(2)),
+            plan_cache: PlanCache::new(self.fonts.count().max(2), self.hasher.clone()),
             buffer: UnicodeBuffer::new(),
             row: String::new(),
             rowmap: vec![],
+            row_sourced: vec![],
             viewport: self.viewport,
-            cached: Atlas::new(&self.fonts, CACHE_WIDTH, CACHE_HEIGHT),
+            grid_rounding: self.grid_rounding,
+            integer_scaling: self.integer_scaling,
+            scale_factor: self.scale_factor,
+            logical_metrics,
+            resize_policy: self.resize_policy,
+            cached,
+            atlas_uploads: vec![],
+            text_batches: vec![],
             text_cache,
             text_mask,
             bg_vertices: vec![],
@@ -541,16 +1469,47 @@ impl<'a, P: PostProcessor> Builder<'a, P> {
             text_bg_compositor,
             text_fg_compositor,
             wgpu_state,
+            frame_buffers: FrameBuffers::default(),
             fonts: self.fonts,
-            reset_fg: self.reset_fg,
-            reset_bg: self.reset_bg,
+            glyph_antialiasing: self.glyph_antialiasing,
+            subpixel_aa,
+            hinting: self.hinting,
+            fake_italic_skew: self.fake_italic_skew,
+            emoji_mode: self.emoji_mode,
+            missing_glyph: self.missing_glyph,
+            bidi: self.bidi,
+            min_contrast: self.min_contrast,
+            dim_mode: self.dim_mode,
+            cell_shader: self.cell_shader,
+            overrender_margin: self.overrender_margin,
+            vertical_text: self.vertical_text,
+            glyph_disk_cache: self.glyph_disk_cache.map(GlyphDiskCache::new),
+            cursor_style: self.cursor_style,
+            cursor_visible: false,
+            cursor_blink: self.cursor_blink,
+            underline_style: self.underline_style,
+            padding_color: self.padding_color,
+            clear_padding: true,
+            last_cursor_toggle: Instant::now(),
+            show_cursor: true,
+            reset_fg: c2c(self.reset_fg, BLACK, &self.palette),
+            reset_bg: c2c(self.reset_bg, WHITE, &self.palette),
+            reset_colors: (self.reset_fg, self.reset_bg),
+            palette: self.palette,
+            transparent_background: self.transparent_background,
+            blink: self.blink,
             fast_duration: self.fast_blink,
             last_fast_toggle: Instant::now(),
             show_fast: true,
             slow_duration: self.slow_blink,
             last_slow_toggle: Instant::now(),
             show_slow: true,
-        })
+            min_frame_interval: self.min_frame_interval,
+            last_flush: None,
+        };
+        backend.sync_glyph_disk_cache();
+
+        Ok(backend)
     }
 }
 
@@ -586,52 +1545,79 @@ fn build_text_bg_compositor(device: &Device, screen_size: &Buffer) -> TextCacheB
         push_constant_ranges: &[],
     });
 
-    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-        label: Some("Text Bg Compositor Pipeline"),
-        layout: Some(&pipeline_layout),
-        vertex: VertexState {
-            module: &shader,
-            entry_point: Some("vs_main"),
-            compilation_options: PipelineCompilationOptions::default(),
-            buffers: &[VertexBufferLayout {
-                array_stride: size_of::<TextBgVertexMember>() as u64,
-                step_mode: VertexStepMode::Vertex,
-                attributes: &vertex_attr_array![0 => Float32x2, 1 => Uint32],
-            }],
-        },
-        primitive: PrimitiveState {
-            topology: PrimitiveTopology::TriangleList,
-            ..Default::default()
-        },
-        depth_stencil: None,
-        multisample: MultisampleState::default(),
-        fragment: Some(FragmentState {
-            module: &shader,
-            entry_point: Some("fs_main"),
-            compilation_options: PipelineCompilationOptions::default(),
-            targets: &[Some(ColorTargetState {
-                format: TextureFormat::Rgba8Unorm,
-                blend: None,
-                write_mask: ColorWrites::ALL,
-            })],
+    let build_pipeline = |label, blend| {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[VertexBufferLayout {
+                    array_stride: size_of::<TextBgVertexMember>() as u64,
+                    step_mode: VertexStepMode::Vertex,
+                    attributes: &vertex_attr_array![0 => Float32x2, 1 => Uint32],
+                }],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format: TextureFormat::Rgba8Unorm,
+                    blend,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+            cache: None,
+        })
+    };
+
+    let pipeline = build_pipeline("Text Bg Compositor Pipeline", None);
+    // Overlays are drawn over glyphs, so unlike cell backgrounds they need to blend
+    // with what's already there.
+    let overlay_pipeline = build_pipeline(
+        "Text Overlay Compositor Pipeline",
+        Some(BlendState::ALPHA_BLENDING),
+    );
+    // Drawing white with this inverts the colors underneath.
+    let invert_pipeline = build_pipeline(
+        "Text Invert Compositor Pipeline",
+        Some(BlendState {
+            color: BlendComponent {
+                src_factor: BlendFactor::OneMinusDst,
+                dst_factor: BlendFactor::Zero,
+                operation: BlendOperation::Add,
+            },
+            alpha: BlendComponent::OVER,
         }),
-        multiview: None,
-        cache: None,
-    });
+    );
 
     TextCacheBgPipeline {
         pipeline,
+        overlay_pipeline,
+        invert_pipeline,
         fs_uniforms,
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn build_text_fg_compositor(
     device: &Device,
     screen_size: &Buffer,
-    atlas_size: &Buffer,
-    cache_view: &TextureView,
-    mask_view: &TextureView,
-    sampler: &Sampler,
+    atlas_size: Buffer,
+    sampler: Sampler,
+    text_cache: &Texture,
+    text_mask: &Texture,
+    subpixel_aa: bool,
+    blend: BlendMode,
 ) -> TextCacheFgPipeline {
     let shader = device.create_shader_module(include_wgsl!("shaders/composite_fg.wgsl"));
 
@@ -657,7 +1643,7 @@ fn build_text_fg_compositor(
                 visibility: ShaderStages::FRAGMENT,
                 ty: BindingType::Texture {
                     sample_type: TextureSampleType::Float { filterable: true },
-                    view_dimension: TextureViewDimension::D2,
+                    view_dimension: TextureViewDimension::D2Array,
                     multisampled: false,
                 },
                 count: None,
@@ -667,7 +1653,7 @@ fn build_text_fg_compositor(
                 visibility: ShaderStages::FRAGMENT,
                 ty: BindingType::Texture {
                     sample_type: TextureSampleType::Float { filterable: true },
-                    view_dimension: TextureViewDimension::D2,
+                    view_dimension: TextureViewDimension::D2Array,
                     multisampled: false,
                 },
                 count: None,
@@ -700,28 +1686,14 @@ fn build_text_fg_compositor(
         }],
     });
 
-    let atlas_bindings = device.create_bind_group(&BindGroupDescriptor {
-        label: Some("Text Compositor Fragment Binding"),
-        layout: &fragment_shader_layout,
-        entries: &[
-            BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::TextureView(cache_view),
-            },
-            BindGroupEntry {
-                binding: 1,
-                resource: BindingResource::TextureView(mask_view),
-            },
-            BindGroupEntry {
-                binding: 2,
-                resource: BindingResource::Sampler(sampler),
-            },
-            BindGroupEntry {
-                binding: 3,
-                resource: atlas_size.as_entire_binding(),
-            },
-        ],
-    });
+    let atlas_bindings = build_atlas_bindings(
+        device,
+        &fragment_shader_layout,
+        &atlas_size,
+        &sampler,
+        text_cache,
+        text_mask,
+    );
 
     let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
         label: Some("Text Compositor Layout"),
@@ -729,42 +1701,148 @@ fn build_text_fg_compositor(
         push_constant_ranges: &[],
     });
 
-    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-        label: Some("Text Compositor Pipeline"),
-        layout: Some(&pipeline_layout),
-        vertex: VertexState {
-            module: &shader,
-            entry_point: Some("vs_main"),
-            compilation_options: PipelineCompilationOptions::default(),
-            buffers: &[VertexBufferLayout {
-                array_stride: size_of::<TextVertexMember>() as u64,
-                step_mode: VertexStepMode::Vertex,
-                attributes: &vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Uint32, 3 => Uint32, 4 => Uint32],
-            }],
-        },
-        primitive: PrimitiveState {
-            topology: PrimitiveTopology::TriangleList,
-            ..Default::default()
-        },
-        depth_stencil: None,
-        multisample: MultisampleState::default(),
-        fragment: Some(FragmentState {
-            module: &shader,
-            entry_point: Some("fs_main"),
-            compilation_options: PipelineCompilationOptions::default(),
-            targets: &[Some(ColorTargetState {
-                format: TextureFormat::Rgba8Unorm,
-                blend: Some(BlendState::ALPHA_BLENDING),
-                write_mask: ColorWrites::ALL,
-            })],
-        }),
-        multiview: None,
-        cache: None,
+    let build_pipeline = |label, entry_point, blend| {
+        device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(label),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[VertexBufferLayout {
+                    array_stride: size_of::<TextVertexMember>() as u64,
+                    step_mode: VertexStepMode::Vertex,
+                    attributes: &vertex_attr_array![0 => Float32x2, 1 => Float32x2, 2 => Uint32, 3 => Uint32],
+                }],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some(entry_point),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format: TextureFormat::Rgba8Unorm,
+                    blend: Some(blend),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+            cache: None,
+        })
+    };
+
+    let premultiplied = |color| BlendState {
+        color,
+        alpha: BlendState::ALPHA_BLENDING.alpha,
+    };
+    let (entry_point, blend) = match blend {
+        BlendMode::SourceOver => ("fs_main", BlendState::ALPHA_BLENDING),
+        BlendMode::Multiply => (
+            "fs_premultiplied",
+            premultiplied(BlendComponent {
+                src_factor: BlendFactor::Dst,
+                dst_factor: BlendFactor::OneMinusSrcAlpha,
+                operation: BlendOperation::Add,
+            }),
+        ),
+        BlendMode::Screen => (
+            "fs_premultiplied",
+            premultiplied(BlendComponent {
+                src_factor: BlendFactor::OneMinusDst,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            }),
+        ),
+        BlendMode::Add => (
+            "fs_premultiplied",
+            premultiplied(BlendComponent {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Add,
+            }),
+        ),
+        BlendMode::Subtract => (
+            "fs_premultiplied",
+            premultiplied(BlendComponent {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::ReverseSubtract,
+            }),
+        ),
+        BlendMode::Darken => (
+            "fs_darken",
+            premultiplied(BlendComponent {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Min,
+            }),
+        ),
+        // Lightening takes the maximum, so the premultiplied color already
+        // fades to black where the glyph doesn't cover the destination.
+        BlendMode::Lighten => (
+            "fs_premultiplied",
+            premultiplied(BlendComponent {
+                src_factor: BlendFactor::One,
+                dst_factor: BlendFactor::One,
+                operation: BlendOperation::Max,
+            }),
+        ),
+    };
+    let pipeline = build_pipeline("Text Compositor Pipeline", entry_point, blend);
+
+    // Blending each channel with its own coverage would need dual-source blending,
+    // which isn't available everywhere. Instead, the destination is scaled by the
+    // inverse coverage in one pass and the coverage-weighted color is added in a
+    // second pass, which works out to the same thing.
+    let subpixel_pipelines = subpixel_aa.then(|| {
+        [
+            build_pipeline(
+                "Text Compositor Subpixel Coverage Pipeline",
+                "fs_subpixel_coverage",
+                BlendState {
+                    color: BlendComponent {
+                        src_factor: BlendFactor::Zero,
+                        dst_factor: BlendFactor::OneMinusSrc,
+                        operation: BlendOperation::Add,
+                    },
+                    alpha: BlendComponent {
+                        src_factor: BlendFactor::Zero,
+                        dst_factor: BlendFactor::OneMinusSrcAlpha,
+                        operation: BlendOperation::Add,
+                    },
+                },
+            ),
+            build_pipeline(
+                "Text Compositor Subpixel Color Pipeline",
+                "fs_subpixel_color",
+                BlendState {
+                    color: BlendComponent {
+                        src_factor: BlendFactor::One,
+                        dst_factor: BlendFactor::One,
+                        operation: BlendOperation::Add,
+                    },
+                    alpha: BlendComponent {
+                        src_factor: BlendFactor::One,
+                        dst_factor: BlendFactor::One,
+                        operation: BlendOperation::Add,
+                    },
+                },
+            ),
+        ]
     });
 
     TextCacheFgPipeline {
         pipeline,
+        subpixel_pipelines,
         fs_uniforms,
+        atlas_layout: fragment_shader_layout,
+        atlas_size,
+        sampler,
         atlas_bindings,
     }
 }
@@ -0,0 +1,100 @@
+//! Images sent through escapes drawn into cells, as described in the crate
+//! docs.
+
+#[cfg(feature = "kitty-graphics")]
+use crate::backend::kitty::{
+    self,
+    KittyParser,
+};
+#[cfg(feature = "sixel")]
+use crate::backend::sixel;
+
+/// A command parsed from a complete escape.
+#[derive(Debug, PartialEq)]
+pub(crate) enum Command {
+    Display(Image),
+    #[cfg(feature = "kitty-graphics")]
+    DeleteAll,
+}
+
+/// An image to display at the cell holding the start of its escape.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Image {
+    /// The number of columns to display the image over, or enough to fit its
+    /// pixels if unset.
+    pub(crate) columns: Option<u16>,
+    /// The number of rows to display the image over, or enough to fit its
+    /// pixels if unset.
+    pub(crate) rows: Option<u16>,
+    pub(crate) width: u32,
+    pub(crate) height: u32,
+    /// `width * height` rgba pixels.
+    pub(crate) rgba: Vec<u8>,
+}
+
+#[derive(Default)]
+pub(crate) struct EscapeParser {
+    #[cfg(feature = "kitty-graphics")]
+    kitty: KittyParser,
+}
+
+impl EscapeParser {
+    /// Parse the image escapes in `symbol`, which was drawn to the cell at
+    /// `anchor`, appending every command they complete to `commands` along
+    /// with the cell it should be placed at. `cell_size` is the size of a cell
+    /// in pixels, and `max_dimension` the largest texture the device supports.
+    ///
+    /// Returns false if `symbol` doesn't hold an image escape and should be
+    /// drawn as text.
+    #[cfg_attr(not(feature = "sixel"), allow(unused_variables))]
+    pub(crate) fn parse(
+        &mut self,
+        symbol: &str,
+        anchor: (u16, u16),
+        cell_size: (u32, u32),
+        max_dimension: u32,
+        commands: &mut Vec<((u16, u16), Command)>,
+    ) -> bool {
+        #[cfg(feature = "kitty-graphics")]
+        if kitty::is_escape(symbol) {
+            self.kitty.parse(symbol, anchor, commands);
+            return true;
+        }
+
+        #[cfg(feature = "sixel")]
+        if sixel::is_escape(symbol) {
+            if let Some(image) = sixel::decode(symbol, max_dimension) {
+                commands.push((anchor, Command::Display(image.snap_to_cells(cell_size))));
+            }
+            return true;
+        }
+
+        false
+    }
+}
+
+#[cfg(feature = "sixel")]
+impl Image {
+    /// Pad the image with transparent pixels to a whole number of cells of
+    /// `cell_size`, and display it over exactly those cells so its pixels
+    /// aren't stretched.
+    pub(crate) fn snap_to_cells(self, (cell_width, cell_height): (u32, u32)) -> Self {
+        let columns = self.width.div_ceil(cell_width);
+        let rows = self.height.div_ceil(cell_height);
+        let width = columns * cell_width;
+        let height = rows * cell_height;
+
+        let mut rgba = vec![0; width as usize * height as usize * 4];
+        for (y, row) in self.rgba.chunks_exact(self.width as usize * 4).enumerate() {
+            rgba[y * width as usize * 4..][..row.len()].copy_from_slice(row);
+        }
+
+        Self {
+            columns: Some(columns as u16),
+            rows: Some(rows as u16),
+            width,
+            height,
+            rgba,
+        }
+    }
+}
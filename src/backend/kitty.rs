@@ -0,0 +1,318 @@
+//! Parsing for the subset of the kitty graphics protocol described in the
+//! crate docs.
+
+use crate::backend::escapes::{
+    Command,
+    Image,
+};
+
+const ESCAPE_START: &str = "\x1b_G";
+const ESCAPE_END: &str = "\x1b\\";
+
+/// A transmission which is waiting on more chunks.
+struct Pending {
+    anchor: (u16, u16),
+    controls: Controls,
+    payload: String,
+}
+
+/// Whether `symbol` holds a kitty graphics escape.
+pub(crate) fn is_escape(symbol: &str) -> bool {
+    symbol.starts_with(ESCAPE_START)
+}
+
+#[derive(Default)]
+pub(crate) struct KittyParser {
+    pending: Option<Pending>,
+}
+
+impl KittyParser {
+    /// Parse the escapes in `symbol`, which was drawn to the cell at `anchor`,
+    /// appending every command they complete to `commands` along with the
+    /// cell it should be placed at.
+    pub(crate) fn parse(
+        &mut self,
+        symbol: &str,
+        anchor: (u16, u16),
+        commands: &mut Vec<((u16, u16), Command)>,
+    ) {
+        for escape in symbol.split_terminator(ESCAPE_END) {
+            let Some(escape) = escape.strip_prefix(ESCAPE_START) else {
+                warn!("Ignoring malformed kitty graphics escape");
+                continue;
+            };
+            let (controls, payload) = escape.split_once(';').unwrap_or((escape, ""));
+            let controls = Controls::parse(controls);
+
+            let pending = match self.pending.take() {
+                Some(mut pending) => {
+                    pending.payload.push_str(payload);
+                    pending.controls.more = controls.more;
+                    pending
+                }
+                None => Pending {
+                    anchor,
+                    controls,
+                    payload: payload.to_owned(),
+                },
+            };
+
+            if pending.controls.more {
+                self.pending = Some(pending);
+                continue;
+            }
+
+            if let Some(command) = pending.controls.into_command(&pending.payload) {
+                commands.push((pending.anchor, command));
+            }
+        }
+    }
+}
+
+/// The control data of an escape, with defaults for any keys it's missing.
+struct Controls {
+    action: char,
+    format: u32,
+    medium: char,
+    compression: Option<char>,
+    delete: char,
+    width: u32,
+    height: u32,
+    columns: Option<u16>,
+    rows: Option<u16>,
+    more: bool,
+}
+
+impl Controls {
+    fn parse(controls: &str) -> Self {
+        let mut parsed = Self {
+            action: 't',
+            format: 32,
+            medium: 'd',
+            compression: None,
+            delete: 'a',
+            width: 0,
+            height: 0,
+            columns: None,
+            rows: None,
+            more: false,
+        };
+
+        for (key, value) in controls
+            .split(',')
+            .filter_map(|control| control.split_once('='))
+        {
+            let char_value = value.chars().next().unwrap_or_default();
+            match key {
+                "a" => parsed.action = char_value,
+                "f" => parsed.format = value.parse().unwrap_or_default(),
+                "t" => parsed.medium = char_value,
+                "o" => parsed.compression = Some(char_value),
+                "d" => parsed.delete = char_value,
+                "s" => parsed.width = value.parse().unwrap_or_default(),
+                "v" => parsed.height = value.parse().unwrap_or_default(),
+                "c" => parsed.columns = value.parse().ok().filter(|c| *c != 0),
+                "r" => parsed.rows = value.parse().ok().filter(|r| *r != 0),
+                "m" => parsed.more = value == "1",
+                _ => {}
+            }
+        }
+
+        parsed
+    }
+
+    fn into_command(self, payload: &str) -> Option<Command> {
+        match self.action {
+            'T' => {}
+            'd' if matches!(self.delete, 'a' | 'A') => return Some(Command::DeleteAll),
+            action => {
+                warn!("Ignoring unsupported kitty graphics action {action:?}");
+                return None;
+            }
+        }
+
+        if self.medium != 'd' || self.compression.is_some() {
+            warn!(
+                "Ignoring kitty graphics image which isn't transmitted directly and uncompressed"
+            );
+            return None;
+        }
+
+        let Some(data) = decode_base64(payload) else {
+            warn!("Ignoring kitty graphics image with invalid base64 data");
+            return None;
+        };
+
+        let pixels = self.width as usize * self.height as usize;
+        let rgba = match self.format {
+            32 if pixels != 0 && data.len() == pixels * 4 => data,
+            24 if pixels != 0 && data.len() == pixels * 3 => data
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+                .collect(),
+            32 | 24 => {
+                warn!(
+                    "Ignoring {}x{} kitty graphics image with {} bytes of data",
+                    self.width,
+                    self.height,
+                    data.len()
+                );
+                return None;
+            }
+            format => {
+                warn!("Ignoring kitty graphics image with unsupported format {format}");
+                return None;
+            }
+        };
+
+        Some(Command::Display(Image {
+            columns: self.columns,
+            rows: self.rows,
+            width: self.width,
+            height: self.height,
+            rgba,
+        }))
+    }
+}
+
+/// Decode standard base64, with or without padding.
+fn decode_base64(data: &str) -> Option<Vec<u8>> {
+    fn value(byte: u8) -> Option<u32> {
+        Some(match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        } as u32)
+    }
+
+    let data = data.trim_end_matches('=').as_bytes();
+    if data.len() % 4 == 1 {
+        return None;
+    }
+
+    let mut decoded = Vec::with_capacity(data.len() * 3 / 4);
+    for chunk in data.chunks(4) {
+        let mut bits = 0;
+        for (index, byte) in chunk.iter().enumerate() {
+            bits |= value(*byte)? << (18 - 6 * index);
+        }
+
+        let bytes = bits.to_be_bytes();
+        decoded.extend_from_slice(&bytes[1..chunk.len()]);
+    }
+
+    Some(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(symbols: &[&str]) -> Vec<((u16, u16), Command)> {
+        let mut parser = KittyParser::default();
+        let mut commands = vec![];
+        for (x, symbol) in symbols.iter().enumerate() {
+            parser.parse(symbol, (x as u16, 0), &mut commands);
+        }
+        commands
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert_eq!(decode_base64("Zg==").unwrap(), b"f");
+        assert_eq!(decode_base64("Zm8=").unwrap(), b"fo");
+        assert_eq!(decode_base64("Zm9v").unwrap(), b"foo");
+        assert_eq!(decode_base64("Zm9vYmE").unwrap(), b"fooba");
+        assert_eq!(decode_base64("/+8A").unwrap(), [0xff, 0xef, 0x00]);
+        assert!(decode_base64("Zm9vY").is_none());
+        assert!(decode_base64("Zm9*").is_none());
+    }
+
+    #[test]
+    fn transmit_and_display() {
+        // A 1x2 image of red over green.
+        assert_eq!(
+            parse(&["\x1b_Ga=T,f=32,s=1,v=2,c=3;/wAA/wD/AP8=\x1b\\"]),
+            [(
+                (0, 0),
+                Command::Display(Image {
+                    columns: Some(3),
+                    rows: None,
+                    width: 1,
+                    height: 2,
+                    rgba: vec![255, 0, 0, 255, 0, 255, 0, 255],
+                })
+            )]
+        );
+    }
+
+    #[test]
+    fn rgb() {
+        assert_eq!(
+            parse(&["\x1b_Ga=T,f=24,s=1,v=1;AAD/\x1b\\"]),
+            [(
+                (0, 0),
+                Command::Display(Image {
+                    columns: None,
+                    rows: None,
+                    width: 1,
+                    height: 1,
+                    rgba: vec![0, 0, 255, 255],
+                })
+            )]
+        );
+    }
+
+    #[test]
+    fn chunked() {
+        let expected = [(
+            (0, 0),
+            Command::Display(Image {
+                columns: None,
+                rows: None,
+                width: 1,
+                height: 2,
+                rgba: vec![255, 0, 0, 255, 0, 255, 0, 255],
+            }),
+        )];
+
+        assert_eq!(
+            parse(&["\x1b_Ga=T,s=1,v=2,m=1;/wAA\x1b\\\x1b_Gm=0;/wD/AP8=\x1b\\"]),
+            expected
+        );
+        assert_eq!(
+            parse(&[
+                "\x1b_Ga=T,s=1,v=2,m=1;/wAA\x1b\\",
+                "\x1b_Gm=1;/wD/\x1b\\",
+                "\x1b_Gm=0;AP8=\x1b\\"
+            ]),
+            expected
+        );
+    }
+
+    #[test]
+    fn delete_all() {
+        assert_eq!(
+            parse(&["\x1b_Ga=d\x1b\\", "\x1b_Ga=d,d=A\x1b\\"]),
+            [((0, 0), Command::DeleteAll), ((1, 0), Command::DeleteAll)]
+        );
+    }
+
+    #[test]
+    fn unsupported() {
+        assert!(parse(&[
+            "\x1b_Ga=t,s=1,v=1;AAAA/w==\x1b\\",
+            "\x1b_Ga=p,i=1\x1b\\",
+            "\x1b_Ga=T,f=100;iVBORw==\x1b\\",
+            "\x1b_Ga=T,t=f,s=1,v=1;L3RtcC9pbWFnZQ==\x1b\\",
+            "\x1b_Ga=T,o=z,s=1,v=1;AAAA/w==\x1b\\",
+            "\x1b_Ga=T,s=2,v=1;AAAA/w==\x1b\\",
+            "\x1b_Ga=d,d=i,i=1\x1b\\",
+        ])
+        .is_empty());
+    }
+}
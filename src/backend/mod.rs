@@ -1,20 +1,62 @@
 pub(crate) mod builder;
+#[cfg(any(feature = "kitty-graphics", feature = "sixel"))]
+pub(crate) mod escapes;
+#[cfg(feature = "kitty-graphics")]
+pub(crate) mod kitty;
+pub(crate) mod pane;
+#[cfg(feature = "sixel")]
+pub(crate) mod sixel;
 pub(crate) mod wgpu_backend;
 
-use std::num::NonZeroU32;
+use std::{
+    mem::size_of,
+    num::{
+        NonZeroU32,
+        NonZeroU64,
+    },
+};
 
-use ratatui::style::Color;
+use ratatui::{
+    layout::Rect,
+    style::Color,
+};
 use wgpu::{
+    include_wgsl,
+    vertex_attr_array,
     Adapter,
+    AddressMode,
     BindGroup,
+    BindGroupDescriptor,
+    BindGroupEntry,
+    BindGroupLayout,
+    BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry,
+    BindingResource,
+    BindingType,
+    BlendState,
     Buffer,
+    BufferBindingType,
     BufferDescriptor,
     BufferUsages,
+    ColorTargetState,
+    ColorWrites,
     CommandEncoder,
     Device,
     Extent3d,
+    FilterMode,
+    FragmentState,
+    MultisampleState,
+    PipelineCompilationOptions,
+    PipelineLayoutDescriptor,
+    PrimitiveState,
+    PrimitiveTopology,
     Queue,
     RenderPipeline,
+    RenderPipelineDescriptor,
+    Sampler,
+    SamplerBindingType,
+    SamplerDescriptor,
+    ShaderStages,
     Surface,
     SurfaceConfiguration,
     SurfaceTexture,
@@ -22,15 +64,25 @@ use wgpu::{
     TextureDescriptor,
     TextureDimension,
     TextureFormat,
+    TextureSampleType,
     TextureUsages,
     TextureView,
     TextureViewDescriptor,
+    TextureViewDimension,
+    VertexBufferLayout,
+    VertexState,
+    VertexStepMode,
+    COPY_BUFFER_ALIGNMENT,
+    COPY_BYTES_PER_ROW_ALIGNMENT,
 };
 
-use crate::colors::{
-    named::*,
-    Rgb,
-    ANSI_TO_RGB,
+use crate::{
+    colors::{
+        Palette,
+        Rgb,
+        Rgba,
+    },
+    Fonts,
 };
 
 /// A pipeline for post-processing rendered text.
@@ -86,6 +138,16 @@ pub trait PostProcessor {
     fn needs_update(&self) -> bool {
         false
     }
+
+    /// Called when the pointer position changes through
+    /// [`WgpuBackend::set_pointer`](crate::WgpuBackend::set_pointer). The
+    /// position is in pixels relative to the top-left of the surface, or `None`
+    /// if the pointer left the surface. Processors which react to the pointer
+    /// will usually want to return true from [`PostProcessor::needs_update`]
+    /// after this is called. By default, this does nothing.
+    fn set_pointer(&mut self, position: Option<(f32, f32)>) {
+        let _ = position;
+    }
 }
 
 /// The surface dimensions of the backend in pixels.
@@ -94,12 +156,30 @@ pub struct Dimensions {
     pub height: NonZeroU32,
 }
 
+impl Dimensions {
+    /// Create dimensions from a size in physical pixels, such as a winit
+    /// `PhysicalSize<u32>`. Zero widths or heights, e.g. from a minimized
+    /// window, are clamped to 1.
+    pub fn from_physical(width: u32, height: u32) -> Self {
+        Self {
+            width: NonZeroU32::new(width).unwrap_or(NonZeroU32::MIN),
+            height: NonZeroU32::new(height).unwrap_or(NonZeroU32::MIN),
+        }
+    }
+}
+
 impl From<(NonZeroU32, NonZeroU32)> for Dimensions {
     fn from((width, height): (NonZeroU32, NonZeroU32)) -> Self {
         Self { width, height }
     }
 }
 
+impl From<(u32, u32)> for Dimensions {
+    fn from((width, height): (u32, u32)) -> Self {
+        Self::from_physical(width, height)
+    }
+}
+
 /// Controls the area the text is rendered to relative to the presentation
 /// surface.
 #[derive(Debug, Default, Clone, Copy)]
@@ -111,6 +191,348 @@ pub enum Viewport {
     /// Render to a reduced area starting at the top right and rendering up to
     /// the bottom left - (width, height).
     Shrink { width: u32, height: u32 },
+    /// Render to the entire surface, but center the grid in it instead of
+    /// anchoring it at the top left. The slack left over from rounding is split
+    /// evenly between opposite edges and filled with the padding color, or the
+    /// reset background color if none is set. The grid is never stretched to
+    /// cover the surface.
+    Centered,
+}
+
+/// Controls how the number of columns and rows in the grid is derived from
+/// the drawable area in pixels when it isn't an exact multiple of the cell
+/// size.
+///
+/// Unless a padding color is set or [`Viewport::Centered`] is used, the
+/// composited text is scaled to fill the drawable area of the surface, so the
+/// grid is slightly stretched when it is smaller than the drawable area and
+/// slightly squeezed when it is larger. When using
+/// [`Viewport::Shrink`], the drawable area excludes the inset. See
+/// [`Builder::with_integer_scaling`](crate::Builder::with_integer_scaling) to
+/// size the surface to whole cells instead.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round down, leaving a partial cell's worth of slack which the grid is
+    /// stretched over.
+    #[default]
+    Floor,
+    /// Round to the nearest whole number of cells.
+    Round,
+    /// Round up, squeezing an additional partial cell into the drawable area.
+    Ceil,
+}
+
+impl Rounding {
+    pub(crate) fn cells(self, pixels: u32, cell_pixels: u32) -> u32 {
+        match self {
+            Rounding::Floor => pixels / cell_pixels,
+            Rounding::Round => (pixels + cell_pixels / 2) / cell_pixels,
+            Rounding::Ceil => pixels.div_ceil(cell_pixels),
+        }
+    }
+}
+
+/// The largest size which fits in `width` by `height` and holds a whole number
+/// of `cell_width` by `cell_height` cells, plus the `inset` of the viewport.
+/// The size always holds at least one cell.
+fn whole_cell_size(
+    (width, height): (u32, u32),
+    (inset_width, inset_height): (u32, u32),
+    (cell_width, cell_height): (u32, u32),
+) -> (u32, u32) {
+    let columns = (width.saturating_sub(inset_width) / cell_width).max(1);
+    let rows = (height.saturating_sub(inset_height) / cell_height).max(1);
+    (
+        columns * cell_width + inset_width,
+        rows * cell_height + inset_height,
+    )
+}
+
+/// The font size, line height, and cell padding set on the builder or with
+/// [`WgpuBackend::update_fonts`](crate::WgpuBackend::update_fonts), in
+/// logical pixels. Fonts are rendered with these multiplied by the scale
+/// factor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LogicalMetrics {
+    height: u32,
+    line_height: Option<u32>,
+    width_padding: u32,
+}
+
+impl LogicalMetrics {
+    fn of(fonts: &Fonts) -> Self {
+        Self {
+            height: fonts.height_px(),
+            line_height: fonts.line_height_px(),
+            width_padding: fonts.width_padding_px(),
+        }
+    }
+
+    /// Set the metrics of `fonts` to these metrics multiplied by `scale`.
+    fn apply(&self, fonts: &mut Fonts, scale: f32) {
+        let scaled = |px: u32| (px as f32 * scale).round() as u32;
+        fonts.set_size_px(scaled(self.height).max(1));
+        fonts.set_line_height_px(self.line_height.map(scaled));
+        fonts.set_width_padding_px(scaled(self.width_padding));
+    }
+}
+
+/// Controls what happens to the contents of the screen when a resize changes
+/// the number of columns or rows in the grid.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ResizePolicy {
+    /// Clear the screen. The next call to `draw` must provide the full
+    /// contents of the screen.
+    Clear,
+    /// Keep the contents of every cell which still fits in the resized grid.
+    /// Cells which no longer fit are discarded and newly exposed cells are
+    /// empty.
+    ///
+    /// Note that [`ratatui::Terminal`] clears the backend when it notices the
+    /// size has changed during `draw`, so this mostly keeps the screen
+    /// populated between a resize and your next draw.
+    #[default]
+    Reflow,
+}
+
+/// Controls whether glyph outlines are fitted to the pixel grid before they
+/// are rasterized. Hinting trades some fidelity to the font's design for
+/// sharper stems and edges, which mostly matters at small font sizes. Color
+/// and bitmap glyphs are never hinted.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HintingMode {
+    /// Rasterize outlines exactly as designed.
+    #[default]
+    None,
+    /// Snap outline points to whole pixels vertically, keeping baselines,
+    /// x-heights, and horizontal strokes crisp.
+    Vertical,
+    /// Snap outline points to whole pixels both vertically and horizontally.
+    Full,
+}
+
+/// Controls how glyphs with color data, such as emoji, are rendered.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum EmojiMode {
+    /// Render color glyphs with their own colors.
+    #[default]
+    Color,
+    /// Ignore color data and render every glyph from its outline using the
+    /// foreground color of its cell, like any other text. Glyphs which only
+    /// exist as color layers or color bitmaps render as blank.
+    Monochrome,
+}
+
+/// The shape of the cursor drawn at the cursor position when the cursor is
+/// shown. The cursor inverts the colors of the pixels it covers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CursorStyle {
+    /// Cover the entire cell.
+    #[default]
+    Block,
+    /// A thin vertical bar along the left edge of the cell.
+    Bar,
+    /// A thin horizontal bar along the bottom edge of the cell.
+    Underline,
+}
+
+/// How an image drawn with
+/// [`WgpuBackend::draw_image`](crate::WgpuBackend::draw_image) is sampled when
+/// it's scaled to fit the cells it covers.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SampleMode {
+    /// Blend neighboring pixels, which gives smooth results for photos and
+    /// other continuous images.
+    #[default]
+    Linear,
+    /// Use the nearest pixel, which keeps pixel art and other hard-edged
+    /// images crisp.
+    Nearest,
+}
+
+/// How glyphs are blended with the cell background beneath them. Every mode
+/// is weighted by the coverage of the glyph, so antialiased edges fade into
+/// the background.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    /// Draw glyphs over the background.
+    #[default]
+    SourceOver,
+    /// Multiply the background by the glyph color, which darkens it like a
+    /// highlighter would.
+    Multiply,
+    /// Multiply the inverse of the background by the inverse of the glyph
+    /// color, which lightens it.
+    Screen,
+    /// Add the glyph color to the background.
+    Add,
+    /// Subtract the glyph color from the background.
+    Subtract,
+    /// Keep the darker of the glyph color and the background for each channel.
+    Darken,
+    /// Keep the lighter of the glyph color and the background for each channel.
+    Lighten,
+}
+
+/// How text with [`Modifier::DIM`](ratatui::style::Modifier::DIM) is
+/// dimmed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DimMode {
+    /// Draw glyphs at half opacity, letting whatever is beneath them show
+    /// through.
+    #[default]
+    Alpha,
+    /// Draw glyphs at full opacity in a color halfway between the foreground
+    /// and background of the cell, as most terminal emulators do. This also
+    /// dims reversed text against its cell's original foreground.
+    Blend,
+}
+
+/// How underlined text is decorated.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnderlineStyle {
+    /// A single straight line.
+    #[default]
+    Single,
+    /// Two straight lines.
+    Double,
+    /// A wavy line, also known as an undercurl.
+    Curly,
+    /// A line of evenly spaced dots.
+    Dotted,
+    /// A line of evenly spaced dashes.
+    Dashed,
+}
+
+/// Reference lines drawn over every cell by
+/// [`WgpuBackend::set_metric_overlay`](crate::WgpuBackend::set_metric_overlay).
+/// Line positions are derived from the metrics of the primary font. Lines set
+/// to `None` are not drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricLines {
+    /// The color of the baseline. Defaults to red.
+    pub baseline: Option<Color>,
+    /// The color of the ascender line. Defaults to green.
+    pub ascender: Option<Color>,
+    /// The color of the descender line. Defaults to blue.
+    pub descender: Option<Color>,
+    /// The color of the x-height line. Defaults to magenta.
+    pub x_height: Option<Color>,
+}
+
+impl Default for MetricLines {
+    fn default() -> Self {
+        Self {
+            baseline: Some(Color::Red),
+            ascender: Some(Color::Green),
+            descender: Some(Color::Blue),
+            x_height: Some(Color::Magenta),
+        }
+    }
+}
+
+/// Statistics about the most recent frame rendered by
+/// [`WgpuBackend::flush`](ratatui::backend::Backend::flush), returned by
+/// [`WgpuBackend::last_frame_stats`](crate::WgpuBackend::last_frame_stats).
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FrameStats {
+    /// The number of glyphs rasterized into the text atlas for the frame,
+    /// including those from flushes batched into it.
+    pub glyphs_rasterized: usize,
+    /// The number of cells which were redrawn.
+    pub dirty_cells: usize,
+    /// The number of vertices submitted for backgrounds and text.
+    pub vertices: usize,
+    /// The number of glyphs cached in the text atlas.
+    pub atlas_entries: usize,
+    /// The number of glyphs the text atlas can hold with its current pages.
+    pub atlas_capacity: usize,
+}
+
+/// Changes to how a single cell is drawn, returned by the shader set with
+/// [`Builder::with_cell_shader`](crate::Builder::with_cell_shader). Fields
+/// which are `None` leave the cell as it would otherwise be drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct CellOverride {
+    /// The color of the cell's glyphs. Like colors written with
+    /// [`WgpuBackend::print_rgba`](crate::WgpuBackend::print_rgba), this takes
+    /// precedence over the cell's style and its alpha is combined with any
+    /// alpha from modifiers.
+    pub fg: Option<Rgba>,
+    /// The color of the cell's background.
+    pub bg: Option<Rgba>,
+    /// An opacity from 0.0 to 1.0 which is multiplied into the alpha of the
+    /// cell's glyphs and background, along with any opacity set with
+    /// [`WgpuBackend::set_cell_opacity`](crate::WgpuBackend::set_cell_opacity).
+    pub alpha: Option<f32>,
+}
+
+/// A function which can override how a cell is drawn, given the cell and its
+/// column and row.
+pub type CellShader = fn(&ratatui::buffer::Cell, u16, u16) -> CellOverride;
+
+/// A color stop in a background gradient set with
+/// [`WgpuBackend::set_region_gradient`](crate::WgpuBackend::set_region_gradient).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GradientStop {
+    /// Where the stop lies along the gradient, from 0.0 at its start to 1.0 at
+    /// its end.
+    pub offset: f32,
+    /// The color of the gradient at the stop.
+    pub color: Rgba,
+}
+
+impl GradientStop {
+    /// Create a new stop with `color` at `offset` along the gradient.
+    pub const fn new(offset: f32, color: Rgba) -> Self {
+        Self { offset, color }
+    }
+}
+
+/// A linear gradient filling the background of the cells in `area`. `start`
+/// and `end` are relative to `area`, with (0, 0) at its top left and (1, 1)
+/// at its bottom right. `stops` is sorted by offset.
+#[derive(Debug, Clone, PartialEq)]
+struct RegionGradient {
+    area: ratatui::layout::Rect,
+    start: [f32; 2],
+    end: [f32; 2],
+    stops: Vec<GradientStop>,
+}
+
+impl RegionGradient {
+    /// The color of the gradient at `[x, y]`, in pixels relative to the top
+    /// left of its area. `size` is the size of the area in pixels.
+    fn color_at(&self, [x, y]: [f32; 2], size: [f32; 2]) -> Rgba {
+        let start = [self.start[0] * size[0], self.start[1] * size[1]];
+        let dir = [
+            self.end[0] * size[0] - start[0],
+            self.end[1] * size[1] - start[1],
+        ];
+        let len2 = dir[0] * dir[0] + dir[1] * dir[1];
+        let t = if len2 == 0.0 {
+            0.0
+        } else {
+            ((x - start[0]) * dir[0] + (y - start[1]) * dir[1]) / len2
+        };
+
+        let Some(after) = self.stops.iter().position(|stop| stop.offset > t) else {
+            return self.stops.last().map_or(Rgba::default(), |stop| stop.color);
+        };
+        if after == 0 {
+            return self.stops[0].color;
+        }
+
+        let (from, to) = (self.stops[after - 1], self.stops[after]);
+        let mix = (t - from.offset) / (to.offset - from.offset);
+        let lerp = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * mix).round() as u8;
+        Rgba::new(
+            lerp(from.color.r, to.color.r),
+            lerp(from.color.g, to.color.g),
+            lerp(from.color.b, to.color.b),
+            lerp(from.color.a, to.color.a),
+        )
+    }
 }
 
 mod private {
@@ -120,6 +542,7 @@ mod private {
         HeadlessSurface,
         HeadlessTarget,
         RenderTarget,
+        TextureTarget,
     };
 
     pub trait Sealed {}
@@ -128,6 +551,7 @@ mod private {
 
     impl<'s> Sealed for Surface<'s> {}
     impl Sealed for HeadlessSurface {}
+    impl Sealed for TextureTarget {}
     impl Sealed for RenderTarget {}
     impl Sealed for HeadlessTarget {}
 }
@@ -173,6 +597,10 @@ pub trait RenderSurface<'s>: private::Sealed {
     fn configure(&mut self, device: &Device, config: &SurfaceConfiguration, _token: private::Token);
 
     fn get_current_texture(&self, _token: private::Token) -> Option<Self::Target>;
+
+    fn headless(&self, _token: private::Token) -> Option<&HeadlessSurface> {
+        None
+    }
 }
 
 pub struct RenderTarget {
@@ -226,11 +654,16 @@ impl<'s> RenderSurface<'s> for Surface<'s> {
     }
 }
 
-pub(crate) struct HeadlessTarget {
+pub struct HeadlessTarget {
     view: TextureView,
 }
 
-pub(crate) struct HeadlessSurface {
+/// A surface which renders to an offscreen texture rather than a window.
+/// Created by [`Builder::build_headless`](crate::Builder::build_headless), and
+/// read back using [`WgpuBackend::read_rgba`](crate::WgpuBackend::read_rgba).
+/// This is useful for producing screenshots of your application in
+/// environments without a display, e.g. for snapshot testing in CI.
+pub struct HeadlessSurface {
     pub(crate) texture: Option<Texture>,
     pub(crate) buffer: Option<Buffer>,
     pub(crate) buffer_width: u32,
@@ -309,7 +742,9 @@ impl RenderSurface<'static> for HeadlessSurface {
             view_formats: &[],
         }));
 
-        self.buffer_width = config.width * 4;
+        // Texture to buffer copies require rows to be aligned.
+        self.buffer_width = (config.width * 4).div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT)
+            * COPY_BYTES_PER_ROW_ALIGNMENT;
         self.buffer = Some(device.create_buffer(&BufferDescriptor {
             label: None,
             size: (self.buffer_width * config.height) as u64,
@@ -325,6 +760,76 @@ impl RenderSurface<'static> for HeadlessSurface {
             view: t.create_view(&TextureViewDescriptor::default()),
         })
     }
+
+    fn headless(&self, _token: private::Token) -> Option<&HeadlessSurface> {
+        Some(self)
+    }
+}
+
+/// A surface which renders into a texture owned by the caller. Created by
+/// [`Builder::build_with_texture`](crate::Builder::build_with_texture).
+pub struct TextureTarget {
+    pub(crate) texture: Texture,
+}
+
+impl TextureTarget {
+    fn new(texture: Texture) -> Self {
+        Self { texture }
+    }
+
+    fn config(&self) -> SurfaceConfiguration {
+        SurfaceConfiguration {
+            usage: self.texture.usage(),
+            format: self.texture.format(),
+            width: self.texture.width(),
+            height: self.texture.height(),
+            present_mode: wgpu::PresentMode::Immediate,
+            desired_maximum_frame_latency: 2,
+            alpha_mode: wgpu::CompositeAlphaMode::Auto,
+            view_formats: vec![],
+        }
+    }
+}
+
+impl RenderSurface<'static> for TextureTarget {
+    type Target = HeadlessTarget;
+
+    fn wgpu_surface(&self, _token: private::Token) -> Option<&Surface<'static>> {
+        None
+    }
+
+    fn get_default_config(
+        &self,
+        _adapter: &Adapter,
+        _width: u32,
+        _height: u32,
+        _token: private::Token,
+    ) -> Option<SurfaceConfiguration> {
+        Some(self.config())
+    }
+
+    fn configure(
+        &mut self,
+        _device: &Device,
+        config: &SurfaceConfiguration,
+        _token: private::Token,
+    ) {
+        if config.width != self.texture.width() || config.height != self.texture.height() {
+            warn!(
+                "Texture targets can't be resized, rendering {}x{} into a {}x{} texture",
+                config.width,
+                config.height,
+                self.texture.width(),
+                self.texture.height()
+            );
+        }
+    }
+
+    fn get_current_texture(&self, _token: private::Token) -> Option<Self::Target> {
+        Some(HeadlessTarget {
+            view: self.texture.create_view(&TextureViewDescriptor::default()),
+        })
+    }
 }
 
 #[repr(C)]
@@ -334,53 +839,139 @@ struct TextBgVertexMember {
     bg_color: u32,
 }
 
-// Vertex + UVCoord + Color
+// Vertex + UVCoord + Color + Atlas Page
 #[repr(C)]
 #[derive(bytemuck::Pod, bytemuck::Zeroable, Debug, Clone, Copy)]
 struct TextVertexMember {
     vertex: [f32; 2],
     uv: [f32; 2],
     fg_color: u32,
-    underline_pos: u32,
-    underline_color: u32,
+    page: u32,
+}
+
+// Vertex + UVCoord
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Debug, Clone, Copy)]
+struct ImageVertexMember {
+    vertex: [f32; 2],
+    uv: [f32; 2],
+}
+
+struct ImagePipeline {
+    pipeline: RenderPipeline,
+    fs_uniforms: BindGroup,
+    image_layout: BindGroupLayout,
+    nearest: Sampler,
+    linear: Sampler,
+}
+
+/// Copies the slots of the horizontally rendered grid into their places in
+/// the vertical layout with a single draw. See
+/// [`Builder::with_vertical_text`](crate::Builder::with_vertical_text).
+struct VerticalPipeline {
+    pipeline: RenderPipeline,
+    screen_size: Buffer,
+    fs_uniforms: BindGroup,
+    grid_layout: BindGroupLayout,
+    sampler: Sampler,
+}
+
+/// An image drawn over an area of the grid with
+/// [`WgpuBackend::draw_image`](crate::WgpuBackend::draw_image).
+struct CellImage {
+    area: Rect,
+    texture: Texture,
+    bindings: BindGroup,
 }
 
 struct TextCacheBgPipeline {
     pipeline: RenderPipeline,
+    overlay_pipeline: RenderPipeline,
+    invert_pipeline: RenderPipeline,
     fs_uniforms: BindGroup,
 }
 
 struct TextCacheFgPipeline {
     pipeline: RenderPipeline,
+    /// The coverage and color pipelines used in place of `pipeline` when
+    /// subpixel antialiasing is enabled.
+    subpixel_pipelines: Option<[RenderPipeline; 2]>,
     fs_uniforms: BindGroup,
+    /// Kept around so the atlas bindings can be rebuilt when the atlas gains
+    /// pages.
+    atlas_layout: BindGroupLayout,
+    atlas_size: Buffer,
+    sampler: Sampler,
     atlas_bindings: BindGroup,
 }
 
 struct WgpuState {
+    text_dest: Texture,
     text_dest_view: TextureView,
+    /// An oversized target which text is rendered into when an over-render
+    /// margin is configured. The region covered by the grid is copied into
+    /// `text_dest` before post processing.
+    overrender: Option<(Texture, TextureView)>,
 }
 
-fn c2c(color: ratatui::style::Color, reset: Rgb) -> Rgb {
+/// The vertex and index buffers submitted each frame. These are kept between
+/// frames and only reallocated when a frame's contents don't fit in them.
+#[derive(Default)]
+struct FrameBuffers {
+    bg_vertices: Option<Buffer>,
+    text_vertices: Option<Buffer>,
+    indices: Option<Buffer>,
+    image_vertices: Option<Buffer>,
+    vertical_vertices: Option<Buffer>,
+}
+
+/// Write `contents` to the start of the buffer in `slot`, replacing it with a
+/// larger buffer first if it's missing or too small. Buffers grow to the next
+/// power of two so that slowly growing frames don't reallocate every time.
+fn write_reused_buffer<'b>(
+    slot: &'b mut Option<Buffer>,
+    device: &Device,
+    queue: &Queue,
+    label: &'static str,
+    usage: BufferUsages,
+    contents: &[u8],
+) -> &'b Buffer {
+    let size = contents.len() as u64;
+    if slot.as_ref().map_or(true, |buffer| buffer.size() < size) {
+        *slot = Some(device.create_buffer(&BufferDescriptor {
+            label: Some(label),
+            size: size.next_power_of_two().max(COPY_BUFFER_ALIGNMENT),
+            usage: usage | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        }));
+    }
+
+    let buffer = slot.as_ref().unwrap();
+    queue.write_buffer(buffer, 0, contents);
+    buffer
+}
+
+fn c2c(color: ratatui::style::Color, reset: Rgb, palette: &Palette) -> Rgb {
     match color {
         Color::Reset => reset,
-        Color::Black => BLACK,
-        Color::Red => RED,
-        Color::Green => GREEN,
-        Color::Yellow => YELLOW,
-        Color::Blue => BLUE,
-        Color::Magenta => MAGENTA,
-        Color::Cyan => CYAN,
-        Color::Gray => GRAY,
-        Color::DarkGray => DARKGRAY,
-        Color::LightRed => LIGHTRED,
-        Color::LightGreen => LIGHTGREEN,
-        Color::LightYellow => LIGHTYELLOW,
-        Color::LightBlue => LIGHTBLUE,
-        Color::LightMagenta => LIGHTMAGENTA,
-        Color::LightCyan => LIGHTCYAN,
-        Color::White => WHITE,
+        Color::Black => palette.named[0],
+        Color::Red => palette.named[1],
+        Color::Green => palette.named[2],
+        Color::Yellow => palette.named[3],
+        Color::Blue => palette.named[4],
+        Color::Magenta => palette.named[5],
+        Color::Cyan => palette.named[6],
+        Color::Gray => palette.named[7],
+        Color::DarkGray => palette.named[8],
+        Color::LightRed => palette.named[9],
+        Color::LightGreen => palette.named[10],
+        Color::LightYellow => palette.named[11],
+        Color::LightBlue => palette.named[12],
+        Color::LightMagenta => palette.named[13],
+        Color::LightCyan => palette.named[14],
+        Color::White => palette.named[15],
         Color::Rgb(r, g, b) => [r, g, b],
-        Color::Indexed(idx) => ANSI_TO_RGB[idx as usize],
+        Color::Indexed(idx) => palette.indexed[idx as usize],
     }
 }
 
@@ -396,11 +987,334 @@ fn build_wgpu_state(device: &Device, drawable_width: u32, drawable_height: u32)
         sample_count: 1,
         dimension: TextureDimension::D2,
         format: TextureFormat::Rgba8Unorm,
-        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+        usage: TextureUsages::TEXTURE_BINDING
+            | TextureUsages::RENDER_ATTACHMENT
+            | TextureUsages::COPY_SRC
+            | TextureUsages::COPY_DST,
         view_formats: &[],
     });
 
     let text_dest_view = text_dest.create_view(&TextureViewDescriptor::default());
 
-    WgpuState { text_dest_view }
+    WgpuState {
+        text_dest,
+        text_dest_view,
+        overrender: None,
+    }
+}
+
+/// Build the text atlas and its mask as texture arrays with one layer per
+/// page.
+fn build_text_atlas(device: &Device, width: u32, height: u32, pages: u32) -> (Texture, Texture) {
+    // The gl backend creates textures with a single layer as plain 2d textures,
+    // which can't be bound as arrays.
+    let pages = pages.max(2);
+    let build = |label, format| {
+        device.create_texture(&TextureDescriptor {
+            label: Some(label),
+            size: Extent3d {
+                width,
+                height,
+                depth_or_array_layers: pages,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::COPY_SRC,
+            view_formats: &[],
+        })
+    };
+
+    (
+        build("Text Atlas", TextureFormat::Rgba8Unorm),
+        build("Text Mask", TextureFormat::R8Unorm),
+    )
+}
+
+fn build_atlas_bindings(
+    device: &Device,
+    layout: &BindGroupLayout,
+    atlas_size: &Buffer,
+    sampler: &Sampler,
+    text_cache: &Texture,
+    text_mask: &Texture,
+) -> BindGroup {
+    let array_view = |texture: &Texture| {
+        texture.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..Default::default()
+        })
+    };
+
+    device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Text Compositor Fragment Binding"),
+        layout,
+        entries: &[
+            BindGroupEntry {
+                binding: 0,
+                resource: BindingResource::TextureView(&array_view(text_cache)),
+            },
+            BindGroupEntry {
+                binding: 1,
+                resource: BindingResource::TextureView(&array_view(text_mask)),
+            },
+            BindGroupEntry {
+                binding: 2,
+                resource: BindingResource::Sampler(sampler),
+            },
+            BindGroupEntry {
+                binding: 3,
+                resource: atlas_size.as_entire_binding(),
+            },
+        ],
+    })
+}
+
+fn build_image_compositor(device: &Device, screen_size: &Buffer) -> ImagePipeline {
+    let shader = device.create_shader_module(include_wgsl!("shaders/composite_image.wgsl"));
+
+    let uniforms_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Image Compositor Uniforms Binding Layout"),
+        entries: &[BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::VERTEX,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: Some(NonZeroU64::new(size_of::<[f32; 4]>() as u64).unwrap()),
+            },
+            count: None,
+        }],
+    });
+
+    let image_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Image Compositor Fragment Binding Layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+    });
+
+    let fs_uniforms = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Image Compositor Uniforms Binding"),
+        layout: &uniforms_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: screen_size.as_entire_binding(),
+        }],
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Image Compositor Layout"),
+        bind_group_layouts: &[&uniforms_layout, &image_layout],
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Image Compositor Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            compilation_options: PipelineCompilationOptions::default(),
+            buffers: &[VertexBufferLayout {
+                array_stride: size_of::<ImageVertexMember>() as u64,
+                step_mode: VertexStepMode::Vertex,
+                attributes: &vertex_attr_array![0 => Float32x2, 1 => Float32x2],
+            }],
+        },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleStrip,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            compilation_options: PipelineCompilationOptions::default(),
+            targets: &[Some(ColorTargetState {
+                format: TextureFormat::Rgba8Unorm,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+        cache: None,
+    });
+
+    let sampler = |filter| {
+        device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        })
+    };
+
+    ImagePipeline {
+        pipeline,
+        fs_uniforms,
+        image_layout,
+        nearest: sampler(FilterMode::Nearest),
+        linear: sampler(FilterMode::Linear),
+    }
+}
+
+fn build_vertical_compositor(device: &Device) -> VerticalPipeline {
+    // Slots are copied with the image shader, but replace what's under them
+    // rather than blending so that transparent cells don't accumulate.
+    let shader = device.create_shader_module(include_wgsl!("shaders/composite_image.wgsl"));
+
+    let screen_size = device.create_buffer(&BufferDescriptor {
+        label: Some("Vertical Compositor Screen Size"),
+        size: size_of::<[f32; 4]>() as u64,
+        usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+
+    let uniforms_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Vertical Compositor Uniforms Binding Layout"),
+        entries: &[BindGroupLayoutEntry {
+            binding: 0,
+            visibility: ShaderStages::VERTEX,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: Some(NonZeroU64::new(size_of::<[f32; 4]>() as u64).unwrap()),
+            },
+            count: None,
+        }],
+    });
+
+    let grid_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("Vertical Compositor Fragment Binding Layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Texture {
+                    sample_type: TextureSampleType::Float { filterable: true },
+                    view_dimension: TextureViewDimension::D2,
+                    multisampled: false,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                count: None,
+            },
+        ],
+    });
+
+    let fs_uniforms = device.create_bind_group(&BindGroupDescriptor {
+        label: Some("Vertical Compositor Uniforms Binding"),
+        layout: &uniforms_layout,
+        entries: &[BindGroupEntry {
+            binding: 0,
+            resource: screen_size.as_entire_binding(),
+        }],
+    });
+
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("Vertical Compositor Layout"),
+        bind_group_layouts: &[&uniforms_layout, &grid_layout],
+        push_constant_ranges: &[],
+    });
+
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("Vertical Compositor Pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &shader,
+            entry_point: Some("vs_main"),
+            compilation_options: PipelineCompilationOptions::default(),
+            buffers: &[VertexBufferLayout {
+                array_stride: size_of::<ImageVertexMember>() as u64,
+                step_mode: VertexStepMode::Vertex,
+                attributes: &vertex_attr_array![0 => Float32x2, 1 => Float32x2],
+            }],
+        },
+        primitive: PrimitiveState {
+            topology: PrimitiveTopology::TriangleList,
+            ..Default::default()
+        },
+        depth_stencil: None,
+        multisample: MultisampleState::default(),
+        fragment: Some(FragmentState {
+            module: &shader,
+            entry_point: Some("fs_main"),
+            compilation_options: PipelineCompilationOptions::default(),
+            targets: &[Some(ColorTargetState {
+                format: TextureFormat::Rgba8Unorm,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        multiview: None,
+        cache: None,
+    });
+
+    let sampler = device.create_sampler(&SamplerDescriptor {
+        address_mode_u: AddressMode::ClampToEdge,
+        address_mode_v: AddressMode::ClampToEdge,
+        address_mode_w: AddressMode::ClampToEdge,
+        mag_filter: FilterMode::Nearest,
+        min_filter: FilterMode::Nearest,
+        mipmap_filter: FilterMode::Nearest,
+        ..Default::default()
+    });
+
+    VerticalPipeline {
+        pipeline,
+        screen_size,
+        fs_uniforms,
+        grid_layout,
+        sampler,
+    }
+}
+
+fn build_overrender_target(device: &Device, width: u32, height: u32) -> (Texture, TextureView) {
+    let limits = device.limits();
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("Text Compositor Over-render"),
+        size: Extent3d {
+            width: width.clamp(1, limits.max_texture_dimension_2d),
+            height: height.clamp(1, limits.max_texture_dimension_2d),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: TextureFormat::Rgba8Unorm,
+        usage: TextureUsages::RENDER_ATTACHMENT
+            | TextureUsages::COPY_SRC
+            | TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+
+    let view = texture.create_view(&TextureViewDescriptor::default());
+    (texture, view)
 }
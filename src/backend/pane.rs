@@ -0,0 +1,284 @@
+use std::hash::BuildHasher;
+
+use ratatui::{
+    backend::{
+        Backend,
+        ClearType,
+        WindowSize,
+    },
+    buffer::Cell,
+    layout::{
+        Position,
+        Rect,
+        Size,
+    },
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::backend::{
+    wgpu_backend::{
+        WgpuBackend,
+        NULL_CELL,
+    },
+    PostProcessor,
+    RenderSurface,
+};
+
+/// Identifies a pane added with [`WgpuBackend::add_pane`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PaneId(pub(super) usize);
+
+/// A pane added with [`WgpuBackend::add_pane`].
+pub(super) struct PaneState {
+    pub(super) area: Rect,
+    /// The cells of the pane as of the last draw or clear, and the area of the
+    /// grid they cover, or `None` if nothing has changed since the pane was
+    /// last flushed.
+    pub(super) pending: Option<(Rect, Vec<Cell>)>,
+}
+
+impl PaneState {
+    pub(super) fn new(area: Rect) -> Self {
+        Self {
+            area,
+            pending: None,
+        }
+    }
+}
+
+/// A rectangular region of a [`WgpuBackend`]'s grid which can be drawn to as
+/// if it were a backend of its own, returned by [`WgpuBackend::pane`].
+///
+/// Coordinates are relative to the top left of the pane, its size is the size
+/// of the pane, and anything drawn outside of it is discarded, including the
+/// part of a wide character which would spill out of it. Clearing the pane
+/// only clears the cells it covers.
+///
+/// Each pane keeps track of what's drawn to it until it's flushed, and only
+/// the cells which end up different from what's on the grid are updated, so
+/// clearing and redrawing a pane only repaints what changed. Flushing a pane
+/// also flushes the whole backend, so wrap the draws to several panes in
+/// [`WgpuBackend::begin_batch`] and [`WgpuBackend::end_batch`] to render them
+/// in a single frame.
+///
+/// A [`Terminal`](ratatui::Terminal) owns its backend, so a terminal over a
+/// pane only lives as long as the pane borrows the backend. Since a new
+/// terminal doesn't know what the pane already contains, clear it before
+/// drawing:
+///
+/// ```no_run
+/// # use ratatui::{widgets::Paragraph, Terminal};
+/// # use ratatui_wgpu::{PaneId, WgpuBackend};
+/// # fn draw(backend: &mut WgpuBackend, left: PaneId, right: PaneId) -> std::io::Result<()> {
+/// backend.begin_batch();
+/// for (pane, text) in [(left, "Left"), (right, "Right")] {
+///     let mut terminal = Terminal::new(backend.pane(pane))?;
+///     terminal.clear()?;
+///     terminal.draw(|f| f.render_widget(Paragraph::new(text), f.area()))?;
+/// }
+/// backend.end_batch()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Pane<'b, 'f, 's, P: PostProcessor, S: RenderSurface<'s>, H: BuildHasher + Clone> {
+    pub(super) backend: &'b mut WgpuBackend<'f, 's, P, S, H>,
+    pub(super) id: PaneId,
+}
+
+impl<'f, 's, P: PostProcessor, S: RenderSurface<'s>, H: BuildHasher + Clone>
+    Pane<'_, 'f, 's, P, S, H>
+{
+    /// Get the id of this pane.
+    pub fn id(&self) -> PaneId {
+        self.id
+    }
+
+    /// Get the area of the grid covered by this pane, limited to the bounds of
+    /// the grid.
+    pub fn area(&self) -> Rect {
+        let bounds = self.backend.size().unwrap();
+        self.backend
+            .pane_area(self.id)
+            .intersection(Rect::from((Position::ORIGIN, bounds)))
+    }
+
+    /// Get the cells drawn to the pane since it was last flushed, starting from
+    /// what's on the grid if nothing has been drawn yet.
+    fn pending(&mut self) -> (Rect, &mut Vec<Cell>) {
+        let area = self.area();
+        let width = self.backend.size().unwrap().width as usize;
+        self.backend.fit_to_grid();
+        let backend = &mut *self.backend;
+        let pending = &mut backend.panes[self.id.0].pending;
+        if pending
+            .as_ref()
+            .map_or(true, |(pending, _)| *pending != area)
+        {
+            let cells = &backend.cells;
+            *pending = Some((
+                area,
+                area.positions()
+                    .map(|pos| cells[pos.y as usize * width + pos.x as usize].clone())
+                    .collect(),
+            ));
+        }
+
+        let (area, cells) = pending.as_mut().unwrap();
+        (*area, cells)
+    }
+
+    /// Reset the pending cells in `area`, which is relative to the grid, to
+    /// empty.
+    fn clear_cells(&mut self, area: Rect) {
+        let width = self.backend.size().unwrap().width as usize;
+        self.backend.cell_opacity.retain(|index, _| {
+            !area.contains(Position::new(
+                (index % width) as u16,
+                (index / width) as u16,
+            ))
+        });
+
+        let (pane, cells) = self.pending();
+        for pos in area.intersection(pane).positions() {
+            let index = (pos.y - pane.y) as usize * pane.width as usize + (pos.x - pane.x) as usize;
+            cells[index] = Cell::EMPTY;
+        }
+    }
+}
+
+impl<'f, 's, P: PostProcessor, S: RenderSurface<'s>, H: BuildHasher + Clone> Backend
+    for Pane<'_, 'f, 's, P, S, H>
+{
+    fn draw<'a, I>(&mut self, content: I) -> std::io::Result<()>
+    where
+        I: Iterator<Item = (u16, u16, &'a Cell)>,
+    {
+        let (area, cells) = self.pending();
+        let width = area.width as usize;
+        for (x, y, cell) in content {
+            if x >= area.width || y >= area.height {
+                continue;
+            }
+
+            let index = y as usize * width + x as usize;
+            let columns = cell.symbol().width().max(1);
+            if x as usize + columns > width {
+                // The rest of the character would be drawn over whatever is next to the
+                // pane, so it's left out entirely.
+                cells[index] = cell.clone();
+                cells[index].set_symbol(" ");
+            } else {
+                cells[index] = cell.clone();
+                cells[index + 1..index + columns].fill(NULL_CELL);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn hide_cursor(&mut self) -> std::io::Result<()> {
+        self.backend.hide_cursor()
+    }
+
+    fn show_cursor(&mut self) -> std::io::Result<()> {
+        self.backend.show_cursor()
+    }
+
+    fn get_cursor_position(&mut self) -> std::io::Result<Position> {
+        let area = self.area();
+        let cursor = self.backend.get_cursor_position()?;
+        Ok(Position::new(
+            cursor.x.saturating_sub(area.x),
+            cursor.y.saturating_sub(area.y),
+        ))
+    }
+
+    fn set_cursor_position<Pos: Into<Position>>(&mut self, position: Pos) -> std::io::Result<()> {
+        let area = self.area();
+        let pos: Position = position.into();
+        self.backend.set_cursor_position(Position::new(
+            area.x + pos.x.min(area.width.saturating_sub(1)),
+            area.y + pos.y.min(area.height.saturating_sub(1)),
+        ))
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        let area = self.area();
+        self.clear_cells(area);
+        self.backend.set_cursor_position(area.as_position())
+    }
+
+    fn size(&self) -> std::io::Result<Size> {
+        Ok(self.area().as_size())
+    }
+
+    fn window_size(&mut self) -> std::io::Result<WindowSize> {
+        let size = self.area().as_size();
+        Ok(WindowSize {
+            columns_rows: size,
+            pixels: Size {
+                width: size.width * self.backend.fonts.cell_width_px() as u16,
+                height: size.height * self.backend.fonts.cell_height_px() as u16,
+            },
+        })
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if let Some((area, pending)) = self.backend.panes[self.id.0].pending.take() {
+            if area == self.area() {
+                let width = self.backend.size()?.width as usize;
+                self.backend.fit_to_grid();
+                let cells = &self.backend.cells;
+                let changed = area
+                    .positions()
+                    .zip(&pending)
+                    .filter(|(pos, cell)| cells[pos.y as usize * width + pos.x as usize] != **cell)
+                    .map(|(pos, cell)| (pos.x, pos.y, cell))
+                    .collect::<Vec<_>>();
+                self.backend.draw(changed.into_iter())?;
+            }
+        }
+
+        self.backend.flush()
+    }
+
+    fn clear_region(&mut self, clear_type: ClearType) -> std::io::Result<()> {
+        let area = self.area();
+        let cursor = self.backend.get_cursor_position()?;
+        if !area.contains(cursor) {
+            return if clear_type == ClearType::All {
+                self.clear()
+            } else {
+                Ok(())
+            };
+        }
+
+        let above = Rect::new(area.x, area.y, area.width, cursor.y - area.y);
+        let below = Rect::new(
+            area.x,
+            cursor.y + 1,
+            area.width,
+            area.bottom() - cursor.y - 1,
+        );
+        let line = Rect::new(area.x, cursor.y, area.width, 1);
+        let before = Rect::new(area.x, cursor.y, cursor.x - area.x, 1);
+        let after = Rect::new(cursor.x + 1, cursor.y, area.right() - cursor.x - 1, 1);
+        let until_newline = Rect::new(cursor.x, cursor.y, area.right() - cursor.x, 1);
+
+        match clear_type {
+            ClearType::All => return self.clear(),
+            ClearType::AfterCursor => {
+                self.clear_cells(after);
+                self.clear_cells(below);
+            }
+            ClearType::BeforeCursor => {
+                self.clear_cells(above);
+                self.clear_cells(before);
+            }
+            ClearType::CurrentLine => self.clear_cells(line),
+            ClearType::UntilNewLine => self.clear_cells(until_newline),
+        }
+
+        Ok(())
+    }
+}
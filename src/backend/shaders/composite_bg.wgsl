@@ -1,36 +1,36 @@
 struct VertexOutput {
-    @location(0) @interpolate(flat) BgColor: u32,
+    @location(0) BgColor: vec4<f32>,
     @builtin(position) gl_Position: vec4<f32>,
 }
 
 @group(0) @binding(0)
 var<uniform> ScreenSize: vec4<f32>;
 
+fn unpack_color(color: u32) -> vec4<f32> {
+    return vec4<f32>(
+        f32(color >> 24) / 255.0,
+        f32((color >> 16) & 0xFF) / 255.0,
+        f32((color >> 8) & 0xFF) / 255.0,
+        f32(color & 0xFF) / 255.0,
+    );
+}
+
 @vertex
 fn vs_main(
     @location(0) VertexCoord: vec2<f32>,
     @location(1) BgColor: u32,
 ) -> VertexOutput {
-    let gl_Position = vec4<f32>((2.0 * VertexCoord / ScreenSize.xy - 1.0) * vec2(1.0, -1.0), 0.0, 1.0);
-    return VertexOutput(BgColor, gl_Position);
+    let gl_Position = vec4<f32>((2.0 * (VertexCoord + ScreenSize.zw) / ScreenSize.xy - 1.0) * vec2(1.0, -1.0), 0.0, 1.0);
+    // Colors are interpolated so that quads with different colors at each corner can
+    // draw gradients. Solid quads use the same color at every corner.
+    return VertexOutput(unpack_color(BgColor), gl_Position);
 }
 
 struct FragmentOutput {
     @location(0) FragColor: vec4<f32>,
 }
 
-fn unpack_color(color: u32) -> vec4<f32> {
-    return vec4<f32>(
-        f32(color >> 24) / 255.0,
-        f32((color >> 16) & 0xFF) / 255.0,
-        f32((color >> 8) & 0xFF) / 255.0,
-        f32(color & 0xFF) / 255.0,
-    );
-}
-
-
 @fragment
-fn fs_main(@location(0) @interpolate(flat) BgColor: u32) -> FragmentOutput {
-    let bgColorUnpacked = unpack_color(BgColor);
-    return FragmentOutput(bgColorUnpacked);
-}
\ No newline at end of file
+fn fs_main(@location(0) BgColor: vec4<f32>) -> FragmentOutput {
+    return FragmentOutput(BgColor);
+}
@@ -1,8 +1,7 @@
 struct VertexOutput {
     @location(0) UV: vec2<f32>,
     @location(1) @interpolate(flat) FgColor: u32,
-    @location(2) @interpolate(flat) UnderlinePos: u32,
-    @location(3) @interpolate(flat) UnderlineColor: u32,
+    @location(2) @interpolate(flat) Page: u32,
     @builtin(position) gl_Position: vec4<f32>,
 }
 
@@ -14,11 +13,10 @@ fn vs_main(
     @location(0) VertexCoord: vec2<f32>,
     @location(1) UV: vec2<f32>,
     @location(2) FgColor: u32,
-    @location(3) UnderlinePos: u32,
-    @location(4) UnderlineColor: u32,
+    @location(3) Page: u32,
 ) -> VertexOutput {
-    let gl_Position = vec4<f32>((2.0 * VertexCoord / ScreenSize.xy - 1.0) * vec2(1.0, -1.0), 0.0, 1.0);
-    return VertexOutput(UV, FgColor, UnderlinePos, UnderlineColor, gl_Position);
+    let gl_Position = vec4<f32>((2.0 * (VertexCoord + ScreenSize.zw) / ScreenSize.xy - 1.0) * vec2(1.0, -1.0), 0.0, 1.0);
+    return VertexOutput(UV, FgColor, Page, gl_Position);
 }
 
 struct FragmentOutput {
@@ -26,14 +24,29 @@ struct FragmentOutput {
 }
 
 @group(1) @binding(0) 
-var Atlas: texture_2d<f32>;
+var Atlas: texture_2d_array<f32>;
 @group(1) @binding(1) 
-var Mask: texture_2d<f32>;
+var Mask: texture_2d_array<f32>;
 @group(1) @binding(2) 
 var Sampler: sampler;
 
+struct AtlasUniforms {
+    size: vec2<f32>,
+    text_gamma: f32,
+    _pad0: f32,
+}
+
 @group(1) @binding(3) 
-var<uniform> AtlasSize: vec4<f32>;
+var<uniform> AtlasInfo: AtlasUniforms;
+
+// Coverage is raised to 1 / gamma, so values above 1.0 make text heavier and
+// values below 1.0 make it lighter.
+fn apply_text_gamma(coverage: vec4<f32>) -> vec4<f32> {
+    if AtlasInfo.text_gamma == 1.0 {
+        return coverage;
+    }
+    return pow(coverage, vec4(1.0 / AtlasInfo.text_gamma));
+}
 
 fn unpack_color(color: u32) -> vec4<f32> {
     return vec4<f32>(
@@ -45,29 +58,91 @@ fn unpack_color(color: u32) -> vec4<f32> {
 }
 
 
+fn text_color(UV: vec2<f32>, FgColor: u32, Page: u32) -> vec4<f32> {
+    var fgColorUnpacked = unpack_color(FgColor);
+    var textureColor = textureSample(Atlas, Sampler, UV / AtlasInfo.size, Page);
+    let mask = textureSample(Mask, Sampler, UV / AtlasInfo.size, Page);
+
+    let coverage = select(apply_text_gamma(textureColor).a, textureColor.a, mask.r == 1.0);
+    let alpha = coverage * fgColorUnpacked.a;
+    textureColor.a = alpha;
+    fgColorUnpacked.a = alpha;
+
+    return select(fgColorUnpacked, textureColor, mask.r == 1.0);
+}
+
 @fragment
 fn fs_main(
     @location(0) UV: vec2<f32>,
     @location(1) @interpolate(flat) FgColor: u32,
-    @location(2) @interpolate(flat) UnderlinePos: u32,
-    @location(3) @interpolate(flat) UnderlineColor: u32,
+    @location(2) @interpolate(flat) Page: u32,
 ) -> FragmentOutput {
-    let underLineColorUnpacked = unpack_color(UnderlineColor);
+    return FragmentOutput(text_color(UV, FgColor, Page));
+}
 
-    var fgColorUnpacked = unpack_color(FgColor);
-    var textureColor = textureSample(Atlas, Sampler, UV / AtlasSize.xy);
+// Blend modes other than source over weight the color by coverage in the
+// shader, since their blend factors don't use the source alpha.
+@fragment
+fn fs_premultiplied(
+    @location(0) UV: vec2<f32>,
+    @location(1) @interpolate(flat) FgColor: u32,
+    @location(2) @interpolate(flat) Page: u32,
+) -> FragmentOutput {
+    let color = text_color(UV, FgColor, Page);
+    return FragmentOutput(vec4(color.rgb * color.a, color.a));
+}
 
-    let alpha = textureColor.a * fgColorUnpacked.a;
-    textureColor.a = alpha;
-    fgColorUnpacked.a = alpha;
+// Darkening takes the minimum of the source and destination, so uncovered
+// parts of the glyph fade to white rather than black to leave the destination
+// unchanged.
+@fragment
+fn fs_darken(
+    @location(0) UV: vec2<f32>,
+    @location(1) @interpolate(flat) FgColor: u32,
+    @location(2) @interpolate(flat) Page: u32,
+) -> FragmentOutput {
+    let color = text_color(UV, FgColor, Page);
+    return FragmentOutput(vec4(mix(vec3(1.0), color.rgb, color.a), color.a));
+}
 
-    let mask = textureSample(Mask, Sampler, UV / AtlasSize.xy);
+// Subpixel antialiased glyphs are drawn in two passes. The first scales the
+// destination by the inverse of each channel's coverage, and the second adds
+// the fg color weighted by that coverage. Grayscale glyphs store the same
+// coverage in every channel, so they blend exactly as they do in fs_main.
+struct SubpixelSample {
+    color: vec4<f32>,
+    coverage: vec4<f32>,
+}
+
+fn subpixel_sample(UV: vec2<f32>, FgColor: u32, Page: u32) -> SubpixelSample {
+    let fgColorUnpacked = unpack_color(FgColor);
+    let textureColor = textureSample(Atlas, Sampler, UV / AtlasInfo.size, Page);
+    let mask = textureSample(Mask, Sampler, UV / AtlasInfo.size, Page);
 
-    var fgColor = select(fgColorUnpacked, textureColor, mask.r == 1.0);
+    if mask.r == 1.0 {
+        let alpha = textureColor.a * fgColorUnpacked.a;
+        return SubpixelSample(textureColor, vec4(vec3(alpha), alpha));
+    }
 
-    let yMax = UnderlinePos & 0xFFFF;
-    let yMin = UnderlinePos >> 16;
-    fgColor = select(fgColor, underLineColorUnpacked, u32(UV.y) >= yMin && u32(UV.y) < yMax);
+    return SubpixelSample(fgColorUnpacked, apply_text_gamma(textureColor) * fgColorUnpacked.a);
+}
 
-    return FragmentOutput(fgColor);
-}
\ No newline at end of file
+@fragment
+fn fs_subpixel_coverage(
+    @location(0) UV: vec2<f32>,
+    @location(1) @interpolate(flat) FgColor: u32,
+    @location(2) @interpolate(flat) Page: u32,
+) -> FragmentOutput {
+    let sample = subpixel_sample(UV, FgColor, Page);
+    return FragmentOutput(sample.coverage);
+}
+
+@fragment
+fn fs_subpixel_color(
+    @location(0) UV: vec2<f32>,
+    @location(1) @interpolate(flat) FgColor: u32,
+    @location(2) @interpolate(flat) Page: u32,
+) -> FragmentOutput {
+    let sample = subpixel_sample(UV, FgColor, Page);
+    return FragmentOutput(vec4(sample.color.rgb * sample.coverage.rgb, sample.coverage.a));
+}
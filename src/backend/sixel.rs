@@ -0,0 +1,331 @@
+//! Decoding for the sixel images described in the crate docs.
+
+use std::{
+    iter::Peekable,
+    str::Bytes,
+};
+
+use crate::backend::escapes::Image;
+
+const ESCAPE_START: &str = "\x1bP";
+const ESCAPE_END: &str = "\x1b\\";
+
+/// The VT340's default color registers, as rgb percentages.
+const DEFAULT_PALETTE: [[u32; 3]; 16] = [
+    [0, 0, 0],
+    [20, 20, 80],
+    [80, 13, 13],
+    [20, 80, 20],
+    [80, 20, 80],
+    [20, 80, 80],
+    [80, 80, 20],
+    [53, 53, 53],
+    [26, 26, 26],
+    [33, 33, 60],
+    [60, 26, 26],
+    [33, 60, 33],
+    [60, 33, 60],
+    [33, 60, 60],
+    [60, 60, 33],
+    [80, 80, 80],
+];
+
+const COLOR_REGISTERS: usize = 256;
+
+/// Whether `symbol` holds a sixel escape.
+pub(crate) fn is_escape(symbol: &str) -> bool {
+    symbol.strip_prefix(ESCAPE_START).is_some_and(|params| {
+        params
+            .trim_start_matches(|c: char| c.is_ascii_digit() || c == ';')
+            .starts_with('q')
+    })
+}
+
+/// A run of identical sixels.
+struct Run {
+    x: u32,
+    band: u32,
+    count: u32,
+    bits: u8,
+    color: [u8; 4],
+}
+
+/// Decode the sixel escape in `symbol` into an image the size of its raster
+/// attributes, or of the pixels it sets if those are missing or smaller.
+/// Pixels which aren't set are transparent. Images wider or taller than
+/// `max_dimension` are rejected before their pixels are allocated.
+pub(crate) fn decode(symbol: &str, max_dimension: u32) -> Option<Image> {
+    let data = symbol.strip_prefix(ESCAPE_START)?;
+    let data = data.strip_suffix(ESCAPE_END).unwrap_or(data);
+    let (_, data) = data.split_once('q')?;
+    let mut data = data.bytes().peekable();
+
+    let mut palette = [[0, 0, 0, 255]; COLOR_REGISTERS];
+    for (register, [r, g, b]) in palette.iter_mut().zip(DEFAULT_PALETTE) {
+        *register = [percent(r), percent(g), percent(b), 255];
+    }
+
+    let mut color = palette[0];
+    let (mut x, mut band) = (0u32, 0u32);
+    let (mut width, mut height) = (0, 0);
+    let mut runs = vec![];
+
+    while let Some(byte) = data.next() {
+        let (count, bits) = match byte {
+            b'"' => {
+                if let [_, _, raster_width, raster_height, ..] = params(&mut data)[..] {
+                    width = raster_width;
+                    height = raster_height;
+                }
+                continue;
+            }
+            b'#' => {
+                let params = params(&mut data);
+                let register = params[0] as usize % COLOR_REGISTERS;
+                if let [_, space, a, b, c] = params[..] {
+                    palette[register] = match space {
+                        1 => hls(a, b, c),
+                        2 => [percent(a), percent(b), percent(c), 255],
+                        _ => palette[register],
+                    };
+                }
+                color = palette[register];
+                continue;
+            }
+            b'!' => {
+                let count = params(&mut data)[0].max(1);
+                match data.next() {
+                    Some(byte @ b'?'..=b'~') => (count, byte - b'?'),
+                    _ => continue,
+                }
+            }
+            b'$' => {
+                x = 0;
+                continue;
+            }
+            b'-' => {
+                x = 0;
+                band = band.saturating_add(1);
+                continue;
+            }
+            b'?'..=b'~' => (1, byte - b'?'),
+            _ => continue,
+        };
+
+        if bits != 0 {
+            runs.push(Run {
+                x,
+                band,
+                count,
+                bits,
+                color,
+            });
+            height = height.max(
+                band.saturating_mul(6)
+                    .saturating_add(8 - bits.leading_zeros()),
+            );
+        }
+        x = x.saturating_add(count);
+        width = width.max(x);
+    }
+
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    if width > max_dimension || height > max_dimension {
+        warn!("Ignoring {width}x{height} sixel image larger than the device supports");
+        return None;
+    }
+
+    let mut rgba = vec![0; width as usize * height as usize * 4];
+    for run in runs {
+        for bit in 0..6 {
+            let y = run.band.saturating_mul(6).saturating_add(bit);
+            if run.bits & (1 << bit) == 0 || y >= height {
+                continue;
+            }
+
+            let row = y as usize * width as usize;
+            for x in run.x..run.x.saturating_add(run.count).min(width) {
+                rgba[(row + x as usize) * 4..][..4].copy_from_slice(&run.color);
+            }
+        }
+    }
+
+    Some(Image {
+        columns: None,
+        rows: None,
+        width,
+        height,
+        rgba,
+    })
+}
+
+/// Read `;` separated numeric parameters, with missing ones read as zero.
+fn params(data: &mut Peekable<Bytes>) -> Vec<u32> {
+    let mut params = vec![];
+    let mut param = 0u32;
+    while let Some(byte) = data.next_if(|b| b.is_ascii_digit() || *b == b';') {
+        if byte == b';' {
+            params.push(param);
+            param = 0;
+        } else {
+            param = param
+                .saturating_mul(10)
+                .saturating_add((byte - b'0') as u32);
+        }
+    }
+    params.push(param);
+    params
+}
+
+fn percent(value: u32) -> u8 {
+    (value.min(100) * 255 / 100) as u8
+}
+
+/// Convert a sixel hls color to rgba. Sixel hues put blue at 0 degrees and red
+/// at 120, rather than red at 0.
+fn hls(hue: u32, lightness: u32, saturation: u32) -> [u8; 4] {
+    let hue = ((hue as u64 + 240) % 360) as f32 / 60.;
+    let lightness = lightness.min(100) as f32 / 100.;
+    let saturation = saturation.min(100) as f32 / 100.;
+
+    let chroma = (1. - (2. * lightness - 1.).abs()) * saturation;
+    let x = chroma * (1. - (hue % 2. - 1.).abs());
+    let [r, g, b] = match hue as u32 {
+        0 => [chroma, x, 0.],
+        1 => [x, chroma, 0.],
+        2 => [0., chroma, x],
+        3 => [0., x, chroma],
+        4 => [x, 0., chroma],
+        _ => [chroma, 0., x],
+    };
+    let m = lightness - chroma / 2.;
+
+    let channel = |c: f32| ((c + m) * 255.).round() as u8;
+    [channel(r), channel(g), channel(b), 255]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
+    const LIMIT: u32 = 64;
+
+    fn pixels(image: &Image) -> Vec<[u8; 4]> {
+        image
+            .rgba
+            .chunks_exact(4)
+            .map(|px| px.try_into().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn escape() {
+        assert!(is_escape("\x1bPq#0~\x1b\\"));
+        assert!(is_escape("\x1bP0;1;0q#0~\x1b\\"));
+        assert!(!is_escape("\x1bP$qm\x1b\\"));
+        assert!(!is_escape("\x1b_Ga=T\x1b\\"));
+        assert!(!is_escape("q"));
+    }
+
+    #[test]
+    fn decode_sixels() {
+        // Two full columns of red, a column with only its top pixel set, and
+        // an empty column, followed by a repeat which sets the second row of
+        // the next band to blue.
+        let image = decode("\x1bPq#1;2;100;0;0~~@?$-#2;1;0;50;100!2A\x1b\\", LIMIT).unwrap();
+        assert_eq!(image.width, 4);
+        assert_eq!(image.height, 8);
+
+        #[rustfmt::skip]
+        let expected = [
+            RED,   RED,   RED,   CLEAR,
+            RED,   RED,   CLEAR, CLEAR,
+            RED,   RED,   CLEAR, CLEAR,
+            RED,   RED,   CLEAR, CLEAR,
+            RED,   RED,   CLEAR, CLEAR,
+            RED,   RED,   CLEAR, CLEAR,
+            CLEAR, CLEAR, CLEAR, CLEAR,
+            BLUE,  BLUE,  CLEAR, CLEAR,
+        ];
+        assert_eq!(pixels(&image), expected);
+    }
+
+    #[test]
+    fn raster_attributes() {
+        let image = decode("\x1bPq\"1;1;3;2#2@\x1b\\", LIMIT).unwrap();
+        assert_eq!((image.width, image.height), (3, 2));
+
+        let red = [percent(80), percent(13), percent(13), 255];
+        assert_eq!(pixels(&image), [red, CLEAR, CLEAR, CLEAR, CLEAR, CLEAR]);
+    }
+
+    #[test]
+    fn empty() {
+        assert!(decode("\x1bPq\x1b\\", LIMIT).is_none());
+        assert!(decode("\x1bPq#0??\x1b\\", LIMIT).is_none());
+    }
+
+    #[test]
+    fn oversized() {
+        // Raster attributes are enough to reject an image, whatever pixels it sets.
+        assert!(decode("\x1bPq\"1;1;65;1#0~\x1b\\", LIMIT).is_none());
+        assert!(decode("\x1bPq\"1;1;1;4294967295#0~\x1b\\", LIMIT).is_none());
+        assert!(decode("\x1bPq#0!65~\x1b\\", LIMIT).is_none());
+        assert!(decode(&format!("\x1bPq#0{}~\x1b\\", "-".repeat(11)), LIMIT).is_none());
+
+        let image = decode("\x1bPq\"1;1;64;64#0~\x1b\\", LIMIT).unwrap();
+        assert_eq!((image.width, image.height), (LIMIT, LIMIT));
+    }
+
+    /// Decode `symbol`, which mustn't panic, and check the image is in bounds.
+    fn decode_checked(symbol: &str) {
+        if let Some(image) = decode(symbol, LIMIT) {
+            assert!(image.width <= LIMIT && image.height <= LIMIT);
+            assert_eq!(
+                image.rgba.len(),
+                image.width as usize * image.height as usize * 4
+            );
+        }
+    }
+
+    #[test]
+    fn hostile() {
+        // Parameters are clamped as they're read, and mustn't overflow when used.
+        for symbol in [
+            "\x1bPq#0;1;4294967295;50;100~",
+            "\x1bPq#99999999999;1;99999999999;99999999999;99999999999~",
+            "\x1bPq#0!4294967295~!4294967295~",
+            "\x1bPq#0!4294967295~$!4294967295~",
+            "\x1bPq\"4294967295;4294967295;4294967295;4294967295~",
+            "\x1bPq#0;1",
+            "\x1bPq\"",
+            "\x1bPq!",
+            "\x1bPq#",
+        ] {
+            decode_checked(symbol);
+        }
+
+        // Random strings of the bytes sixels are made of.
+        let alphabet = b"0123456789;#!\"$-?@A~q\x1b\\";
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        for _ in 0..2000 {
+            let mut symbol = String::from(ESCAPE_START) + "q";
+            for _ in 0..next() % 64 {
+                symbol.push(alphabet[next() as usize % alphabet.len()] as char);
+            }
+            decode_checked(&symbol);
+        }
+    }
+}
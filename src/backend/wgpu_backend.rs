@@ -3,9 +3,17 @@ use std::{
         HashMap,
         HashSet,
     },
+    hash::{
+        BuildHasher,
+        Hasher,
+    },
     marker::PhantomData,
     mem::size_of,
     num::NonZeroU64,
+    sync::{
+        mpsc,
+        Arc,
+    },
 };
 
 use bitvec::{
@@ -30,9 +38,13 @@ use ratatui::{
     buffer::Cell,
     layout::{
         Position,
+        Rect,
         Size,
     },
-    style::Modifier,
+    style::{
+        Color,
+        Modifier,
+    },
 };
 use rustybuzz::{
     shape_with_plan,
@@ -42,6 +54,7 @@ use rustybuzz::{
         RasterImageFormat,
         RgbaColor,
     },
+    Direction,
     GlyphBuffer,
     UnicodeBuffer,
 };
@@ -63,21 +76,26 @@ use web_time::{
     Instant,
 };
 use wgpu::{
-    util::{
-        BufferInitDescriptor,
-        DeviceExt,
-    },
+    AdapterInfo,
+    BindGroupDescriptor,
+    BindGroupEntry,
+    BindingResource,
     Buffer,
+    BufferDescriptor,
     BufferUsages,
     CommandEncoderDescriptor,
     Device,
     Extent3d,
+    ImageCopyBuffer,
     ImageCopyTexture,
     ImageDataLayout,
     IndexFormat,
     LoadOp,
+    Maintain,
+    MapMode,
     Operations,
     Origin3d,
+    PresentMode,
     Queue,
     RenderPassColorAttachment,
     RenderPassDescriptor,
@@ -86,30 +104,89 @@ use wgpu::{
     SurfaceConfiguration,
     Texture,
     TextureAspect,
+    TextureDescriptor,
+    TextureDimension,
+    TextureFormat,
+    TextureUsages,
+    TextureView,
+    TextureViewDescriptor,
+    COPY_BYTES_PER_ROW_ALIGNMENT,
 };
 
+#[cfg(any(feature = "kitty-graphics", feature = "sixel"))]
+use crate::backend::escapes::{
+    self,
+    EscapeParser,
+};
 use crate::{
     backend::{
+        build_atlas_bindings,
+        build_image_compositor,
+        build_overrender_target,
+        build_text_atlas,
         build_wgpu_state,
         c2c,
+        pane::{
+            Pane,
+            PaneId,
+            PaneState,
+        },
         private::Token,
+        whole_cell_size,
+        write_reused_buffer,
+        CellImage,
+        CellOverride,
+        CellShader,
+        CursorStyle,
+        DimMode,
+        EmojiMode,
+        FrameBuffers,
+        FrameStats,
+        GradientStop,
+        HeadlessSurface,
+        HintingMode,
+        ImagePipeline,
+        ImageVertexMember,
+        LogicalMetrics,
+        MetricLines,
         PostProcessor,
+        RegionGradient,
         RenderSurface,
         RenderTexture,
+        ResizePolicy,
+        Rounding,
+        SampleMode,
         TextBgVertexMember,
         TextCacheBgPipeline,
         TextCacheFgPipeline,
         TextVertexMember,
+        TextureTarget,
+        UnderlineStyle,
+        VerticalPipeline,
         Viewport,
         WgpuState,
     },
-    colors::Rgb,
+    colors::{
+        ensure_contrast,
+        named::{
+            BLACK,
+            WHITE,
+        },
+        Palette,
+        Rgb,
+        Rgba,
+    },
     fonts::{
         Font,
         Fonts,
     },
     shaders::DefaultPostProcessor,
     utils::{
+        glyph_cache::{
+            DiskKey,
+            Fnv1a,
+            GlyphDiskCache,
+        },
         plan_cache::PlanCache,
         text_atlas::{
             Atlas,
@@ -120,24 +197,40 @@ use crate::{
         Outline,
         Painter,
     },
+    Error,
     RandomState,
+    Result,
 };
 
-const NULL_CELL: Cell = Cell::new("");
+pub(super) const NULL_CELL: Cell = Cell::new("");
+
+/// Fake bold widens glyph stems by the cell height divided by this, in pixels.
+const FAKE_BOLD_DIVISOR: f32 = 24.0;
 
 pub(super) struct RenderInfo {
     cell: usize,
     cached: CacheRect,
     underline_pos_min: u16,
     underline_pos_max: u16,
+    strikeout_pos_min: u16,
+    strikeout_pos_max: u16,
 }
 /// Map from (x, y, glyph) -> (cell index, cache entry).
 /// We use an IndexMap because we want a consistent rendering order for
 /// vertices.
-type Rendered = IndexMap<(i32, i32, GlyphId), RenderInfo, RandomState>;
+type Rendered<H> = IndexMap<(i32, i32, GlyphId), RenderInfo, H>;
 
 /// Set of (x, y, glyph, char width).
-type Sourced = HashSet<(i32, i32, GlyphId, u32), RandomState>;
+type Sourced<H> = HashSet<(i32, i32, GlyphId, u32), H>;
+
+/// The contents of the main screen, saved while the alternate screen is
+/// active.
+pub(super) struct SavedScreen {
+    cells: Vec<Cell>,
+    fast_blinking: BitVec,
+    slow_blinking: BitVec,
+    cursor: (u16, u16),
+}
 
 /// A ratatui backend leveraging wgpu for rendering.
 ///
@@ -145,10 +238,11 @@ type Sourced = HashSet<(i32, i32, GlyphId, u32), RandomState>;
 ///
 /// The first lifetime parameter is the lifetime of the data for referenced
 /// [`Font`] objects. The second lifetime parameter is the lifetime of the
-/// referenced [`Surface`] (typically the lifetime of your window object).
+/// referenced [`Surface`] (typically the lifetime of your window object). The
+/// hasher used by the backend's internal caches can be supplied with
+/// [`Builder::with_hasher`](crate::Builder::with_hasher).
 ///
 /// Limitations:
-/// - The cursor is tracked but not rendered.
 /// - No builtin accessibilty, although [`WgpuBackend::get_text`] is provided to
 ///   access the screen's contents.
 pub struct WgpuBackend<
@@ -156,33 +250,88 @@ pub struct WgpuBackend<
     's,
     P: PostProcessor = DefaultPostProcessor,
     S: RenderSurface<'s> = Surface<'s>,
+    H = RandomState,
 > {
     pub(super) post_process: P,
+    pub(super) post_process_enabled: bool,
+    pub(super) blitter: Option<DefaultPostProcessor>,
+    pub(super) pointer: Option<(f32, f32)>,
+    pub(super) paused: bool,
+    pub(super) batching: bool,
+    pub(super) batch_pending: bool,
+    pub(super) metric_overlay: Option<MetricLines>,
+    pub(super) focus_ring: Option<(Rect, Rgba, u32)>,
+    pub(super) panes: Vec<PaneState>,
+    pub(super) region_gradients: Vec<RegionGradient>,
+    pub(super) images: Vec<CellImage>,
+    pub(super) image_compositor: Option<ImagePipeline>,
+    /// Indices into `images` of the images drawn by the next render, in the
+    /// order they're drawn.
+    pub(super) image_draws: Vec<usize>,
+    pub(super) image_vertices: Vec<ImageVertexMember>,
+    pub(super) vertical_compositor: Option<VerticalPipeline>,
+    /// Quads copying the slots dirtied by the last flush into the vertical
+    /// layout, six vertices each.
+    pub(super) vertical_vertices: Vec<ImageVertexMember>,
+    #[cfg(any(feature = "kitty-graphics", feature = "sixel"))]
+    pub(super) escapes: EscapeParser,
+    /// The areas of images displayed by image escapes, keyed by the cell
+    /// holding the escape.
+    #[cfg(any(feature = "kitty-graphics", feature = "sixel"))]
+    pub(super) escape_placements: HashMap<(u16, u16), Rect, RandomState>,
+    pub(super) overlay_quads: u32,
+    pub(super) cursor_quads: u32,
 
     pub(super) cells: Vec<Cell>,
     pub(super) dirty_rows: Vec<bool>,
     pub(super) dirty_cells: BitVec,
-    pub(super) rendered: Vec<Rendered>,
-    pub(super) sourced: Vec<Sourced>,
+    pub(super) flushed_cells: BitVec,
+    pub(super) damage: Option<Rect>,
+    pub(super) needs_present: bool,
+    pub(super) missing_glyphs: HashSet<char>,
+    /// Glyphs rasterized by flushes since the last rendered frame.
+    pub(super) glyphs_rasterized: usize,
+    pub(super) last_frame_stats: FrameStats,
+    pub(super) hasher: H,
+    pub(super) rendered: Vec<Rendered<H>>,
+    pub(super) sourced: Vec<Sourced<H>>,
     pub(super) fast_blinking: BitVec,
     pub(super) slow_blinking: BitVec,
+    pub(super) cell_opacity: HashMap<usize, f32, RandomState>,
+    pub(super) color_overrides: HashMap<usize, (Rgba, Rgba), RandomState>,
 
     pub(super) cursor: (u16, u16),
+    pub(super) saved_screen: Option<SavedScreen>,
 
     pub(super) viewport: Viewport,
+    pub(super) grid_rounding: Rounding,
+    pub(super) integer_scaling: bool,
+    pub(super) scale_factor: f32,
+    pub(super) logical_metrics: LogicalMetrics,
+    pub(super) resize_policy: ResizePolicy,
 
     pub(super) surface: S,
     pub(super) _surface: PhantomData<&'s S>,
     pub(super) surface_config: SurfaceConfiguration,
-    pub(super) device: Device,
-    pub(super) queue: Queue,
+    pub(super) present_modes: Vec<PresentMode>,
+    pub(super) adapter_info: Option<AdapterInfo>,
+    pub(super) device: Arc<Device>,
+    pub(super) queue: Arc<Queue>,
 
-    pub(super) plan_cache: PlanCache,
+    pub(super) plan_cache: PlanCache<H>,
     pub(super) buffer: UnicodeBuffer,
     pub(super) row: String,
     pub(super) rowmap: Vec<u16>,
+    /// The glyphs sourced for each cell of the row being shaped, kept between
+    /// rows so their sets keep their allocations.
+    pub(super) row_sourced: Vec<Sourced<H>>,
 
     pub(super) cached: Atlas,
+    /// Rasterized glyphs waiting to be uploaded to the atlas, sorted by epoch.
+    pub(super) atlas_uploads: Vec<(CacheRect, Vec<u32>, bool)>,
+    pub(super) glyph_disk_cache: Option<GlyphDiskCache>,
+    /// The epoch and end quad of each batch of text quads, in draw order.
+    pub(super) text_batches: Vec<(u64, u32)>,
     pub(super) text_cache: Texture,
     pub(super) text_mask: Texture,
     pub(super) bg_vertices: Vec<TextBgVertexMember>,
@@ -193,20 +342,52 @@ pub struct WgpuBackend<
     pub(super) text_screen_size_buffer: Buffer,
 
     pub(super) wgpu_state: WgpuState,
+    pub(super) frame_buffers: FrameBuffers,
 
     pub(super) fonts: Fonts<'f>,
+    pub(super) glyph_antialiasing: bool,
+    pub(super) subpixel_aa: bool,
+    pub(super) hinting: HintingMode,
+    pub(super) fake_italic_skew: f32,
+    pub(super) emoji_mode: EmojiMode,
+    pub(super) missing_glyph: Option<char>,
+    pub(super) bidi: bool,
+    pub(super) min_contrast: f32,
+    pub(super) dim_mode: DimMode,
+    pub(super) cell_shader: Option<CellShader>,
+    pub(super) overrender_margin: u16,
+    pub(super) vertical_text: bool,
+    pub(super) cursor_style: CursorStyle,
+    pub(super) cursor_visible: bool,
+    pub(super) cursor_blink: Option<Duration>,
+    pub(super) underline_style: UnderlineStyle,
+    pub(super) padding_color: Option<Color>,
+    pub(super) clear_padding: bool,
+    pub(super) last_cursor_toggle: Instant,
+    pub(super) show_cursor: bool,
     pub(super) reset_fg: Rgb,
     pub(super) reset_bg: Rgb,
+    /// The colors `reset_fg` and `reset_bg` are resolved from, which may be
+    /// named colors from the palette.
+    pub(super) reset_colors: (Color, Color),
+    pub(super) palette: Palette,
+    pub(super) transparent_background: bool,
 
+    pub(super) blink: bool,
     pub(super) fast_duration: Duration,
     pub(super) last_fast_toggle: Instant,
     pub(super) show_fast: bool,
     pub(super) slow_duration: Duration,
     pub(super) last_slow_toggle: Instant,
     pub(super) show_slow: bool,
+
+    pub(super) min_frame_interval: Duration,
+    pub(super) last_flush: Option<Instant>,
 }
 
-impl<'f, 's, P: PostProcessor, S: RenderSurface<'s>> WgpuBackend<'f, 's, P, S> {
+impl<'f, 's, P: PostProcessor, S: RenderSurface<'s>, H: BuildHasher + Clone>
+    WgpuBackend<'f, 's, P, S, H>
+{
     /// Get the [`PostProcessor`] associated with this backend.
     pub fn post_processor(&self) -> &P {
         &self.post_process
@@ -218,1218 +399,7783 @@ impl<'f, 's, P: PostProcessor, S: RenderSurface<'s>> WgpuBackend<'f, 's, P, S> {
         &mut self.post_process
     }
 
-    /// Resize the rendering surface. This should be called e.g. to keep the
-    /// backend in sync with your window size.
-    pub fn resize(&mut self, width: u32, height: u32) {
-        let limits = self.device.limits();
-        let width = width.min(limits.max_texture_dimension_2d);
-        let height = height.min(limits.max_texture_dimension_2d);
+    /// Get the [`Device`] used for rendering, e.g. to create resources which
+    /// are shared with a [`PostProcessor`].
+    pub fn device(&self) -> &Device {
+        &self.device
+    }
 
-        if width == self.surface_config.width && height == self.surface_config.height
-            || width == 0
-            || height == 0
-        {
+    /// Get the [`Queue`] used for rendering, e.g. to upload data to resources
+    /// created with [`WgpuBackend::device`].
+    pub fn queue(&self) -> &Queue {
+        &self.queue
+    }
+
+    /// Get a view of the composited text. This is the same texture view which
+    /// is passed to [`PostProcessor::process`] as `text_view`, and is useful
+    /// for custom compositing which doesn't fit the [`PostProcessor`] model.
+    ///
+    /// <div class="warning">
+    ///
+    /// Retaining a reference to this view will cause a panic if the swapchain
+    /// is recreated.
+    ///
+    /// </div>
+    pub fn text_texture_view(&self) -> &TextureView {
+        &self.wgpu_state.text_dest_view
+    }
+
+    /// Set the pointer position in pixels relative to the top-left of the
+    /// surface, or `None` if the pointer isn't over the surface. The position
+    /// is forwarded to [`PostProcessor::set_pointer`] for effects which react
+    /// to the pointer.
+    pub fn set_pointer(&mut self, position: Option<(f32, f32)>) {
+        if position == self.pointer {
             return;
         }
 
-        let (inset_width, inset_height) = match self.viewport {
-            Viewport::Full => (0, 0),
-            Viewport::Shrink { width, height } => (width, height),
-        };
+        self.pointer = position;
+        self.post_process.set_pointer(position);
+    }
 
-        let dims = self.size().unwrap();
-        let current_width = dims.width;
-        let current_height = dims.height;
+    /// Get the pointer position last set with
+    /// [`WgpuBackend::set_pointer`].
+    pub fn pointer(&self) -> Option<(f32, f32)> {
+        self.pointer
+    }
 
-        self.surface_config.width = width;
-        self.surface_config.height = height;
-        self.surface
-            .configure(&self.device, &self.surface_config, Token);
+    /// Enable or disable post processing. When disabled, the composited text is
+    /// blitted directly to the surface and the configured [`PostProcessor`] is
+    /// skipped entirely - including any calls to
+    /// [`PostProcessor::needs_update`]. This is useful for isolating whether a
+    /// rendering issue is caused by text compositing or post processing. The
+    /// last rendered frame is presented again with the new setting, without
+    /// laying out any cells. Defaults to enabled.
+    pub fn set_post_processing_enabled(&mut self, enabled: bool) {
+        if enabled == self.post_process_enabled {
+            return;
+        }
 
-        let width = width - inset_width;
-        let height = height - inset_height;
+        if !enabled && self.blitter.is_none() {
+            self.blitter = Some(DefaultPostProcessor::compile(
+                &self.device,
+                &self.wgpu_state.text_dest_view,
+                &self.surface_config,
+                (),
+            ));
+        }
 
-        let chars_wide = width / self.fonts.min_width_px();
-        let chars_high = height / self.fonts.height_px();
+        self.post_process_enabled = enabled;
+        self.present();
+    }
 
-        if chars_wide != current_width as u32 || chars_high != current_height as u32 {
-            self.cells.clear();
-            self.rendered.clear();
-            self.sourced.clear();
-            self.fast_blinking.clear();
-            self.slow_blinking.clear();
+    /// Pause or resume rendering. While paused, calls to
+    /// [`WgpuBackend::flush`] keep track of changes to the screen but don't
+    /// render or present anything. Blinking text stops toggling while paused.
+    /// Resuming rendering will cause a full repaint of the screen the next time
+    /// [`WgpuBackend::flush`] is called.
+    ///
+    /// This is useful to avoid wasted work while e.g. your window is minimized
+    /// or occluded.
+    pub fn set_rendering_paused(&mut self, paused: bool) {
+        if paused == self.paused {
+            return;
         }
 
-        // This always needs to be cleared because the surface is cleared when it is
-        // resized. If we don't re-render the rows, we end up with a blank surface when
-        // the resize is less than a character dimension.
-        self.dirty_rows.clear();
+        self.paused = paused;
+        if !paused {
+            self.dirty_rows.fill(true);
+            self.last_fast_toggle = Instant::now();
+            self.last_slow_toggle = Instant::now();
+            self.last_cursor_toggle = Instant::now();
+        }
+    }
 
-        self.wgpu_state = build_wgpu_state(
-            &self.device,
-            chars_wide * self.fonts.min_width_px(),
-            chars_high * self.fonts.height_px(),
-        );
+    /// Blink the cursor at the specified interval, or draw it solidly if
+    /// `None`. See
+    /// [`Builder::with_cursor_blink_millis`](crate::Builder::with_cursor_blink_millis).
+    pub fn set_cursor_blink(&mut self, blink: Option<Duration>) {
+        self.cursor_blink = blink;
+        self.last_cursor_toggle = Instant::now();
+        if !std::mem::replace(&mut self.show_cursor, true) && self.cursor_visible {
+            self.dirty_cursor_row();
+        }
+    }
 
-        self.post_process.resize(
-            &self.device,
-            &self.wgpu_state.text_dest_view,
-            &self.surface_config,
-        );
+    /// Get the current cursor blink interval. See
+    /// [`WgpuBackend::set_cursor_blink`].
+    pub fn cursor_blink(&self) -> Option<Duration> {
+        self.cursor_blink
+    }
 
-        info!(
-            "Resized from {}x{} to {}x{}",
-            current_width, current_height, chars_wide, chars_high,
-        );
+    /// Returns true if rendering is currently paused. See
+    /// [`WgpuBackend::set_rendering_paused`].
+    pub fn rendering_paused(&self) -> bool {
+        self.paused
     }
 
-    /// Get the text currently displayed on the screen.
-    pub fn get_text(&self) -> String {
-        let bounds = self.size().unwrap();
-        self.cells.chunks(bounds.width as usize).fold(
-            String::with_capacity((bounds.width + 1) as usize * bounds.height as usize),
-            |dest, row| {
-                let mut dest = row.iter().fold(dest, |mut dest, s| {
-                    dest.push_str(s.symbol());
-                    dest
-                });
-                dest.push('\n');
-                dest
-            },
-        )
+    /// Begin a batch of updates. Until [`WgpuBackend::end_batch`] is called,
+    /// calls to [`WgpuBackend::flush`] still process changes to the screen but
+    /// defer rendering, so that several draws can be composed into a single
+    /// render and submission.
+    pub fn begin_batch(&mut self) {
+        self.batching = true;
     }
 
-    /// Update the fonts used for rendering. This will cause a full repaint of
-    /// the screen the next time [`WgpuBackend::flush`] is called.
-    pub fn update_fonts(&mut self, new_fonts: Fonts<'f>) {
-        self.dirty_rows.clear();
-        self.cached.match_fonts(&new_fonts);
-        self.fonts = new_fonts;
+    /// End a batch of updates started by [`WgpuBackend::begin_batch`],
+    /// rendering any changes which were flushed during the batch. Changes which
+    /// have been drawn but not flushed are flushed as well.
+    pub fn end_batch(&mut self) -> std::io::Result<()> {
+        self.batching = false;
+        self.flush()
     }
 
-    fn render(&mut self) {
-        let bounds = self.window_size().unwrap();
+    /// Begin a synchronized update, following the semantics of the synchronized
+    /// output mode (DEC private mode 2026) supported by many terminals. Until
+    /// [`WgpuBackend::end_synchronized_update`] is called, the previously
+    /// presented frame stays on screen and nothing is rendered or presented,
+    /// no matter how many times the backend is drawn to or flushed.
+    ///
+    /// This maps directly to an application's `CSI ? 2026 h` and is
+    /// implemented with the same mechanism as
+    /// [`WgpuBackend::begin_batch`].
+    pub fn begin_synchronized_update(&mut self) {
+        self.begin_batch();
+    }
 
-        let mut encoder = self
-            .device
-            .create_command_encoder(&CommandEncoderDescriptor {
-                label: Some("Draw Encoder"),
-            });
+    /// End a synchronized update started by
+    /// [`WgpuBackend::begin_synchronized_update`], flushing any pending draws
+    /// and presenting every change made during the update as a single frame.
+    /// This maps directly to an application's `CSI ? 2026 l`.
+    pub fn end_synchronized_update(&mut self) -> std::io::Result<()> {
+        self.end_batch()
+    }
 
-        if !self.text_vertices.is_empty() {
-            {
-                let mut uniforms = self
-                    .queue
-                    .write_buffer_with(
-                        &self.text_screen_size_buffer,
-                        0,
-                        NonZeroU64::new(size_of::<[f32; 4]>() as u64).unwrap(),
-                    )
-                    .unwrap();
-                uniforms.copy_from_slice(bytemuck::cast_slice(&[
-                    bounds.columns_rows.width as f32 * self.fonts.min_width_px() as f32,
-                    bounds.columns_rows.height as f32 * self.fonts.height_px() as f32,
-                    0.0,
-                    0.0,
-                ]));
-            }
+    /// Set the opacity of the cell at `pos`, from 0.0 (fully transparent) to
+    /// 1.0 (fully opaque). This is multiplied into the alpha of both the cell's
+    /// glyphs and its background, on top of any alpha from [`Modifier::DIM`],
+    /// [`Modifier::HIDDEN`], or blinking. Animate fades by updating the
+    /// opacity and flushing again.
+    ///
+    /// Opacities are reset when the backend is cleared or the grid changes
+    /// size. Positions outside of the grid are ignored.
+    pub fn set_cell_opacity(&mut self, pos: Position, alpha: f32) {
+        let bounds = self.size().unwrap();
+        if pos.y >= bounds.height {
+            return;
+        }
 
-            let bg_vertices = self.device.create_buffer_init(&BufferInitDescriptor {
-                label: Some("Text Bg Vertices"),
-                contents: bytemuck::cast_slice(&self.bg_vertices),
-                usage: BufferUsages::VERTEX,
-            });
+        let index = pos.y as usize * bounds.width as usize + pos.x as usize;
+        let alpha = alpha.clamp(0.0, 1.0);
+        let previous = if alpha == 1.0 {
+            self.cell_opacity.remove(&index)
+        } else {
+            self.cell_opacity.insert(index, alpha)
+        };
 
-            let fg_vertices = self.device.create_buffer_init(&BufferInitDescriptor {
-                label: Some("Text Vertices"),
-                contents: bytemuck::cast_slice(&self.text_vertices),
-                usage: BufferUsages::VERTEX,
-            });
+        if previous.unwrap_or(1.0) != alpha {
+            if let Some(dirty) = self.dirty_rows.get_mut(pos.y as usize) {
+                *dirty = true;
+            }
+        }
+    }
 
-            let indices = self.device.create_buffer_init(&BufferInitDescriptor {
-                label: Some("Text Indices"),
-                contents: bytemuck::cast_slice(&self.text_indices),
-                usage: BufferUsages::INDEX,
-            });
+    /// Write `text` to the screen starting at `pos` using explicit foreground
+    /// and background colors, bypassing [`ratatui::style::Color`]. Text which
+    /// doesn't fit on the row is truncated.
+    ///
+    /// Explicit colors take precedence over the ratatui style of the cells
+    /// they are written to, including [`Modifier::REVERSED`], and their alpha
+    /// is combined with any alpha from [`WgpuBackend::set_cell_opacity`]. They
+    /// are discarded when a cell is next written by
+    /// [`Backend::draw`](ratatui::backend::Backend::draw), cleared, or
+    /// resized. Note that [`ratatui::Terminal`] doesn't know about cells
+    /// written this way, so it will only overwrite them where its own buffer
+    /// changes.
+    pub fn print_rgba(&mut self, pos: Position, text: &str, fg: Rgba, bg: Rgba) {
+        let bounds = self.size().unwrap();
+        if pos.x >= bounds.width || pos.y >= bounds.height {
+            return;
+        }
 
-            {
-                let mut text_render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                    label: Some("Text Render Pass"),
-                    color_attachments: &[Some(RenderPassColorAttachment {
-                        view: &self.wgpu_state.text_dest_view,
-                        resolve_target: None,
-                        ops: Operations {
-                            load: LoadOp::Load,
-                            store: StoreOp::Store,
-                        },
-                    })],
-                    ..Default::default()
-                });
+        let mut cells = Vec::<(u16, Cell)>::new();
+        let mut x = pos.x;
+        for ch in text.chars().filter(|ch| !ch.is_control()) {
+            let width = ch.width().unwrap_or_default() as u16;
+            if width == 0 {
+                if let Some((_, cell)) = cells.last_mut() {
+                    let symbol = format!("{}{ch}", cell.symbol());
+                    cell.set_symbol(&symbol);
+                    continue;
+                }
+            }
 
-                text_render_pass.set_index_buffer(indices.slice(..), IndexFormat::Uint32);
+            if x.saturating_add(width.max(1)) > bounds.width {
+                break;
+            }
 
-                text_render_pass.set_pipeline(&self.text_bg_compositor.pipeline);
-                text_render_pass.set_bind_group(0, &self.text_bg_compositor.fs_uniforms, &[]);
-                text_render_pass.set_vertex_buffer(0, bg_vertices.slice(..));
-                text_render_pass.draw_indexed(0..(self.bg_vertices.len() as u32 / 4) * 6, 0, 0..1);
+            let mut cell = Cell::EMPTY;
+            cell.set_char(ch);
+            cells.push((x, cell));
+            x += width.max(1);
+        }
 
-                text_render_pass.set_pipeline(&self.text_fg_compositor.pipeline);
-                text_render_pass.set_bind_group(0, &self.text_fg_compositor.fs_uniforms, &[]);
-                text_render_pass.set_bind_group(1, &self.text_fg_compositor.atlas_bindings, &[]);
+        self.draw(cells.iter().map(|(x, cell)| (*x, pos.y, cell)))
+            .unwrap();
 
-                text_render_pass.set_vertex_buffer(0, fg_vertices.slice(..));
-                text_render_pass.draw_indexed(
-                    0..(self.text_vertices.len() as u32 / 4) * 6,
-                    0,
-                    0..1,
-                );
-            }
+        for (x, _) in cells {
+            self.color_overrides.insert(
+                pos.y as usize * bounds.width as usize + x as usize,
+                (fg, bg),
+            );
         }
+    }
 
-        let Some(texture) = self.surface.get_current_texture(Token) else {
+    /// Draw reference lines for the baseline, ascender, descender, and
+    /// x-height of the primary font over every cell, or stop drawing them with
+    /// `None`. This is intended as a debugging aid for font metrics and
+    /// baseline alignment. The lines are drawn behind glyphs, so they remain
+    /// visible wherever a glyph doesn't cover them.
+    pub fn set_metric_overlay(&mut self, overlay: Option<MetricLines>) {
+        if overlay == self.metric_overlay {
             return;
-        };
-
-        self.post_process.process(
-            &mut encoder,
-            &self.queue,
-            &self.wgpu_state.text_dest_view,
-            &self.surface_config,
-            texture.get_view(Token),
-        );
+        }
 
-        self.queue.submit(Some(encoder.finish()));
-        texture.present(Token);
+        self.metric_overlay = overlay;
+        self.dirty_rows.fill(true);
     }
-}
-
-impl<'f, 's, P: PostProcessor, S: RenderSurface<'s>> Backend for WgpuBackend<'f, 's, P, S> {
-    fn draw<'a, I>(&mut self, content: I) -> std::io::Result<()>
-    where
-        I: Iterator<Item = (u16, u16, &'a Cell)>,
-    {
-        let bounds = self.size()?;
 
-        self.cells
-            .resize(bounds.height as usize * bounds.width as usize, Cell::EMPTY);
-        self.sourced.resize_with(
-            bounds.height as usize * bounds.width as usize,
-            Sourced::default,
-        );
-        self.rendered.resize_with(
-            bounds.height as usize * bounds.width as usize,
-            Rendered::default,
-        );
-        self.fast_blinking
-            .resize(bounds.height as usize * bounds.width as usize, false);
-        self.slow_blinking
-            .resize(bounds.height as usize * bounds.width as usize, false);
-        self.dirty_rows.resize(bounds.height as usize, true);
+    /// Get the currently configured metric overlay. See
+    /// [`WgpuBackend::set_metric_overlay`].
+    pub fn metric_overlay(&self) -> Option<MetricLines> {
+        self.metric_overlay
+    }
 
-        for (x, y, cell) in content {
-            let index = y as usize * bounds.width as usize + x as usize;
+    /// Fill the area of the surface which isn't covered by whole cells with
+    /// `color`, or stretch the text to cover the whole surface with `None`. See
+    /// [`Builder::with_padding_color`](crate::Builder::with_padding_color).
+    pub fn set_padding_color(&mut self, color: Option<Color>) {
+        if color == self.padding_color {
+            return;
+        }
 
-            self.fast_blinking
-                .set(index, cell.modifier.contains(Modifier::RAPID_BLINK));
-            self.slow_blinking
-                .set(index, cell.modifier.contains(Modifier::SLOW_BLINK));
+        let was_padded = self.padded();
+        self.padding_color = color;
+        let resize_dest = was_padded != self.padded();
+        self.clear_padding = true;
+        self.dirty_rows.fill(true);
+
+        if resize_dest {
+            let overrender = self.wgpu_state.overrender.take();
+            let (width, height) = self.text_dest_size();
+            self.wgpu_state = build_wgpu_state(&self.device, width, height);
+            self.wgpu_state.overrender = overrender;
+
+            self.post_process.resize(
+                &self.device,
+                &self.wgpu_state.text_dest_view,
+                &self.surface_config,
+            );
+            if let Some(blitter) = self.blitter.as_mut() {
+                blitter.resize(
+                    &self.device,
+                    &self.wgpu_state.text_dest_view,
+                    &self.surface_config,
+                );
+            }
+        }
+    }
 
-            self.cells[index] = cell.clone();
+    /// Change the color used for one of the 16 named colors, from
+    /// [`Color::Black`] to [`Color::White`]. Every cell and the padding are
+    /// repainted on the next flush so that they pick up the change, including
+    /// through default colors set to `color`. Other colors are ignored.
+    pub fn set_named_color(&mut self, color: Color, rgb: [u8; 3]) {
+        let index = match color {
+            Color::Black => 0,
+            Color::Red => 1,
+            Color::Green => 2,
+            Color::Yellow => 3,
+            Color::Blue => 4,
+            Color::Magenta => 5,
+            Color::Cyan => 6,
+            Color::Gray => 7,
+            Color::DarkGray => 8,
+            Color::LightRed => 9,
+            Color::LightGreen => 10,
+            Color::LightYellow => 11,
+            Color::LightBlue => 12,
+            Color::LightMagenta => 13,
+            Color::LightCyan => 14,
+            Color::White => 15,
+            Color::Reset | Color::Rgb(..) | Color::Indexed(_) => return,
+        };
 
-            let width = cell.symbol().width().max(1);
-            let start = (index + 1).min(self.cells.len());
-            let end = (index + width).min(self.cells.len());
-            self.cells[start..end].fill(NULL_CELL);
-            self.dirty_rows[y as usize] = true;
+        if self.palette.named[index] != rgb {
+            self.palette.named[index] = rgb;
+            let (fg, bg) = self.reset_colors;
+            self.reset_fg = c2c(fg, BLACK, &self.palette);
+            self.reset_bg = c2c(bg, WHITE, &self.palette);
+            self.dirty_rows.fill(true);
+            self.clear_padding = true;
         }
+    }
 
-        Ok(())
+    /// Get the colors used for named and indexed colors. See
+    /// [`Builder::with_palette`](crate::Builder::with_palette).
+    pub fn palette(&self) -> &Palette {
+        &self.palette
     }
 
-    fn hide_cursor(&mut self) -> std::io::Result<()> {
-        Ok(())
+    /// Get the color used to fill the area of the surface outside of the grid.
+    /// See [`WgpuBackend::set_padding_color`].
+    pub fn padding_color(&self) -> Option<Color> {
+        self.padding_color
     }
 
-    fn show_cursor(&mut self) -> std::io::Result<()> {
-        Ok(())
+    /// Whether the area outside of the grid is filled instead of the grid being
+    /// stretched over it.
+    fn padded(&self) -> bool {
+        self.padding_color.is_some() || matches!(self.viewport, Viewport::Centered)
     }
 
-    fn get_cursor_position(&mut self) -> std::io::Result<Position> {
-        Ok(Position::new(self.cursor.0, self.cursor.1))
+    /// The size of the grid in pixels, as presented on the surface.
+    fn grid_pixels(&self) -> (u32, u32) {
+        let bounds = self.size().unwrap();
+        let (width, height) = self.screen_cell_size();
+        if self.vertical_text {
+            (bounds.height as u32 * width, bounds.width as u32 * height)
+        } else {
+            (bounds.width as u32 * width, bounds.height as u32 * height)
+        }
     }
 
-    fn set_cursor_position<Pos: Into<Position>>(&mut self, position: Pos) -> std::io::Result<()> {
-        let bounds = self.size()?;
-        let pos: Position = position.into();
-        self.cursor = (pos.x.min(bounds.width - 1), pos.y.min(bounds.height - 1));
-        Ok(())
+    /// The size in pixels of the slots cells are presented in down and across
+    /// the surface. With vertical text, each slot is two cells wide so that
+    /// wide characters fit upright.
+    fn screen_cell_size(&self) -> (u32, u32) {
+        let (width, height) = (self.fonts.cell_width_px(), self.fonts.cell_height_px());
+        if self.vertical_text {
+            (width * 2, height)
+        } else {
+            (width, height)
+        }
     }
 
-    fn clear(&mut self) -> std::io::Result<()> {
-        self.cells.clear();
-        self.dirty_rows.clear();
-        self.cursor = (0, 0);
+    /// The pixel offset of the top left of the grid in the text destination
+    /// texture.
+    fn grid_offset(&self) -> (u32, u32) {
+        if !matches!(self.viewport, Viewport::Centered) {
+            return (0, 0);
+        }
 
-        Ok(())
+        let (text_width, text_height) = self.grid_pixels();
+        (
+            self.surface_config.width.saturating_sub(text_width) / 2,
+            self.surface_config.height.saturating_sub(text_height) / 2,
+        )
     }
 
-    fn size(&self) -> std::io::Result<Size> {
-        let (inset_width, inset_height) = match self.viewport {
-            Viewport::Full => (0, 0),
-            Viewport::Shrink { width, height } => (width, height),
-        };
-        let width = self.surface_config.width - inset_width;
-        let height = self.surface_config.height - inset_height;
+    /// The size of the texture text is composited into. This is the size of the
+    /// grid, unless the area outside of the grid is being padded.
+    fn text_dest_size(&self) -> (u32, u32) {
+        let (text_width, text_height) = self.grid_pixels();
+        if self.padded() {
+            (
+                text_width.max(self.surface_config.width),
+                text_height.max(self.surface_config.height),
+            )
+        } else {
+            (text_width, text_height)
+        }
+    }
 
-        Ok(Size {
-            width: (width / self.fonts.min_width_px()) as u16,
-            height: (height / self.fonts.height_px()) as u16,
-        })
+    /// Draw a rectangular outline `thickness` pixels wide just inside the edges
+    /// of the cells in `area`, or stop drawing it with `None`. The ring is
+    /// drawn over glyphs and blended using the alpha of its color, but doesn't
+    /// change the contents of any cells. This is useful for highlighting the
+    /// focused widget without giving up cells to a border.
+    ///
+    /// The thickness is in unscaled pixels of the text grid and is limited to
+    /// half the width or height of `area`. Parts of `area` outside of the
+    /// grid are not drawn.
+    pub fn set_focus_ring(&mut self, ring: Option<(Rect, Rgba, u32)>) {
+        if ring == self.focus_ring {
+            return;
+        }
+
+        // The ring is composited into the text, so the rows under both the old and
+        // new rings need to be repainted.
+        for (area, _, _) in self.focus_ring.iter().chain(ring.iter()) {
+            for y in area.top()..area.bottom() {
+                if let Some(dirty) = self.dirty_rows.get_mut(y as usize) {
+                    *dirty = true;
+                }
+            }
+        }
+
+        self.focus_ring = ring;
     }
 
-    fn window_size(&mut self) -> std::io::Result<WindowSize> {
-        let (inset_width, inset_height) = match self.viewport {
-            Viewport::Full => (0, 0),
-            Viewport::Shrink { width, height } => (width, height),
-        };
-        let width = self.surface_config.width - inset_width;
-        let height = self.surface_config.height - inset_height;
+    /// Get the currently configured focus ring. See
+    /// [`WgpuBackend::set_focus_ring`].
+    pub fn focus_ring(&self) -> Option<(Rect, Rgba, u32)> {
+        self.focus_ring
+    }
 
-        Ok(WindowSize {
-            columns_rows: Size {
-                width: (width / self.fonts.min_width_px()) as u16,
-                height: (height / self.fonts.height_px()) as u16,
-            },
-            pixels: Size {
-                width: width as u16,
-                height: height as u16,
-            },
-        })
+    /// Add a pane covering `area` of the grid. Each pane can be drawn to
+    /// independently through [`WgpuBackend::pane`], e.g. to show several
+    /// tmux-style splits with their own [`Terminal`](ratatui::Terminal)s.
+    /// Panes share the grid, so drawing to the backend directly or to
+    /// overlapping panes overwrites the same cells. Parts of `area` outside of
+    /// the grid are ignored.
+    pub fn add_pane(&mut self, area: Rect) -> PaneId {
+        self.panes.push(PaneState::new(area));
+        PaneId(self.panes.len() - 1)
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        let bounds = self.size()?;
-        self.dirty_cells.clear();
-        self.dirty_cells.resize(self.cells.len(), false);
+    /// Move or resize a pane added with [`WgpuBackend::add_pane`]. Cells which
+    /// were covered by the pane keep their contents, and anything drawn to the
+    /// pane since it was last flushed is discarded.
+    ///
+    /// # Panics
+    /// Panics if `pane` was added to a different backend.
+    pub fn set_pane_area(&mut self, pane: PaneId, area: Rect) {
+        self.panes[pane.0] = PaneState::new(area);
+    }
 
-        let fast_toggle_dirty = self.last_fast_toggle.elapsed() >= self.fast_duration;
-        if fast_toggle_dirty {
-            self.last_fast_toggle = Instant::now();
-            self.show_fast = !self.show_fast;
+    /// Get the area of the grid covered by a pane added with
+    /// [`WgpuBackend::add_pane`].
+    ///
+    /// # Panics
+    /// Panics if `pane` was added to a different backend.
+    pub fn pane_area(&self, pane: PaneId) -> Rect {
+        self.panes[pane.0].area
+    }
 
-            for index in self.fast_blinking.iter_ones() {
-                self.dirty_cells.set(index, true);
-            }
+    /// Get a [`Pane`] which draws to the area of the grid covered by `pane`.
+    ///
+    /// # Panics
+    /// Panics if `pane` was added to a different backend.
+    pub fn pane(&mut self, pane: PaneId) -> Pane<'_, 'f, 's, P, S, H> {
+        assert!(pane.0 < self.panes.len(), "Unknown pane");
+        Pane {
+            backend: self,
+            id: pane,
         }
+    }
 
-        let slow_toggle_dirty = self.last_slow_toggle.elapsed() >= self.slow_duration;
-        if slow_toggle_dirty {
-            self.last_slow_toggle = Instant::now();
-            self.show_slow = !self.show_slow;
+    /// Fill the background of the cells in `area` with a linear gradient
+    /// running from `start` to `end` through `stops`, e.g. to draw a gradient
+    /// title bar. `start` and `end` are relative to `area`, with `[0.0, 0.0]`
+    /// at its top left and `[1.0, 1.0]` at its bottom right, so `[0.0, 0.5]` to
+    /// `[1.0, 0.5]` runs from left to right. Colors before the first stop and
+    /// after the last stop are extended to the edges of `area`.
+    ///
+    /// The gradient replaces the background color of the cells it covers, and
+    /// is blended with any alpha from [`WgpuBackend::set_cell_opacity`]. Glyphs
+    /// are still drawn over it. Setting a gradient for an area which already
+    /// has one replaces it, and an empty `stops` removes it. Where areas
+    /// overlap, the most recently set gradient is drawn. Gradients are kept
+    /// when the backend is cleared or resized, but parts of `area` outside of
+    /// the grid are not drawn.
+    pub fn set_region_gradient(
+        &mut self,
+        area: Rect,
+        start: [f32; 2],
+        end: [f32; 2],
+        mut stops: Vec<GradientStop>,
+    ) {
+        stops.sort_by(|l, r| l.offset.total_cmp(&r.offset));
+
+        self.region_gradients
+            .retain(|gradient| gradient.area != area);
+        if !stops.is_empty() {
+            self.region_gradients.push(RegionGradient {
+                area,
+                start,
+                end,
+                stops,
+            });
+        }
 
-            for index in self.slow_blinking.iter_ones() {
-                self.dirty_cells.set(index, true);
+        for y in area.top()..area.bottom() {
+            if let Some(dirty) = self.dirty_rows.get_mut(y as usize) {
+                *dirty = true;
             }
         }
+    }
 
-        let mut pending_cache_updates = HashMap::<_, _, RandomState>::default();
-
-        for (y, (row, sourced)) in self
-            .cells
-            .chunks(bounds.width as usize)
-            .zip(self.sourced.chunks_mut(bounds.width as usize))
-            .enumerate()
-        {
-            if !self.dirty_rows[y] {
-                continue;
+    /// Remove all gradients set with [`WgpuBackend::set_region_gradient`].
+    pub fn clear_region_gradients(&mut self) {
+        for gradient in self.region_gradients.drain(..) {
+            for y in gradient.area.top()..gradient.area.bottom() {
+                if let Some(dirty) = self.dirty_rows.get_mut(y as usize) {
+                    *dirty = true;
+                }
             }
+        }
+    }
 
-            self.dirty_rows[y] = false;
-            let mut new_sourced = vec![Sourced::default(); bounds.width as usize];
-
-            // This block concatenates the strings for the row into one string for bidi
-            // resolution, then maps bytes for the string to their associated cell index. It
-            // also maps the row's cell index to the font that can source all glyphs for
-            // that cell.
-            self.row.clear();
-            self.rowmap.clear();
-            let mut fontmap = Vec::with_capacity(self.rowmap.capacity());
-            for (idx, cell) in row.iter().enumerate() {
-                self.row.push_str(cell.symbol());
-                self.rowmap
-                    .resize(self.rowmap.len() + cell.symbol().len(), idx as u16);
-                fontmap.push(self.fonts.font_for_cell(cell));
+    /// Draw an image over the cells in `area`, like a terminal image protocol
+    /// would. `rgba` holds `width * height` unpremultiplied rgba pixels, row by
+    /// row, and is stretched to cover `area` using `sample`.
+    ///
+    /// Images are drawn above cell backgrounds and beneath glyphs, so leave the
+    /// cells under an image blank to show it unobstructed, or draw text into
+    /// them to caption it. Images are blended using their alpha. Drawing an
+    /// image over an area which already has one replaces it. Where areas
+    /// overlap, the most recently drawn image is on top. Images are kept when
+    /// the backend is cleared or resized, but parts of `area` outside of the
+    /// grid are not drawn.
+    ///
+    /// # Panics
+    /// Panics if `width` or `height` is zero or larger than the device
+    /// supports, or if `rgba` doesn't hold exactly `width * height` pixels.
+    pub fn draw_image(
+        &mut self,
+        area: Rect,
+        rgba: &[u8],
+        width: u32,
+        height: u32,
+        sample: SampleMode,
+    ) {
+        assert!(width != 0 && height != 0, "Image has no pixels");
+        assert_eq!(
+            rgba.len(),
+            width as usize * height as usize * 4,
+            "Image data doesn't match its dimensions"
+        );
+
+        let compositor = self.image_compositor.get_or_insert_with(|| {
+            build_image_compositor(&self.device, &self.text_screen_size_buffer)
+        });
+
+        let size = Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        // Images redrawn at the same size reuse their texture.
+        let texture = match self.images.iter().position(|image| image.area == area) {
+            Some(index) if self.images[index].texture.size() == size => {
+                self.images.remove(index).texture
             }
+            _ => self.device.create_texture(&TextureDescriptor {
+                label: Some("Cell Image"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            }),
+        };
+        self.images.retain(|image| image.area != area);
+
+        self.queue.write_texture(
+            ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            rgba,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(width * 4),
+                rows_per_image: Some(height),
+            },
+            size,
+        );
 
-            let mut x = 0;
-            // rustbuzz provides a non-zero x-advance for the first character in a cluster
-            // with combining characters. The remainder of the cluster doesn't account for
-            // this advance, so if we advance prior to rendering them, we end up with all of
-            // the associated characters being offset by a cell. To combat this, we only
-            // bump the x-advance after we've finished processing all of the characters in a
-            // cell. This assumes that we 1) always get a non-zero advance at the beginning
-            // of a cluster and 2) the next cluster in the sequence starts with a non-zero
-            // advance.
-            let mut next_advance = 0;
-            let mut shape = |font: &Font,
-                             fake_bold,
-                             fake_italic,
-                             buffer: GlyphBuffer|
-             -> UnicodeBuffer {
-                let metrics = font.font();
-                let advance_scale = self.fonts.height_px() as f32 / metrics.height() as f32;
-
-                for (info, position) in buffer
-                    .glyph_infos()
-                    .iter()
-                    .zip(buffer.glyph_positions().iter())
-                {
-                    let cell_idx = self.rowmap[info.cluster as usize] as usize;
-                    let cell = &row[cell_idx];
-                    let max_width = cell.symbol().width();
-                    let sourced = &mut new_sourced[cell_idx];
-
-                    let basey = y as i32 * self.fonts.height_px() as i32
-                        + (position.y_offset as f32 * advance_scale) as i32;
-                    let mut advance = (position.x_advance as f32 * advance_scale) as i32;
-                    if advance != 0 {
-                        x += next_advance;
-                        advance =
-                            max_width as i32 * advance.signum() * self.fonts.min_width_px() as i32;
-                        next_advance = advance;
-                    }
-                    let basex = x + (position.x_offset as f32 * advance_scale) as i32;
+        let bindings = self.device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Cell Image Binding"),
+            layout: &compositor.image_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(
+                        &texture.create_view(&TextureViewDescriptor::default()),
+                    ),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(match sample {
+                        SampleMode::Linear => &compositor.linear,
+                        SampleMode::Nearest => &compositor.nearest,
+                    }),
+                },
+            ],
+        });
+
+        self.images.push(CellImage {
+            area,
+            texture,
+            bindings,
+        });
+
+        for y in area.top()..area.bottom() {
+            if let Some(dirty) = self.dirty_rows.get_mut(y as usize) {
+                *dirty = true;
+            }
+        }
+    }
 
-                    // This assumes that we only want to underline the first character in the
-                    // cluster, and that the remaining characters are all combining characters
-                    // which don't need an underline.
-                    let set = if advance != 0 {
-                        Modifier::BOLD | Modifier::ITALIC | Modifier::UNDERLINED
-                    } else {
-                        Modifier::BOLD | Modifier::ITALIC
-                    };
+    /// Remove all images drawn with [`WgpuBackend::draw_image`].
+    pub fn clear_images(&mut self) {
+        for image in self.images.drain(..) {
+            for y in image.area.top()..image.area.bottom() {
+                if let Some(dirty) = self.dirty_rows.get_mut(y as usize) {
+                    *dirty = true;
+                }
+            }
+        }
+    }
 
-                    let key = Key {
-                        style: cell.modifier.intersection(set),
-                        glyph: info.glyph_id,
-                        font: font.id(),
-                    };
+    #[cfg(any(feature = "kitty-graphics", feature = "sixel"))]
+    fn remove_image(&mut self, area: Rect) {
+        self.images.retain(|image| image.area != area);
+        for y in area.top()..area.bottom() {
+            if let Some(dirty) = self.dirty_rows.get_mut(y as usize) {
+                *dirty = true;
+            }
+        }
+    }
 
-                    let ch = self.row[info.cluster as usize..].chars().next().unwrap();
-                    let width = (metrics
-                        .glyph_hor_advance(GlyphId(info.glyph_id as _))
-                        .unwrap_or_default() as f32
-                        * advance_scale) as u32;
-                    let chars_wide = ch.width().unwrap_or(max_width) as u32;
-                    let chars_wide = if chars_wide == 0 { 1 } else { chars_wide };
-                    let width = if width == 0 {
-                        chars_wide * self.fonts.min_width_px()
-                    } else {
-                        width
-                    };
+    /// Switch to the alternate screen, saving the contents of the main screen
+    /// and the cursor position. The alternate screen starts out blank. This
+    /// mirrors the behavior of `DECSET 1049` in terminal emulators. Does
+    /// nothing if the alternate screen is already active.
+    ///
+    /// Note that if you are using a [`Terminal`](ratatui::Terminal), its
+    /// internal buffers are not aware of the switch, so you will need to
+    /// redraw its contents.
+    pub fn enter_alternate_screen(&mut self) {
+        if self.saved_screen.is_some() {
+            return;
+        }
 
-                    let cached = self.cached.get(
-                        &key,
-                        chars_wide * self.fonts.min_width_px(),
-                        self.fonts.height_px(),
-                    );
+        let len = self.cells.len();
+        self.saved_screen = Some(SavedScreen {
+            cells: std::mem::replace(&mut self.cells, vec![Cell::EMPTY; len]),
+            fast_blinking: std::mem::replace(&mut self.fast_blinking, BitVec::repeat(false, len)),
+            slow_blinking: std::mem::replace(&mut self.slow_blinking, BitVec::repeat(false, len)),
+            cursor: std::mem::take(&mut self.cursor),
+        });
 
-                    let offset = (basey.max(0) as usize / self.fonts.height_px() as usize)
-                        .min(bounds.height as usize - 1)
-                        * bounds.width as usize
-                        + (basex.max(0) as usize / self.fonts.min_width_px() as usize)
-                            .min(bounds.width as usize - 1);
-
-                    sourced.insert((basex, basey, GlyphId(info.glyph_id as _), chars_wide));
-
-                    let mut underline_pos_min = 0;
-                    let mut underline_pos_max = 0;
-                    if key.style.contains(Modifier::UNDERLINED) {
-                        let underline_position = (metrics.ascender() as f32 * advance_scale) as u16;
-                        let underline_thickness = metrics
-                            .underline_metrics()
-                            .map(|m| (m.thickness as f32 * advance_scale) as u16)
-                            .unwrap_or(1);
-                        underline_pos_min = underline_position;
-                        underline_pos_max = underline_pos_min + underline_thickness;
-                    }
+        self.invalidate();
+    }
 
-                    self.rendered[offset].insert(
-                        (basex, basey, GlyphId(info.glyph_id as _)),
-                        RenderInfo {
-                            cell: y * bounds.width as usize + cell_idx,
-                            cached: *cached,
-                            underline_pos_min,
-                            underline_pos_max,
-                        },
-                    );
-                    for x_offset in 0..chars_wide as usize {
-                        self.dirty_cells.set(offset + x_offset, true);
-                    }
+    /// Leave the alternate screen, restoring the contents of the main screen
+    /// and the cursor position. If the screen was resized while the alternate
+    /// screen was active, the main screen is cleared instead. Does nothing if
+    /// the alternate screen is not active.
+    pub fn leave_alternate_screen(&mut self) {
+        let Some(saved) = self.saved_screen.take() else {
+            return;
+        };
 
-                    if cached.cached() {
-                        continue;
-                    }
+        let bounds = self.size().unwrap();
+        if saved.cells.len() == bounds.width as usize * bounds.height as usize {
+            self.cells = saved.cells;
+            self.fast_blinking = saved.fast_blinking;
+            self.slow_blinking = saved.slow_blinking;
+            self.cursor = saved.cursor;
+        } else {
+            self.cells.fill(Cell::EMPTY);
+            self.fast_blinking.fill(false);
+            self.slow_blinking.fill(false);
+            self.cursor = (0, 0);
+        }
 
-                    pending_cache_updates.entry(key).or_insert_with(|| {
-                        let is_emoji = ch.is_emoji_char()
-                            && !matches!(ch.general_category_group(), GeneralCategoryGroup::Number);
-
-                        let (rect, image) = rasterize_glyph(
-                            cached,
-                            metrics,
-                            info,
-                            fake_italic & !is_emoji,
-                            fake_bold & !is_emoji,
-                            advance_scale,
-                            width,
-                        );
-                        (rect, image, is_emoji)
-                    });
-                }
+        self.invalidate();
+    }
 
-                buffer.clear()
-            };
+    /// Returns true if the alternate screen is active.
+    pub fn is_alternate_screen(&self) -> bool {
+        self.saved_screen.is_some()
+    }
 
-            let bidi = ParagraphBidiInfo::new(&self.row, None);
-            let (levels, runs) = bidi.visual_runs(0..bidi.levels.len());
+    /// Resize the rendering surface. This should be called e.g. to keep the
+    /// backend in sync with your window size.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let limits = self.device.limits();
+        let width = width.min(limits.max_texture_dimension_2d);
+        let height = height.min(limits.max_texture_dimension_2d);
 
-            let (mut current_font, mut current_fake_bold, mut current_fake_italic) = fontmap[0];
-            let mut current_level = Level::ltr();
+        if width == 0 || height == 0 {
+            return;
+        }
 
-            for (level, range) in runs.into_iter().map(|run| (levels[run.start], run)) {
-                let chars = &self.row[range.clone()];
-                let cells = &self.rowmap[range.clone()];
-                for (idx, ch) in chars.char_indices() {
-                    let cell_idx = cells[idx] as usize;
-                    let (font, fake_bold, fake_italic) = fontmap[cell_idx];
+        let (inset_width, inset_height) = match self.viewport {
+            Viewport::Full | Viewport::Centered => (0, 0),
+            Viewport::Shrink { width, height } => (width, height),
+        };
 
-                    if font.id() != current_font.id()
-                        || current_fake_bold != fake_bold
-                        || current_fake_italic != fake_italic
-                        || current_level != level
-                    {
-                        let mut buffer = std::mem::take(&mut self.buffer);
-
-                        self.buffer = shape(
-                            current_font,
-                            current_fake_bold,
-                            current_fake_italic,
-                            shape_with_plan(
-                                current_font.font(),
-                                self.plan_cache.get(current_font, &mut buffer),
-                                buffer,
-                            ),
-                        );
+        let (width, height) = if self.integer_scaling {
+            whole_cell_size(
+                (width, height),
+                (inset_width, inset_height),
+                self.screen_cell_size(),
+            )
+        } else {
+            (width, height)
+        };
 
-                        current_font = font;
-                        current_fake_bold = fake_bold;
-                        current_fake_italic = fake_italic;
-                        current_level = level;
-                    }
+        if width == self.surface_config.width && height == self.surface_config.height {
+            return;
+        }
 
-                    self.buffer.add(ch, (range.start + idx) as u32);
-                }
-            }
+        let previous = self.size().unwrap();
 
-            let mut buffer = std::mem::take(&mut self.buffer);
-            self.buffer = shape(
-                current_font,
-                current_fake_bold,
-                current_fake_italic,
-                shape_with_plan(
-                    current_font.font(),
-                    self.plan_cache.get(current_font, &mut buffer),
-                    buffer,
-                ),
+        self.surface_config.width = width;
+        self.surface_config.height = height;
+        self.surface
+            .configure(&self.device, &self.surface_config, Token);
+
+        self.refit(previous);
+    }
+
+    /// Switch the surface to the given [`wgpu::PresentMode`], e.g. to toggle
+    /// vsync at runtime with [`PresentMode::AutoVsync`] and
+    /// [`PresentMode::AutoNoVsync`]. Those two are always accepted, since wgpu
+    /// picks a supported mode for them. Other modes the surface doesn't support
+    /// are ignored with a warning, which includes every other mode for surfaces
+    /// which don't belong to a window.
+    pub fn set_present_mode(&mut self, mode: PresentMode) {
+        let auto = matches!(mode, PresentMode::AutoVsync | PresentMode::AutoNoVsync);
+        if !auto && !self.present_modes.contains(&mode) {
+            warn!(
+                "Surface doesn't support the {mode:?} present mode, keeping {:?}",
+                self.surface_config.present_mode
             );
+            return;
+        }
 
-            for (new, old) in new_sourced.into_iter().zip(sourced.iter_mut()) {
-                if new != *old {
-                    for (x, y, glyph, width) in old.difference(&new) {
-                        let cell = ((*y).max(0) as usize / self.fonts.height_px() as usize)
-                            .min(bounds.height as usize - 1)
-                            * bounds.width as usize
-                            + ((*x).max(0) as usize / self.fonts.min_width_px() as usize)
-                                .min(bounds.width as usize - 1);
+        self.surface_config.present_mode = mode;
+        self.surface
+            .configure(&self.device, &self.surface_config, Token);
+    }
 
-                        for offset_x in 0..*width as usize {
-                            if cell >= self.dirty_cells.len() {
-                                break;
-                            }
+    /// Get information about the adapter the backend is rendering with,
+    /// including which graphics API it uses. This is useful to include in bug
+    /// reports. Returns `None` for backends built with
+    /// [`Builder::build_with_texture`](crate::Builder::build_with_texture),
+    /// since the adapter isn't known.
+    pub fn adapter_info(&self) -> Option<&AdapterInfo> {
+        self.adapter_info.as_ref()
+    }
 
-                            self.dirty_cells.set(cell + offset_x, true);
-                        }
+    /// Get the [`wgpu::PresentMode`] the surface is configured with.
+    pub fn present_mode(&self) -> PresentMode {
+        self.surface_config.present_mode
+    }
 
-                        self.rendered[cell].shift_remove(&(*x, *y, *glyph));
-                    }
-                    *old = new;
+    /// Rebuild the grid and the targets text is rendered into after the size
+    /// of the surface or of a cell changes. `previous` is the size of the grid
+    /// before the change.
+    fn refit(&mut self, previous: Size) {
+        let current_width = previous.width;
+        let current_height = previous.height;
+
+        let dims = self.size().unwrap();
+        let chars_wide = dims.width as u32;
+        let chars_high = dims.height as u32;
+
+        // This always needs to be cleared because the surface is cleared when it is
+        // resized. If we don't re-render the rows, we end up with a blank surface when
+        // the resize is less than a character dimension.
+        self.dirty_rows.clear();
+
+        if chars_wide != current_width as u32 || chars_high != current_height as u32 {
+            self.flushed_cells.clear();
+            match self.resize_policy {
+                ResizePolicy::Clear => {
+                    self.cells.clear();
+                    self.rendered.clear();
+                    self.sourced.clear();
+                    self.fast_blinking.clear();
+                    self.slow_blinking.clear();
+                    self.cell_opacity.clear();
+                    self.color_overrides.clear();
                 }
+                ResizePolicy::Reflow => self.reflow(
+                    (current_width as usize, current_height as usize),
+                    (chars_wide as usize, chars_high as usize),
+                ),
             }
         }
+        // Flushing before the next draw would otherwise read cells sized for the
+        // old grid.
+        self.fit_to_grid();
+
+        let text_width = chars_wide * self.fonts.cell_width_px();
+        let text_height = chars_high * self.fonts.cell_height_px();
+
+        // The over-render target can be kept as long as the grid still fits in it,
+        // in which case its contents are still valid.
+        let preserved = self.wgpu_state.overrender.take().filter(|(texture, _)| {
+            texture.width() >= text_width && texture.height() >= text_height
+        });
+        let preserved_contents = preserved.is_some();
+        let overrender = preserved.or_else(|| {
+            (self.overrender_margin != 0 || self.vertical_text).then(|| {
+                build_overrender_target(
+                    &self.device,
+                    text_width + self.overrender_margin as u32 * self.fonts.cell_width_px(),
+                    text_height + self.overrender_margin as u32 * self.fonts.cell_height_px(),
+                )
+            })
+        });
 
-        for (_, (cached, image, mask)) in pending_cache_updates {
-            self.queue.write_texture(
-                ImageCopyTexture {
-                    texture: &self.text_cache,
-                    mip_level: 0,
-                    origin: Origin3d {
-                        x: cached.x,
-                        y: cached.y,
-                        z: 0,
-                    },
-                    aspect: TextureAspect::All,
-                },
-                bytemuck::cast_slice(&image),
-                ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(cached.width * size_of::<u32>() as u32),
-                    rows_per_image: Some(cached.height),
-                },
-                Extent3d {
-                    width: cached.width,
-                    height: cached.height,
-                    depth_or_array_layers: 1,
-                },
+        let (dest_width, dest_height) = self.text_dest_size();
+        self.wgpu_state = build_wgpu_state(&self.device, dest_width, dest_height);
+        self.wgpu_state.overrender = overrender;
+        self.clear_padding = true;
+
+        self.post_process.resize(
+            &self.device,
+            &self.wgpu_state.text_dest_view,
+            &self.surface_config,
+        );
+
+        if let Some(blitter) = self.blitter.as_mut() {
+            blitter.resize(
+                &self.device,
+                &self.wgpu_state.text_dest_view,
+                &self.surface_config,
             );
+        }
 
-            self.queue.write_texture(
-                ImageCopyTexture {
-                    texture: &self.text_mask,
-                    mip_level: 0,
-                    origin: Origin3d {
-                        x: cached.x,
-                        y: cached.y,
-                        z: 0,
-                    },
-                    aspect: TextureAspect::All,
-                },
-                &vec![if mask { 255 } else { 0 }; (cached.width * cached.height) as usize],
-                ImageDataLayout {
-                    offset: 0,
-                    bytes_per_row: Some(cached.width),
-                    rows_per_image: Some(cached.height),
-                },
-                Extent3d {
-                    width: cached.width,
-                    height: cached.height,
-                    depth_or_array_layers: 1,
-                },
-            )
+        if preserved_contents {
+            // Present what was already rendered right away, so the surface isn't left
+            // blank until the next draw.
+            self.present();
         }
 
-        if self.post_process.needs_update() || self.dirty_cells.any() {
-            self.bg_vertices.clear();
-            self.text_vertices.clear();
-            self.text_indices.clear();
+        info!(
+            "Resized from {}x{} to {}x{}",
+            current_width, current_height, chars_wide, chars_high,
+        );
+    }
 
-            let mut index_offset = 0;
-            for index in self.dirty_cells.iter_ones() {
-                let cell = &self.cells[index];
-                let to_render = &self.rendered[index];
+    /// Get the text currently displayed on the screen.
+    pub fn get_text(&self) -> String {
+        let bounds = self.size().unwrap();
+        if bounds.width == 0 {
+            return String::new();
+        }
+        self.cells.chunks(bounds.width as usize).fold(
+            String::with_capacity((bounds.width + 1) as usize * bounds.height as usize),
+            |dest, row| {
+                let mut dest = row.iter().fold(dest, |mut dest, s| {
+                    dest.push_str(s.symbol());
+                    dest
+                });
+                dest.push('\n');
+                dest
+            },
+        )
+    }
 
-                let reverse = cell.modifier.contains(Modifier::REVERSED);
-                let bg_color = if reverse {
-                    c2c(cell.fg, self.reset_fg)
-                } else {
-                    c2c(cell.bg, self.reset_bg)
-                };
+    /// Get the cell at the given position, or `None` if it lies outside of the
+    /// screen. Unlike [`WgpuBackend::get_text`], this includes the styling of
+    /// the cell.
+    pub fn get_cell(&self, x: u16, y: u16) -> Option<&Cell> {
+        let bounds = self.size().unwrap();
+        if x >= bounds.width || y >= bounds.height {
+            return None;
+        }
 
-                let [r, g, b] = bg_color;
-                let bg_color_u32: u32 = u32::from_be_bytes([r, g, b, 255]);
+        self.cells
+            .get(y as usize * bounds.width as usize + x as usize)
+    }
 
-                let y = (index as u32 / bounds.width as u32 * self.fonts.height_px()) as f32;
-                let x = (index as u32 % bounds.width as u32 * self.fonts.min_width_px()) as f32;
-                for offset_x in 0..cell.symbol().width() {
-                    let x = x + (offset_x as u32 * self.fonts.min_width_px()) as f32;
-                    self.bg_vertices.push(TextBgVertexMember {
-                        vertex: [x, y],
-                        bg_color: bg_color_u32,
-                    });
-                    self.bg_vertices.push(TextBgVertexMember {
-                        vertex: [x + self.fonts.min_width_px() as f32, y],
-                        bg_color: bg_color_u32,
-                    });
-                    self.bg_vertices.push(TextBgVertexMember {
-                        vertex: [x, y + self.fonts.height_px() as f32],
-                        bg_color: bg_color_u32,
-                    });
-                    self.bg_vertices.push(TextBgVertexMember {
-                        vertex: [
-                            x + self.fonts.min_width_px() as f32,
-                            y + self.fonts.height_px() as f32,
-                        ],
-                        bg_color: bg_color_u32,
-                    });
-                }
+    /// Iterate over the cells in `rect` in row-major order, along with their x
+    /// and y positions. `rect` is clipped to the screen.
+    pub fn cells_in_rect(&self, rect: Rect) -> impl Iterator<Item = (u16, u16, &Cell)> + '_ {
+        let bounds = self.size().unwrap();
+        let rect = rect.intersection(Rect::new(0, 0, bounds.width, bounds.height));
+        let cells = &self.cells;
+        rect.positions().map(move |pos| {
+            let index = pos.y as usize * bounds.width as usize + pos.x as usize;
+            (pos.x, pos.y, &cells[index])
+        })
+    }
 
-                for (
-                    (x, y, _),
-                    RenderInfo {
-                        cell,
-                        cached,
-                        underline_pos_min,
-                        underline_pos_max,
-                    },
-                ) in to_render.iter()
-                {
-                    let cell = &self.cells[*cell];
-                    let reverse = cell.modifier.contains(Modifier::REVERSED);
-                    let fg_color = if reverse {
-                        c2c(cell.bg, self.reset_bg)
-                    } else {
-                        c2c(cell.fg, self.reset_fg)
-                    };
+    /// Read back the pixels for the cells in `area` as tightly packed RGBA8
+    /// rows, returning the pixel data along with its width and height. `area`
+    /// is clipped to the screen, so the returned dimensions may be smaller
+    /// than requested (or zero if `area` lies entirely outside of it).
+    ///
+    /// Pixels are read from the composited text before any [`PostProcessor`]
+    /// runs, so they reflect the most recent [`Backend::flush`] at the native
+    /// cell resolution rather than the scaled surface.
+    pub fn capture_region(&mut self, area: Rect) -> Result<(Vec<u8>, u32, u32)> {
+        self.read_text_region(area)
+    }
 
-                    let alpha = if cell.modifier.contains(Modifier::HIDDEN)
-                        | (cell.modifier.contains(Modifier::RAPID_BLINK) & !self.show_fast)
-                        | (cell.modifier.contains(Modifier::SLOW_BLINK) & !self.show_slow)
-                    {
-                        0
-                    } else if cell.modifier.contains(Modifier::DIM) {
-                        127
-                    } else {
-                        255
-                    };
+    /// Save the most recently rendered frame as a PNG image at `path`. For
+    /// backends built with
+    /// [`Builder::build_headless`](crate::Builder::build_headless), this is
+    /// the final output after post processing at the size of the
+    /// surface. Other surfaces can't be read back, so the composited text is
+    /// saved instead, as with [`WgpuBackend::capture_region`].
+    ///
+    /// This blocks until the GPU has finished copying the frame, by polling
+    /// the device.
+    #[cfg(feature = "png")]
+    pub fn save_png(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        let (pixels, width, height) = match self.surface.headless(Token) {
+            Some(surface) => (self.read_headless(surface), surface.width, surface.height),
+            None => self
+                .read_text_region(Rect::new(0, 0, u16::MAX, u16::MAX))
+                .map_err(std::io::Error::other)?,
+        };
 
-                    let underline_color = fg_color;
-                    let [r, g, b] = fg_color;
-                    let fg_color: u32 = u32::from_be_bytes([r, g, b, alpha]);
+        let file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let mut encoder = png::Encoder::new(file, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.write_header()?.write_image_data(&pixels)?;
 
-                    let [r, g, b] = underline_color;
-                    let underline_color = u32::from_be_bytes([r, g, b, alpha]);
+        Ok(())
+    }
 
-                    for offset_x in (0..cached.width).step_by(self.fonts.min_width_px() as usize) {
-                        self.text_indices.push([
-                            index_offset,     // x, y
-                            index_offset + 1, // x + w, y
-                            index_offset + 2, // x, y + h
-                            index_offset + 2, // x, y + h
-                            index_offset + 3, // x + w, y + h
-                            index_offset + 1, // x + w y
-                        ]);
-                        index_offset += 4;
+    fn read_text_region(&self, area: Rect) -> Result<(Vec<u8>, u32, u32)> {
+        let bounds = self.size().unwrap();
+        let area = area.intersection(Rect::new(0, 0, bounds.width, bounds.height));
 
-                        let x = *x as f32 + offset_x as f32;
-                        let y = *y as f32;
-                        let uvx = cached.x + offset_x;
-                        let uvy = cached.y;
+        let width = area.width as u32 * self.fonts.cell_width_px();
+        let height = area.height as u32 * self.fonts.cell_height_px();
+        if width == 0 || height == 0 {
+            return Ok((vec![], 0, 0));
+        }
 
-                        let underline_pos = (*underline_pos_min as u32 + uvy) << 16
-                            | (*underline_pos_max as u32 + uvy);
+        // Vertical text is rendered horizontally before being laid out on the
+        // surface, so read it back from that layout.
+        let (texture, (offset_x, offset_y)) = match &self.wgpu_state.overrender {
+            Some((overrender, _)) if self.vertical_text => (overrender, (0, 0)),
+            _ => (&self.wgpu_state.text_dest, self.grid_offset()),
+        };
+        let tight_bytes_per_row = width * 4;
+        let bytes_per_row = tight_bytes_per_row.div_ceil(COPY_BYTES_PER_ROW_ALIGNMENT)
+            * COPY_BYTES_PER_ROW_ALIGNMENT;
 
-                        // 0
-                        self.text_vertices.push(TextVertexMember {
-                            vertex: [x, y],
-                            uv: [uvx as f32, uvy as f32],
-                            fg_color,
-                            underline_pos,
-                            underline_color,
-                        });
-                        // 1
-                        self.text_vertices.push(TextVertexMember {
-                            vertex: [x + self.fonts.min_width_px() as f32, y],
-                            uv: [uvx as f32 + self.fonts.min_width_px() as f32, uvy as f32],
-                            fg_color,
-                            underline_pos,
-                            underline_color,
-                        });
-                        // 2
-                        self.text_vertices.push(TextVertexMember {
-                            vertex: [x, y + self.fonts.height_px() as f32],
-                            uv: [uvx as f32, uvy as f32 + self.fonts.height_px() as f32],
-                            fg_color,
-                            underline_pos,
-                            underline_color,
-                        });
-                        // 3
-                        self.text_vertices.push(TextVertexMember {
-                            vertex: [
-                                x + self.fonts.min_width_px() as f32,
-                                y + self.fonts.height_px() as f32,
-                            ],
-                            uv: [
-                                uvx as f32 + self.fonts.min_width_px() as f32,
-                                uvy as f32 + self.fonts.height_px() as f32,
-                            ],
-                            fg_color,
-                            underline_pos,
-                            underline_color,
-                        });
-                    }
-                }
-            }
+        let staging = self.device.create_buffer(&BufferDescriptor {
+            label: Some("Capture Region Staging Buffer"),
+            size: (bytes_per_row * height) as u64,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
 
-            self.render();
-        }
-
-        Ok(())
-    }
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Capture Region Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: offset_x + area.x as u32 * self.fonts.cell_width_px(),
+                    y: offset_y + area.y as u32 * self.fonts.cell_height_px(),
+                    z: 0,
+                },
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &staging,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(height),
+                },
+            },
+            Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
 
-    fn clear_region(&mut self, clear_type: ClearType) -> std::io::Result<()> {
-        let bounds = self.size()?;
-        let line_start = self.cursor.1 as usize * bounds.width as usize;
-        let idx = line_start + self.cursor.0 as usize;
+        let slice = staging.slice(..);
+        let (send, recv) = mpsc::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = send.send(result);
+        });
+        self.device.poll(Maintain::Wait);
+        recv.recv()
+            .expect("Map callback dropped")
+            .map_err(Error::BufferMapFailed)?;
+
+        let pixels = {
+            let data = slice.get_mapped_range();
+            data.chunks(bytes_per_row as usize)
+                .flat_map(|row| &row[..tight_bytes_per_row as usize])
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        staging.unmap();
 
-        match clear_type {
-            ClearType::All => self.clear(),
-            ClearType::AfterCursor => {
-                self.cells.truncate(idx + 1);
-                Ok(())
-            }
-            ClearType::BeforeCursor => {
-                self.cells[..idx].fill(Cell::EMPTY);
-                Ok(())
-            }
-            ClearType::CurrentLine => {
-                self.cells[line_start..line_start + bounds.width as usize].fill(Cell::EMPTY);
-                Ok(())
-            }
-            ClearType::UntilNewLine => {
-                let remain = (bounds.width - self.cursor.0) as usize;
-                self.cells[idx..idx + remain].fill(Cell::EMPTY);
-                Ok(())
-            }
-        }
+        Ok((pixels, width, height))
     }
-}
-
-fn rasterize_glyph(
-    cached: Entry,
-    metrics: &rustybuzz::Face,
-    info: &rustybuzz::GlyphInfo,
-    fake_italic: bool,
-    fake_bold: bool,
-    advance_scale: f32,
-    actual_width: u32,
-) -> (CacheRect, Vec<u32>) {
-    let scale = cached.width as f32 / actual_width as f32;
-    let computed_offset_x = -(cached.width as f32 * (1.0 - scale));
-    let computed_offset_y = cached.height as f32 * (1.0 - scale);
-    let scale = scale * advance_scale * 2.0;
-
-    let skew = if fake_italic {
-        Transform::new(
-            /* scale x */ 1.0,
-            /* skew x */ 0.0,
-            /* skew y */ -0.25,
-            /* scale y */ 1.0,
-            /* translate x */ -0.25 * cached.width as f32,
-            /* translate y */ 0.0,
-        )
-    } else {
-        Transform::default()
-    };
 
-    let mut image = vec![0u32; cached.width as usize * 2 * cached.height as usize * 2];
-    let mut target = DrawTarget::from_backing(
-        cached.width as i32 * 2,
-        cached.height as i32 * 2,
-        &mut image[..],
-    );
+    fn read_headless(&self, surface: &HeadlessSurface) -> Vec<u8> {
+        let (Some(texture), Some(buffer)) = (&surface.texture, &surface.buffer) else {
+            return vec![];
+        };
 
-    let mut painter = Painter::new(
-        metrics,
-        &mut target,
-        skew,
-        scale,
-        metrics.ascender() as f32 * scale + computed_offset_y,
-        computed_offset_x,
-    );
-    if metrics
-        .paint_color_glyph(
-            GlyphId(info.glyph_id as _),
-            0,
-            RgbaColor::new(255, 255, 255, 255),
-            &mut painter,
-        )
-        .is_some()
-    {
-        let mut final_image = DrawTarget::new(cached.width as i32, cached.height as i32);
-        final_image.draw_image_with_size_at(
-            cached.width as f32,
-            cached.height as f32,
-            0.,
-            0.,
-            &raqote::Image {
-                width: cached.width as i32 * 2,
-                height: cached.height as i32 * 2,
-                data: &image,
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Read RGBA Encoder"),
+            });
+        encoder.copy_texture_to_buffer(
+            texture.as_image_copy(),
+            ImageCopyBuffer {
+                buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(surface.buffer_width),
+                    rows_per_image: Some(surface.height),
+                },
             },
-            &DrawOptions {
-                blend_mode: raqote::BlendMode::Src,
-                antialias: raqote::AntialiasMode::None,
-                ..Default::default()
+            Extent3d {
+                width: surface.width,
+                height: surface.height,
+                depth_or_array_layers: 1,
             },
         );
+        self.queue.submit(Some(encoder.finish()));
 
-        let mut final_image = final_image.into_vec();
-        for argb in final_image.iter_mut() {
-            let [a, r, g, b] = argb.to_be_bytes();
-            *argb = u32::from_le_bytes([r, g, b, a]);
-        }
+        let slice = buffer.slice(..);
+        let (send, recv) = mpsc::channel();
+        slice.map_async(MapMode::Read, move |result| {
+            let _ = send.send(result);
+        });
+        self.device.poll(Maintain::Wait);
+        recv.recv()
+            .expect("Map callback dropped")
+            .expect("Failed to map surface buffer");
+
+        let tight_bytes_per_row = surface.width as usize * 4;
+        let pixels = {
+            let data = slice.get_mapped_range();
+            data.chunks(surface.buffer_width as usize)
+                .flat_map(|row| &row[..tight_bytes_per_row])
+                .copied()
+                .collect::<Vec<_>>()
+        };
+        buffer.unmap();
 
-        return (*cached, final_image);
+        pixels
     }
 
-    if let Some(raster) = metrics.glyph_raster_image(GlyphId(info.glyph_id as _), u16::MAX) {
-        if let Some(value) = extract_color_image(&mut image, raster, cached, advance_scale) {
-            return value;
-        }
+    /// Get the positions of the cells which were re-rendered by the most recent
+    /// [`Backend::flush`], in row-major order. Rows are shaped as a unit, so
+    /// drawing to one cell may cause other cells in its row to be re-rendered
+    /// as well. Cells which are drawn but not yet flushed (including flushes
+    /// deferred by [`Self::begin_batch`]) are not included until they are
+    /// rendered.
+    ///
+    /// This is empty after a resize which changes the size of the grid, until
+    /// the next flush.
+    pub fn dirty_cells_since_last_flush(&self) -> impl Iterator<Item = Position> + '_ {
+        let width = self.size().unwrap().width as usize;
+        self.flushed_cells
+            .iter_ones()
+            .map(move |index| Position::new((index % width) as u16, (index / width) as u16))
     }
 
-    let mut render = Outline::default();
-    if let Some(bounds) = metrics.outline_glyph(GlyphId(info.glyph_id as _), &mut render) {
-        let path = render.finish();
+    /// Take the pixel rectangle which has changed since the last call, or
+    /// `None` if nothing has been rendered since then. This is the union of
+    /// the cells repainted by each [`Backend::flush`], or the whole text
+    /// area when the post processor requested an update. Embedders can use
+    /// this to limit the region they present.
+    ///
+    /// The rectangle is in pixels of the composited text, which match the
+    /// pixels of the surface unless the grid is being stretched over it.
+    pub fn take_damage(&mut self) -> Option<Rect> {
+        self.damage.take()
+    }
 
-        // Some fonts return bounds that are entirely negative. I'm not sure why this
-        // is, but it means the glyph won't render at all. We check for this here and
-        // offset it if so. This seems to let those fonts render correctly.
-        let x_off = if bounds.x_max < 0 {
-            -bounds.x_min as f32
-        } else {
-            0.
-        };
-        let x_off = x_off * scale + computed_offset_x;
-        let y_off = metrics.ascender() as f32 * scale + computed_offset_y;
+    /// Run post processing over the text which was last rendered and present
+    /// the result, without laying out any cells. This lets animated post
+    /// processors advance while nothing is being drawn, e.g. by calling this
+    /// from an idle event loop. Does nothing while rendering is paused.
+    pub fn present(&mut self) {
+        if self.paused {
+            return;
+        }
 
-        let mut target = DrawTarget::from_backing(
-            cached.width as i32 * 2,
-            cached.height as i32 * 2,
-            &mut image[..],
-        );
-        target.set_transform(
-            &Transform::scale(scale, -scale)
-                .then(&skew)
-                .then_translate((x_off, y_off).into()),
-        );
+        self.bg_vertices.clear();
+        self.text_vertices.clear();
+        self.text_indices.clear();
+        self.overlay_quads = 0;
+        self.cursor_quads = 0;
+        self.render();
+    }
 
-        target.fill(
-            &path,
-            &raqote::Source::Solid(SolidSource::from_unpremultiplied_argb(255, 255, 255, 255)),
-            &DrawOptions::default(),
-        );
+    /// Check whether the most recent [`Backend::flush`] rendered a new frame to
+    /// the surface. This is false when nothing changed and the post processor
+    /// didn't request an update, or when rendering is paused or deferred by a
+    /// batch. Event loops can use this to skip requesting a redraw.
+    pub fn needs_present(&self) -> bool {
+        self.needs_present
+    }
 
-        if fake_bold {
-            target.stroke(
-                &path,
-                &raqote::Source::Solid(SolidSource::from_unpremultiplied_argb(255, 255, 255, 255)),
-                &StrokeStyle {
-                    width: 1.5,
-                    ..Default::default()
-                },
-                &DrawOptions::new(),
-            );
-        }
+    /// Get every character which none of the fonts could render since the
+    /// backend was built or the fonts were last updated. These are drawn with
+    /// the missing glyph of the font which was tried last, or the placeholder
+    /// from [`Builder::with_missing_glyph`](crate::Builder::with_missing_glyph).
+    pub fn missing_glyphs(&self) -> &HashSet<char> {
+        &self.missing_glyphs
+    }
 
-        let mut final_image = DrawTarget::new(cached.width as i32, cached.height as i32);
-        final_image.draw_image_with_size_at(
-            cached.width as f32,
-            cached.height as f32,
-            0.,
-            0.,
-            &raqote::Image {
-                width: cached.width as i32 * 2,
-                height: cached.height as i32 * 2,
-                data: &image,
-            },
-            &DrawOptions {
-                blend_mode: raqote::BlendMode::Src,
-                antialias: raqote::AntialiasMode::None,
-                ..Default::default()
-            },
-        );
+    /// Get statistics about the most recently rendered frame. Flushes which
+    /// don't need to redraw anything leave these unchanged.
+    pub fn last_frame_stats(&self) -> FrameStats {
+        self.last_frame_stats
+    }
 
-        return (*cached, final_image.into_vec());
+    /// Get the size of a single cell in pixels. This is the width of the
+    /// narrowest font and the height of the tallest font in the font stack.
+    pub fn cell_size(&self) -> Size {
+        Size {
+            width: self.fonts.cell_width_px() as u16,
+            height: self.fonts.cell_height_px() as u16,
+        }
     }
 
-    if let Some(raster) = metrics.glyph_raster_image(GlyphId(info.glyph_id as _), u16::MAX) {
-        if let Some(value) = extract_bw_image(&mut image, raster, cached, advance_scale) {
-            return value;
+    /// Get the size in pixels of the area covered by whole cells. This is the
+    /// number of columns and rows multiplied by [`WgpuBackend::cell_size`], so
+    /// it differs from the drawable area of the surface when that isn't an
+    /// exact multiple of the cell size. See [`Rounding`].
+    pub fn grid_size_px(&self) -> Size {
+        let (width, height) = self.grid_pixels();
+        Size {
+            width: width as u16,
+            height: height as u16,
         }
     }
 
-    (
-        *cached,
-        vec![0u32; cached.width as usize * cached.height as usize],
-    )
-}
+    /// Get the number of cells the renderer will use for a glyph for `c`. This
+    /// matches the width used during [`Backend::flush`], which differs from
+    /// [`UnicodeWidthChar::width`] for zero-width and control characters (they
+    /// are always given at least one cell).
+    pub fn char_cell_width(&self, c: char) -> u8 {
+        glyph_cell_width(c, 1) as u8
+    }
 
-fn extract_color_image(
-    image: &mut Vec<u32>,
-    raster: RasterGlyphImage,
-    cached: Entry,
-    scale: f32,
-) -> Option<(CacheRect, Vec<u32>)> {
-    match raster.format {
-        RasterImageFormat::PNG => {
-            #[cfg(feature = "png")]
-            {
-                let decoder = png::Decoder::new(std::io::Cursor::new(raster.data));
-                if let Ok(mut info) = decoder.read_info() {
-                    image.resize(info.output_buffer_size() / size_of::<u32>(), 0);
-                    if info.next_frame(bytemuck::cast_slice_mut(image)).is_err() {
-                        return None;
-                    }
+    /// Export the current contents of the screen as an SVG document. Cell
+    /// backgrounds are emitted as `<rect>` elements and cell contents as
+    /// `<text>` elements using the family name of the last-resort font.
+    ///
+    /// This is computed entirely on the cpu from the current cells, so it does
+    /// not include the effects of any [`PostProcessor`] and the final glyphs
+    /// are chosen by whatever renders the SVG.
+    pub fn to_svg(&self) -> String {
+        let bounds = self.size().unwrap();
+        if bounds.width == 0 {
+            return "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"0\" height=\"0\" \
+                    viewBox=\"0 0 0 0\">\n</svg>\n"
+                .to_string();
+        }
+        let cell_width = self.fonts.cell_width_px();
+        let cell_height = self.fonts.cell_height_px();
+        let font_height = self.fonts.height_px();
+        let width = bounds.width as u32 * cell_width;
+        let height = bounds.height as u32 * cell_height;
+
+        let last_resort = self.fonts.last_resort();
+        let metrics = last_resort.font();
+        let baseline = self.fonts.line_padding_top() as f32
+            + metrics.ascender() as f32 * font_height as f32 / metrics.height() as f32;
+        let family = last_resort
+            .family_name()
+            .unwrap_or_else(|| "monospace".to_string());
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+             viewBox=\"0 0 {width} {height}\">\n"
+        );
 
-                    for rgba in image.iter_mut() {
-                        let [r, g, b, a] = rgba.to_be_bytes();
-                        *rgba = u32::from_be_bytes([a, r, g, b]);
-                    }
-                } else {
-                    return None;
+        let mut row = String::new();
+        let mut rowmap = vec![];
+        let mut visual = vec![];
+        for (y, cells) in self.cells.chunks(bounds.width as usize).enumerate() {
+            row.clear();
+            rowmap.clear();
+            visual.clear();
+            // Cells covered by a preceding wide character are part of that character's
+            // area, so they're left out rather than drawn over it.
+            let mut covered = 0usize;
+            for (idx, cell) in cells.iter().enumerate() {
+                if covered > 0 || cell.symbol().is_empty() {
+                    covered = covered.saturating_sub(1);
+                    continue;
                 }
+                covered = cell_columns(cell) - 1;
+                row.push_str(cell.symbol());
+                rowmap.resize(rowmap.len() + cell.symbol().len(), idx);
             }
-            #[cfg(not(feature = "png"))]
-            return None;
-        }
-        RasterImageFormat::BitmapPremulBgra32 => {
-            image.resize(raster.width as usize * raster.height as usize, 0);
-            for (y, row) in raster.data.chunks(raster.width as usize * 4).enumerate() {
-                for (x, pixel) in row.chunks(4).enumerate() {
-                    let pixel: &[u8; 4] = pixel.try_into().expect("Invalid chunk size");
-                    let [b, g, r, a] = *pixel;
-                    let pixel = u32::from_be_bytes([
-                        a,
-                        r.saturating_mul(255 / a),
-                        g.saturating_mul(255 / a),
-                        b.saturating_mul(255 / a),
-                    ]);
-                    image[y * raster.width as usize + x] = pixel;
+
+            // Lay cells out in the same visual order that flush uses for rendering.
+            let (levels, runs) = if self.bidi {
+                let bidi = ParagraphBidiInfo::new(&row, None);
+                bidi.visual_runs(0..bidi.levels.len())
+            } else {
+                (
+                    vec![Level::ltr(); row.len()],
+                    std::iter::once(0..row.len()).collect(),
+                )
+            };
+            for run in runs {
+                let start = visual.len();
+                for &idx in &rowmap[run.clone()] {
+                    if visual[start..].last() != Some(&idx) {
+                        visual.push(idx);
+                    }
                 }
+                if levels[run.start].is_rtl() {
+                    visual[start..].reverse();
+                }
+            }
+
+            let mut x = 0;
+            for idx in visual.iter().copied() {
+                let cell = &cells[idx];
+                let cell_px = (cell_columns(cell) as u32 * cell_width).min(width - x);
+                let reverse = cell.modifier.contains(Modifier::REVERSED);
+                let (fg, bg) = if reverse {
+                    (
+                        c2c(cell.bg, self.reset_bg, &self.palette),
+                        c2c(cell.fg, self.reset_fg, &self.palette),
+                    )
+                } else {
+                    (
+                        c2c(cell.fg, self.reset_fg, &self.palette),
+                        c2c(cell.bg, self.reset_bg, &self.palette),
+                    )
+                };
+
+                let [r, g, b] = bg;
+                let top = y as u32 * cell_height;
+                svg.push_str(&format!(
+                    "<rect x=\"{x}\" y=\"{top}\" width=\"{cell_px}\" height=\"{cell_height}\" \
+                     fill=\"#{r:02x}{g:02x}{b:02x}\"/>\n"
+                ));
+
+                if !cell.symbol().trim().is_empty() && !cell.modifier.contains(Modifier::HIDDEN) {
+                    let dim = cell.modifier.contains(Modifier::DIM);
+                    let [r, g, b] = if dim && self.dim_mode == DimMode::Blend {
+                        blend_halfway(fg, bg)
+                    } else {
+                        fg
+                    };
+                    let mut attributes = String::new();
+                    if cell.modifier.contains(Modifier::BOLD) {
+                        attributes.push_str(" font-weight=\"bold\"");
+                    }
+                    if cell.modifier.contains(Modifier::ITALIC) {
+                        attributes.push_str(" font-style=\"italic\"");
+                    }
+                    match (
+                        cell.modifier.contains(Modifier::UNDERLINED),
+                        cell.modifier.contains(Modifier::CROSSED_OUT),
+                    ) {
+                        (true, true) => {
+                            attributes.push_str(" text-decoration=\"underline line-through\"")
+                        }
+                        (true, false) => attributes.push_str(" text-decoration=\"underline\""),
+                        (false, true) => attributes.push_str(" text-decoration=\"line-through\""),
+                        (false, false) => {}
+                    }
+                    if dim && self.dim_mode == DimMode::Alpha {
+                        attributes.push_str(" fill-opacity=\"0.5\"");
+                    }
+
+                    svg.push_str(&format!(
+                        "<text x=\"{}\" y=\"{}\" font-family=\"{}\" font-size=\"{font_height}\" \
+                         fill=\"#{r:02x}{g:02x}{b:02x}\"{attributes} xml:space=\"preserve\">{}</text>\n",
+                        x + cell.symbol().width() as u32 * self.fonts.width_padding_px() / 2,
+                        top as f32 + baseline,
+                        escape_xml(&family),
+                        escape_xml(cell.symbol()),
+                    ));
+                }
+
+                x += cell_px;
             }
         }
-        _ => return None,
+
+        svg.push_str("</svg>\n");
+        svg
     }
 
-    let mut final_image = DrawTarget::new(cached.width as i32, cached.height as i32);
-    final_image.draw_image_with_size_at(
-        cached.width as f32,
-        cached.height as f32,
-        raster.x as f32 * scale,
-        raster.y as f32 * scale,
-        &raqote::Image {
-            width: raster.width as i32,
-            height: raster.height as i32,
-            data: &*image,
-        },
-        &DrawOptions {
-            blend_mode: raqote::BlendMode::Src,
-            antialias: raqote::AntialiasMode::None,
-            ..Default::default()
-        },
-    );
+    /// Update the fonts used for rendering. This will cause a full repaint of
+    /// the screen the next time [`WgpuBackend::flush`] is called. Any line
+    /// height or cell padding set with [`crate::Builder::with_line_height_px`]
+    /// or [`crate::Builder::with_cell_width_padding_px`] is kept. The size of
+    /// `new_fonts` is multiplied by the scale factor, like the font size given
+    /// to the builder.
+    pub fn update_fonts(&mut self, mut new_fonts: Fonts<'f>) {
+        self.logical_metrics.height = new_fonts.height_px();
+        self.logical_metrics
+            .apply(&mut new_fonts, self.scale_factor);
+        self.missing_glyphs.clear();
+        self.dirty_rows.clear();
+        self.cached.match_fonts(&new_fonts);
+        self.plan_cache.resize(new_fonts.count().max(2));
+        self.fonts = new_fonts;
+    }
 
-    let mut final_image = final_image.into_vec();
-    for argb in final_image.iter_mut() {
-        let [a, r, g, b] = argb.to_be_bytes();
-        *argb = u32::from_le_bytes([r, g, b, a]);
+    /// Change the factor the font size, line height, and cell padding are
+    /// multiplied by, e.g. when winit reports that the window's scale factor
+    /// changed. See
+    /// [`Builder::with_scale_factor`](crate::Builder::with_scale_factor).
+    ///
+    /// This changes the size of a cell, so the glyph cache is cleared and the
+    /// grid is resized to fit the surface, following the
+    /// [`ResizePolicy`]. Every cell is repainted on the next flush.
+    /// Non-positive values are ignored.
+    pub fn set_scale_factor(&mut self, scale: f32) {
+        if !(scale > 0.0 && scale.is_finite()) || scale == self.scale_factor {
+            return;
+        }
+
+        let previous = self.size().unwrap();
+        self.scale_factor = scale;
+        self.logical_metrics.apply(&mut self.fonts, scale);
+        self.missing_glyphs.clear();
+        self.cached.match_fonts(&self.fonts);
+        self.invalidate();
+
+        // Anything rendered at the previous scale is the wrong size to present.
+        self.wgpu_state.overrender = None;
+        self.refit(previous);
     }
 
-    Some((*cached, final_image))
-}
+    /// Get the current scale factor. See [`WgpuBackend::set_scale_factor`].
+    pub fn scale_factor(&self) -> f32 {
+        self.scale_factor
+    }
 
-fn extract_bw_image(
-    image: &mut Vec<u32>,
-    raster: RasterGlyphImage,
-    cached: Entry,
-    scale: f32,
-) -> Option<(CacheRect, Vec<u32>)> {
-    image.resize(raster.width as usize * raster.height as usize, 0);
+    /// Get the fraction of the glyph atlas which has been filled, between 0
+    /// and 1. Once the atlas is full, glyphs are evicted to make room for new
+    /// ones, and frames which need more glyphs than the atlas can hold are
+    /// rendered in several passes. Apps which churn through many glyphs can
+    /// use this to decide when to call [`WgpuBackend::clear_glyph_cache`].
+    pub fn atlas_usage(&self) -> f32 {
+        self.cached.usage()
+    }
 
-    match raster.format {
-        RasterImageFormat::BitmapMono => {
-            from_gray_unpacked::<1, 2>(image, raster, LUT_1);
+    /// Drop every glyph from the atlas, freeing all of its space. Every cell is
+    /// shaped and rasterized again on the next flush.
+    pub fn clear_glyph_cache(&mut self) {
+        self.cached.clear();
+        self.invalidate();
+    }
+
+    /// Drop all shaped glyphs and mark every row dirty so that the next call to
+    /// flush fully repaints the screen, without changing the contents of any
+    /// cells. This is useful after changing state which affects how every
+    /// cell is drawn, or when the contents of the surface may have been lost,
+    /// e.g. after the window was occluded.
+    pub fn invalidate(&mut self) {
+        self.rendered.iter_mut().for_each(Rendered::clear);
+        self.sourced.iter_mut().for_each(Sourced::clear);
+        self.dirty_rows.fill(true);
+    }
+
+    /// Grow or shrink the per-cell state to the size of the grid, filling new
+    /// cells with empty ones, and keep the cursor inside of the grid.
+    pub(super) fn fit_to_grid(&mut self) {
+        let bounds = self.size().unwrap();
+        let len = bounds.height as usize * bounds.width as usize;
+
+        self.cells.resize(len, Cell::EMPTY);
+        self.sourced
+            .resize_with(len, || Sourced::with_hasher(self.hasher.clone()));
+        self.rendered
+            .resize_with(len, || Rendered::with_hasher(self.hasher.clone()));
+        self.fast_blinking.resize(len, false);
+        self.slow_blinking.resize(len, false);
+        self.dirty_rows.resize(bounds.height as usize, true);
+        self.cursor = (
+            self.cursor.0.min(bounds.width.saturating_sub(1)),
+            self.cursor.1.min(bounds.height.saturating_sub(1)),
+        );
+    }
+
+    fn reflow(&mut self, (old_width, old_height): (usize, usize), (width, height): (usize, usize)) {
+        if self.cells.len() != old_width * old_height {
+            // Nothing has been drawn at the old size, so there's nothing to keep.
+            self.cells.clear();
+            self.rendered.clear();
+            self.sourced.clear();
+            self.fast_blinking.clear();
+            self.slow_blinking.clear();
+            self.cell_opacity.clear();
+            self.color_overrides.clear();
+            return;
         }
-        RasterImageFormat::BitmapMonoPacked => {
-            from_gray_packed::<1, 2>(image, raster, LUT_1);
+
+        let mut cells = vec![Cell::EMPTY; width * height];
+        let mut fast_blinking = BitVec::repeat(false, width * height);
+        let mut slow_blinking = BitVec::repeat(false, width * height);
+        let mut cell_opacity = HashMap::default();
+        let mut color_overrides = HashMap::default();
+
+        for y in 0..old_height.min(height) {
+            for x in 0..old_width.min(width) {
+                let old = y * old_width + x;
+                let new = y * width + x;
+                cells[new] = std::mem::take(&mut self.cells[old]);
+                fast_blinking.set(new, self.fast_blinking[old]);
+                slow_blinking.set(new, self.slow_blinking[old]);
+                if let Some(opacity) = self.cell_opacity.get(&old) {
+                    cell_opacity.insert(new, *opacity);
+                }
+                if let Some(colors) = self.color_overrides.get(&old) {
+                    color_overrides.insert(new, *colors);
+                }
+            }
         }
-        RasterImageFormat::BitmapGray2 => {
-            from_gray_unpacked::<2, 4>(image, raster, LUT_2);
+
+        self.cells = cells;
+        self.fast_blinking = fast_blinking;
+        self.slow_blinking = slow_blinking;
+        self.cell_opacity = cell_opacity;
+        self.color_overrides = color_overrides;
+
+        self.rendered.clear();
+        self.rendered.resize_with(width * height, || {
+            Rendered::with_hasher(self.hasher.clone())
+        });
+        self.sourced.clear();
+        self.sourced
+            .resize_with(width * height, || Sourced::with_hasher(self.hasher.clone()));
+        self.dirty_rows.resize(height, true);
+    }
+
+    /// Compute the focus ring clipped to the grid. Returns the clipped area,
+    /// the number of columns and rows along its edges which the ring
+    /// covers, the pixel bounds of the ring's top, bottom, left, and right
+    /// edges, and its color.
+    #[allow(clippy::type_complexity)]
+    fn focus_ring_quads(&self) -> Option<(Rect, u16, u16, [[f32; 4]; 4], u32)> {
+        let (area, color, thickness) = self.focus_ring?;
+        let bounds = self.size().unwrap();
+        let area = area.intersection(Rect::new(0, 0, bounds.width, bounds.height));
+        if area.is_empty() || thickness == 0 {
+            return None;
         }
-        RasterImageFormat::BitmapGray2Packed => {
-            from_gray_packed::<2, 4>(image, raster, LUT_2);
+
+        let cell_width = self.fonts.cell_width_px();
+        let cell_height = self.fonts.cell_height_px();
+        let x0 = area.x as u32 * cell_width;
+        let y0 = area.y as u32 * cell_height;
+        let x1 = area.right() as u32 * cell_width;
+        let y1 = area.bottom() as u32 * cell_height;
+        let thickness_x = thickness.min((x1 - x0) / 2);
+        let thickness_y = thickness.min((y1 - y0) / 2);
+
+        let [x0, y0, x1, y1, tx, ty] =
+            [x0, y0, x1, y1, thickness_x, thickness_y].map(|value| value as f32);
+
+        Some((
+            area,
+            thickness_x.div_ceil(cell_width) as u16,
+            thickness_y.div_ceil(cell_height) as u16,
+            [
+                [x0, y0, x1, y0 + ty],
+                [x0, y1 - ty, x1, y1],
+                [x0, y0 + ty, x0 + tx, y1 - ty],
+                [x1 - tx, y0 + ty, x1, y1 - ty],
+            ],
+            u32::from_be_bytes([color.r, color.g, color.b, color.a]),
+        ))
+    }
+
+    /// Build the quads which place the slots of dirty cells for vertical text,
+    /// returning the area of the text destination they cover. Each row of the
+    /// grid becomes a column of the surface, from right to left, and its cells
+    /// are stacked from top to bottom one slot each. Narrow glyphs are centered
+    /// across their slot, and wide glyphs are centered down the two slots of
+    /// the cells they cover.
+    fn layout_vertical(&mut self, bounds: Size) -> Option<Rect> {
+        self.vertical_vertices.clear();
+        let (overrender, _) = self.wgpu_state.overrender.as_ref()?;
+        let [texture_width, texture_height] =
+            [overrender.width() as f32, overrender.height() as f32];
+
+        let (offset_x, offset_y) = self.grid_offset();
+        let (cell_width, cell_height) = (self.fonts.cell_width_px(), self.fonts.cell_height_px());
+        let mut quad = |[x0, y0, x1, y1]: [u32; 4], [u0, v0, u1, v1]: [f32; 4]| {
+            let [x0, y0, x1, y1] = [x0, y0, x1, y1].map(|px| px as f32);
+            let [u0, u1] = [u0, u1].map(|u| u / texture_width);
+            let [v0, v1] = [v0, v1].map(|v| v / texture_height);
+            self.vertical_vertices.extend(
+                [
+                    ([x0, y0], [u0, v0]),
+                    ([x1, y0], [u1, v0]),
+                    ([x0, y1], [u0, v1]),
+                    ([x0, y1], [u0, v1]),
+                    ([x1, y0], [u1, v0]),
+                    ([x1, y1], [u1, v1]),
+                ]
+                .map(|(vertex, uv)| ImageVertexMember { vertex, uv }),
+            );
+        };
+
+        let mut damage = None::<Rect>;
+        for y in 0..bounds.height {
+            let slot_x = (bounds.height - 1 - y) as u32 * cell_width * 2;
+            let src_y = y as u32 * cell_height;
+            let mut x = 0;
+            while x < bounds.width {
+                let index = y as usize * bounds.width as usize + x as usize;
+                let columns = cell_columns(&self.cells[index])
+                    .clamp(1, 2)
+                    .min((bounds.width - x) as usize);
+                let dirty = self.dirty_cells[index..index + columns].any();
+                let (slot_y, src_x) = (x as u32 * cell_height, x as u32 * cell_width);
+                x += columns as u16;
+                if !dirty {
+                    continue;
+                }
+
+                // Fill the slots with the background at the corner of the cell, then
+                // place the glyph in the middle of them.
+                let slots = [
+                    slot_x,
+                    slot_y,
+                    slot_x + cell_width * 2,
+                    slot_y + columns as u32 * cell_height,
+                ];
+                let [u, v] = [src_x as f32 + 0.5, src_y as f32 + 0.5];
+                quad(slots, [u, v, u, v]);
+
+                let glyph = if columns == 1 {
+                    let x0 = slot_x + cell_width / 2;
+                    [x0, slot_y, x0 + cell_width, slot_y + cell_height]
+                } else {
+                    let y0 = slot_y + cell_height / 2;
+                    [slot_x, y0, slot_x + cell_width * 2, y0 + cell_height]
+                };
+                quad(
+                    glyph,
+                    [
+                        src_x as f32,
+                        src_y as f32,
+                        (src_x + columns as u32 * cell_width) as f32,
+                        (src_y + cell_height) as f32,
+                    ],
+                );
+
+                let area = Rect::new(
+                    (offset_x + slots[0]) as u16,
+                    (offset_y + slots[1]) as u16,
+                    (slots[2] - slots[0]) as u16,
+                    (slots[3] - slots[1]) as u16,
+                );
+                damage = Some(damage.map_or(area, |damage| damage.union(area)));
+            }
         }
-        RasterImageFormat::BitmapGray4 => {
-            from_gray_unpacked::<4, 16>(image, raster, LUT_4);
+
+        damage
+    }
+
+    /// Compute the pixel bounds of the cursor, if it should be drawn.
+    fn cursor_quad(&self) -> Option<[f32; 4]> {
+        if !self.cursor_visible || !self.show_cursor {
+            return None;
         }
-        RasterImageFormat::BitmapGray4Packed => {
-            from_gray_packed::<4, 16>(image, raster, LUT_4);
+
+        let bounds = self.size().unwrap();
+        let (x, y) = self.cursor;
+        if x >= bounds.width || y >= bounds.height {
+            return None;
         }
-        RasterImageFormat::BitmapGray8 => {
-            for (byte, dst) in raster.data.iter().zip(image.iter_mut()) {
-                *dst = u32::from_be_bytes([*byte, 255, 255, 255]);
-            }
+
+        let index = y as usize * bounds.width as usize + x as usize;
+        let columns = self
+            .cells
+            .get(index)
+            .map_or(1, |cell| cell_columns(cell).max(1) as u32)
+            .min((bounds.width - x) as u32);
+
+        let cell_width = self.fonts.cell_width_px();
+        let cell_height = self.fonts.cell_height_px();
+        let x0 = x as u32 * cell_width;
+        let y0 = y as u32 * cell_height;
+        let x1 = x0 + columns * cell_width;
+        let y1 = y0 + cell_height;
+
+        let [x0, y0, x1, y1] = match self.cursor_style {
+            CursorStyle::Block => [x0, y0, x1, y1],
+            CursorStyle::Bar => [x0, y0, x0 + (cell_width / 6).max(1), y1],
+            CursorStyle::Underline => [x0, y1 - (cell_height / 12).max(1), x1, y1],
+        };
+
+        Some([x0 as f32, y0 as f32, x1 as f32, y1 as f32])
+    }
+
+    /// Mark the row containing the cursor as needing to be repainted.
+    fn dirty_cursor_row(&mut self) {
+        if let Some(dirty) = self.dirty_rows.get_mut(self.cursor.1 as usize) {
+            *dirty = true;
         }
-        _ => return None,
     }
 
-    let mut final_image = DrawTarget::new(cached.width as i32, cached.height as i32);
-    final_image.draw_image_with_size_at(
-        cached.width as f32,
-        cached.height as f32,
-        raster.x as f32 * scale,
-        raster.y as f32 * scale,
-        &raqote::Image {
-            width: raster.width as i32,
-            height: raster.height as i32,
-            data: &*image,
-        },
-        &DrawOptions {
-            blend_mode: raqote::BlendMode::Src,
-            antialias: raqote::AntialiasMode::None,
-            ..Default::default()
-        },
-    );
+    /// The overrides for the cell at `index` from
+    /// [`WgpuBackend::print_rgba`], [`WgpuBackend::set_cell_opacity`], and the
+    /// cell shader, in a grid `width` cells wide.
+    fn cell_overrides(&self, index: usize, width: u16) -> CellOverride {
+        let colors = self.color_overrides.get(&index);
+        let mut overrides = CellOverride {
+            fg: colors.map(|(fg, _)| *fg),
+            bg: colors.map(|(_, bg)| *bg),
+            alpha: self.cell_opacity.get(&index).copied(),
+        };
 
-    let mut final_image = final_image.into_vec();
-    for argb in final_image.iter_mut() {
-        let [a, r, g, b] = argb.to_be_bytes();
-        *argb = u32::from_le_bytes([r, g, b, a]);
+        if let Some(shader) = self.cell_shader {
+            let shaded = shader(
+                &self.cells[index],
+                (index % width as usize) as u16,
+                (index / width as usize) as u16,
+            );
+            overrides.fg = shaded.fg.or(overrides.fg);
+            overrides.bg = shaded.bg.or(overrides.bg);
+            overrides.alpha = match (overrides.alpha, shaded.alpha) {
+                (Some(opacity), Some(alpha)) => Some(opacity * alpha.clamp(0.0, 1.0)),
+                (opacity, alpha) => opacity.or(alpha.map(|alpha| alpha.clamp(0.0, 1.0))),
+            };
+        }
+
+        overrides
     }
 
-    Some((*cached, final_image))
-}
+    fn metric_lines(&self) -> Vec<(f32, u32)> {
+        let Some(overlay) = self.metric_overlay else {
+            return vec![];
+        };
 
-fn from_gray_unpacked<const BITS: usize, const ENTRIES: usize>(
-    image: &mut [u32],
-    raster: RasterGlyphImage,
-    steps: [u8; ENTRIES],
-) {
-    for (bits, dst) in raster
-        .data
-        .chunks((raster.width as usize / (8 / BITS)) + 1)
-        .zip(image.chunks_mut(raster.width as usize))
-    {
-        let bits = BitSlice::<_, Lsb0>::from_slice(bits);
-        for (bits, dst) in bits.chunks(BITS).zip(dst.iter_mut()) {
-            let mut index = 0;
-            for idx in bits.iter_ones() {
-                index |= 1 << (BITS - idx - 1);
-            }
-            let value = steps[index as usize];
-            *dst = u32::from_be_bytes([value, 255, 255, 255]);
+        let metrics = self.fonts.last_resort().font();
+        let scale = self.fonts.height_px() as f32 / metrics.height() as f32;
+        let max_y = self.fonts.cell_height_px().saturating_sub(1) as f32;
+        let baseline = self.fonts.line_padding_top() as f32 + metrics.ascender() as f32 * scale;
+
+        [
+            (overlay.baseline, baseline),
+            (overlay.ascender, self.fonts.line_padding_top() as f32),
+            (
+                overlay.descender,
+                baseline - metrics.descender() as f32 * scale,
+            ),
+            (
+                overlay.x_height,
+                baseline - metrics.x_height().unwrap_or_default() as f32 * scale,
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(color, y)| {
+            let [r, g, b] = c2c(color?, self.reset_fg, &self.palette);
+            Some((
+                y.round().clamp(0.0, max_y),
+                u32::from_be_bytes([r, g, b, 255]),
+            ))
+        })
+        .collect()
+    }
+
+    /// Recreate the atlas textures with enough layers for every page in use,
+    /// copying over the glyphs already cached in the existing pages.
+    fn grow_atlas(&mut self) {
+        let (text_cache, text_mask) = build_text_atlas(
+            &self.device,
+            self.text_cache.width(),
+            self.text_cache.height(),
+            self.cached.pages(),
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Atlas Growth Encoder"),
+            });
+        for (old, new) in [
+            (&self.text_cache, &text_cache),
+            (&self.text_mask, &text_mask),
+        ] {
+            encoder.copy_texture_to_texture(old.as_image_copy(), new.as_image_copy(), old.size());
         }
+        self.queue.submit(Some(encoder.finish()));
+
+        self.text_fg_compositor.atlas_bindings = build_atlas_bindings(
+            &self.device,
+            &self.text_fg_compositor.atlas_layout,
+            &self.text_fg_compositor.atlas_size,
+            &self.text_fg_compositor.sampler,
+            &text_cache,
+            &text_mask,
+        );
+        self.text_cache = text_cache;
+        self.text_mask = text_mask;
     }
-}
 
-fn from_gray_packed<const BITS: usize, const ENTRIES: usize>(
-    image: &mut [u32],
-    raster: RasterGlyphImage,
-    steps: [u8; ENTRIES],
-) {
-    let bits = BitSlice::<_, Lsb0>::from_slice(raster.data);
-    for (bits, dst) in bits.chunks(BITS).zip(image.iter_mut()) {
-        let mut index = 0;
-        for idx in bits.iter_ones() {
-            index |= 1 << (BITS - idx - 1);
+    /// Identifies the settings which change how glyphs are rasterized, other
+    /// than those which are part of their keys in the atlas.
+    fn glyph_settings(&self) -> u64 {
+        let mut hasher = Fnv1a::default();
+        hasher.write(env!("CARGO_PKG_VERSION").as_bytes());
+        for value in [
+            self.fonts.height_px(),
+            self.fonts.cell_height_px(),
+            self.fonts.min_width_px(),
+            self.fake_italic_skew.to_bits(),
+        ] {
+            hasher.write(&value.to_le_bytes());
         }
-        let value = steps[index as usize];
-        *dst = u32::from_be_bytes([value, 255, 255, 255]);
+        hasher.write(&[
+            self.glyph_antialiasing as u8,
+            self.subpixel_aa as u8,
+            self.emoji_mode as u8,
+        ]);
+        hasher.finish()
     }
-}
 
-const LUT_1: [u8; 2] = [0, 255];
-const LUT_2: [u8; 4] = [0, 255 / 3, 2 * (255 / 3), 255];
-const LUT_4: [u8; 16] = [
-    0,
-    (255 / 15),
-    2 * (255 / 15),
-    3 * (255 / 15),
-    4 * (255 / 15),
-    5 * (255 / 15),
-    6 * (255 / 15),
-    7 * (255 / 15),
-    8 * (255 / 15),
-    9 * (255 / 15),
-    10 * (255 / 15),
-    11 * (255 / 15),
-    12 * (255 / 15),
-    13 * (255 / 15),
-    14 * (255 / 15),
-    255,
-];
+    /// Load the glyphs cached on disk for the current settings if they've
+    /// changed since the cache was last loaded, and add as many as fit to the
+    /// atlas.
+    pub(super) fn sync_glyph_disk_cache(&mut self) {
+        let settings = self.glyph_settings();
+        let Some(disk_cache) = self
+            .glyph_disk_cache
+            .as_mut()
+            .filter(|cache| cache.settings() != Some(settings))
+        else {
+            return;
+        };
 
-#[cfg(test)]
-mod tests {
-    use std::num::NonZeroU32;
+        let fonts = self.fonts.ids().collect::<HashSet<_, RandomState>>();
+        disk_cache.load(settings, |font| fonts.contains(&font));
 
-    use image::{
-        load_from_memory,
-        GenericImageView,
-        ImageBuffer,
-        Rgba,
-    };
-    use ratatui::{
-        style::{
-            Color,
-            Stylize,
-        },
-        text::Line,
-        widgets::{
-            Block,
-            Paragraph,
-        },
-        Terminal,
-    };
-    use rustybuzz::ttf_parser::{
-        RasterGlyphImage,
-        RasterImageFormat,
-    };
-    use serial_test::serial;
-    use wgpu::{
-        CommandEncoderDescriptor,
-        Device,
-        Extent3d,
-        ImageCopyBuffer,
-        ImageDataLayout,
-        Queue,
-        TextureFormat,
-    };
+        let height = self.fonts.cell_height_px();
+        let room = self.cached.capacity().saturating_sub(self.cached.len());
+        for (key, (image, color)) in disk_cache
+            .glyphs()
+            .filter(|(key, (image, _))| image.len() == (key.key.width * height) as usize)
+            .take(room)
+        {
+            let cached = self.cached.get(&key.key, key.key.width, height);
+            if !cached.cached() {
+                self.atlas_uploads.push((*cached, image.clone(), *color));
+            }
+        }
+        self.atlas_uploads.sort_by_key(|(cached, ..)| cached.epoch);
+    }
 
-    use crate::{
-        backend::{
-            wgpu_backend::{
-                extract_bw_image,
-                LUT_2,
-                LUT_4,
-            },
-            HeadlessSurface,
-        },
-        shaders::DefaultPostProcessor,
-        utils::text_atlas::{
-            CacheRect,
-            Entry,
-        },
-        Builder,
-        Dimensions,
-        Font,
-    };
+    /// Upload rasterized glyphs from every epoch up to and including `epoch` to
+    /// the atlas.
+    fn upload_glyphs(&mut self, epoch: u64) {
+        let count = self
+            .atlas_uploads
+            .partition_point(|(cached, ..)| cached.epoch <= epoch);
+        for (cached, image, mask) in self.atlas_uploads.drain(..count) {
+            self.queue.write_texture(
+                ImageCopyTexture {
+                    texture: &self.text_cache,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: cached.x,
+                        y: cached.y,
+                        z: cached.page,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                bytemuck::cast_slice(&image),
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(cached.width * size_of::<u32>() as u32),
+                    rows_per_image: Some(cached.height),
+                },
+                Extent3d {
+                    width: cached.width,
+                    height: cached.height,
+                    depth_or_array_layers: 1,
+                },
+            );
 
-    fn tex2buffer(device: &Device, queue: &Queue, surface: &HeadlessSurface) {
-        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
-        encoder.copy_texture_to_buffer(
-            surface.texture.as_ref().unwrap().as_image_copy(),
-            ImageCopyBuffer {
-                buffer: surface.buffer.as_ref().unwrap(),
-                layout: ImageDataLayout {
+            self.queue.write_texture(
+                ImageCopyTexture {
+                    texture: &self.text_mask,
+                    mip_level: 0,
+                    origin: Origin3d {
+                        x: cached.x,
+                        y: cached.y,
+                        z: cached.page,
+                    },
+                    aspect: TextureAspect::All,
+                },
+                &vec![if mask { 255 } else { 0 }; (cached.width * cached.height) as usize],
+                ImageDataLayout {
                     offset: 0,
-                    bytes_per_row: Some(surface.buffer_width),
-                    rows_per_image: Some(surface.height),
+                    bytes_per_row: Some(cached.width),
+                    rows_per_image: Some(cached.height),
                 },
-            },
-            Extent3d {
-                width: surface.width,
-                height: surface.height,
-                depth_or_array_layers: 1,
-            },
+                Extent3d {
+                    width: cached.width,
+                    height: cached.height,
+                    depth_or_array_layers: 1,
+                },
+            )
+        }
+    }
+
+    fn render(&mut self) {
+        let bounds = self.window_size().unwrap();
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&CommandEncoderDescriptor {
+                label: Some("Draw Encoder"),
+            });
+
+        if self.padded() && self.clear_padding {
+            self.clear_padding = false;
+            // This also clears the grid, which is fully redrawn whenever the padding
+            // needs to be cleared.
+            let padding = self.padding_color.unwrap_or(Color::Reset);
+            let color = if self.transparent_background && padding == Color::Reset {
+                wgpu::Color::TRANSPARENT
+            } else {
+                let [r, g, b] = c2c(padding, self.reset_bg, &self.palette);
+                wgpu::Color {
+                    r: r as f64 / 255.0,
+                    g: g as f64 / 255.0,
+                    b: b as f64 / 255.0,
+                    a: 1.0,
+                }
+            };
+            encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("Padding Clear Pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &self.wgpu_state.text_dest_view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(color),
+                        store: StoreOp::Store,
+                    },
+                })],
+                ..Default::default()
+            });
+        }
+
+        if !self.text_vertices.is_empty() {
+            {
+                let mut uniforms = self
+                    .queue
+                    .write_buffer_with(
+                        &self.text_screen_size_buffer,
+                        0,
+                        NonZeroU64::new(size_of::<[f32; 4]>() as u64).unwrap(),
+                    )
+                    .unwrap();
+                // When over-rendering, the grid is offset as part of copying it into the
+                // destination texture instead.
+                let (texture, (x, y)) = self.wgpu_state.overrender.as_ref().map_or(
+                    (&self.wgpu_state.text_dest, self.grid_offset()),
+                    |(texture, _)| (texture, (0, 0)),
+                );
+                let [width, height] = [texture.width() as f32, texture.height() as f32];
+                uniforms
+                    .copy_from_slice(bytemuck::cast_slice(&[width, height, x as f32, y as f32]));
+            }
+
+            // Taken so the buffers can stay borrowed while glyphs are uploaded between
+            // batches. They're larger than this frame's contents, but every draw below
+            // only reads the part which was just written.
+            let mut buffers = std::mem::take(&mut self.frame_buffers);
+            let bg_vertices = write_reused_buffer(
+                &mut buffers.bg_vertices,
+                &self.device,
+                &self.queue,
+                "Text Bg Vertices",
+                BufferUsages::VERTEX,
+                bytemuck::cast_slice(&self.bg_vertices),
+            );
+
+            let fg_vertices = write_reused_buffer(
+                &mut buffers.text_vertices,
+                &self.device,
+                &self.queue,
+                "Text Vertices",
+                BufferUsages::VERTEX,
+                bytemuck::cast_slice(&self.text_vertices),
+            );
+
+            let indices = write_reused_buffer(
+                &mut buffers.indices,
+                &self.device,
+                &self.queue,
+                "Text Indices",
+                BufferUsages::INDEX,
+                bytemuck::cast_slice(&self.text_indices),
+            );
+
+            let image_vertices = (!self.image_vertices.is_empty()).then(|| {
+                write_reused_buffer(
+                    &mut buffers.image_vertices,
+                    &self.device,
+                    &self.queue,
+                    "Image Vertices",
+                    BufferUsages::VERTEX,
+                    bytemuck::cast_slice(&self.image_vertices),
+                )
+            });
+
+            let batches = std::mem::take(&mut self.text_batches);
+            let bg_quads = self.bg_vertices.len() as u32 / 4;
+            let overlay_end = bg_quads - self.cursor_quads;
+            let cell_bg_quads = overlay_end - self.overlay_quads;
+
+            for (batch, &(epoch, end)) in batches.iter().enumerate() {
+                self.upload_glyphs(epoch);
+                let start = batch.checked_sub(1).map_or(0, |prev| batches[prev].1);
+                let last = batch + 1 == batches.len();
+
+                {
+                    let mut text_render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                        label: Some("Text Render Pass"),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view: self
+                                .wgpu_state
+                                .overrender
+                                .as_ref()
+                                .map_or(&self.wgpu_state.text_dest_view, |(_, view)| view),
+                            resolve_target: None,
+                            ops: Operations {
+                                load: LoadOp::Load,
+                                store: StoreOp::Store,
+                            },
+                        })],
+                        ..Default::default()
+                    });
+
+                    text_render_pass.set_index_buffer(indices.slice(..), IndexFormat::Uint32);
+
+                    if batch == 0 {
+                        text_render_pass.set_pipeline(&self.text_bg_compositor.pipeline);
+                        text_render_pass.set_bind_group(
+                            0,
+                            &self.text_bg_compositor.fs_uniforms,
+                            &[],
+                        );
+                        text_render_pass.set_vertex_buffer(0, bg_vertices.slice(..));
+                        text_render_pass.draw_indexed(0..cell_bg_quads * 6, 0, 0..1);
+
+                        if let (Some(compositor), Some(image_vertices)) =
+                            (self.image_compositor.as_ref(), image_vertices.as_ref())
+                        {
+                            text_render_pass.set_pipeline(&compositor.pipeline);
+                            text_render_pass.set_bind_group(0, &compositor.fs_uniforms, &[]);
+                            text_render_pass.set_vertex_buffer(0, image_vertices.slice(..));
+                            for (draw, index) in self.image_draws.iter().enumerate() {
+                                let first = draw as u32 * 4;
+                                text_render_pass.set_bind_group(
+                                    1,
+                                    &self.images[*index].bindings,
+                                    &[],
+                                );
+                                text_render_pass.draw(first..first + 4, 0..1);
+                            }
+                        }
+                    }
+
+                    text_render_pass.set_bind_group(0, &self.text_fg_compositor.fs_uniforms, &[]);
+                    text_render_pass.set_bind_group(
+                        1,
+                        &self.text_fg_compositor.atlas_bindings,
+                        &[],
+                    );
+
+                    text_render_pass.set_vertex_buffer(0, fg_vertices.slice(..));
+                    if let Some(pipelines) = self.text_fg_compositor.subpixel_pipelines.as_ref() {
+                        for pipeline in pipelines {
+                            text_render_pass.set_pipeline(pipeline);
+                            text_render_pass.draw_indexed(start * 6..end * 6, 0, 0..1);
+                        }
+                    } else {
+                        text_render_pass.set_pipeline(&self.text_fg_compositor.pipeline);
+                        text_render_pass.draw_indexed(start * 6..end * 6, 0, 0..1);
+                    }
+
+                    if last && self.overlay_quads != 0 {
+                        text_render_pass.set_pipeline(&self.text_bg_compositor.overlay_pipeline);
+                        text_render_pass.set_bind_group(
+                            0,
+                            &self.text_bg_compositor.fs_uniforms,
+                            &[],
+                        );
+                        text_render_pass.set_vertex_buffer(0, bg_vertices.slice(..));
+                        text_render_pass.draw_indexed(cell_bg_quads * 6..overlay_end * 6, 0, 0..1);
+                    }
+
+                    if last && self.cursor_quads != 0 {
+                        text_render_pass.set_pipeline(&self.text_bg_compositor.invert_pipeline);
+                        text_render_pass.set_bind_group(
+                            0,
+                            &self.text_bg_compositor.fs_uniforms,
+                            &[],
+                        );
+                        text_render_pass.set_vertex_buffer(0, bg_vertices.slice(..));
+                        text_render_pass.draw_indexed(overlay_end * 6..bg_quads * 6, 0, 0..1);
+                    }
+                }
+
+                // Later batches upload glyphs over the ones this batch draws, so it has to
+                // be submitted before those uploads are queued.
+                if !last {
+                    let batch_encoder = std::mem::replace(
+                        &mut encoder,
+                        self.device
+                            .create_command_encoder(&CommandEncoderDescriptor {
+                                label: Some("Draw Encoder"),
+                            }),
+                    );
+                    self.queue.submit(Some(batch_encoder.finish()));
+                }
+            }
+            self.text_batches = batches;
+            self.frame_buffers = buffers;
+        }
+        self.upload_glyphs(u64::MAX);
+
+        if let (Some(compositor), Some((_, overrender))) = (
+            self.vertical_compositor.as_ref(),
+            self.wgpu_state.overrender.as_ref(),
+        ) {
+            if !self.vertical_vertices.is_empty() {
+                let (x, y) = self.grid_offset();
+                let [width, height] = [
+                    self.wgpu_state.text_dest.width() as f32,
+                    self.wgpu_state.text_dest.height() as f32,
+                ];
+                self.queue.write_buffer(
+                    &compositor.screen_size,
+                    0,
+                    bytemuck::cast_slice(&[width, height, x as f32, y as f32]),
+                );
+
+                let vertices = write_reused_buffer(
+                    &mut self.frame_buffers.vertical_vertices,
+                    &self.device,
+                    &self.queue,
+                    "Vertical Text Vertices",
+                    BufferUsages::VERTEX,
+                    bytemuck::cast_slice(&self.vertical_vertices),
+                );
+                let grid = self.device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("Vertical Compositor Fragment Binding"),
+                    layout: &compositor.grid_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(overrender),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Sampler(&compositor.sampler),
+                        },
+                    ],
+                });
+
+                let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+                    label: Some("Vertical Text Pass"),
+                    color_attachments: &[Some(RenderPassColorAttachment {
+                        view: &self.wgpu_state.text_dest_view,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Load,
+                            store: StoreOp::Store,
+                        },
+                    })],
+                    ..Default::default()
+                });
+                pass.set_pipeline(&compositor.pipeline);
+                pass.set_bind_group(0, &compositor.fs_uniforms, &[]);
+                pass.set_bind_group(1, &grid, &[]);
+                pass.set_vertex_buffer(0, vertices.slice(..));
+                pass.draw(0..self.vertical_vertices.len() as u32, 0..1);
+            }
+        } else if let Some((overrender, _)) = &self.wgpu_state.overrender {
+            let (x, y) = self.grid_offset();
+            encoder.copy_texture_to_texture(
+                overrender.as_image_copy(),
+                ImageCopyTexture {
+                    origin: Origin3d { x, y, z: 0 },
+                    ..self.wgpu_state.text_dest.as_image_copy()
+                },
+                Extent3d {
+                    width: bounds.columns_rows.width as u32 * self.fonts.cell_width_px(),
+                    height: bounds.columns_rows.height as u32 * self.fonts.cell_height_px(),
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        let Some(texture) = self.surface.get_current_texture(Token) else {
+            return;
+        };
+
+        if self.post_process_enabled {
+            self.post_process.process(
+                &mut encoder,
+                &self.queue,
+                &self.wgpu_state.text_dest_view,
+                &self.surface_config,
+                texture.get_view(Token),
+            );
+        } else if let Some(blitter) = self.blitter.as_mut() {
+            blitter.process(
+                &mut encoder,
+                &self.queue,
+                &self.wgpu_state.text_dest_view,
+                &self.surface_config,
+                texture.get_view(Token),
+            );
+        }
+
+        self.queue.submit(Some(encoder.finish()));
+        texture.present(Token);
+        self.needs_present = true;
+    }
+}
+
+impl<P: PostProcessor, H: BuildHasher + Clone> WgpuBackend<'_, 'static, P, HeadlessSurface, H> {
+    /// Read back the most recently rendered frame as tightly packed RGBA8
+    /// rows. This is the final output after post processing, at the size of
+    /// the surface.
+    ///
+    /// This blocks until the GPU has finished copying the frame, by polling
+    /// the device.
+    pub fn read_rgba(&self) -> Vec<u8> {
+        self.read_headless(&self.surface)
+    }
+}
+
+impl<P: PostProcessor, H: BuildHasher + Clone> WgpuBackend<'_, 'static, P, TextureTarget, H> {
+    /// Get the texture the backend renders into.
+    pub fn texture(&self) -> &Texture {
+        &self.surface.texture
+    }
+
+    /// Render into a different texture, e.g. one of a new size after the area
+    /// the terminal is embedded in was resized. The grid is resized to match
+    /// the new texture. The texture must have been created on the same device
+    /// as the original with [`wgpu::TextureUsages::RENDER_ATTACHMENT`].
+    pub fn set_texture(&mut self, texture: Texture) {
+        let (width, height) = (texture.width(), texture.height());
+        self.surface.texture = texture;
+        self.resize(width, height);
+    }
+}
+
+impl<'f, 's, P: PostProcessor, S: RenderSurface<'s>, H: BuildHasher + Clone> Backend
+    for WgpuBackend<'f, 's, P, S, H>
+{
+    fn draw<'a, I>(&mut self, content: I) -> std::io::Result<()>
+    where
+        I: Iterator<Item = (u16, u16, &'a Cell)>,
+    {
+        let bounds = self.size()?;
+        self.fit_to_grid();
+
+        #[cfg(any(feature = "kitty-graphics", feature = "sixel"))]
+        let mut escape_commands = vec![];
+        #[cfg(any(feature = "kitty-graphics", feature = "sixel"))]
+        let blank = Cell::EMPTY;
+
+        for (x, y, cell) in content {
+            let index = y as usize * bounds.width as usize + x as usize;
+            self.color_overrides.remove(&index);
+
+            // Escapes are consumed rather than drawn as text, and an image
+            // placed by an escape is removed once its cell is overwritten.
+            #[cfg(any(feature = "kitty-graphics", feature = "sixel"))]
+            let cell = if self.escapes.parse(
+                cell.symbol(),
+                (x, y),
+                (self.fonts.cell_width_px(), self.fonts.cell_height_px()),
+                self.device.limits().max_texture_dimension_2d,
+                &mut escape_commands,
+            ) {
+                &blank
+            } else {
+                if let Some(area) = self.escape_placements.remove(&(x, y)) {
+                    self.remove_image(area);
+                }
+                cell
+            };
+
+            if self.blink {
+                self.fast_blinking
+                    .set(index, cell.modifier.contains(Modifier::RAPID_BLINK));
+                self.slow_blinking
+                    .set(index, cell.modifier.contains(Modifier::SLOW_BLINK));
+            }
+
+            self.cells[index] = cell.clone();
+
+            let width = cell.symbol().width().max(1);
+            let start = (index + 1).min(self.cells.len());
+            let end = (index + width).min(self.cells.len());
+            self.cells[start..end].fill(NULL_CELL);
+            self.dirty_rows[y as usize] = true;
+        }
+
+        #[cfg(any(feature = "kitty-graphics", feature = "sixel"))]
+        for ((x, y), command) in escape_commands {
+            match command {
+                escapes::Command::Display(image) => {
+                    let max = self.device.limits().max_texture_dimension_2d;
+                    if image.width > max || image.height > max {
+                        warn!(
+                            "Ignoring {}x{} image larger than the device supports",
+                            image.width, image.height
+                        );
+                        continue;
+                    }
+
+                    let columns = image
+                        .columns
+                        .unwrap_or_else(|| image.width.div_ceil(self.fonts.cell_width_px()) as u16);
+                    let rows = image.rows.unwrap_or_else(|| {
+                        image.height.div_ceil(self.fonts.cell_height_px()) as u16
+                    });
+                    let area = Rect::new(x, y, columns, rows);
+                    self.draw_image(
+                        area,
+                        &image.rgba,
+                        image.width,
+                        image.height,
+                        SampleMode::Linear,
+                    );
+                    if let Some(old) = self.escape_placements.insert((x, y), area) {
+                        if old != area {
+                            self.remove_image(old);
+                        }
+                    }
+                }
+                #[cfg(feature = "kitty-graphics")]
+                escapes::Command::DeleteAll => {
+                    self.escape_placements.clear();
+                    self.clear_images();
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn hide_cursor(&mut self) -> std::io::Result<()> {
+        if self.cursor_visible {
+            self.cursor_visible = false;
+            self.dirty_cursor_row();
+        }
+        Ok(())
+    }
+
+    fn show_cursor(&mut self) -> std::io::Result<()> {
+        if !self.cursor_visible {
+            self.cursor_visible = true;
+            self.dirty_cursor_row();
+        }
+        Ok(())
+    }
+
+    fn get_cursor_position(&mut self) -> std::io::Result<Position> {
+        Ok(Position::new(self.cursor.0, self.cursor.1))
+    }
+
+    fn set_cursor_position<Pos: Into<Position>>(&mut self, position: Pos) -> std::io::Result<()> {
+        let bounds = self.size()?;
+        let pos: Position = position.into();
+        let cursor = (
+            pos.x.min(bounds.width.saturating_sub(1)),
+            pos.y.min(bounds.height.saturating_sub(1)),
+        );
+        if cursor != self.cursor && self.cursor_visible {
+            self.dirty_cursor_row();
+            self.cursor = cursor;
+            self.dirty_cursor_row();
+        } else {
+            self.cursor = cursor;
+        }
+        Ok(())
+    }
+
+    fn clear(&mut self) -> std::io::Result<()> {
+        self.cells.clear();
+        self.cell_opacity.clear();
+        self.color_overrides.clear();
+        self.dirty_rows.clear();
+        self.cursor = (0, 0);
+
+        Ok(())
+    }
+
+    fn size(&self) -> std::io::Result<Size> {
+        let (inset_width, inset_height) = match self.viewport {
+            Viewport::Full | Viewport::Centered => (0, 0),
+            Viewport::Shrink { width, height } => (width, height),
+        };
+        let width = self.surface_config.width.saturating_sub(inset_width);
+        let height = self.surface_config.height.saturating_sub(inset_height);
+
+        if self.vertical_text {
+            let (slot_width, slot_height) = self.screen_cell_size();
+            return Ok(Size {
+                width: self.grid_rounding.cells(height, slot_height) as u16,
+                height: self.grid_rounding.cells(width, slot_width) as u16,
+            });
+        }
+
+        Ok(Size {
+            width: self.grid_rounding.cells(width, self.fonts.cell_width_px()) as u16,
+            height: self
+                .grid_rounding
+                .cells(height, self.fonts.cell_height_px()) as u16,
+        })
+    }
+
+    fn window_size(&mut self) -> std::io::Result<WindowSize> {
+        let (inset_width, inset_height) = match self.viewport {
+            Viewport::Full | Viewport::Centered => (0, 0),
+            Viewport::Shrink { width, height } => (width, height),
+        };
+        let width = self.surface_config.width.saturating_sub(inset_width);
+        let height = self.surface_config.height.saturating_sub(inset_height);
+
+        Ok(WindowSize {
+            columns_rows: self.size()?,
+            pixels: Size {
+                width: width as u16,
+                height: height as u16,
+            },
+        })
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.needs_present = false;
+        if self.paused {
+            return Ok(());
+        }
+
+        // Skipped flushes leave everything dirty, so the changes are picked up by the
+        // next flush which isn't throttled.
+        if self
+            .last_flush
+            .is_some_and(|last| last.elapsed() < self.min_frame_interval)
+        {
+            return Ok(());
+        }
+        self.last_flush = Some(Instant::now());
+
+        let bounds = self.size()?;
+        // The surface is too small to hold a single cell, e.g. because the window
+        // is minimized, so there is nothing to render.
+        if bounds.width == 0 || bounds.height == 0 {
+            return Ok(());
+        }
+        // Cells dirtied by flushes during a batch haven't been rendered yet, so
+        // keep them around unless the grid has changed size.
+        if !std::mem::take(&mut self.batch_pending) || self.dirty_cells.len() != self.cells.len() {
+            self.dirty_cells.clear();
+        }
+        self.dirty_cells.resize(self.cells.len(), false);
+
+        let fast_toggle_dirty = self.blink && self.last_fast_toggle.elapsed() >= self.fast_duration;
+        if fast_toggle_dirty {
+            self.last_fast_toggle = Instant::now();
+            self.show_fast = !self.show_fast;
+
+            for index in self.fast_blinking.iter_ones() {
+                self.dirty_cells.set(index, true);
+            }
+        }
+
+        let slow_toggle_dirty = self.blink && self.last_slow_toggle.elapsed() >= self.slow_duration;
+        if slow_toggle_dirty {
+            self.last_slow_toggle = Instant::now();
+            self.show_slow = !self.show_slow;
+
+            for index in self.slow_blinking.iter_ones() {
+                self.dirty_cells.set(index, true);
+            }
+        }
+
+        if let Some(cursor_duration) = self.cursor_blink {
+            if self.last_cursor_toggle.elapsed() >= cursor_duration {
+                self.last_cursor_toggle = Instant::now();
+                self.show_cursor = !self.show_cursor;
+
+                let index = self.cursor.1 as usize * bounds.width as usize + self.cursor.0 as usize;
+                if self.cursor_visible && index < self.dirty_cells.len() {
+                    self.dirty_cells.set(index, true);
+                }
+            }
+        }
+
+        // Glyphs cached before the atlas last overflowed may have been replaced since,
+        // so rows with cells which need redrawing are shaped again.
+        let epoch = self.cached.epoch();
+        for index in self.dirty_cells.iter_ones() {
+            let row = index / bounds.width as usize;
+            if self
+                .rendered
+                .get(index)
+                .is_some_and(|rendered| rendered.values().any(|info| info.cached.epoch < epoch))
+            {
+                if let Some(dirty) = self.dirty_rows.get_mut(row) {
+                    *dirty = true;
+                }
+            }
+        }
+
+        self.sync_glyph_disk_cache();
+        self.cached.next_frame();
+        let mut pending_cache_updates = HashMap::<_, _, RandomState>::default();
+        let mut redrawn = vec![false; bounds.height as usize];
+        let mut fontmap = vec![];
+
+        loop {
+            for (y, (row, sourced)) in self
+                .cells
+                .chunks(bounds.width as usize)
+                .zip(self.sourced.chunks_mut(bounds.width as usize))
+                .enumerate()
+            {
+                if !self.dirty_rows[y] {
+                    continue;
+                }
+
+                self.dirty_rows[y] = false;
+                redrawn[y] = true;
+                let mut new_sourced = std::mem::take(&mut self.row_sourced);
+                new_sourced.resize(
+                    bounds.width as usize,
+                    Sourced::with_hasher(self.hasher.clone()),
+                );
+                new_sourced.iter_mut().for_each(Sourced::clear);
+
+                // This block concatenates the strings for the row into one string for bidi
+                // resolution, then maps bytes for the string to their associated cell index. It
+                // also maps the row's cell index to the font that can source all glyphs for
+                // that cell.
+                self.row.clear();
+                self.rowmap.clear();
+                fontmap.clear();
+                for (idx, cell) in row.iter().enumerate() {
+                    // A zero width symbol still occupies its own column in the grid, so we give
+                    // it a space to attach to. Otherwise it would be shaped as part of the
+                    // previous cell and everything after it would shift left by a column.
+                    if cell_columns(cell) != 0 && cell.symbol().width() == 0 {
+                        self.row.push(' ');
+                        self.rowmap.push(idx as u16);
+                    }
+                    self.row.push_str(cell.symbol());
+                    self.rowmap
+                        .resize(self.rowmap.len() + cell.symbol().len(), idx as u16);
+                    fontmap.push(self.fonts.font_for_cell(cell));
+                }
+
+                let mut x = 0;
+                // rustbuzz provides a non-zero x-advance for the first character in a cluster
+                // with combining characters. The remainder of the cluster doesn't account for
+                // this advance, so if we advance prior to rendering them, we end up with all of
+                // the associated characters being offset by a cell. To combat this, we only
+                // bump the x-advance after we've finished processing all of the characters in a
+                // cell. This assumes that we 1) always get a non-zero advance at the beginning
+                // of a cluster and 2) the next cluster in the sequence starts with a non-zero
+                // advance.
+                let mut next_advance = 0;
+                // A cell's glyphs only ever occupy the columns of that cell, so only the first
+                // glyph in a cell with an advance moves to the next cell. This keeps e.g. a
+                // zero width joiner sequence which a font can't compose into a single glyph
+                // from spilling into the following cells.
+                let mut advanced_cell = None;
+                let mut component = 0;
+                let mut shape = |font: &Font,
+                                 fake_bold,
+                                 fake_italic,
+                                 buffer: GlyphBuffer|
+                 -> UnicodeBuffer {
+                    let metrics = font.font();
+                    let advance_scale = self.fonts.height_px() as f32 / metrics.height() as f32;
+
+                    // If a font can't compose a zero width joiner sequence into a single glyph,
+                    // we end up with multiple glyphs with an advance (or .notdef glyphs) for
+                    // the cell. Rather than stacking them on top of each other, we lay the
+                    // components out side by side within the cell's columns.
+                    let mut components = vec![0u32; row.len()];
+                    for (info, position) in buffer
+                        .glyph_infos()
+                        .iter()
+                        .zip(buffer.glyph_positions().iter())
+                    {
+                        if (position.x_advance as f32 * advance_scale) as i32 != 0 {
+                            components[self.rowmap[info.cluster as usize] as usize] += 1;
+                        }
+                    }
+
+                    for (info, position) in buffer
+                        .glyph_infos()
+                        .iter()
+                        .zip(buffer.glyph_positions().iter())
+                    {
+                        let cell_idx = self.rowmap[info.cluster as usize] as usize;
+                        let cell = &row[cell_idx];
+                        let max_width = cell_columns(cell);
+                        let sourced = &mut new_sourced[cell_idx];
+
+                        let basey = y as i32 * self.fonts.cell_height_px() as i32
+                            + (position.y_offset as f32 * advance_scale) as i32;
+                        let decomposed =
+                            components[cell_idx] > 1 && cell.symbol().contains('\u{200D}');
+                        let mut advance = (position.x_advance as f32 * advance_scale) as i32;
+                        let mut component_offset = 0;
+                        if advanced_cell == Some(cell_idx) {
+                            if decomposed && advance != 0 {
+                                component += 1;
+                                component_offset =
+                                    (component * max_width as u32 * self.fonts.cell_width_px()
+                                        / components[cell_idx])
+                                        as i32;
+                            }
+                            advance = 0;
+                        }
+                        if advance != 0 {
+                            advanced_cell = Some(cell_idx);
+                            component = 0;
+                            x += next_advance;
+                            advance = max_width as i32
+                                * advance.signum()
+                                * self.fonts.cell_width_px() as i32;
+                            next_advance = advance;
+                        }
+                        let basex = x
+                            + component_offset
+                            + (position.x_offset as f32 * advance_scale) as i32;
+
+                        // This assumes that we only want to underline the first character in the
+                        // cluster, and that the remaining characters are all combining characters
+                        // which don't need an underline.
+                        let set = if advance != 0 {
+                            Modifier::BOLD | Modifier::ITALIC | Modifier::UNDERLINED
+                        } else {
+                            Modifier::BOLD | Modifier::ITALIC
+                        };
+
+                        let ch = self.row[info.cluster as usize..].chars().next().unwrap();
+                        if info.glyph_id == 0 {
+                            self.missing_glyphs.insert(ch);
+                        }
+
+                        // Characters which no font can source shape to the .notdef glyph. If
+                        // there's a placeholder for them, it's drawn from the last-resort font
+                        // instead.
+                        let (glyph_font, glyph, glyph_ch) = match self.missing_glyph {
+                            Some(missing) if info.glyph_id == 0 => self
+                                .fonts
+                                .last_resort()
+                                .font()
+                                .glyph_index(missing)
+                                .map_or((font, info.glyph_id, ch), |glyph| {
+                                    (self.fonts.last_resort(), glyph.0 as u32, missing)
+                                }),
+                            _ => (font, info.glyph_id, ch),
+                        };
+                        let glyph_metrics = glyph_font.font();
+                        let glyph_scale =
+                            self.fonts.height_px() as f32 / glyph_metrics.height() as f32;
+
+                        let width = (glyph_metrics
+                            .glyph_hor_advance(GlyphId(glyph as _))
+                            .unwrap_or_default() as f32
+                            * glyph_scale) as u32;
+                        let chars_wide = if decomposed {
+                            (max_width as u32 / components[cell_idx]).max(1)
+                        } else {
+                            glyph_cell_width(ch, max_width)
+                        };
+
+                        let key = Key {
+                            style: cell.modifier.intersection(set),
+                            glyph,
+                            font: glyph_font.id(),
+                            width: chars_wide * self.fonts.min_width_px(),
+                            hinting: self.hinting,
+                        };
+                        let width = if width == 0 {
+                            chars_wide * self.fonts.min_width_px()
+                        } else {
+                            width
+                        };
+
+                        let cached = self.cached.get(
+                            &key,
+                            chars_wide * self.fonts.min_width_px(),
+                            self.fonts.cell_height_px(),
+                        );
+
+                        let offset = (basey.max(0) as usize / self.fonts.cell_height_px() as usize)
+                            .min(bounds.height as usize - 1)
+                            * bounds.width as usize
+                            + (basex.max(0) as usize / self.fonts.cell_width_px() as usize)
+                                .min(bounds.width as usize - 1);
+
+                        sourced.insert((basex, basey, GlyphId(info.glyph_id as _), chars_wide));
+
+                        let mut underline_pos_min = 0;
+                        let mut underline_pos_max = 0;
+                        if key.style.contains(Modifier::UNDERLINED) {
+                            let underline_position = self.fonts.line_padding_top() as u16
+                                + (metrics.ascender() as f32 * advance_scale) as u16;
+                            let underline_thickness = metrics
+                                .underline_metrics()
+                                .map(|m| (m.thickness as f32 * advance_scale) as u16)
+                                .unwrap_or(1);
+                            underline_pos_min = underline_position;
+                            underline_pos_max = underline_pos_min + underline_thickness;
+                        }
+
+                        let mut strikeout_pos_min = 0;
+                        let mut strikeout_pos_max = 0;
+                        if advance != 0 && cell.modifier.contains(Modifier::CROSSED_OUT) {
+                            // Fonts without strikeout metrics get a line through the middle of
+                            // their lowercase letters.
+                            let (position, thickness) = metrics
+                                .strikeout_metrics()
+                                .map(|m| (m.position, m.thickness))
+                                .unwrap_or_else(|| {
+                                    (
+                                        metrics.x_height().unwrap_or(metrics.ascender() * 2 / 3)
+                                            / 2,
+                                        metrics.underline_metrics().map_or(0, |m| m.thickness),
+                                    )
+                                });
+                            strikeout_pos_min = self.fonts.line_padding_top() as u16
+                                + ((metrics.ascender() - position) as f32 * advance_scale) as u16;
+                            strikeout_pos_max = strikeout_pos_min
+                                + ((thickness as f32 * advance_scale) as u16).max(1);
+                        }
+
+                        self.rendered[offset].insert(
+                            (basex, basey, GlyphId(info.glyph_id as _)),
+                            RenderInfo {
+                                cell: y * bounds.width as usize + cell_idx,
+                                cached: *cached,
+                                underline_pos_min,
+                                underline_pos_max,
+                                strikeout_pos_min,
+                                strikeout_pos_max,
+                            },
+                        );
+                        // A cluster in the last column may report a width which extends past the
+                        // edge of the grid. Clamp to the end of the row so we don't dirty cells at
+                        // the start of the next row (or past the end of the final row).
+                        let row_end = (offset / bounds.width as usize + 1) * bounds.width as usize;
+                        for index in offset..(offset + chars_wide as usize).min(row_end) {
+                            self.dirty_cells.set(index, true);
+                        }
+
+                        if cached.cached() {
+                            continue;
+                        }
+
+                        pending_cache_updates
+                            .entry((cached.epoch, key))
+                            .or_insert_with(|| {
+                                let disk_key = DiskKey {
+                                    key,
+                                    fake_italic,
+                                    fake_bold,
+                                };
+                                let from_disk = self
+                                    .glyph_disk_cache
+                                    .as_ref()
+                                    .and_then(|cache| cache.get(&disk_key))
+                                    .filter(|(image, _)| {
+                                        image.len() == (cached.width * cached.height) as usize
+                                    });
+                                if let Some((image, is_emoji)) = from_disk {
+                                    return (*cached, image.clone(), *is_emoji);
+                                }
+
+                                let is_emoji = self.emoji_mode == EmojiMode::Color
+                                    && glyph_ch.is_emoji_char()
+                                    && !matches!(
+                                        glyph_ch.general_category_group(),
+                                        GeneralCategoryGroup::Number
+                                    );
+
+                                let (rect, image) = rasterize_glyph(
+                                    cached,
+                                    glyph_metrics,
+                                    GlyphId(glyph as _),
+                                    fake_italic & !is_emoji,
+                                    fake_bold & !is_emoji,
+                                    glyph_scale,
+                                    width,
+                                    self.glyph_antialiasing,
+                                    self.subpixel_aa,
+                                    self.hinting,
+                                    self.fake_italic_skew,
+                                    self.fonts.cell_height_px() - self.fonts.height_px(),
+                                    self.emoji_mode,
+                                );
+                                if let Some(cache) = self.glyph_disk_cache.as_mut() {
+                                    cache.insert(disk_key, (image.clone(), is_emoji));
+                                }
+                                (rect, image, is_emoji)
+                            });
+                    }
+
+                    buffer.clear()
+                };
+
+                let (levels, runs) = if self.bidi {
+                    let bidi = ParagraphBidiInfo::new(&self.row, None);
+                    bidi.visual_runs(0..bidi.levels.len())
+                } else {
+                    (
+                        vec![Level::ltr(); self.row.len()],
+                        std::iter::once(0..self.row.len()).collect(),
+                    )
+                };
+
+                let (mut current_font, mut current_fake_bold, mut current_fake_italic) = fontmap[0];
+                let mut current_level = Level::ltr();
+
+                for (level, range) in runs.into_iter().map(|run| (levels[run.start], run)) {
+                    let chars = &self.row[range.clone()];
+                    let cells = &self.rowmap[range.clone()];
+                    for (idx, ch) in chars.char_indices() {
+                        let cell_idx = cells[idx] as usize;
+                        let (font, fake_bold, fake_italic) = fontmap[cell_idx];
+
+                        if font.id() != current_font.id()
+                            || current_fake_bold != fake_bold
+                            || current_fake_italic != fake_italic
+                            || current_level != level
+                        {
+                            let mut buffer = std::mem::take(&mut self.buffer);
+                            if !self.bidi {
+                                buffer.set_direction(Direction::LeftToRight);
+                            }
+
+                            self.buffer = shape(
+                                current_font,
+                                current_fake_bold,
+                                current_fake_italic,
+                                shape_with_plan(
+                                    current_font.font(),
+                                    self.plan_cache.get(current_font, &mut buffer),
+                                    buffer,
+                                ),
+                            );
+
+                            current_font = font;
+                            current_fake_bold = fake_bold;
+                            current_fake_italic = fake_italic;
+                            current_level = level;
+                        }
+
+                        self.buffer.add(ch, (range.start + idx) as u32);
+                    }
+                }
+
+                let mut buffer = std::mem::take(&mut self.buffer);
+                if !self.bidi {
+                    buffer.set_direction(Direction::LeftToRight);
+                }
+                self.buffer = shape(
+                    current_font,
+                    current_fake_bold,
+                    current_fake_italic,
+                    shape_with_plan(
+                        current_font.font(),
+                        self.plan_cache.get(current_font, &mut buffer),
+                        buffer,
+                    ),
+                );
+
+                for (new, old) in new_sourced.iter_mut().zip(sourced.iter_mut()) {
+                    if new != old {
+                        for (x, y, glyph, width) in old.difference(new) {
+                            let cell = ((*y).max(0) as usize
+                                / self.fonts.cell_height_px() as usize)
+                                .min(bounds.height as usize - 1)
+                                * bounds.width as usize
+                                + ((*x).max(0) as usize / self.fonts.cell_width_px() as usize)
+                                    .min(bounds.width as usize - 1);
+
+                            let row_end =
+                                (cell / bounds.width as usize + 1) * bounds.width as usize;
+                            for index in cell..(cell + *width as usize).min(row_end) {
+                                self.dirty_cells.set(index, true);
+                            }
+
+                            self.rendered[cell].shift_remove(&(*x, *y, *glyph));
+                        }
+                        std::mem::swap(old, new);
+                    }
+                }
+                self.row_sourced = new_sourced;
+            }
+
+            // Glyphs evicted from the atlas may still be referenced by rows which weren't
+            // redrawn, and which would otherwise pick up whatever replaced them the next
+            // time one of their cells is drawn (e.g. when blinking). Redraw those rows so
+            // their glyphs are rasterized again.
+            let evicted = self
+                .cached
+                .take_evicted()
+                .into_iter()
+                .map(|rect| (rect.page, rect.x, rect.y))
+                .collect::<HashSet<_, RandomState>>();
+            if evicted.is_empty() {
+                break;
+            }
+
+            let mut stale = false;
+            for (y, row) in self.rendered.chunks(bounds.width as usize).enumerate() {
+                if redrawn[y] {
+                    continue;
+                }
+
+                if row
+                    .iter()
+                    .flat_map(|rendered| rendered.values())
+                    .any(|info| evicted.contains(&(info.cached.page, info.cached.x, info.cached.y)))
+                {
+                    self.dirty_rows[y] = true;
+                    stale = true;
+                }
+            }
+
+            if !stale {
+                break;
+            }
+        }
+
+        if self.cached.pages() > self.text_cache.depth_or_array_layers() {
+            self.grow_atlas();
+        }
+
+        self.glyphs_rasterized += pending_cache_updates.len();
+        self.atlas_uploads
+            .extend(pending_cache_updates.into_values());
+        if let Some(cache) = self.glyph_disk_cache.as_mut() {
+            cache.save();
+        }
+        // Glyphs from earlier epochs must be uploaded and drawn before those from
+        // later epochs overwrite them.
+        self.atlas_uploads.sort_by_key(|(cached, ..)| cached.epoch);
+
+        if self.batching {
+            self.batch_pending = true;
+            return Ok(());
+        }
+
+        self.flushed_cells.clone_from(&self.dirty_cells);
+
+        if (self.post_process_enabled && self.post_process.needs_update()) || self.dirty_cells.any()
+        {
+            self.bg_vertices.clear();
+            self.text_vertices.clear();
+            self.text_indices.clear();
+
+            let grid_width_px = bounds.width as u32 * self.fonts.cell_width_px();
+            let metric_lines = self.metric_lines();
+            let focus_ring = self.focus_ring_quads();
+            let cursor = self.cursor_quad();
+            // The cursor inverts whatever is under it, so the cell under it has to be
+            // repainted each time it's drawn.
+            if cursor.is_some() {
+                self.dirty_cells.set(
+                    self.cursor.1 as usize * bounds.width as usize + self.cursor.0 as usize,
+                    true,
+                );
+            }
+
+            // Cells under the ring are drawn again so that the ring isn't blended over
+            // itself, which would accumulate its alpha.
+            if let Some((area, cols, rows, _, _)) = focus_ring {
+                for y in area.top()..area.bottom() {
+                    for x in area.left()..area.right() {
+                        if y < area.top() + rows
+                            || y >= area.bottom() - rows
+                            || x < area.left() + cols
+                            || x >= area.right() - cols
+                        {
+                            self.dirty_cells
+                                .set(y as usize * bounds.width as usize + x as usize, true);
+                        }
+                    }
+                }
+            }
+
+            // Images cover the whole of their area, so every cell under an image is
+            // drawn again along with it. Otherwise the image would cover glyphs which
+            // aren't redrawn. Redrawing the cells under one image may pull in another
+            // overlapping image.
+            let grid = Rect::new(0, 0, bounds.width, bounds.height);
+            self.image_draws.clear();
+            loop {
+                let mut added = false;
+                for (index, image) in self.images.iter().enumerate() {
+                    if self.image_draws.contains(&index) {
+                        continue;
+                    }
+
+                    let area = image.area.intersection(grid);
+                    let cells = || {
+                        area.positions()
+                            .map(|pos| pos.y as usize * bounds.width as usize + pos.x as usize)
+                            .filter(|index| *index < self.dirty_cells.len())
+                    };
+                    if !cells().any(|index| self.dirty_cells[index]) {
+                        continue;
+                    }
+
+                    for index in cells().collect::<Vec<_>>() {
+                        self.dirty_cells.set(index, true);
+                    }
+                    self.image_draws.push(index);
+                    added = true;
+                }
+
+                if !added {
+                    break;
+                }
+            }
+            self.image_draws.sort_unstable();
+
+            self.image_vertices.clear();
+            let [cell_width, cell_height] = [
+                self.fonts.cell_width_px() as f32,
+                self.fonts.cell_height_px() as f32,
+            ];
+            for image in self.image_draws.iter().map(|index| &self.images[*index]) {
+                let area = image.area;
+                let visible = area.intersection(grid);
+                let [u0, u1] = [visible.left(), visible.right()]
+                    .map(|x| (x - area.x) as f32 / area.width as f32);
+                let [v0, v1] = [visible.top(), visible.bottom()]
+                    .map(|y| (y - area.y) as f32 / area.height as f32);
+                let [x0, x1] = [visible.left(), visible.right()].map(|x| x as f32 * cell_width);
+                let [y0, y1] = [visible.top(), visible.bottom()].map(|y| y as f32 * cell_height);
+                self.image_vertices.extend([
+                    ImageVertexMember {
+                        vertex: [x0, y0],
+                        uv: [u0, v0],
+                    },
+                    ImageVertexMember {
+                        vertex: [x1, y0],
+                        uv: [u1, v0],
+                    },
+                    ImageVertexMember {
+                        vertex: [x0, y1],
+                        uv: [u0, v1],
+                    },
+                    ImageVertexMember {
+                        vertex: [x1, y1],
+                        uv: [u1, v1],
+                    },
+                ]);
+            }
+
+            let vertical_damage = if self.vertical_text {
+                self.layout_vertical(bounds)
+            } else {
+                None
+            };
+            let damage = if self.post_process_enabled && self.post_process.needs_update() {
+                let (width, height) = self.text_dest_size();
+                Some(Rect::new(0, 0, width as u16, height as u16))
+            } else if self.vertical_text {
+                vertical_damage
+            } else {
+                let (offset_x, offset_y) = self.grid_offset();
+                let [cell_width, cell_height] = [
+                    self.fonts.cell_width_px() as u16,
+                    self.fonts.cell_height_px() as u16,
+                ];
+                self.dirty_cells
+                    .iter_ones()
+                    .map(|index| {
+                        let x = (index % bounds.width as usize) as u16;
+                        let y = (index / bounds.width as usize) as u16;
+                        let columns = cell_columns(&self.cells[index])
+                            .min((bounds.width - x) as usize)
+                            as u16;
+                        Rect::new(
+                            offset_x as u16 + x * cell_width,
+                            offset_y as u16 + y * cell_height,
+                            columns * cell_width,
+                            cell_height,
+                        )
+                    })
+                    .reduce(Rect::union)
+            };
+            self.damage = match (self.damage, damage) {
+                (Some(damage), Some(new)) => Some(damage.union(new)),
+                (damage, new) => damage.or(new),
+            };
+
+            let mut underlines = Vec::<([f32; 4], u32)>::new();
+            let mut strikeouts = Vec::<([f32; 4], u32)>::new();
+            let mut index_offset = 0;
+            let mut quad_epochs = vec![];
+            // The first vertex of the last solid background quad, which the next cell
+            // can extend if it's adjacent and the same color.
+            let mut bg_run = None::<usize>;
+            for index in self.dirty_cells.iter_ones() {
+                let cell = &self.cells[index];
+                let to_render = &self.rendered[index];
+
+                let reverse = cell.modifier.contains(Modifier::REVERSED);
+                let bg_color = if reverse {
+                    c2c(cell.fg, self.reset_fg, &self.palette)
+                } else {
+                    c2c(cell.bg, self.reset_bg, &self.palette)
+                };
+
+                let overrides = self.cell_overrides(index, bounds.width);
+                let ([r, g, b], bg_alpha) = match overrides.bg {
+                    Some(bg) => ([bg.r, bg.g, bg.b], bg.a),
+                    // Transparent black keeps the composited text premultiplied once glyphs
+                    // are blended over it.
+                    None if self.transparent_background && !reverse && cell.bg == Color::Reset => {
+                        ([0, 0, 0], 0)
+                    }
+                    None => (bg_color, 255),
+                };
+                let bg_alpha = overrides
+                    .alpha
+                    .map_or(bg_alpha, |opacity| (bg_alpha as f32 * opacity) as u8);
+                let bg_color_u32: u32 = u32::from_be_bytes([r, g, b, bg_alpha]);
+
+                let y = (index as u32 / bounds.width as u32 * self.fonts.cell_height_px()) as f32;
+                let x = (index as u32 % bounds.width as u32 * self.fonts.cell_width_px()) as f32;
+                let remaining_columns = bounds.width as usize - index % bounds.width as usize;
+                for offset_x in 0..cell_columns(cell).min(remaining_columns) {
+                    let x = x + (offset_x as u32 * self.fonts.cell_width_px()) as f32;
+                    let [w, h] = [
+                        self.fonts.cell_width_px() as f32,
+                        self.fonts.cell_height_px() as f32,
+                    ];
+                    let corners = [[x, y], [x + w, y], [x, y + h], [x + w, y + h]];
+
+                    let position = Position::new(
+                        (index % bounds.width as usize + offset_x) as u16,
+                        (index / bounds.width as usize) as u16,
+                    );
+                    let gradient = self
+                        .region_gradients
+                        .iter()
+                        .rev()
+                        .find(|gradient| gradient.area.contains(position));
+                    let colors = corners.map(|vertex| {
+                        let Some(gradient) = gradient else {
+                            return bg_color_u32;
+                        };
+
+                        let area = gradient.area;
+                        let origin = [area.x as f32 * w, area.y as f32 * h];
+                        let color = gradient.color_at(
+                            [vertex[0] - origin[0], vertex[1] - origin[1]],
+                            [area.width as f32 * w, area.height as f32 * h],
+                        );
+                        let alpha = overrides
+                            .alpha
+                            .map_or(color.a, |opacity| (color.a as f32 * opacity) as u8);
+                        u32::from_be_bytes([color.r, color.g, color.b, alpha])
+                    });
+
+                    let run = bg_run
+                        .filter(|run| {
+                            gradient.is_none()
+                                && *run + 4 == self.bg_vertices.len()
+                                && self.bg_vertices[*run + 1].vertex == [x, y]
+                                && self.bg_vertices[*run].bg_color == bg_color_u32
+                        })
+                        .map(|run| &mut self.bg_vertices[run..]);
+                    if let Some([_, top_right, _, bottom_right]) = run {
+                        top_right.vertex[0] = x + w;
+                        bottom_right.vertex[0] = x + w;
+                    } else {
+                        bg_run = gradient.is_none().then_some(self.bg_vertices.len());
+                        for (vertex, bg_color) in corners.into_iter().zip(colors) {
+                            self.bg_vertices
+                                .push(TextBgVertexMember { vertex, bg_color });
+                        }
+                    }
+
+                    for (line_y, color) in metric_lines.iter().copied() {
+                        let y = y + line_y;
+                        let width = self.fonts.cell_width_px() as f32;
+                        self.bg_vertices.push(TextBgVertexMember {
+                            vertex: [x, y],
+                            bg_color: color,
+                        });
+                        self.bg_vertices.push(TextBgVertexMember {
+                            vertex: [x + width, y],
+                            bg_color: color,
+                        });
+                        self.bg_vertices.push(TextBgVertexMember {
+                            vertex: [x, y + 1.0],
+                            bg_color: color,
+                        });
+                        self.bg_vertices.push(TextBgVertexMember {
+                            vertex: [x + width, y + 1.0],
+                            bg_color: color,
+                        });
+                    }
+                }
+
+                let (cell_x, cell_y) = (x, y);
+                for (
+                    (x, y, _),
+                    RenderInfo {
+                        cell,
+                        cached,
+                        underline_pos_min,
+                        underline_pos_max,
+                        strikeout_pos_min,
+                        strikeout_pos_max,
+                    },
+                ) in to_render.iter()
+                {
+                    let overrides = self.cell_overrides(*cell, bounds.width);
+                    let opacity = overrides.alpha.unwrap_or(1.0);
+                    let fg_override = overrides.fg;
+                    let cell = &self.cells[*cell];
+                    let reverse = cell.modifier.contains(Modifier::REVERSED);
+                    let fg_color = if let Some(fg) = fg_override {
+                        [fg.r, fg.g, fg.b]
+                    } else if reverse {
+                        c2c(cell.bg, self.reset_bg, &self.palette)
+                    } else {
+                        c2c(cell.fg, self.reset_fg, &self.palette)
+                    };
+                    let dim = cell.modifier.contains(Modifier::DIM);
+                    let blend_dim = dim && self.dim_mode == DimMode::Blend;
+                    let bg_color = || {
+                        if let Some(bg) = overrides.bg {
+                            [bg.r, bg.g, bg.b]
+                        } else if reverse {
+                            c2c(cell.fg, self.reset_fg, &self.palette)
+                        } else {
+                            c2c(cell.bg, self.reset_bg, &self.palette)
+                        }
+                    };
+                    let fg_color = if self.min_contrast > 1.0 {
+                        ensure_contrast(fg_color, bg_color(), self.min_contrast)
+                    } else {
+                        fg_color
+                    };
+                    let fg_color = if blend_dim {
+                        blend_halfway(fg_color, bg_color())
+                    } else {
+                        fg_color
+                    };
+
+                    let alpha = if cell.modifier.contains(Modifier::HIDDEN)
+                        | (cell.modifier.contains(Modifier::RAPID_BLINK) & !self.show_fast)
+                        | (cell.modifier.contains(Modifier::SLOW_BLINK) & !self.show_slow)
+                    {
+                        0
+                    } else if dim && !blend_dim {
+                        127
+                    } else {
+                        255
+                    };
+                    let alpha =
+                        fg_override.map_or(alpha, |fg| (alpha as u16 * fg.a as u16 / 255) as u8);
+                    let alpha = (alpha as f32 * opacity) as u8;
+
+                    let [r, g, b] = fg_color;
+                    let fg_color: u32 = u32::from_be_bytes([r, g, b, alpha]);
+
+                    // Glyphs are rasterized at the width of the font, so they're centered in
+                    // cells which are padded out wider than that.
+                    let columns = cached.width / self.fonts.min_width_px();
+                    let glyph_offset = columns * self.fonts.width_padding_px() / 2;
+
+                    // Underlines and strikethroughs are drawn as rules aligned to the grid
+                    // rather than with each glyph, since glyph offsets don't always tile and
+                    // would leave gaps. Rules for adjacent cells are merged when their
+                    // position and color match.
+                    for (rules, pos_min, pos_max) in [
+                        (&mut underlines, underline_pos_min, underline_pos_max),
+                        (&mut strikeouts, strikeout_pos_min, strikeout_pos_max),
+                    ] {
+                        if pos_max <= pos_min {
+                            continue;
+                        }
+
+                        let x0 = cell_x;
+                        let x1 = (x0 + columns as f32 * self.fonts.cell_width_px() as f32)
+                            .min(grid_width_px as f32);
+                        let y0 = cell_y + *pos_min as f32;
+                        let y1 = cell_y + *pos_max as f32;
+                        match rules.last_mut() {
+                            Some(([_, last_y0, last_x1, last_y1], color))
+                                if *last_x1 == x0
+                                    && *last_y0 == y0
+                                    && *last_y1 == y1
+                                    && *color == fg_color =>
+                            {
+                                *last_x1 = x1;
+                            }
+                            _ => rules.push(([x0, y0, x1, y1], fg_color)),
+                        }
+                    }
+
+                    for offset_x in (0..cached.width).step_by(self.fonts.min_width_px() as usize) {
+                        // Glyphs in the last column (e.g. combining marks or wide characters)
+                        // may extend past the edge of the grid. Clip their quads to the
+                        // drawable width so they can't bleed outside of the grid.
+                        let x = *x as f32 + (glyph_offset + offset_x) as f32;
+                        if x >= grid_width_px as f32 {
+                            break;
+                        }
+                        let width =
+                            (grid_width_px as f32 - x).min(self.fonts.min_width_px() as f32);
+
+                        self.text_indices.push([
+                            index_offset,     // x, y
+                            index_offset + 1, // x + w, y
+                            index_offset + 2, // x, y + h
+                            index_offset + 2, // x, y + h
+                            index_offset + 3, // x + w, y + h
+                            index_offset + 1, // x + w y
+                        ]);
+                        index_offset += 4;
+                        quad_epochs.push(cached.epoch);
+
+                        let y = *y as f32;
+                        let uvx = cached.x + offset_x;
+                        let uvy = cached.y;
+
+                        // 0
+                        self.text_vertices.push(TextVertexMember {
+                            vertex: [x, y],
+                            uv: [uvx as f32, uvy as f32],
+                            fg_color,
+                            page: cached.page,
+                        });
+                        // 1
+                        self.text_vertices.push(TextVertexMember {
+                            vertex: [x + width, y],
+                            uv: [uvx as f32 + width, uvy as f32],
+                            fg_color,
+                            page: cached.page,
+                        });
+                        // 2
+                        self.text_vertices.push(TextVertexMember {
+                            vertex: [x, y + self.fonts.cell_height_px() as f32],
+                            uv: [uvx as f32, uvy as f32 + self.fonts.cell_height_px() as f32],
+                            fg_color,
+                            page: cached.page,
+                        });
+                        // 3
+                        self.text_vertices.push(TextVertexMember {
+                            vertex: [x + width, y + self.fonts.cell_height_px() as f32],
+                            uv: [
+                                uvx as f32 + width,
+                                uvy as f32 + self.fonts.cell_height_px() as f32,
+                            ],
+                            fg_color,
+                            page: cached.page,
+                        });
+                    }
+                }
+            }
+
+            self.overlay_quads = 0;
+            let cell_size = [
+                self.fonts.cell_width_px() as f32,
+                self.fonts.cell_height_px() as f32,
+            ];
+            let underlines = underlines.into_iter().flat_map(|(rule, color)| {
+                underline_quads(self.underline_style, rule, cell_size)
+                    .into_iter()
+                    .map(move |quad| (quad, color))
+            });
+            for ([x0, y0, x1, y1], color) in underlines.chain(strikeouts) {
+                for vertex in [[x0, y0], [x1, y0], [x0, y1], [x1, y1]] {
+                    self.bg_vertices.push(TextBgVertexMember {
+                        vertex,
+                        bg_color: color,
+                    });
+                }
+                self.overlay_quads += 1;
+            }
+
+            if let Some((_, _, _, quads, color)) = focus_ring {
+                for [x0, y0, x1, y1] in quads {
+                    for vertex in [[x0, y0], [x1, y0], [x0, y1], [x1, y1]] {
+                        self.bg_vertices.push(TextBgVertexMember {
+                            vertex,
+                            bg_color: color,
+                        });
+                    }
+                    self.overlay_quads += 1;
+                }
+            }
+
+            self.cursor_quads = 0;
+            if let Some([x0, y0, x1, y1]) = cursor {
+                for vertex in [[x0, y0], [x1, y0], [x0, y1], [x1, y1]] {
+                    self.bg_vertices.push(TextBgVertexMember {
+                        vertex,
+                        bg_color: u32::MAX,
+                    });
+                }
+                self.cursor_quads = 1;
+            }
+
+            // The background pass shares the index buffer with the text pass, so it needs
+            // to cover any extra quads from the metric overlay, focus ring, and cursor as
+            // well.
+            for quad in self.text_indices.len() as u32..self.bg_vertices.len() as u32 / 4 {
+                let offset = quad * 4;
+                self.text_indices.push([
+                    offset,
+                    offset + 1,
+                    offset + 2,
+                    offset + 2,
+                    offset + 3,
+                    offset + 1,
+                ]);
+            }
+
+            // Quads are drawn in batches by epoch, so that glyphs are drawn before a
+            // later epoch replaces them in the atlas.
+            if quad_epochs.windows(2).any(|pair| pair[0] != pair[1]) {
+                let mut order = (0..quad_epochs.len()).collect::<Vec<_>>();
+                order.sort_by_key(|&quad| quad_epochs[quad]);
+                self.text_vertices = order
+                    .iter()
+                    .flat_map(|&quad| self.text_vertices[quad * 4..quad * 4 + 4].iter().copied())
+                    .collect();
+                quad_epochs.sort_unstable();
+            }
+            self.text_batches.clear();
+            for (quad, epoch) in quad_epochs.into_iter().enumerate() {
+                match self.text_batches.last_mut() {
+                    Some((last, end)) if *last == epoch => *end = quad as u32 + 1,
+                    _ => self.text_batches.push((epoch, quad as u32 + 1)),
+                }
+            }
+
+            self.last_frame_stats = FrameStats {
+                glyphs_rasterized: std::mem::take(&mut self.glyphs_rasterized),
+                dirty_cells: self.dirty_cells.count_ones(),
+                vertices: self.bg_vertices.len() + self.text_vertices.len(),
+                atlas_entries: self.cached.len(),
+                atlas_capacity: self.cached.capacity(),
+            };
+
+            self.render();
+        } else {
+            self.upload_glyphs(u64::MAX);
+        }
+
+        Ok(())
+    }
+
+    fn clear_region(&mut self, clear_type: ClearType) -> std::io::Result<()> {
+        let bounds = self.size()?;
+        self.fit_to_grid();
+        let line_start = self.cursor.1 as usize * bounds.width as usize;
+        let idx = line_start + self.cursor.0 as usize;
+
+        match clear_type {
+            ClearType::All => self.clear(),
+            ClearType::AfterCursor => {
+                self.cells[idx + 1..].fill(Cell::EMPTY);
+                Ok(())
+            }
+            ClearType::BeforeCursor => {
+                self.cells[..idx].fill(Cell::EMPTY);
+                Ok(())
+            }
+            ClearType::CurrentLine => {
+                self.cells[line_start..line_start + bounds.width as usize].fill(Cell::EMPTY);
+                Ok(())
+            }
+            ClearType::UntilNewLine => {
+                let remain = (bounds.width - self.cursor.0) as usize;
+                self.cells[idx..idx + remain].fill(Cell::EMPTY);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Mix `fg` halfway towards `bg`, which is how [`DimMode::Blend`] dims text.
+fn blend_halfway(fg: [u8; 3], bg: [u8; 3]) -> [u8; 3] {
+    std::array::from_fn(|idx| ((fg[idx] as u16 + bg[idx] as u16) / 2) as u8)
+}
+
+/// The number of grid columns a cell occupies. Empty cells are placeholders
+/// covered by a preceding wide character and occupy no columns, while any other
+/// symbol occupies at least one column even if it is zero width.
+fn cell_columns(cell: &Cell) -> usize {
+    if cell.symbol().is_empty() {
+        0
+    } else {
+        cell.symbol().width().max(1)
+    }
+}
+
+fn glyph_cell_width(ch: char, fallback: usize) -> u32 {
+    match ch.width().unwrap_or(fallback) {
+        0 => 1,
+        width => width as u32,
+    }
+}
+
+/// Split an underline rule into the quads which draw it in the given style.
+/// The quads are kept within the row containing the rule. Patterns are aligned
+/// to the grid so that they line up between separately drawn rules.
+fn underline_quads(
+    style: UnderlineStyle,
+    [x0, y0, x1, y1]: [f32; 4],
+    [cell_width, cell_height]: [f32; 2],
+) -> Vec<[f32; 4]> {
+    let thickness = y1 - y0;
+    let row_top = (y0 / cell_height).floor() * cell_height;
+    let row_bottom = row_top + cell_height;
+    // Move the rule up if the style needs more height than is available below it.
+    let fit = |height: f32| y0.min(row_bottom - height).max(row_top);
+
+    let segments = |length: f32, gap: f32| {
+        let period = length + gap;
+        let mut x = (x0 / period).floor() * period;
+        let mut quads = vec![];
+        while x < x1 {
+            let start = x.max(x0);
+            let end = (x + length).min(x1);
+            if start < end {
+                quads.push([start, y0, end, y1]);
+            }
+            x += period;
+        }
+        quads
+    };
+
+    match style {
+        UnderlineStyle::Single => vec![[x0, y0, x1, y1]],
+        UnderlineStyle::Double => {
+            let y0 = fit(thickness * 3.0);
+            vec![
+                [x0, y0, x1, y0 + thickness],
+                [x0, y0 + thickness * 2.0, x1, y0 + thickness * 3.0],
+            ]
+        }
+        UnderlineStyle::Dotted => segments(thickness, thickness),
+        UnderlineStyle::Dashed => segments(thickness * 4.0, thickness * 2.0),
+        UnderlineStyle::Curly => {
+            let amplitude = (thickness * 2.0).max(2.0);
+            let y0 = fit(amplitude + thickness);
+            (x0 as u32..x1 as u32)
+                .map(|x| {
+                    let phase = x as f32 / cell_width * std::f32::consts::TAU;
+                    let y = (y0 + amplitude * (1.0 - phase.cos()) / 2.0).round();
+                    [x as f32, y, x as f32 + 1.0, y + thickness]
+                })
+                .collect()
+        }
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+#[allow(clippy::too_many_arguments)]
+fn rasterize_glyph(
+    cached: Entry,
+    metrics: &rustybuzz::Face,
+    glyph: GlyphId,
+    fake_italic: bool,
+    fake_bold: bool,
+    advance_scale: f32,
+    actual_width: u32,
+    antialias: bool,
+    subpixel_aa: bool,
+    hinting: HintingMode,
+    italic_skew: f32,
+    line_padding: u32,
+    emoji_mode: EmojiMode,
+) -> (CacheRect, Vec<u32>) {
+    let scale = cached.width as f32 / actual_width as f32;
+    let computed_offset_x = -(cached.width as f32 * (1.0 - scale));
+    // Cells taller than the font center the glyph vertically, with any odd pixel of
+    // padding going below it.
+    let computed_offset_y =
+        (cached.height - line_padding) as f32 * (1.0 - scale) + (line_padding / 2) as f32 * 2.0;
+    let scale = scale * advance_scale * 2.0;
+
+    let skew = if fake_italic {
+        Transform::new(
+            /* scale x */ 1.0,
+            /* skew x */ 0.0,
+            /* skew y */ -italic_skew,
+            /* scale y */ 1.0,
+            /* translate x */ -italic_skew * cached.width as f32,
+            /* translate y */ 0.0,
+        )
+    } else {
+        Transform::default()
+    };
+
+    let mut image = vec![0u32; cached.width as usize * 2 * cached.height as usize * 2];
+    let mut target = DrawTarget::from_backing(
+        cached.width as i32 * 2,
+        cached.height as i32 * 2,
+        &mut image[..],
+    );
+
+    let mut painter = Painter::new(
+        metrics,
+        &mut target,
+        skew,
+        scale,
+        metrics.ascender() as f32 * scale + computed_offset_y,
+        computed_offset_x,
+    );
+    if emoji_mode == EmojiMode::Color
+        && metrics
+            .paint_color_glyph(glyph, 0, RgbaColor::new(255, 255, 255, 255), &mut painter)
+            .is_some()
+    {
+        let mut final_image = DrawTarget::new(cached.width as i32, cached.height as i32);
+        final_image.draw_image_with_size_at(
+            cached.width as f32,
+            cached.height as f32,
+            0.,
+            0.,
+            &raqote::Image {
+                width: cached.width as i32 * 2,
+                height: cached.height as i32 * 2,
+                data: &image,
+            },
+            &DrawOptions {
+                blend_mode: raqote::BlendMode::Src,
+                antialias: raqote::AntialiasMode::None,
+                ..Default::default()
+            },
+        );
+
+        let mut final_image = final_image.into_vec();
+        for argb in final_image.iter_mut() {
+            let [a, r, g, b] = argb.to_be_bytes();
+            *argb = u32::from_le_bytes([r, g, b, a]);
+        }
+
+        return (*cached, final_image);
+    }
+
+    if let Some(raster) = metrics
+        .glyph_raster_image(glyph, u16::MAX)
+        .filter(|_| emoji_mode == EmojiMode::Color)
+    {
+        if let Some((_, glyph)) = extract_color_image(
+            &mut image,
+            raster,
+            Entry::Uncached(CacheRect {
+                height: cached.height - line_padding,
+                ..*cached
+            }),
+            advance_scale,
+        ) {
+            return (*cached, pad_to_cell(glyph, *cached, line_padding));
+        }
+    }
+
+    let mut render = Outline::default();
+    if let Some(bounds) = metrics.outline_glyph(glyph, &mut render) {
+        // Some fonts return bounds that are entirely negative. I'm not sure why this
+        // is, but it means the glyph won't render at all. We check for this here and
+        // offset it if so. This seems to let those fonts render correctly.
+        let x_off = if bounds.x_max < 0 {
+            -bounds.x_min as f32
+        } else {
+            0.
+        };
+        let x_off = x_off * scale + computed_offset_x;
+        let y_off = metrics.ascender() as f32 * scale + computed_offset_y;
+
+        // The outline is rendered to a 2x target and downsampled, so hinting snaps to
+        // every other pixel of the target.
+        let path = render.finish_hinted(hinting, scale / 2.0, x_off / 2.0, y_off / 2.0);
+
+        // Subpixel antialiasing renders each of the red, green, and blue subpixels as
+        // its own column of the target.
+        let subpixels = if subpixel_aa && antialias { 3 } else { 1 };
+        if subpixels != 1 {
+            let len = image.len() * subpixels;
+            image.clear();
+            image.resize(len, 0);
+        }
+
+        let mut target = DrawTarget::from_backing(
+            cached.width as i32 * 2 * subpixels as i32,
+            cached.height as i32 * 2,
+            &mut image[..],
+        );
+        target.set_transform(
+            &Transform::scale(scale, -scale)
+                .then(&skew)
+                .then_translate((x_off, y_off).into())
+                .then_scale(subpixels as f32, 1.0),
+        );
+
+        target.fill(
+            &path,
+            &raqote::Source::Solid(SolidSource::from_unpremultiplied_argb(255, 255, 255, 255)),
+            &DrawOptions::default(),
+        );
+
+        if fake_bold {
+            // Dilate the glyph by stroking its outline. The stroke is centered on the
+            // outline, so each edge moves out by half the stroke width and stems end up
+            // one stroke width wider. The path is in font units, so the width has to be
+            // converted from pixels of the 2x target.
+            let width = cached.height as f32 / FAKE_BOLD_DIVISOR * 2.0;
+            target.stroke(
+                &path,
+                &raqote::Source::Solid(SolidSource::from_unpremultiplied_argb(255, 255, 255, 255)),
+                &StrokeStyle {
+                    width: width / scale,
+                    join: raqote::LineJoin::Round,
+                    ..Default::default()
+                },
+                &DrawOptions::new(),
+            );
+        }
+
+        if subpixels != 1 {
+            return (
+                *cached,
+                downsample_subpixels(&image, cached.width, cached.height),
+            );
+        }
+
+        let mut final_image = DrawTarget::new(cached.width as i32, cached.height as i32);
+        final_image.draw_image_with_size_at(
+            cached.width as f32,
+            cached.height as f32,
+            0.,
+            0.,
+            &raqote::Image {
+                width: cached.width as i32 * 2,
+                height: cached.height as i32 * 2,
+                data: &image,
+            },
+            &DrawOptions {
+                blend_mode: raqote::BlendMode::Src,
+                antialias: raqote::AntialiasMode::None,
+                ..Default::default()
+            },
+        );
+
+        let mut final_image = final_image.into_vec();
+        if !antialias {
+            // Downsampling from the 2x target always produces partial coverage, so we
+            // threshold the final image rather than just disabling antialiasing in the
+            // fill.
+            for argb in final_image.iter_mut() {
+                *argb = if *argb >> 24 >= 128 { u32::MAX } else { 0 };
+            }
+        }
+
+        return (*cached, final_image);
+    }
+
+    if let Some(raster) = metrics.glyph_raster_image(glyph, u16::MAX) {
+        if let Some((_, glyph)) = extract_bw_image(
+            &mut image,
+            raster,
+            Entry::Uncached(CacheRect {
+                height: cached.height - line_padding,
+                ..*cached
+            }),
+            advance_scale,
+        ) {
+            return (*cached, pad_to_cell(glyph, *cached, line_padding));
+        }
+    }
+
+    (
+        *cached,
+        vec![0u32; cached.width as usize * cached.height as usize],
+    )
+}
+
+/// Weights for spreading each subpixel's coverage over its neighbors, out of
+/// 256. This is FreeType's default LCD filter, and reduces color fringing at
+/// the cost of some sharpness.
+const LCD_FILTER: [u32; 5] = [8, 77, 86, 77, 8];
+
+/// Downsample a glyph rendered at 2x with three columns per pixel into per
+/// channel coverage. The result is in the atlas's rgba layout, with the alpha
+/// channel holding the largest coverage of the three channels.
+fn downsample_subpixels(image: &[u32], width: u32, height: u32) -> Vec<u32> {
+    let subpixels = width as usize * 3;
+    let stride = subpixels * 2;
+
+    let mut coverage = vec![0u32; subpixels];
+    let mut final_image = Vec::with_capacity(width as usize * height as usize);
+    for rows in image.chunks_exact(stride * 2).take(height as usize) {
+        let (top, bottom) = rows.split_at(stride);
+        for (subpixel, coverage) in coverage.iter_mut().enumerate() {
+            let range = subpixel * 2..subpixel * 2 + 2;
+            *coverage = top[range.clone()]
+                .iter()
+                .chain(&bottom[range])
+                .map(|argb| argb >> 24)
+                .sum::<u32>()
+                / 4;
+        }
+
+        for x in 0..width as usize {
+            let [r, g, b] = std::array::from_fn(|channel| {
+                let center = x * 3 + channel;
+                LCD_FILTER
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(tap, weight)| {
+                        (center + tap)
+                            .checked_sub(LCD_FILTER.len() / 2)
+                            .and_then(|subpixel| coverage.get(subpixel))
+                            .map(|coverage| coverage * weight)
+                    })
+                    .sum::<u32>()
+                    / 256
+            });
+            let a = r.max(g).max(b);
+            final_image.push(u32::from_le_bytes([r as u8, g as u8, b as u8, a as u8]));
+        }
+    }
+
+    final_image
+}
+
+/// Bitmap glyphs are stretched to fill the image they're extracted into, so
+/// they are extracted at the height of the font and then padded out to the
+/// height of the cell.
+fn pad_to_cell(glyph: Vec<u32>, cell: CacheRect, line_padding: u32) -> Vec<u32> {
+    if line_padding == 0 {
+        return glyph;
+    }
+
+    let top = (line_padding / 2) as usize * cell.width as usize;
+    let mut image = vec![0u32; cell.width as usize * cell.height as usize];
+    image[top..top + glyph.len()].copy_from_slice(&glyph);
+    image
+}
+
+fn extract_color_image(
+    image: &mut Vec<u32>,
+    raster: RasterGlyphImage,
+    cached: Entry,
+    scale: f32,
+) -> Option<(CacheRect, Vec<u32>)> {
+    match raster.format {
+        RasterImageFormat::PNG => {
+            #[cfg(feature = "png")]
+            {
+                let decoder = png::Decoder::new(std::io::Cursor::new(raster.data));
+                if let Ok(mut info) = decoder.read_info() {
+                    image.resize(info.output_buffer_size() / size_of::<u32>(), 0);
+                    if info.next_frame(bytemuck::cast_slice_mut(image)).is_err() {
+                        return None;
+                    }
+
+                    for rgba in image.iter_mut() {
+                        let [r, g, b, a] = rgba.to_be_bytes();
+                        *rgba = u32::from_be_bytes([a, r, g, b]);
+                    }
+                } else {
+                    return None;
+                }
+            }
+            #[cfg(not(feature = "png"))]
+            return None;
+        }
+        RasterImageFormat::BitmapPremulBgra32 => {
+            image.resize(raster.width as usize * raster.height as usize, 0);
+            for (y, row) in raster.data.chunks(raster.width as usize * 4).enumerate() {
+                for (x, pixel) in row.chunks(4).enumerate() {
+                    let pixel: &[u8; 4] = pixel.try_into().expect("Invalid chunk size");
+                    let [b, g, r, a] = *pixel;
+                    let pixel = u32::from_be_bytes([
+                        a,
+                        r.saturating_mul(255 / a),
+                        g.saturating_mul(255 / a),
+                        b.saturating_mul(255 / a),
+                    ]);
+                    image[y * raster.width as usize + x] = pixel;
+                }
+            }
+        }
+        _ => return None,
+    }
+
+    let mut final_image = DrawTarget::new(cached.width as i32, cached.height as i32);
+    final_image.draw_image_with_size_at(
+        cached.width as f32,
+        cached.height as f32,
+        raster.x as f32 * scale,
+        raster.y as f32 * scale,
+        &raqote::Image {
+            width: raster.width as i32,
+            height: raster.height as i32,
+            data: &*image,
+        },
+        &DrawOptions {
+            blend_mode: raqote::BlendMode::Src,
+            antialias: raqote::AntialiasMode::None,
+            ..Default::default()
+        },
+    );
+
+    let mut final_image = final_image.into_vec();
+    for argb in final_image.iter_mut() {
+        let [a, r, g, b] = argb.to_be_bytes();
+        *argb = u32::from_le_bytes([r, g, b, a]);
+    }
+
+    Some((*cached, final_image))
+}
+
+fn extract_bw_image(
+    image: &mut Vec<u32>,
+    raster: RasterGlyphImage,
+    cached: Entry,
+    scale: f32,
+) -> Option<(CacheRect, Vec<u32>)> {
+    image.resize(raster.width as usize * raster.height as usize, 0);
+
+    match raster.format {
+        RasterImageFormat::BitmapMono => {
+            from_gray_unpacked::<1, 2>(image, raster, LUT_1);
+        }
+        RasterImageFormat::BitmapMonoPacked => {
+            from_gray_packed::<1, 2>(image, raster, LUT_1);
+        }
+        RasterImageFormat::BitmapGray2 => {
+            from_gray_unpacked::<2, 4>(image, raster, LUT_2);
+        }
+        RasterImageFormat::BitmapGray2Packed => {
+            from_gray_packed::<2, 4>(image, raster, LUT_2);
+        }
+        RasterImageFormat::BitmapGray4 => {
+            from_gray_unpacked::<4, 16>(image, raster, LUT_4);
+        }
+        RasterImageFormat::BitmapGray4Packed => {
+            from_gray_packed::<4, 16>(image, raster, LUT_4);
+        }
+        RasterImageFormat::BitmapGray8 => {
+            for (byte, dst) in raster.data.iter().zip(image.iter_mut()) {
+                *dst = u32::from_be_bytes([*byte, 255, 255, 255]);
+            }
+        }
+        _ => return None,
+    }
+
+    let mut final_image = DrawTarget::new(cached.width as i32, cached.height as i32);
+    final_image.draw_image_with_size_at(
+        cached.width as f32,
+        cached.height as f32,
+        raster.x as f32 * scale,
+        raster.y as f32 * scale,
+        &raqote::Image {
+            width: raster.width as i32,
+            height: raster.height as i32,
+            data: &*image,
+        },
+        &DrawOptions {
+            blend_mode: raqote::BlendMode::Src,
+            antialias: raqote::AntialiasMode::None,
+            ..Default::default()
+        },
+    );
+
+    let mut final_image = final_image.into_vec();
+    for argb in final_image.iter_mut() {
+        let [a, r, g, b] = argb.to_be_bytes();
+        *argb = u32::from_le_bytes([r, g, b, a]);
+    }
+
+    Some((*cached, final_image))
+}
+
+fn from_gray_unpacked<const BITS: usize, const ENTRIES: usize>(
+    image: &mut [u32],
+    raster: RasterGlyphImage,
+    steps: [u8; ENTRIES],
+) {
+    for (bits, dst) in raster
+        .data
+        .chunks((raster.width as usize / (8 / BITS)) + 1)
+        .zip(image.chunks_mut(raster.width as usize))
+    {
+        let bits = BitSlice::<_, Lsb0>::from_slice(bits);
+        for (bits, dst) in bits.chunks(BITS).zip(dst.iter_mut()) {
+            let mut index = 0;
+            for idx in bits.iter_ones() {
+                index |= 1 << (BITS - idx - 1);
+            }
+            let value = steps[index as usize];
+            *dst = u32::from_be_bytes([value, 255, 255, 255]);
+        }
+    }
+}
+
+fn from_gray_packed<const BITS: usize, const ENTRIES: usize>(
+    image: &mut [u32],
+    raster: RasterGlyphImage,
+    steps: [u8; ENTRIES],
+) {
+    let bits = BitSlice::<_, Lsb0>::from_slice(raster.data);
+    for (bits, dst) in bits.chunks(BITS).zip(image.iter_mut()) {
+        let mut index = 0;
+        for idx in bits.iter_ones() {
+            index |= 1 << (BITS - idx - 1);
+        }
+        let value = steps[index as usize];
+        *dst = u32::from_be_bytes([value, 255, 255, 255]);
+    }
+}
+
+const LUT_1: [u8; 2] = [0, 255];
+const LUT_2: [u8; 4] = [0, 255 / 3, 2 * (255 / 3), 255];
+const LUT_4: [u8; 16] = [
+    0,
+    (255 / 15),
+    2 * (255 / 15),
+    3 * (255 / 15),
+    4 * (255 / 15),
+    5 * (255 / 15),
+    6 * (255 / 15),
+    7 * (255 / 15),
+    8 * (255 / 15),
+    9 * (255 / 15),
+    10 * (255 / 15),
+    11 * (255 / 15),
+    12 * (255 / 15),
+    13 * (255 / 15),
+    14 * (255 / 15),
+    255,
+];
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{
+            hash_map::DefaultHasher,
+            HashSet,
+        },
+        hash::{
+            BuildHasher,
+            BuildHasherDefault,
+            Hash,
+            Hasher,
+        },
+        num::NonZeroU32,
+        sync::Arc,
+    };
+
+    use image::{
+        load_from_memory,
+        GenericImageView,
+        ImageBuffer,
+        Rgba,
+    };
+    use ratatui::{
+        backend::{
+            Backend,
+            ClearType,
+        },
+        buffer::Buffer,
+        layout::{
+            Position,
+            Rect,
+            Size,
+        },
+        style::{
+            Color,
+            Modifier,
+            Style,
+            Stylize,
+        },
+        text::{
+            Line,
+            Span,
+        },
+        widgets::{
+            Block,
+            Paragraph,
+        },
+        Terminal,
+    };
+    use rustybuzz::ttf_parser::{
+        RasterGlyphImage,
+        RasterImageFormat,
+    };
+    use serial_test::serial;
+    use unicode_width::UnicodeWidthChar;
+    use wgpu::{
+        Backends,
+        CommandEncoderDescriptor,
+        CompositeAlphaMode,
+        Device,
+        Extent3d,
+        ImageCopyBuffer,
+        ImageDataLayout,
+        InstanceDescriptor,
+        InstanceFlags,
+        PresentMode,
+        Queue,
+        TextureFormat,
+    };
+
+    use crate::{
+        backend::{
+            wgpu_backend::{
+                extract_bw_image,
+                LUT_2,
+                LUT_4,
+            },
+            HeadlessSurface,
+        },
+        shaders::{
+            Chain,
+            CrtConfig,
+            CrtPostProcessor,
+            DefaultPostProcessor,
+            PanelShadowPostProcessor,
+        },
+        utils::text_atlas::{
+            CacheRect,
+            Entry,
+        },
+        BlendMode,
+        Builder,
+        CellOverride,
+        DimMode,
+        Dimensions,
+        EmojiMode,
+        Font,
+        Fonts,
+        FrameStats,
+        GradientStop,
+        HintingMode,
+        MetricLines,
+        Palette,
+        PostProcessor,
+        ResizePolicy,
+        Rounding,
+        SampleMode,
+        UnderlineStyle,
+        Viewport,
+        WgpuBackend,
+    };
+
+    fn tex2buffer(device: &Device, queue: &Queue, surface: &HeadlessSurface) {
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+        encoder.copy_texture_to_buffer(
+            surface.texture.as_ref().unwrap().as_image_copy(),
+            ImageCopyBuffer {
+                buffer: surface.buffer.as_ref().unwrap(),
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(surface.buffer_width),
+                    rows_per_image: Some(surface.height),
+                },
+            },
+            Extent3d {
+                width: surface.width,
+                height: surface.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        queue.submit(Some(encoder.finish()));
+    }
+
+    fn surface_pixels<P: PostProcessor, H: BuildHasher + Clone>(
+        backend: &WgpuBackend<'_, 'static, P, HeadlessSurface, H>,
+    ) -> Vec<Rgba<u8>> {
+        let surface = &backend.surface;
+        tex2buffer(&backend.device, &backend.queue, surface);
+
+        let pixels = {
+            let buffer = surface.buffer.as_ref().unwrap().slice(..);
+
+            let (send, recv) = oneshot::channel();
+            buffer.map_async(wgpu::MapMode::Read, move |data| {
+                send.send(data).unwrap();
+            });
+            backend.device.poll(wgpu::MaintainBase::Wait);
+            recv.recv().unwrap().unwrap();
+
+            let data = buffer.get_mapped_range();
+            let image =
+                ImageBuffer::<Rgba<u8>, _>::from_raw(surface.width, surface.height, data).unwrap();
+            image.pixels().copied().collect::<Vec<_>>()
+        };
+
+        surface.buffer.as_ref().unwrap().unmap();
+        pixels
+    }
+
+    fn headless_terminal(
+        font: &'static [u8],
+        width: u32,
+        height: u32,
+        configure: impl FnOnce(Builder<'static>) -> Builder<'static>,
+    ) -> Terminal<WgpuBackend<'static, 'static, DefaultPostProcessor, HeadlessSurface>> {
+        let builder =
+            Builder::<DefaultPostProcessor>::from_font(Font::new(font).expect("Invalid font file"))
+                .with_width_and_height(Dimensions {
+                    width: NonZeroU32::new(width).unwrap(),
+                    height: NonZeroU32::new(height).unwrap(),
+                });
+
+        Terminal::new(futures_lite::future::block_on(configure(builder).build_headless()).unwrap())
+            .unwrap()
+    }
+
+    fn assert_golden(pixels: &[Rgba<u8>], golden: &[u8]) {
+        let golden = load_from_memory(golden).unwrap();
+        let golden_pixels = golden.pixels().map(|(_, _, px)| px).collect::<Vec<_>>();
+
+        assert!(
+            pixels == golden_pixels,
+            "Rendered image differs from golden"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn a_z() {
+        let mut terminal = Terminal::new(
+            futures_lite::future::block_on(
+                Builder::<DefaultPostProcessor>::from_font(
+                    Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                        .expect("Invalid font file"),
+                )
+                .with_width_and_height(Dimensions {
+                    width: NonZeroU32::new(512).unwrap(),
+                    height: NonZeroU32::new(72).unwrap(),
+                })
+                .build_headless(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        terminal
+            .draw(|f| {
+                let block = Block::bordered();
+                let area = block.inner(f.area());
+                f.render_widget(block, f.area());
+                f.render_widget(Paragraph::new("ABCDEFGHIJKLMNOPQRSTUVWXYZ"), area);
+            })
+            .unwrap();
+
+        let surface = &terminal.backend().surface;
+        tex2buffer(
+            &terminal.backend().device,
+            &terminal.backend().queue,
+            surface,
+        );
+        {
+            let buffer = surface.buffer.as_ref().unwrap().slice(..);
+
+            let (send, recv) = oneshot::channel();
+            buffer.map_async(wgpu::MapMode::Read, move |data| {
+                send.send(data).unwrap();
+            });
+            terminal.backend().device.poll(wgpu::MaintainBase::Wait);
+            recv.recv().unwrap().unwrap();
+
+            let data = buffer.get_mapped_range();
+            let image =
+                ImageBuffer::<Rgba<u8>, _>::from_raw(surface.width, surface.height, data).unwrap();
+
+            let pixels = image.pixels().copied().collect::<Vec<_>>();
+            let golden = load_from_memory(include_bytes!("goldens/a_z.png")).unwrap();
+            let golden_pixels = golden.pixels().map(|(_, _, px)| px).collect::<Vec<_>>();
+
+            assert!(
+                pixels == golden_pixels,
+                "Rendered image differs from golden"
+            );
+        }
+
+        surface.buffer.as_ref().unwrap().unmap();
+    }
+
+    /// Blits like [`DefaultPostProcessor`], counting how often the backend
+    /// calls into it.
+    struct CountingPostProcessor {
+        blitter: DefaultPostProcessor,
+        processed: usize,
+        update_checks: std::cell::Cell<usize>,
+    }
+
+    impl PostProcessor for CountingPostProcessor {
+        type UserData = ();
+
+        fn compile(
+            device: &wgpu::Device,
+            text_view: &wgpu::TextureView,
+            surface_config: &wgpu::SurfaceConfiguration,
+            user_data: Self::UserData,
+        ) -> Self {
+            Self {
+                blitter: DefaultPostProcessor::compile(
+                    device,
+                    text_view,
+                    surface_config,
+                    user_data,
+                ),
+                processed: 0,
+                update_checks: Default::default(),
+            }
+        }
+
+        fn resize(
+            &mut self,
+            device: &wgpu::Device,
+            text_view: &wgpu::TextureView,
+            surface_config: &wgpu::SurfaceConfiguration,
+        ) {
+            self.blitter.resize(device, text_view, surface_config);
+        }
+
+        fn process(
+            &mut self,
+            encoder: &mut wgpu::CommandEncoder,
+            queue: &wgpu::Queue,
+            text_view: &wgpu::TextureView,
+            surface_config: &wgpu::SurfaceConfiguration,
+            surface_view: &wgpu::TextureView,
+        ) {
+            self.processed += 1;
+            self.blitter
+                .process(encoder, queue, text_view, surface_config, surface_view);
+        }
+
+        fn needs_update(&self) -> bool {
+            self.update_checks.set(self.update_checks.get() + 1);
+            false
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn a_z_post_processing_disabled() {
+        let mut terminal = Terminal::new(
+            futures_lite::future::block_on(
+                Builder::<CountingPostProcessor>::from_font(
+                    Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                        .expect("Invalid font file"),
+                )
+                .with_width_and_height(Dimensions {
+                    width: NonZeroU32::new(512).unwrap(),
+                    height: NonZeroU32::new(72).unwrap(),
+                })
+                .build_headless(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        terminal.backend_mut().set_post_processing_enabled(false);
+
+        terminal
+            .draw(|f| {
+                let block = Block::bordered();
+                let area = block.inner(f.area());
+                f.render_widget(block, f.area());
+                f.render_widget(Paragraph::new("ABCDEFGHIJKLMNOPQRSTUVWXYZ"), area);
+            })
+            .unwrap();
+
+        let post_processor = terminal.backend().post_processor();
+        assert_eq!(post_processor.processed, 0);
+        assert_eq!(post_processor.update_checks.get(), 0);
+
+        assert_golden(
+            &surface_pixels(terminal.backend()),
+            include_bytes!("goldens/a_z.png"),
+        );
+
+        // Switching back presents the same text through the post processor
+        // without laying it out again.
+        let stats = terminal.backend().last_frame_stats();
+        terminal.backend_mut().set_post_processing_enabled(true);
+        assert_eq!(terminal.backend().post_processor().processed, 1);
+        assert!(terminal.backend().needs_present());
+        assert_eq!(terminal.backend().last_frame_stats(), stats);
+
+        terminal
+            .draw(|f| {
+                let block = Block::bordered();
+                let area = block.inner(f.area());
+                f.render_widget(block, f.area());
+                f.render_widget(Paragraph::new("abcdefghijklmnopqrstuvwxyz"), area);
+            })
+            .unwrap();
+
+        let post_processor = terminal.backend().post_processor();
+        assert_ne!(post_processor.processed, 0);
+        assert_ne!(post_processor.update_checks.get(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn paused_rendering() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            512,
+            72,
+            |b| b,
+        );
+
+        terminal.backend_mut().set_rendering_paused(true);
+
+        terminal
+            .draw(|f| {
+                let block = Block::bordered();
+                let area = block.inner(f.area());
+                f.render_widget(block, f.area());
+                f.render_widget(Paragraph::new("ABCDEFGHIJKLMNOPQRSTUVWXYZ"), area);
+            })
+            .unwrap();
+
+        assert!(
+            surface_pixels(terminal.backend())
+                .iter()
+                .all(|px| *px == Rgba([0, 0, 0, 0])),
+            "Rendered while paused"
+        );
+
+        terminal.backend_mut().set_rendering_paused(false);
+        terminal.backend_mut().flush().unwrap();
+
+        assert_golden(
+            &surface_pixels(terminal.backend()),
+            include_bytes!("goldens/a_z.png"),
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn batched_flushes() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            512,
+            72,
+            |b| b,
+        );
+
+        terminal.backend_mut().begin_batch();
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Block::bordered(), f.area());
+            })
+            .unwrap();
+        terminal
+            .draw(|f| {
+                let block = Block::bordered();
+                let area = block.inner(f.area());
+                f.render_widget(block, f.area());
+                f.render_widget(Paragraph::new("ABCDEFGHIJKLMNOPQRSTUVWXYZ"), area);
+            })
+            .unwrap();
+
+        assert!(
+            surface_pixels(terminal.backend())
+                .iter()
+                .all(|px| *px == Rgba([0, 0, 0, 0])),
+            "Rendered during batch"
+        );
+
+        terminal.backend_mut().end_batch().unwrap();
+
+        assert_golden(
+            &surface_pixels(terminal.backend()),
+            include_bytes!("goldens/a_z.png"),
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn metric_overlay() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        terminal
+            .backend_mut()
+            .set_metric_overlay(Some(MetricLines::default()));
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Hxgy Ép"), f.area());
+            })
+            .unwrap();
+
+        assert_golden(
+            &surface_pixels(terminal.backend()),
+            include_bytes!("goldens/metric_overlay.png"),
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn cell_opacity() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        let draw = |terminal: &mut Terminal<_>| {
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new("ABC".on_blue()), f.area());
+                })
+                .unwrap();
+        };
+        draw(&mut terminal);
+
+        // The grid doesn't evenly divide the surface, so sample the top of the center
+        // of each cell.
+        let columns = terminal.size().unwrap().width as usize;
+        let alpha_at = |pixels: &[Rgba<u8>], column: usize| {
+            pixels[(column * 2 + 1) * 128 / (columns * 2)].0[3]
+        };
+
+        terminal
+            .backend_mut()
+            .set_cell_opacity(Position::new(1, 0), 0.5);
+        terminal.backend_mut().flush().unwrap();
+
+        let pixels = surface_pixels(terminal.backend());
+        assert_eq!(alpha_at(&pixels, 0), 255);
+        assert_eq!(alpha_at(&pixels, 1), 127);
+        assert_eq!(alpha_at(&pixels, 2), 255);
+
+        terminal
+            .backend_mut()
+            .set_cell_opacity(Position::new(1, 0), 1.0);
+        terminal.backend_mut().flush().unwrap();
+
+        let pixels = surface_pixels(terminal.backend());
+        assert_eq!(alpha_at(&pixels, 1), 255);
+
+        terminal
+            .backend_mut()
+            .set_cell_opacity(Position::new(1, 0), 0.0);
+        terminal.clear().unwrap();
+        draw(&mut terminal);
+
+        let pixels = surface_pixels(terminal.backend());
+        assert_eq!(alpha_at(&pixels, 1), 255);
+    }
+
+    #[test]
+    #[serial]
+    fn zero_width_cells() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        let mut render = |middle: &str| {
+            terminal.clear().unwrap();
+            terminal
+                .draw(|f| {
+                    let buffer = f.buffer_mut();
+                    buffer[(0, 0)].set_symbol("a");
+                    buffer[(1, 0)].set_symbol(middle);
+                    buffer[(2, 0)].set_symbol("b");
+                })
+                .unwrap();
+            surface_pixels(terminal.backend())
+        };
+
+        let expected = render(" ");
+        assert!(
+            render("\u{200B}") == expected,
+            "Zero width space shifted columns"
+        );
+        assert!(
+            render("\u{200D}") == expected,
+            "Zero width joiner shifted columns"
+        );
+
+        terminal.clear().unwrap();
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("\u{1F468}\u{200D}\u{1F469}x"), f.area());
+            })
+            .unwrap();
+
+        let backend = terminal.backend();
+        assert_eq!(backend.cells[0].symbol(), "\u{1F468}\u{200D}\u{1F469}");
+        assert!(!backend.rendered[2].is_empty());
+        assert!(backend.rendered[2]
+            .values()
+            .all(|info| backend.cells[info.cell].symbol() == "x"));
+    }
+
+    #[test]
+    #[serial]
+    fn grid_rounding() {
+        for (rounding, built, resized) in [
+            (Rounding::Floor, (10, 2), (10, 2)),
+            (Rounding::Round, (10, 3), (11, 2)),
+            (Rounding::Ceil, (11, 3), (11, 3)),
+        ] {
+            let mut backend = futures_lite::future::block_on(
+                Builder::<DefaultPostProcessor>::from_font(
+                    Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                        .expect("Invalid font file"),
+                )
+                .with_grid_rounding(rounding)
+                .with_width_and_height(Dimensions {
+                    width: NonZeroU32::new(122).unwrap(),
+                    height: NonZeroU32::new(60).unwrap(),
+                })
+                .build_headless(),
+            )
+            .unwrap();
+
+            let size = backend.size().unwrap();
+            assert_eq!((size.width, size.height), built, "{rounding:?}");
+
+            backend.resize(128, 56);
+            let size = backend.window_size().unwrap().columns_rows;
+            assert_eq!((size.width, size.height), resized, "{rounding:?}");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn resize_reflow() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            96,
+            48,
+            |b| b.with_resize_policy(ResizePolicy::Reflow),
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Paragraph::new(vec![Line::from("ABCDEFGH"), Line::from("IJKLMNOP")]),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        terminal.backend_mut().resize(48, 24);
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(terminal.backend().get_text(), "ABCD\n");
+
+        terminal.backend_mut().resize(96, 48);
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(terminal.backend().get_text(), "ABCD    \n        \n");
+    }
+
+    #[test]
+    #[serial]
+    fn resize_keeps_contents_by_default() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            96,
+            48,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Paragraph::new(vec![Line::from("ABCDEFGH"), Line::from("IJKLMNOP")]),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        terminal.backend_mut().resize(128, 48);
+        assert!(terminal.backend().dirty_rows.iter().all(|dirty| *dirty));
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(terminal.backend().get_text(), "ABCDEFGH  \nIJKLMNOP  \n");
+    }
+
+    #[test]
+    #[serial]
+    fn resize_then_flush() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            48,
+            24,
+            |b| b.with_resize_policy(ResizePolicy::Clear),
+        );
+
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("ABCD"), f.area()))
+            .unwrap();
+        terminal.backend_mut().set_cursor_position((3, 0)).unwrap();
+
+        terminal.backend_mut().resize(96, 48);
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(terminal.backend().get_text(), "        \n        \n");
+
+        terminal.backend_mut().resize(24, 24);
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(
+            terminal.backend_mut().get_cursor_position().unwrap(),
+            (1, 0).into()
+        );
+        for clear_type in [
+            ClearType::AfterCursor,
+            ClearType::BeforeCursor,
+            ClearType::CurrentLine,
+            ClearType::UntilNewLine,
+        ] {
+            terminal.backend_mut().clear_region(clear_type).unwrap();
+            terminal.backend_mut().flush().unwrap();
+            assert_eq!(terminal.backend().get_text(), "  \n");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn empty_grid() {
+        for (dimensions, viewport) in [
+            ((1, 1), Viewport::Full),
+            ((1, 1), Viewport::Centered),
+            (
+                (128, 48),
+                Viewport::Shrink {
+                    width: 256,
+                    height: 96,
+                },
+            ),
+        ] {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                dimensions.0,
+                dimensions.1,
+                |b| b.with_viewport(viewport),
+            );
+
+            assert_eq!(terminal.backend().size().unwrap(), Size::new(0, 0));
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new("Hello"), f.area());
+                    f.set_cursor_position((3, 2));
+                })
+                .unwrap();
+            terminal.backend_mut().set_cursor_position((5, 5)).unwrap();
+            terminal.backend_mut().present();
+            terminal.backend_mut().resize(1, 1);
+            terminal.backend_mut().flush().unwrap();
+            assert_eq!(terminal.backend().get_text(), "");
+            assert_eq!(
+                terminal.backend().to_svg(),
+                "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"0\" height=\"0\" viewBox=\"0 \
+                 0 0 0\">\n</svg>\n"
+            );
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn clear_glyph_cache() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            512,
+            72,
+            |b| b,
+        );
+
+        assert_eq!(terminal.backend().atlas_usage(), 0.0);
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Paragraph::new("The quick brown fox jumps over the lazy dog"),
+                    f.area(),
+                );
+            })
+            .unwrap();
+        let drawn = surface_pixels(terminal.backend());
+        let usage = terminal.backend().atlas_usage();
+        assert!(usage > 0.0 && usage < 1.0);
+
+        let backend = terminal.backend_mut();
+        backend.clear_glyph_cache();
+        assert_eq!(backend.atlas_usage(), 0.0);
+
+        backend.flush().unwrap();
+        assert_eq!(backend.atlas_usage(), usage);
+        assert!(surface_pixels(backend) == drawn);
+    }
+
+    #[test]
+    #[serial]
+    fn print_rgba() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        terminal.backend_mut().print_rgba(
+            Position::new(1, 0),
+            "AB",
+            crate::Rgba::new(255, 0, 0, 255),
+            crate::Rgba::new(0, 255, 0, 128),
+        );
+        terminal.backend_mut().flush().unwrap();
+
+        assert_eq!(
+            terminal.backend().get_text().lines().next().unwrap(),
+            " AB       "
+        );
+
+        // The grid doesn't evenly divide the surface, so sample the top of the center
+        // of each cell.
+        let columns = terminal.size().unwrap().width as usize;
+        let pixel_at =
+            |pixels: &[Rgba<u8>], column: usize| pixels[(column * 2 + 1) * 128 / (columns * 2)];
+
+        let pixels = surface_pixels(terminal.backend());
+        assert_eq!(pixel_at(&pixels, 0), Rgba([255, 255, 255, 255]));
+        assert_eq!(pixel_at(&pixels, 1), Rgba([0, 255, 0, 128]));
+        assert_eq!(pixel_at(&pixels, 2), Rgba([0, 255, 0, 128]));
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("xy"), f.area());
+            })
+            .unwrap();
+
+        let pixels = surface_pixels(terminal.backend());
+        assert_eq!(pixel_at(&pixels, 1), Rgba([255, 255, 255, 255]));
+        assert_eq!(pixel_at(&pixels, 2), Rgba([0, 255, 0, 128]));
+
+        // Text starting past the end of the row is dropped entirely.
+        let text = terminal.backend().get_text();
+        for x in [columns as u16, u16::MAX] {
+            terminal.backend_mut().print_rgba(
+                Position::new(x, 0),
+                "世C",
+                crate::Rgba::new(255, 0, 0, 255),
+                crate::Rgba::new(0, 255, 0, 128),
+            );
+        }
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(terminal.backend().get_text(), text);
+    }
+
+    #[test]
+    #[serial]
+    fn zwj_fallback() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        // Neither of these sequences can be composed by the font, so each component
+        // should get its own column of the two column cell.
+        for sequence in ["\u{1F468}\u{200D}\u{1F469}", "a\u{200D}b"] {
+            terminal.clear().unwrap();
+            terminal
+                .draw(|f| {
+                    let buffer = f.buffer_mut();
+                    buffer[(0, 0)].set_symbol(sequence);
+                    buffer[(2, 0)].set_symbol("x");
+                })
+                .unwrap();
+
+            let backend = terminal.backend();
+            for column in 0..2 {
+                assert!(
+                    !backend.rendered[column].is_empty()
+                        && backend.rendered[column]
+                            .values()
+                            .all(|info| info.cell == 0 && info.cached.width == 12),
+                    "{sequence:?} column {column}"
+                );
+            }
+            assert!(!backend.rendered[2].is_empty());
+            assert!(backend.rendered[2].values().all(|info| info.cell == 2));
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn capture_region() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Block::new().style(Style::new().bg(Color::Red)),
+                    Rect::new(2, 1, 3, 1),
+                );
+            })
+            .unwrap();
+
+        let (full, full_width, full_height) = terminal
+            .backend_mut()
+            .capture_region(Rect::new(0, 0, 10, 2))
+            .unwrap();
+        assert_eq!((full_width, full_height), (120, 48));
+        assert_eq!(full.len(), 120 * 48 * 4);
+
+        // Partially out of bounds, so this is clipped to a single row.
+        let (region, width, height) = terminal
+            .backend_mut()
+            .capture_region(Rect::new(1, 1, 5, 5))
+            .unwrap();
+        assert_eq!((width, height), (60, 24));
+        assert_eq!(region.len(), 60 * 24 * 4);
+
+        for (y, row) in region.chunks(60 * 4).enumerate() {
+            let offset = ((24 + y) * 120 + 12) * 4;
+            assert_eq!(row, &full[offset..offset + 60 * 4]);
+        }
+
+        let pixel = |x: usize| &region[x * 4..x * 4 + 4];
+        assert_eq!(pixel(6), &[255, 255, 255, 255]);
+        assert_eq!(pixel(18), &[255, 0, 0, 255]);
+        assert_eq!(pixel(18), pixel(42));
+
+        assert_eq!(
+            terminal
+                .backend_mut()
+                .capture_region(Rect::new(20, 20, 5, 5))
+                .unwrap(),
+            (vec![], 0, 0)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn dirty_cells_since_last_flush() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("AB\nAB"), f.area());
+            })
+            .unwrap();
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("AB\nAC"), f.area());
+            })
+            .unwrap();
+        // Changed rows are reshaped, so every cell in them is re-rendered.
+        assert_eq!(
+            terminal
+                .backend()
+                .dirty_cells_since_last_flush()
+                .collect::<Vec<_>>(),
+            (0..10).map(|x| Position::new(x, 1)).collect::<Vec<_>>()
+        );
+
+        terminal.backend_mut().begin_batch();
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("AD\nAC"), f.area());
+            })
+            .unwrap();
+        assert_eq!(
+            terminal
+                .backend()
+                .dirty_cells_since_last_flush()
+                .collect::<Vec<_>>(),
+            (0..10).map(|x| Position::new(x, 1)).collect::<Vec<_>>()
+        );
+
+        terminal.backend_mut().end_batch().unwrap();
+        assert_eq!(
+            terminal
+                .backend()
+                .dirty_cells_since_last_flush()
+                .collect::<Vec<_>>(),
+            (0..10).map(|x| Position::new(x, 0)).collect::<Vec<_>>()
+        );
+
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(terminal.backend().dirty_cells_since_last_flush().count(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn update_fonts_resizes_plan_cache() {
+        let font =
+            Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf")).expect("Invalid font file");
+        let fallback = Font::new(include_bytes!("fonts/Fairfax.ttf")).expect("Invalid font file");
+
+        let mut backend = futures_lite::future::block_on(
+            Builder::<DefaultPostProcessor>::from_font(font.clone())
+                .with_width_and_height(Dimensions {
+                    width: NonZeroU32::new(128).unwrap(),
+                    height: NonZeroU32::new(48).unwrap(),
+                })
+                .build_headless(),
+        )
+        .unwrap();
+        assert_eq!(backend.plan_cache.capacity(), 2);
+
+        let mut fonts = Fonts::new(font, 24);
+        fonts.add_regular_fonts(std::iter::repeat(fallback).take(4));
+        backend.update_fonts(fonts);
+        assert_eq!(backend.plan_cache.capacity(), 5);
+
+        let mut terminal = Terminal::new(backend).unwrap();
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Hello"), f.area());
+            })
+            .unwrap();
+        assert_eq!(terminal.backend().get_text(), "Hello     \n          \n");
+    }
+
+    #[test]
+    #[serial]
+    fn focus_ring() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        let draw = |terminal: &mut Terminal<_>| {
+            terminal
+                .draw(|f| {
+                    f.render_widget(Block::new().style(Style::new().bg(Color::Blue)), f.area());
+                })
+                .unwrap();
+        };
+        draw(&mut terminal);
+
+        fn capture(
+            terminal: &mut Terminal<
+                WgpuBackend<'_, 'static, DefaultPostProcessor, HeadlessSurface>,
+            >,
+        ) -> impl Fn(u32, u32) -> [u8; 4] {
+            let (pixels, width, _) = terminal
+                .backend_mut()
+                .capture_region(Rect::new(0, 0, 10, 2))
+                .unwrap();
+            move |x, y| {
+                let offset = ((y * width + x) * 4) as usize;
+                [
+                    pixels[offset],
+                    pixels[offset + 1],
+                    pixels[offset + 2],
+                    pixels[offset + 3],
+                ]
+            }
+        }
+
+        let blue = capture(&mut terminal)(0, 0);
+
+        terminal.backend_mut().set_focus_ring(Some((
+            Rect::new(1, 0, 3, 2),
+            crate::Rgba::new(255, 0, 0, 255),
+            2,
+        )));
+        terminal.backend_mut().flush().unwrap();
+
+        let red = [255, 0, 0, 255];
+        let pixel = capture(&mut terminal);
+        // The ring's outer edge lines up with the edges of cells 1 through 3.
+        assert_eq!(pixel(11, 10), blue);
+        assert_eq!(pixel(12, 10), red);
+        assert_eq!(pixel(13, 10), red);
+        assert_eq!(pixel(14, 10), blue);
+        assert_eq!(pixel(45, 10), blue);
+        assert_eq!(pixel(46, 10), red);
+        assert_eq!(pixel(47, 10), red);
+        assert_eq!(pixel(48, 10), blue);
+        assert_eq!(pixel(20, 0), red);
+        assert_eq!(pixel(20, 1), red);
+        assert_eq!(pixel(20, 2), blue);
+        assert_eq!(pixel(20, 45), blue);
+        assert_eq!(pixel(20, 46), red);
+        assert_eq!(pixel(20, 47), red);
+
+        // Rendering other rows draws the ring again, which must not blend it over
+        // itself.
+        terminal.backend_mut().set_focus_ring(Some((
+            Rect::new(1, 0, 3, 1),
+            crate::Rgba::new(255, 0, 0, 128),
+            2,
+        )));
+        terminal.backend_mut().flush().unwrap();
+        let blended = capture(&mut terminal)(12, 10);
+        assert_ne!(blended, red);
+        assert_ne!(blended, blue);
+
+        let mut cell = ratatui::buffer::Cell::new("x");
+        cell.set_bg(Color::Blue);
+        terminal
+            .backend_mut()
+            .draw([(8, 1, &cell)].into_iter())
+            .unwrap();
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(capture(&mut terminal)(12, 10), blended);
+
+        terminal.backend_mut().set_focus_ring(None);
+        terminal.backend_mut().flush().unwrap();
+        let pixel = capture(&mut terminal);
+        assert_eq!(pixel(12, 10), blue);
+        assert_eq!(pixel(20, 0), blue);
+    }
+
+    #[test]
+    #[serial]
+    fn min_contrast() {
+        let fg = [60, 60, 60];
+        let bg = [40, 40, 40];
+        let rendered_fg = |min_contrast: f32| {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                128,
+                48,
+                |b| b.with_min_contrast(min_contrast),
+            );
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(
+                        Paragraph::new("A").style(
+                            Style::new()
+                                .fg(Color::Rgb(fg[0], fg[1], fg[2]))
+                                .bg(Color::Rgb(bg[0], bg[1], bg[2])),
+                        ),
+                        f.area(),
+                    );
+                })
+                .unwrap();
+
+            let [r, g, b, _] = terminal.backend().text_vertices[0].fg_color.to_be_bytes();
+            [r, g, b]
+        };
+
+        assert_eq!(rendered_fg(1.0), fg);
+
+        let adjusted = rendered_fg(4.5);
+        assert!(adjusted[0] > fg[0]);
+        assert!(crate::colors::contrast_ratio(adjusted, bg) >= 4.5);
+        // The adjustment is only as large as it needs to be.
+        assert!(crate::colors::contrast_ratio(adjusted, bg) < 4.6);
+    }
+
+    #[test]
+    #[serial]
+    fn underline_run() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            256,
+            48,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Paragraph::new("two underlined words".underlined()),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        // The whole phrase is underlined by a single rule.
+        assert_eq!(terminal.backend().overlay_quads, 1);
+
+        assert_golden(
+            &surface_pixels(terminal.backend()),
+            include_bytes!("goldens/underline_run.png"),
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn underline_styles() {
+        for (style, golden) in [
+            (
+                UnderlineStyle::Double,
+                include_bytes!("goldens/underline_double.png").as_slice(),
+            ),
+            (
+                UnderlineStyle::Curly,
+                include_bytes!("goldens/underline_curly.png").as_slice(),
+            ),
+            (
+                UnderlineStyle::Dotted,
+                include_bytes!("goldens/underline_dotted.png").as_slice(),
+            ),
+            (
+                UnderlineStyle::Dashed,
+                include_bytes!("goldens/underline_dashed.png").as_slice(),
+            ),
+        ] {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                128,
+                48,
+                |b| b.with_underline_style(style),
+            );
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new("ABC gjy".underlined()), f.area());
+                })
+                .unwrap();
+
+            assert_golden(&surface_pixels(terminal.backend()), golden);
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn crossed_out() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("ABC".crossed_out()), f.area());
+            })
+            .unwrap();
+
+        assert_eq!(terminal.backend().overlay_quads, 1);
+
+        assert_golden(
+            &surface_pixels(terminal.backend()),
+            include_bytes!("goldens/crossed_out.png"),
+        );
+    }
+
+    fn dim_mode(mode: DimMode, golden: &[u8]) {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b.with_dim_mode(mode),
+        );
+
+        terminal
+            .draw(|f| {
+                let style = Style::new().fg(Color::Yellow).bg(Color::Blue).dim();
+                f.render_widget(
+                    Paragraph::new(vec![
+                        Line::from("Dim").style(style),
+                        Line::from("Reversed").style(style.reversed()),
+                    ]),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        assert_golden(&surface_pixels(terminal.backend()), golden);
+    }
+
+    #[test]
+    #[serial]
+    fn dim_alpha() {
+        dim_mode(DimMode::Alpha, include_bytes!("goldens/dim_alpha.png"));
+    }
+
+    #[test]
+    #[serial]
+    fn dim_blend() {
+        dim_mode(DimMode::Blend, include_bytes!("goldens/dim_blend.png"));
+    }
+
+    #[test]
+    #[serial]
+    fn dim_reversed() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b.with_transparent_background(true),
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Paragraph::new("Dim").style(
+                        Style::new()
+                            .fg(Color::Rgb(255, 255, 0))
+                            .bg(Color::Rgb(0, 0, 255))
+                            .dim()
+                            .reversed(),
+                    ),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        let pixels = surface_pixels(terminal.backend());
+        let width = terminal.backend().surface.width as usize;
+        let cell_width = terminal.backend().fonts.cell_width_px() as usize;
+        let cell_height = terminal.backend().fonts.cell_height_px() as usize;
+
+        // The background is the original foreground and stays opaque, while the
+        // glyphs are dimmed against it rather than drawn at full strength.
+        let cells = (0..cell_height)
+            .flat_map(|y| pixels[y * width..][..cell_width * 3].iter().copied())
+            .collect::<Vec<_>>();
+        assert!(cells.iter().all(|px| px[3] == 255));
+        assert!(cells.contains(&Rgba([255, 255, 0, 255])));
+        assert!(!cells.contains(&Rgba([0, 0, 255, 255])));
+
+        assert_golden(&pixels, include_bytes!("goldens/dim_reversed.png"));
+    }
+
+    #[test]
+    #[serial]
+    fn overrender_margin() {
+        let resized_capture = |margin: u16| {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                128,
+                48,
+                |b| b.with_overrender_margin(margin),
+            );
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new("ABC".on_blue()), f.area());
+                })
+                .unwrap();
+            let before = terminal
+                .backend_mut()
+                .capture_region(Rect::new(0, 0, 3, 1))
+                .unwrap();
+
+            // Grows the grid by one column, which still fits in the margin.
+            terminal.backend_mut().resize(140, 48);
+            let after = terminal
+                .backend_mut()
+                .capture_region(Rect::new(0, 0, 3, 1))
+                .unwrap();
+
+            (before, after)
+        };
+
+        let (before, after) = resized_capture(2);
+        assert_eq!(before, after);
+
+        let (before, after) = resized_capture(0);
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    #[serial]
+    fn vertical_text() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            96,
+            |b| b.with_vertical_text(true),
+        );
+
+        let cell = terminal.backend().cell_size();
+        assert_eq!(
+            terminal.backend().size().unwrap(),
+            Size {
+                width: 96 / cell.height,
+                height: 128 / (cell.width * 2),
+            }
+        );
+
+        let draw = |terminal: &mut Terminal<_>, second: &'static str| {
+            terminal
+                .draw(|f| {
+                    f.render_widget(
+                        Paragraph::new(vec![
+                            Line::from("ABCD".yellow().on_blue()),
+                            Line::from(second.white().on_red()),
+                            Line::from("世界".black().on_green()),
+                        ]),
+                        f.area(),
+                    );
+                })
+                .unwrap();
+        };
+        draw(&mut terminal, "EF");
+
+        assert_golden(
+            &surface_pixels(terminal.backend()),
+            include_bytes!("goldens/vertical_text.png"),
+        );
+
+        // Only the column of the changed row is laid out again.
+        let (width, height) = (cell.width * 2, cell.height);
+        terminal.backend_mut().take_damage();
+        draw(&mut terminal, "EG");
+        assert_eq!(
+            terminal.backend_mut().take_damage(),
+            Some(Rect::new(3 * width, 0, width, 4 * height))
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn synchronized_update() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            512,
+            72,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                let block = Block::bordered();
+                let area = block.inner(f.area());
+                f.render_widget(block, f.area());
+                f.render_widget(Paragraph::new("ABCDEFGHIJKLMNOPQRSTUVWXYZ"), area);
+            })
+            .unwrap();
+
+        let golden = load_from_memory(include_bytes!("goldens/a_z.png")).unwrap();
+        let golden_pixels = golden.pixels().map(|(_, _, px)| px).collect::<Vec<_>>();
+
+        terminal.backend_mut().begin_synchronized_update();
+        terminal
+            .draw(|f| {
+                f.render_widget(Block::bordered(), f.area());
+            })
+            .unwrap();
+        terminal.backend_mut().flush().unwrap();
+
+        assert!(
+            surface_pixels(terminal.backend()) == golden_pixels,
+            "Presented during synchronized update"
+        );
+
+        terminal.backend_mut().end_synchronized_update().unwrap();
+        assert!(
+            surface_pixels(terminal.backend()) != golden_pixels,
+            "Didn't present after synchronized update"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn cursor() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        let draw = |terminal: &mut Terminal<_>, cursor: (u16, u16)| {
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new("Hello\nworld"), f.area());
+                    f.set_cursor_position(cursor);
+                })
+                .unwrap();
+        };
+
+        draw(&mut terminal, (2, 1));
+
+        let golden = load_from_memory(include_bytes!("goldens/cursor.png")).unwrap();
+        let golden_pixels = golden.pixels().map(|(_, _, px)| px).collect::<Vec<_>>();
+
+        assert!(
+            surface_pixels(terminal.backend()) == golden_pixels,
+            "Rendered cursor differs from golden"
+        );
+
+        // Moving away and back shouldn't leave anything behind.
+        draw(&mut terminal, (4, 0));
+        assert!(surface_pixels(terminal.backend()) != golden_pixels);
+        draw(&mut terminal, (2, 1));
+        assert!(
+            surface_pixels(terminal.backend()) == golden_pixels,
+            "Cursor left artifacts after moving"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn cursor_blink() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b.with_cursor_blink_millis(500),
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Hello\nworld"), f.area());
+                f.set_cursor_position((2, 1));
+            })
+            .unwrap();
+
+        let golden = load_from_memory(include_bytes!("goldens/cursor.png")).unwrap();
+        let golden_pixels = golden.pixels().map(|(_, _, px)| px).collect::<Vec<_>>();
+
+        assert!(
+            surface_pixels(terminal.backend()) == golden_pixels,
+            "Cursor not shown before first blink"
+        );
+
+        std::thread::sleep(std::time::Duration::from_millis(600));
+        terminal.backend_mut().flush().unwrap();
+        assert!(
+            surface_pixels(terminal.backend()) != golden_pixels,
+            "Cursor didn't blink"
+        );
+
+        terminal.backend_mut().set_cursor_blink(None);
+        terminal.backend_mut().flush().unwrap();
+        assert!(
+            surface_pixels(terminal.backend()) == golden_pixels,
+            "Cursor not solid after disabling blink"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn blink_disabled() {
+        let build = |blink: bool| {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                128,
+                48,
+                |b| {
+                    b.with_blink(blink)
+                        .with_rapid_blink_millis(0)
+                        .with_slow_blink_millis(0)
+                },
+            );
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new("Fast".rapid_blink()), f.area());
+                    f.render_widget(Paragraph::new("Slow".slow_blink()), Rect::new(0, 1, 4, 1));
+                })
+                .unwrap();
+            terminal
+        };
+
+        // Every flush toggles blinking text, since the blink intervals are zero.
+        let mut blinking = build(true);
+        let hidden = surface_pixels(blinking.backend());
+        blinking.backend_mut().flush().unwrap();
+        assert!(blinking.backend().needs_present());
+        let shown = surface_pixels(blinking.backend());
+        assert!(shown != hidden);
+
+        let mut solid = build(false);
+        assert!(solid.backend().fast_blinking.not_any());
+        assert!(solid.backend().slow_blinking.not_any());
+        assert!(surface_pixels(solid.backend()) == shown);
+
+        solid.backend_mut().flush().unwrap();
+        assert!(!solid.backend().needs_present());
+        assert!(surface_pixels(solid.backend()) == shown);
+    }
+
+    #[test]
+    #[serial]
+    fn invalidate() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("Hello"), f.area()))
+            .unwrap();
+        let drawn = surface_pixels(terminal.backend());
+
+        let backend = terminal.backend_mut();
+        backend.flush().unwrap();
+        assert!(!backend.needs_present());
+
+        backend.invalidate();
+        backend.flush().unwrap();
+        assert!(backend.needs_present());
+        assert_eq!(
+            backend.dirty_cells_since_last_flush().count(),
+            backend.cells.len()
+        );
+        assert_eq!(backend.get_text().lines().next().unwrap(), "Hello     ");
+        assert!(surface_pixels(backend) == drawn);
+    }
+
+    #[test]
+    #[serial]
+    fn atlas_eviction() {
+        let font = Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf")).unwrap();
+        // More unique (glyph, style) pairs than fit in the atlas. This sticks to left
+        // to right scripts so that every cell maps to exactly one glyph.
+        let mut seen = HashSet::new();
+        let glyphs = ('\u{21}'..'\u{590}')
+            .chain('\u{1e00}'..'\u{2000}')
+            .filter(|ch| {
+                ch.is_alphanumeric()
+                    && ch.width() == Some(1)
+                    && font
+                        .font()
+                        .glyph_index(*ch)
+                        .is_some_and(|glyph| seen.insert(glyph))
+            })
+            .flat_map(|ch| {
+                (0..8).map(move |bits| {
+                    let mut style = Modifier::empty();
+                    style.set(Modifier::BOLD, bits & 1 != 0);
+                    style.set(Modifier::ITALIC, bits & 2 != 0);
+                    style.set(Modifier::UNDERLINED, bits & 4 != 0);
+                    (ch, style)
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let build = || {
+            Terminal::new(
+                futures_lite::future::block_on(
+                    Builder::<DefaultPostProcessor>::from_font(font.clone())
+                        .with_width_and_height(Dimensions {
+                            width: NonZeroU32::new(1280).unwrap(),
+                            height: NonZeroU32::new(600).unwrap(),
+                        })
+                        // Toggle blinking text on every flush, so blinking cells are drawn
+                        // without their row being redrawn.
+                        .with_rapid_blink_millis(0)
+                        .build_headless(),
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+
+        // The first row blinks and is never modified. Every other row gets a new
+        // set of glyphs in the second frame, which evicts the first row's glyphs
+        // from the atlas.
+        let draw = |terminal: &mut Terminal<_>, frame: usize| {
+            terminal
+                .draw(|f| {
+                    let area = f.area();
+                    let buffer = f.buffer_mut();
+                    for y in area.top()..area.bottom() {
+                        for x in area.left()..area.right() {
+                            let mut index = (y * area.width + x) as usize;
+                            let mut style = Style::new();
+                            if y == 0 {
+                                style = style.add_modifier(Modifier::RAPID_BLINK);
+                            } else {
+                                index += frame * (area.area() - u32::from(area.width)) as usize;
+                            }
+
+                            let (ch, modifier) = glyphs[index];
+                            buffer[(x, y)]
+                                .set_char(ch)
+                                .set_style(style.add_modifier(modifier));
+                        }
+                    }
+                })
+                .unwrap();
+        };
+
+        let mut terminal = build();
+        draw(&mut terminal, 0);
+        draw(&mut terminal, 1);
+
+        // Drawing the second frame from scratch never evicts anything, so it serves as
+        // the golden.
+        let mut reference = build();
+        draw(&mut reference, 1);
+        draw(&mut reference, 1);
+
+        assert!(
+            surface_pixels(terminal.backend()) == surface_pixels(reference.backend()),
+            "Evicted glyphs weren't rasterized again"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn read_rgba() {
+        // 120 pixels wide, so rows need padding to be copied out of the surface.
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            120,
+            48,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Hello"), f.area());
+                f.render_widget(
+                    Block::new().style(Style::new().bg(Color::Red)),
+                    Rect::new(2, 1, 3, 1),
+                );
+            })
+            .unwrap();
+
+        let pixels = terminal.backend().read_rgba();
+        assert_eq!(pixels.len(), 120 * 48 * 4);
+
+        // The grid exactly fills the surface, so the output matches the text.
+        let (text, _, _) = terminal
+            .backend_mut()
+            .capture_region(Rect::new(0, 0, 10, 2))
+            .unwrap();
+        assert!(pixels == text);
+    }
+
+    #[test]
+    #[serial]
+    fn get_cell() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Paragraph::new("Hello\nworld").style(Style::new().red().on_blue().bold()),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        let backend = terminal.backend();
+        let cell = backend.get_cell(1, 1).unwrap();
+        assert_eq!(cell.symbol(), "o");
+        assert_eq!(cell.fg, Color::Red);
+        assert_eq!(cell.bg, Color::Blue);
+        assert!(cell.modifier.contains(Modifier::BOLD));
+
+        assert!(backend.get_cell(9, 1).is_some());
+        assert!(backend.get_cell(10, 0).is_none());
+        assert!(backend.get_cell(0, 2).is_none());
+
+        let cells = backend
+            .cells_in_rect(Rect::new(3, 0, 10, 10))
+            .map(|(x, y, cell)| (x, y, cell.symbol()))
+            .collect::<Vec<_>>();
+        assert_eq!(cells.len(), 14);
+        assert_eq!(&cells[..3], &[(3, 0, "l"), (4, 0, "o"), (5, 0, " ")]);
+        assert_eq!(cells[7], (3, 1, "l"));
+        assert_eq!(backend.cells_in_rect(Rect::new(20, 20, 5, 5)).count(), 0);
+    }
+
+    #[test]
+    #[serial]
+    fn padding_color() {
+        // A 10x2 grid covers 120x48 pixels, leaving padding on the right and bottom.
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            130,
+            54,
+            |b| b.with_padding_color(Color::Red),
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Block::new().style(Style::new().bg(Color::Blue)), f.area());
+            })
+            .unwrap();
+
+        let pixel = |terminal: &Terminal<WgpuBackend<'_, 'static, _, HeadlessSurface>>,
+                     x: usize,
+                     y: usize| {
+            let pixels = terminal.backend().read_rgba();
+            let offset = (y * 130 + x) * 4;
+            [
+                pixels[offset],
+                pixels[offset + 1],
+                pixels[offset + 2],
+                pixels[offset + 3],
+            ]
+        };
+
+        assert_eq!(pixel(&terminal, 119, 47), [0, 0, 255, 255]);
+        assert_eq!(pixel(&terminal, 120, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(&terminal, 0, 48), [255, 0, 0, 255]);
+        assert_eq!(pixel(&terminal, 129, 53), [255, 0, 0, 255]);
+
+        terminal
+            .backend_mut()
+            .set_padding_color(Some(Color::Rgb(0, 255, 0)));
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(pixel(&terminal, 119, 47), [0, 0, 255, 255]);
+        assert_eq!(pixel(&terminal, 129, 53), [0, 255, 0, 255]);
+
+        // Without padding, the grid is stretched over the whole surface.
+        terminal.backend_mut().set_padding_color(None);
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(pixel(&terminal, 129, 53), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    #[serial]
+    fn centered_viewport() {
+        // A 10x2 grid covers 120x48 pixels, leaving 10 pixels of slack horizontally
+        // and 6 vertically. The offset is applied differently when over-rendering,
+        // so check both paths.
+        for margin in [0, 2] {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                130,
+                54,
+                |b| {
+                    b.with_viewport(Viewport::Centered)
+                        .with_overrender_margin(margin)
+                },
+            );
+
+            assert_eq!(terminal.size().unwrap(), Size::new(10, 2));
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(Block::new().style(Style::new().bg(Color::Blue)), f.area());
+                })
+                .unwrap();
+
+            let pixels = terminal.backend().read_rgba();
+            let pixel = |x: usize, y: usize| {
+                let offset = (y * 130 + x) * 4;
+                [
+                    pixels[offset],
+                    pixels[offset + 1],
+                    pixels[offset + 2],
+                    pixels[offset + 3],
+                ]
+            };
+
+            assert_eq!(pixel(5, 3), [0, 0, 255, 255], "margin {margin}");
+            assert_eq!(pixel(124, 50), [0, 0, 255, 255], "margin {margin}");
+            assert_eq!(pixel(4, 3), [255, 255, 255, 255], "margin {margin}");
+            assert_eq!(pixel(5, 2), [255, 255, 255, 255], "margin {margin}");
+            assert_eq!(pixel(125, 50), [255, 255, 255, 255], "margin {margin}");
+            assert_eq!(pixel(124, 51), [255, 255, 255, 255], "margin {margin}");
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn region_gradient() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        terminal.backend_mut().set_region_gradient(
+            Rect::new(0, 0, 10, 1),
+            [0.0, 0.5],
+            [1.0, 0.5],
+            vec![
+                GradientStop::new(0.0, crate::Rgba::new(255, 0, 0, 255)),
+                GradientStop::new(1.0, crate::Rgba::new(0, 0, 255, 255)),
+            ],
+        );
+        terminal.backend_mut().set_region_gradient(
+            Rect::new(0, 1, 10, 1),
+            [0.0, 0.0],
+            [1.0, 1.0],
+            vec![
+                GradientStop::new(1.0, crate::Rgba::new(255, 255, 0, 255)),
+                GradientStop::new(0.0, crate::Rgba::new(0, 0, 0, 255)),
+                GradientStop::new(0.5, crate::Rgba::new(0, 255, 0, 255)),
+            ],
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Title\nBody"), f.area());
+            })
+            .unwrap();
+
+        assert_golden(
+            &surface_pixels(terminal.backend()),
+            include_bytes!("goldens/region_gradient.png"),
+        );
+
+        // Removing one gradient repaints the cells under it with their own background,
+        // leaving the other gradient alone.
+        let before = terminal.backend().read_rgba();
+        terminal.backend_mut().set_region_gradient(
+            Rect::new(0, 1, 10, 1),
+            [0.0; 2],
+            [1.0; 2],
+            vec![],
+        );
+        terminal.backend_mut().flush().unwrap();
+
+        let after = terminal.backend().read_rgba();
+        let row = 128 * 24 * 4;
+        assert_eq!(before[..row], after[..row]);
+        assert_eq!(after[after.len() - 4..], [255, 255, 255, 255]);
+    }
+
+    #[test]
+    #[serial]
+    fn cell_size() {
+        let terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            130,
+            54,
+            |b| b,
+        );
+
+        assert_eq!(terminal.backend().cell_size(), Size::new(12, 24));
+        assert_eq!(terminal.backend().grid_size_px(), Size::new(120, 48));
+    }
+
+    #[test]
+    #[serial]
+    fn chain_post_processor() {
+        fn render<P: PostProcessor>(user_data: P::UserData, format: TextureFormat) -> Vec<u8> {
+            let mut terminal = Terminal::new(
+                futures_lite::future::block_on(
+                    Builder::<P>::from_font_and_user_data(
+                        Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                            .expect("Invalid font file"),
+                        user_data,
+                    )
+                    .with_width_and_height(Dimensions {
+                        width: NonZeroU32::new(128).unwrap(),
+                        height: NonZeroU32::new(48).unwrap(),
+                    })
+                    .build_headless_with_format(format),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(
+                        Paragraph::new("Chain").style(Style::new().fg(Color::Red).bg(Color::Cyan)),
+                        f.area(),
+                    );
+                })
+                .unwrap();
+
+            terminal.backend().read_rgba()
+        }
+
+        // Blitting twice should be indistinguishable from blitting once, regardless of
+        // whether the surface is sRGB.
+        for format in [TextureFormat::Rgba8Unorm, TextureFormat::Rgba8UnormSrgb] {
+            assert!(
+                render::<Chain<DefaultPostProcessor, DefaultPostProcessor>>(((), ()), format)
+                    == render::<DefaultPostProcessor>((), format),
+                "Chained output differs for {format:?}"
+            );
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn palette() {
+        let mut palette = Palette::default();
+        palette.named[1] = [0x12, 0x34, 0x56];
+        palette.indexed[1] = [0x65, 0x43, 0x21];
+        palette.named[15] = [0xfd, 0xf6, 0xe3];
+
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            120,
+            48,
+            |b| b.with_bg_color(Color::White).with_palette(palette),
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Line::from(vec![
+                        Span::styled(" ", Style::new().bg(Color::Red)),
+                        Span::styled(" ", Style::new().bg(Color::Indexed(1))),
+                        Span::styled(" ", Style::new().bg(Color::Blue)),
+                    ]),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        let pixels = terminal.backend().read_rgba();
+        let pixel = |x: usize, y: usize| {
+            let offset = (y * 120 + x) * 4;
+            [
+                pixels[offset],
+                pixels[offset + 1],
+                pixels[offset + 2],
+                pixels[offset + 3],
+            ]
+        };
+
+        assert_eq!(pixel(6, 12), [0x12, 0x34, 0x56, 255]);
+        assert_eq!(pixel(18, 12), [0x65, 0x43, 0x21, 255]);
+        assert_eq!(pixel(30, 12), [0, 0, 255, 255]);
+        assert_eq!(pixel(6, 36), [0xfd, 0xf6, 0xe3, 255]);
+    }
+
+    #[test]
+    #[serial]
+    fn set_named_color() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            120,
+            48,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Block::new().style(Style::new().bg(Color::Red)), f.area());
+            })
+            .unwrap();
+        assert_eq!(terminal.backend().read_rgba()[..4], [255, 0, 0, 255]);
+
+        // Nothing in the ratatui buffer changes, so the backend has to repaint on its
+        // own.
+        terminal
+            .backend_mut()
+            .set_named_color(Color::Red, [0xdc, 0x32, 0x2f]);
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(terminal.backend().read_rgba()[..4], [0xdc, 0x32, 0x2f, 255]);
+        assert_eq!(terminal.backend().palette().named[1], [0xdc, 0x32, 0x2f]);
+
+        terminal
+            .backend_mut()
+            .set_named_color(Color::Indexed(1), [0, 0, 0]);
+        assert_eq!(terminal.backend().palette(), &{
+            let mut palette = Palette::default();
+            palette.named[1] = [0xdc, 0x32, 0x2f];
+            palette
+        });
+    }
+
+    #[test]
+    #[serial]
+    fn set_named_color_reset_bg() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            125,
+            48,
+            |b| {
+                b.with_bg_color(Color::Blue)
+                    .with_padding_color(Color::Reset)
+            },
+        );
+
+        terminal
+            .draw(|f| f.render_widget(Block::new(), f.area()))
+            .unwrap();
+        let pixel = |rgba: &[u8], x: usize| rgba[x * 4..][..4].to_vec();
+        let rgba = terminal.backend().read_rgba();
+        assert_eq!(pixel(&rgba, 0), [0, 0, 255, 255]);
+        assert_eq!(pixel(&rgba, 124), [0, 0, 255, 255]);
+
+        // Both the cells and the padding are drawn in the default background, which
+        // follows the color it was set to.
+        terminal
+            .backend_mut()
+            .set_named_color(Color::Blue, [0x26, 0x8b, 0xd2]);
+        terminal.backend_mut().flush().unwrap();
+        let rgba = terminal.backend().read_rgba();
+        assert_eq!(pixel(&rgba, 0), [0x26, 0x8b, 0xd2, 255]);
+        assert_eq!(pixel(&rgba, 124), [0x26, 0x8b, 0xd2, 255]);
+    }
+
+    #[test]
+    #[serial]
+    #[cfg(feature = "png")]
+    fn save_png() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            130,
+            54,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Hello".red().on_blue()), f.area());
+            })
+            .unwrap();
+
+        let path = std::env::temp_dir().join("ratatui_wgpu_save_png.png");
+        terminal.backend().save_png(&path).unwrap();
+
+        let saved = image::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(saved.dimensions(), (130, 54));
+        assert!(
+            saved.to_rgba8().into_raw() == terminal.backend().read_rgba(),
+            "Saved image differs from surface"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn take_damage() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            72,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Hello\nworld"), f.area());
+            })
+            .unwrap();
+        assert_eq!(
+            terminal.backend_mut().take_damage(),
+            Some(Rect::new(0, 0, 120, 72))
+        );
+        assert_eq!(terminal.backend_mut().take_damage(), None);
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Hello\nwurld"), f.area());
+            })
+            .unwrap();
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Hello\nwurld\n!"), f.area());
+            })
+            .unwrap();
+        assert_eq!(
+            terminal.backend_mut().take_damage(),
+            Some(Rect::new(0, 24, 120, 48))
+        );
+
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(terminal.backend_mut().take_damage(), None);
+    }
+
+    #[test]
+    #[serial]
+    fn needs_present() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        assert!(!terminal.backend().needs_present());
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Hello"), f.area());
+            })
+            .unwrap();
+        assert!(terminal.backend().needs_present());
+
+        terminal.backend_mut().flush().unwrap();
+        assert!(!terminal.backend().needs_present());
+
+        terminal.backend_mut().begin_batch();
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("World"), f.area());
+            })
+            .unwrap();
+        assert!(!terminal.backend().needs_present());
+
+        terminal.backend_mut().end_batch().unwrap();
+        assert!(terminal.backend().needs_present());
+    }
+
+    #[test]
+    #[serial]
+    fn present() {
+        let mut terminal = Terminal::new(
+            futures_lite::future::block_on(
+                Builder::<CrtPostProcessor>::from_font_and_user_data(
+                    Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                        .expect("Invalid font file"),
+                    CrtConfig::default(),
+                )
+                .with_width_and_height(Dimensions {
+                    width: NonZeroU32::new(128).unwrap(),
+                    height: NonZeroU32::new(48).unwrap(),
+                })
+                .build_headless(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Hello"), f.area());
+            })
+            .unwrap();
+        let drawn = surface_pixels(terminal.backend());
+
+        let backend = terminal.backend_mut();
+        backend.flush().unwrap();
+        assert!(!backend.needs_present());
+
+        backend.present();
+        assert!(backend.needs_present());
+        assert_eq!(backend.get_text().lines().next().unwrap(), "Hello     ");
+        assert!(surface_pixels(backend) == drawn);
+
+        backend.post_processor_mut().set_config(CrtConfig {
+            curvature: 0.1,
+            scanline_intensity: 0.5,
+            vignette: 0.4,
+        });
+        backend.present();
+        assert!(!backend.post_processor().needs_update());
+        assert!(surface_pixels(backend) != drawn);
+
+        backend.set_rendering_paused(true);
+        backend.flush().unwrap();
+        backend.present();
+        assert!(!backend.needs_present());
+    }
+
+    #[test]
+    #[serial]
+    fn adapter_info() {
+        let backend = futures_lite::future::block_on(
+            Builder::<DefaultPostProcessor>::from_font(
+                Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                    .expect("Invalid font file"),
+            )
+            .build_headless(),
+        )
+        .unwrap();
+
+        let adapter = futures_lite::future::block_on(
+            wgpu::Instance::default().request_adapter(&Default::default()),
+        )
+        .unwrap();
+        assert_eq!(backend.adapter_info(), Some(&adapter.get_info()));
+    }
+
+    #[test]
+    #[serial]
+    fn power_preference() {
+        let options = wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::LowPower,
+            ..Default::default()
+        };
+        let adapter =
+            futures_lite::future::block_on(wgpu::Instance::default().request_adapter(&options))
+                .unwrap();
+
+        let backend = futures_lite::future::block_on(
+            Builder::<DefaultPostProcessor>::from_font(
+                Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                    .expect("Invalid font file"),
+            )
+            .with_power_preference(wgpu::PowerPreference::LowPower)
+            .build_headless(),
+        )
+        .unwrap();
+        assert_eq!(backend.adapter_info(), Some(&adapter.get_info()));
+    }
+
+    #[test]
+    #[serial]
+    fn present_mode() {
+        let mut backend = futures_lite::future::block_on(
+            Builder::<DefaultPostProcessor>::from_font(
+                Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                    .expect("Invalid font file"),
+            )
+            .build_headless(),
+        )
+        .unwrap();
+
+        assert_eq!(backend.present_mode(), PresentMode::Immediate);
+        backend.set_present_mode(PresentMode::Fifo);
+        assert_eq!(backend.present_mode(), PresentMode::Immediate);
+
+        backend.present_modes = vec![PresentMode::Immediate, PresentMode::Fifo];
+        backend.set_present_mode(PresentMode::Fifo);
+        assert_eq!(backend.present_mode(), PresentMode::Fifo);
+
+        // Surfaces never list the automatic modes, which are resolved by wgpu.
+        backend.present_modes.clear();
+        backend.set_present_mode(PresentMode::AutoNoVsync);
+        assert_eq!(backend.present_mode(), PresentMode::AutoNoVsync);
+        backend.set_present_mode(PresentMode::AutoVsync);
+        assert_eq!(backend.present_mode(), PresentMode::AutoVsync);
+    }
+
+    #[test]
+    #[serial]
+    fn transparent_background() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            120,
+            48,
+            |b| b.with_transparent_background(true),
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Line::from(vec![
+                        Span::raw(" "),
+                        Span::styled(" ", Style::new().bg(Color::Blue)),
+                        Span::styled("@", Style::new().fg(Color::Rgb(255, 255, 255))),
+                    ]),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        let pixels = terminal.backend().read_rgba();
+        let pixel = |x: usize, y: usize| {
+            let offset = (y * 120 + x) * 4;
+            [
+                pixels[offset],
+                pixels[offset + 1],
+                pixels[offset + 2],
+                pixels[offset + 3],
+            ]
+        };
+
+        assert_eq!(pixel(6, 12), [0, 0, 0, 0]);
+        assert_eq!(pixel(18, 12), [0, 0, 255, 255]);
+        assert_eq!(pixel(6, 36), [0, 0, 0, 0]);
+
+        // Glyphs over a transparent background are premultiplied.
+        let glyph = (24..36)
+            .flat_map(|x| (0..24).map(move |y| (x, y)))
+            .map(|(x, y)| pixel(x, y))
+            .collect::<Vec<_>>();
+        assert!(glyph.iter().any(|[_, _, _, a]| *a == 255));
+        assert!(glyph.iter().any(|[_, _, _, a]| *a != 0 && *a != 255));
+        assert!(glyph.iter().all(|[r, g, b, a]| r == a && g == a && b == a));
+    }
+
+    #[test]
+    #[serial]
+    fn alpha_mode() {
+        let build = |builder: Builder<'static, DefaultPostProcessor>| {
+            futures_lite::future::block_on(
+                builder
+                    .with_width_and_height(Dimensions {
+                        width: NonZeroU32::new(120).unwrap(),
+                        height: NonZeroU32::new(48).unwrap(),
+                    })
+                    .build_headless(),
+            )
+            .unwrap()
+        };
+        let font = || {
+            Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf")).expect("Invalid font file")
+        };
+
+        assert_eq!(
+            build(Builder::from_font(font())).surface_config.alpha_mode,
+            CompositeAlphaMode::Auto
+        );
+        assert_eq!(
+            build(Builder::from_font(font()).with_transparent_background(true))
+                .surface_config
+                .alpha_mode,
+            CompositeAlphaMode::PreMultiplied
+        );
+        assert_eq!(
+            build(
+                Builder::from_font(font())
+                    .with_transparent_background(true)
+                    .with_alpha_mode(CompositeAlphaMode::PostMultiplied)
+            )
+            .surface_config
+            .alpha_mode,
+            CompositeAlphaMode::PostMultiplied
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn fake_bold() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            64,
+            24,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Paragraph::new(Line::from(vec![Span::raw("H "), Span::raw("H").bold()])),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        let pixels = surface_pixels(terminal.backend());
+        assert_golden(&pixels, include_bytes!("goldens/fake_bold.png"));
+
+        // Only one font is loaded, so the bold H is synthesized from the regular
+        // outline and has to cover more of the surface than the regular H.
+        let coverage = |x0: u32| {
+            (0..24)
+                .flat_map(|y| (x0..x0 + 24).map(move |x| (x, y)))
+                .map(|(x, y)| 255 - pixels[(y * 64 + x) as usize].0[0] as u32)
+                .sum::<u32>()
+        };
+        assert!(coverage(24) > coverage(0) * 5 / 4);
+    }
+
+    #[test]
+    #[serial]
+    fn fake_italic_skew() {
+        let ink_width = |skew: f32| {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                64,
+                24,
+                |b| b.with_fake_italic_skew(skew),
+            );
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new("I".italic()), f.area());
+                })
+                .unwrap();
+
+            let pixels = surface_pixels(terminal.backend());
+            let columns = (0..64u32)
+                .filter(|x| (0..24u32).any(|y| pixels[(y * 64 + x) as usize].0[0] < 128))
+                .collect::<Vec<_>>();
+            columns.last().unwrap() - columns.first().unwrap()
+        };
+
+        // An unslanted I is only as wide as its serifs, and leaning it further spreads
+        // it across more columns until it fills the cell.
+        let upright = ink_width(0.0);
+        let slight = ink_width(0.1);
+        let default = ink_width(0.25);
+        assert!(upright < slight, "{upright} >= {slight}");
+        assert!(slight < default, "{slight} >= {default}");
+    }
+
+    #[test]
+    #[serial]
+    fn line_height() {
+        let ink_rows = |line_height: Option<u32>| {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                64,
+                96,
+                |b| match line_height {
+                    Some(line_height) => b.with_line_height_px(line_height),
+                    None => b,
+                },
+            );
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new("H\nH").on_blue(), Rect::new(0, 0, 1, 2));
+                })
+                .unwrap();
+
+            let pixels = surface_pixels(terminal.backend());
+            let rows = (0..96u32)
+                .filter(|y| (0..12u32).any(|x| pixels[(y * 64 + x) as usize].0[2] < 128))
+                .collect::<Vec<_>>();
+            let background = (0..96u32)
+                .filter(|y| pixels[(y * 64) as usize] == Rgba([0, 0, 255, 255]))
+                .count();
+            (terminal.backend().size().unwrap(), rows, background)
+        };
+
+        let (natural_size, natural, natural_background) = ink_rows(None);
+        let (tall_size, tall, tall_background) = ink_rows(Some(32));
+
+        assert_eq!(natural_size.height, 4);
+        assert_eq!(tall_size.height, 3);
+        assert_eq!(tall_size.width, natural_size.width);
+
+        // Both glyphs are pushed down by half of the extra 8 pixels, and the second row
+        // starts 32 pixels after the first.
+        let natural_top = natural[0];
+        let natural_second = natural[natural.len() / 2];
+        assert_eq!(tall[0], natural_top + 4);
+        assert_eq!(tall[tall.len() / 2], natural_second + 8 + 4);
+        assert_eq!(tall.len(), natural.len());
+
+        assert_eq!(natural_background, 48);
+        assert_eq!(tall_background, 64);
+    }
+
+    #[test]
+    #[serial]
+    fn cell_width_padding() {
+        let ink_columns = |padding: u32| {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                192,
+                24,
+                |b| b.with_cell_width_padding_px(padding),
+            );
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new("HH").on_blue(), Rect::new(0, 0, 2, 1));
+                })
+                .unwrap();
+
+            let pixels = surface_pixels(terminal.backend());
+            let columns = (0..192u32)
+                .filter(|x| (0..24u32).any(|y| pixels[(y * 192 + x) as usize].0[2] < 128))
+                .collect::<Vec<_>>();
+            let background = (0..192u32)
+                .filter(|x| pixels[*x as usize] == Rgba([0, 0, 255, 255]))
+                .count();
+            (terminal.backend().size().unwrap(), columns, background)
+        };
+
+        let (natural_size, natural, natural_background) = ink_columns(0);
+        let (padded_size, padded, padded_background) = ink_columns(4);
+
+        assert_eq!(natural_size.width, 16);
+        assert_eq!(padded_size.width, 12);
+        assert_eq!(padded_size.height, natural_size.height);
+
+        // Each glyph is centered in its cell, so the first is pushed right by half of
+        // the padding and the second by the full padding of the first cell plus half of
+        // its own.
+        let half = natural.len() / 2;
+        assert_eq!(padded.len(), natural.len());
+        assert!(padded[..half]
+            .iter()
+            .zip(&natural[..half])
+            .all(|(padded, natural)| *padded == natural + 2));
+        assert!(padded[half..]
+            .iter()
+            .zip(&natural[half..])
+            .all(|(padded, natural)| *padded == natural + 6));
+
+        assert_eq!(natural_background, 24);
+        assert_eq!(padded_background, 32);
+    }
+
+    #[test]
+    #[serial]
+    fn emoji_mode() {
+        let ink = |mode: EmojiMode| {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                64,
+                24,
+                |b| b.with_emoji_mode(mode),
+            );
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new("©".red().on_black()), f.area());
+                })
+                .unwrap();
+
+            let pixels = surface_pixels(terminal.backend());
+            (0..24u32)
+                .flat_map(|y| (0..12u32).map(move |x| (x, y)))
+                .map(|(x, y)| pixels[(y * 64 + x) as usize])
+                .max_by_key(|px| px.0[0])
+                .unwrap()
+        };
+
+        // The copyright sign is an emoji character, so it's normally drawn with its own
+        // colors, which for an outline glyph is plain white. In monochrome mode it's
+        // drawn with the cell's foreground like any other text.
+        assert_eq!(ink(EmojiMode::Color), Rgba([255, 255, 255, 255]));
+        assert_eq!(ink(EmojiMode::Monochrome), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    #[serial]
+    fn missing_glyph() {
+        let render = |text: &'static str, placeholder: Option<char>| {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                64,
+                24,
+                |b| match placeholder {
+                    Some(placeholder) => b.with_missing_glyph(placeholder),
+                    None => b,
+                },
+            );
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new(text), f.area());
+                })
+                .unwrap();
+
+            surface_pixels(terminal.backend())
+        };
+
+        // Cascadia doesn't have any Tibetan, so the letter is drawn as the placeholder.
+        let missing = render("\u{0F40}", None);
+        let placeholder = render("\u{0F40}", Some('\u{25A1}'));
+        assert_ne!(missing, placeholder);
+        assert_eq!(placeholder, render("\u{25A1}", None));
+
+        // Characters the font has are left alone.
+        assert_eq!(render("A", Some('\u{25A1}')), render("A", None));
+    }
+
+    #[test]
+    #[serial]
+    fn missing_glyphs() {
+        let font =
+            Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf")).expect("Invalid font file");
+        let mut terminal = Terminal::new(
+            futures_lite::future::block_on(
+                Builder::<DefaultPostProcessor>::from_font(font.clone())
+                    .with_missing_glyph('\u{25A1}')
+                    .with_width_and_height(Dimensions {
+                        width: NonZeroU32::new(128).unwrap(),
+                        height: NonZeroU32::new(48).unwrap(),
+                    })
+                    .build_headless(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        assert!(terminal.backend().missing_glyphs().is_empty());
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("A\u{0F40}B\n\u{0F41}\u{0F40}"), f.area());
+            })
+            .unwrap();
+
+        assert_eq!(
+            terminal.backend().missing_glyphs(),
+            &HashSet::from(['\u{0F40}', '\u{0F41}'])
+        );
+
+        terminal.backend_mut().update_fonts(Fonts::new(font, 24));
+        assert!(terminal.backend().missing_glyphs().is_empty());
+    }
+
+    #[test]
+    #[serial]
+    fn bidi_disabled() {
+        let render = |bidi: bool| {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                128,
+                24,
+                |b| b.with_bidi(bidi),
+            );
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(
+                        Paragraph::new("\u{645}\u{631}\u{62D}\u{628}\u{627}"),
+                        f.area(),
+                    );
+                })
+                .unwrap();
+
+            let svg = terminal.backend().to_svg();
+            let meem = svg.find(">\u{645}</text>").unwrap();
+            let alef = svg.find(">\u{627}</text>").unwrap();
+            (surface_pixels(terminal.backend()), meem < alef)
+        };
+
+        let (reordered, reordered_meem_first) = render(true);
+        let (logical, logical_meem_first) = render(false);
+
+        // The first logical character is drawn on the right with bidi and on the left
+        // without it.
+        assert!(!reordered_meem_first);
+        assert!(logical_meem_first);
+        assert_ne!(reordered, logical);
+    }
+
+    #[test]
+    #[serial]
+    fn build_with_texture() {
+        let font = || {
+            Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf")).expect("Invalid font file")
+        };
+        let draw = |f: &mut ratatui::Frame| {
+            f.render_widget(
+                Paragraph::new("Embedded\nterminal").block(Block::bordered()),
+                f.area(),
+            );
+        };
+
+        let mut headless = Terminal::new(
+            futures_lite::future::block_on(
+                Builder::<DefaultPostProcessor>::from_font(font())
+                    .with_width_and_height(Dimensions {
+                        width: NonZeroU32::new(128).unwrap(),
+                        height: NonZeroU32::new(96).unwrap(),
+                    })
+                    .build_headless(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        headless.draw(draw).unwrap();
+
+        let instance = wgpu::Instance::default();
+        let adapter =
+            futures_lite::future::block_on(instance.request_adapter(&Default::default())).unwrap();
+        let (device, queue) =
+            futures_lite::future::block_on(adapter.request_device(&Default::default(), None))
+                .unwrap();
+        let (device, queue) = (Arc::new(device), Arc::new(queue));
+        let create_texture = |width| {
+            device.create_texture(&wgpu::TextureDescriptor {
+                label: None,
+                size: Extent3d {
+                    width,
+                    height: 96,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+                view_formats: &[],
+            })
+        };
+
+        let mut embedded = Terminal::new(
+            Builder::<DefaultPostProcessor>::from_font(font())
+                .build_with_texture(device.clone(), queue.clone(), create_texture(128))
+                .unwrap(),
+        )
+        .unwrap();
+        assert!(embedded.backend().adapter_info().is_none());
+        embedded.draw(draw).unwrap();
+
+        // The texture is sized like the headless surface, so both render the same
+        // frame.
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: 128 * 4 * 96,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor::default());
+        encoder.copy_texture_to_buffer(
+            embedded.backend().texture().as_image_copy(),
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(128 * 4),
+                    rows_per_image: Some(96),
+                },
+            },
+            embedded.backend().texture().size(),
+        );
+        queue.submit(Some(encoder.finish()));
+
+        let slice = buffer.slice(..);
+        let (send, recv) = oneshot::channel();
+        slice.map_async(wgpu::MapMode::Read, move |data| {
+            send.send(data).unwrap();
+        });
+        device.poll(wgpu::MaintainBase::Wait);
+        recv.recv().unwrap().unwrap();
+        assert!(*slice.get_mapped_range() == headless.backend().read_rgba());
+
+        embedded.backend_mut().set_texture(create_texture(256));
+        assert_eq!(embedded.backend().texture().width(), 256);
+        assert_eq!(embedded.size().unwrap(), Size::new(21, 4));
+    }
+
+    #[test]
+    #[serial]
+    fn with_device() {
+        let instance = wgpu::Instance::default();
+        let adapter =
+            futures_lite::future::block_on(instance.request_adapter(&Default::default())).unwrap();
+        let (device, queue) =
+            futures_lite::future::block_on(adapter.request_device(&Default::default(), None))
+                .unwrap();
+        let (device, queue) = (Arc::new(device), Arc::new(queue));
+
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            96,
+            |b| {
+                b.with_instance(instance)
+                    .with_device(device.clone(), queue.clone())
+            },
+        );
+
+        assert!(Arc::ptr_eq(&terminal.backend().device, &device));
+        assert!(Arc::ptr_eq(&terminal.backend().queue, &queue));
+
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("Shared"), f.area()))
+            .unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn atlas_size() {
+        let render = |atlas: Option<Dimensions>| {
+            let mut builder = Builder::<DefaultPostProcessor>::from_font(
+                Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                    .expect("Invalid font file"),
+            )
+            .with_width_and_height(Dimensions {
+                width: NonZeroU32::new(64).unwrap(),
+                height: NonZeroU32::new(24).unwrap(),
+            });
+            if let Some(atlas) = atlas {
+                builder = builder.with_atlas_size(atlas);
+            }
+
+            let mut terminal =
+                Terminal::new(futures_lite::future::block_on(builder.build_headless()).unwrap())
+                    .unwrap();
+            terminal
+                .draw(|f| f.render_widget(Paragraph::new("Helo"), f.area()))
+                .unwrap();
+            surface_pixels(terminal.backend())
+        };
+
+        // Room for six double-width entries, enough for the five cells.
+        let small = render(Some(Dimensions {
+            width: NonZeroU32::new(72).unwrap(),
+            height: NonZeroU32::new(48).unwrap(),
+        }));
+        assert_eq!(small, render(None));
+
+        let result = futures_lite::future::block_on(
+            Builder::<DefaultPostProcessor>::from_font(
+                Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                    .expect("Invalid font file"),
+            )
+            .with_atlas_size(Dimensions {
+                width: NonZeroU32::new(u32::MAX).unwrap(),
+                height: NonZeroU32::new(24).unwrap(),
+            })
+            .build_headless(),
+        );
+        assert!(matches!(
+            result,
+            Err(crate::Error::AtlasTooLarge {
+                width: u32::MAX,
+                height: 24,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn required_coverage() {
+        let build = |chars: &[char]| {
+            futures_lite::future::block_on(
+                Builder::<DefaultPostProcessor>::from_font(
+                    Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                        .expect("Invalid font file"),
+                )
+                .with_required_coverage(chars)
+                .build_headless(),
+            )
+        };
+
+        assert!(build(&['A', 'z', '0', '~']).is_ok());
+        assert!(matches!(
+            build(&['A', '漢', 'z', '\u{10FFFD}']),
+            Err(crate::Error::InsufficientFontCoverage { missing })
+                if missing == ['漢', '\u{10FFFD}']
+        ));
+
+        // The check runs before an adapter is requested, so it still reports
+        // missing characters when no backend is available.
+        assert!(matches!(
+            futures_lite::future::block_on(
+                Builder::<DefaultPostProcessor>::from_font(
+                    Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                        .expect("Invalid font file"),
+                )
+                .with_instance_descriptor(InstanceDescriptor {
+                    backends: Backends::empty(),
+                    ..Default::default()
+                })
+                .with_required_coverage(&['漢'])
+                .build_headless(),
+            ),
+            Err(crate::Error::InsufficientFontCoverage { .. })
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn atlas_pages() {
+        let render = |atlas: Option<Dimensions>| {
+            let mut builder = Builder::<DefaultPostProcessor>::from_font(
+                Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                    .expect("Invalid font file"),
+            )
+            .with_width_and_height(Dimensions {
+                width: NonZeroU32::new(192).unwrap(),
+                height: NonZeroU32::new(24).unwrap(),
+            });
+            if let Some(atlas) = atlas {
+                builder = builder.with_atlas_size(atlas);
+            }
+
+            let mut terminal =
+                Terminal::new(futures_lite::future::block_on(builder.build_headless()).unwrap())
+                    .unwrap();
+            terminal
+                .draw(|f| f.render_widget(Paragraph::new("ABCDEFGHIJ"), f.area()))
+                .unwrap();
+            // Needs a third page, so the glyphs cached in the first two are copied over.
+            terminal
+                .draw(|f| f.render_widget(Paragraph::new("ABCDEFGHIJKLMNOP"), f.area()))
+                .unwrap();
+            (
+                surface_pixels(terminal.backend()),
+                terminal.backend().cached.pages(),
+            )
+        };
+
+        // Six entries per page.
+        let (paged, pages) = render(Some(Dimensions {
+            width: NonZeroU32::new(72).unwrap(),
+            height: NonZeroU32::new(48).unwrap(),
+        }));
+        let (single, _) = render(None);
+
+        assert_eq!(pages, 3);
+        assert!(single.iter().any(|px| px.0[0] != 0));
+        assert!(paged == single, "Paged atlas rendered differently");
+    }
+
+    #[test]
+    #[serial]
+    fn with_hasher() {
+        let draw = |f: &mut ratatui::Frame| {
+            f.render_widget(
+                Paragraph::new("Hashed\nterminal").block(Block::bordered()),
+                f.area(),
+            );
+        };
+        let builder = || {
+            Builder::<DefaultPostProcessor>::from_font(
+                Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                    .expect("Invalid font file"),
+            )
+            .with_width_and_height(Dimensions {
+                width: NonZeroU32::new(128).unwrap(),
+                height: NonZeroU32::new(96).unwrap(),
+            })
+        };
+
+        let mut default =
+            Terminal::new(futures_lite::future::block_on(builder().build_headless()).unwrap())
+                .unwrap();
+        default.draw(draw).unwrap();
+
+        let mut hashed = Terminal::new(
+            futures_lite::future::block_on(
+                builder()
+                    .with_hasher(BuildHasherDefault::<DefaultHasher>::default())
+                    .build_headless(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        hashed.draw(draw).unwrap();
+
+        assert!(
+            surface_pixels(hashed.backend()) == surface_pixels(default.backend()),
+            "Rendered image differs with a custom hasher"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn last_frame_stats() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            64,
+            24,
+            |b| b,
+        );
+
+        assert_eq!(terminal.backend().last_frame_stats(), FrameStats::default());
+
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("Hello"), f.area()))
+            .unwrap();
+        let stats = terminal.backend().last_frame_stats();
+        assert_eq!(stats.glyphs_rasterized, 4);
+        assert_eq!(stats.dirty_cells, 5);
+        assert_eq!(stats.atlas_entries, 4);
+        assert!(stats.vertices > 0);
+        assert_eq!(stats.atlas_capacity, (1800 / 24) * (1200 / 24));
+
+        // Nothing changed, so nothing was rendered.
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("Hello"), f.area()))
+            .unwrap();
+        assert_eq!(terminal.backend().last_frame_stats(), stats);
+
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("Hellp"), f.area()))
+            .unwrap();
+        let stats = terminal.backend().last_frame_stats();
+        assert_eq!(stats.glyphs_rasterized, 1);
+        assert_eq!(stats.atlas_entries, 5);
+    }
+
+    #[test]
+    #[serial]
+    fn reused_frame_buffers() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            512,
+            256,
+            |b| b,
+        );
+
+        // Buffers hash by identity, so this changes whenever one is reallocated.
+        let buffers = |terminal: &Terminal<WgpuBackend<'_, 'static, _, HeadlessSurface>>| {
+            let buffers = &terminal.backend().frame_buffers;
+            let mut hasher = DefaultHasher::new();
+            (
+                &buffers.bg_vertices,
+                &buffers.text_vertices,
+                &buffers.indices,
+            )
+                .hash(&mut hasher);
+            hasher.finish()
+        };
+
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("Frame 0"), f.area()))
+            .unwrap();
+        let first = buffers(&terminal);
+
+        for frame in 1..100 {
+            terminal
+                .draw(|f| f.render_widget(Paragraph::new(format!("Frame {frame}")), f.area()))
+                .unwrap();
+            assert_eq!(buffers(&terminal), first);
+        }
+
+        // Redraws a grid with four times as many cells.
+        terminal.backend_mut().resize(1024, 512);
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("Grown"), f.area()))
+            .unwrap();
+        assert_ne!(buffers(&terminal), first);
+    }
+
+    #[test]
+    #[serial]
+    fn glyph_disk_cache() {
+        let dir = std::env::temp_dir().join(format!(
+            "ratatui-wgpu-glyph-disk-cache-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let render = || {
+            let mut terminal = headless_terminal(
+                include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+                128,
+                48,
+                |b| b.with_glyph_disk_cache(dir.clone()),
+            );
+
+            terminal
+                .draw(|f| f.render_widget(Paragraph::new("Hello"), f.area()))
+                .unwrap();
+            (
+                terminal.backend().last_frame_stats().glyphs_rasterized,
+                surface_pixels(terminal.backend()),
+            )
+        };
+
+        let (rasterized, pixels) = render();
+        assert_ne!(rasterized, 0);
+
+        // The second backend loads the glyphs the first one cached into its atlas.
+        let (rasterized, cached_pixels) = render();
+        assert_eq!(rasterized, 0);
+        assert!(pixels == cached_pixels, "Cached glyphs render differently");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    #[serial]
+    fn merged_backgrounds() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            48,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Block::new().on_blue(), f.area());
+                f.render_widget(Block::new().on_red(), Rect::new(2, 0, 1, 1));
+            })
+            .unwrap();
+
+        // Each row is a single quad, except the first which is split around the red
+        // cell.
+        let rows = terminal.backend().size().unwrap().height as usize;
+        assert_eq!(terminal.backend().bg_vertices.len(), (rows + 2) * 4);
+    }
+
+    #[test]
+    #[serial]
+    fn atlas_overflow() {
+        let render = |atlas: Option<Dimensions>| {
+            let mut builder = Builder::<DefaultPostProcessor>::from_font(
+                Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                    .expect("Invalid font file"),
+            )
+            .with_width_and_height(Dimensions {
+                width: NonZeroU32::new(192).unwrap(),
+                height: NonZeroU32::new(72).unwrap(),
+            });
+            if let Some(atlas) = atlas {
+                builder = builder
+                    .with_atlas_size(atlas)
+                    .with_max_atlas_pages(NonZeroU32::new(1).unwrap());
+            }
+
+            let mut terminal =
+                Terminal::new(futures_lite::future::block_on(builder.build_headless()).unwrap())
+                    .unwrap();
+
+            let mut frames = vec![];
+            for text in [
+                "ABCDEFGHIJKLMNOP\nabcdefghijklmnop\n0123456789!@#$%^",
+                "QRSTUVWXYZ&*()[]\nqrstuvwxyz+-=<>?\nABCDEFGHIJKLMNOP",
+            ] {
+                terminal
+                    .draw(|f| f.render_widget(Paragraph::new(text), f.area()))
+                    .unwrap();
+                frames.push(surface_pixels(terminal.backend()));
+            }
+
+            (frames, terminal.backend().cached.epoch())
+        };
+
+        // Six entries, far fewer than the 48 unique glyphs in each frame.
+        let (overflowed, epoch) = render(Some(Dimensions {
+            width: NonZeroU32::new(72).unwrap(),
+            height: NonZeroU32::new(48).unwrap(),
+        }));
+        let (expected, _) = render(None);
+
+        assert!(epoch > 2);
+        for (overflowed, expected) in overflowed.into_iter().zip(expected) {
+            assert!(expected.iter().any(|px| px.0[0] != 0));
+            assert!(
+                overflowed == expected,
+                "Overflowing the atlas corrupted the frame"
+            );
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn subpixel_aa() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            256,
+            72,
+            |b| b.with_subpixel_aa(true),
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Paragraph::new(vec![
+                        Line::from("Hello, world!"),
+                        Line::from("Hello, world!").black().on_white(),
+                        Line::from("Hello, world!").yellow().on_blue(),
+                    ]),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        let pixels = surface_pixels(terminal.backend());
+        assert_golden(&pixels, include_bytes!("goldens/subpixel_aa.png"));
+
+        // White on black text only has color where the subpixels have different
+        // coverage.
+        assert!(pixels[..256 * 24].iter().any(|px| px.0[0] != px.0[2]));
+    }
+
+    fn gamma_hello_world(gamma: f32) -> Vec<Rgba<u8>> {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            256,
+            24,
+            |b| b.with_text_gamma(gamma),
+        );
+
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("Hello, world!"), f.area()))
+            .unwrap();
+
+        surface_pixels(terminal.backend())
+    }
+
+    #[test]
+    #[serial]
+    fn text_gamma() {
+        let linear = gamma_hello_world(1.0);
+        let pixels = gamma_hello_world(1.8);
+        assert_golden(&pixels, include_bytes!("goldens/text_gamma.png"));
+
+        // Raising the gamma darkens partially covered pixels of the default dark
+        // text, and leaves fully covered and uncovered pixels alone.
+        let brightness = |pixels: &[Rgba<u8>]| pixels.iter().map(|px| px.0[0] as u32).sum::<u32>();
+        assert!(brightness(&pixels) < brightness(&linear));
+        assert!(pixels
+            .iter()
+            .zip(&linear)
+            .all(|(px, linear)| px.0[0] <= linear.0[0]));
+    }
+
+    fn blended_block(mode: BlendMode) -> [u8; 4] {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            64,
+            24,
+            |b| b.with_text_blend(mode),
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Paragraph::new("█")
+                        .fg(Color::Rgb(128, 255, 0))
+                        .bg(Color::Rgb(200, 100, 50)),
+                    f.area(),
+                )
+            })
+            .unwrap();
+
+        let cell = terminal.backend().cell_size();
+        let x = cell.width as usize / 2;
+        let y = cell.height as usize / 2;
+        surface_pixels(terminal.backend())[y * 64 + x].0
+    }
+
+    #[test]
+    #[serial]
+    fn text_blend() {
+        let fg = [128.0, 255.0, 0.0];
+        let bg = [200.0, 100.0, 50.0];
+        type Blend = fn(f32, f32) -> f32;
+        let blends: [(BlendMode, Blend); 7] = [
+            (BlendMode::SourceOver, |fg, _| fg),
+            (BlendMode::Multiply, |fg, bg| fg * bg / 255.0),
+            (BlendMode::Screen, |fg, bg| fg + bg - fg * bg / 255.0),
+            (BlendMode::Add, |fg, bg| (fg + bg).min(255.0)),
+            (BlendMode::Subtract, |fg, bg| (bg - fg).max(0.0)),
+            (BlendMode::Darken, f32::min),
+            (BlendMode::Lighten, f32::max),
+        ];
+
+        for (mode, blend) in blends {
+            let pixel = blended_block(mode);
+            for channel in 0..3 {
+                let expected = blend(fg[channel], bg[channel]);
+                assert!(
+                    (pixel[channel] as f32 - expected).abs() <= 2.0,
+                    "{mode:?} produced {pixel:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn panes() {
+        let mut backend = futures_lite::future::block_on(
+            Builder::<DefaultPostProcessor>::from_font(
+                Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                    .expect("Invalid font file"),
+            )
+            .with_width_and_height(Dimensions {
+                width: NonZeroU32::new(512).unwrap(),
+                height: NonZeroU32::new(72).unwrap(),
+            })
+            .build_headless(),
+        )
+        .unwrap();
+
+        let size = backend.size().unwrap();
+        let left = backend.add_pane(Rect::new(0, 0, size.width / 2, size.height));
+        let right = backend.add_pane(Rect::new(
+            size.width / 2,
+            0,
+            size.width - size.width / 2,
+            size.height,
+        ));
+
+        backend.begin_batch();
+        for (pane, text) in [(left, "Left pane"), (right, "Right pane")] {
+            let mut terminal = Terminal::new(backend.pane(pane)).unwrap();
+            terminal.clear().unwrap();
+            terminal
+                .draw(|f| {
+                    assert_eq!(f.area().height, size.height);
+                    f.render_widget(Paragraph::new(text), f.area());
+                })
+                .unwrap();
+        }
+        backend.end_batch().unwrap();
+
+        assert_eq!(backend.get_cell(0, 0).unwrap().symbol(), "L");
+        assert_eq!(backend.get_cell(size.width / 2, 0).unwrap().symbol(), "R");
+        assert_eq!(
+            backend.get_cell(size.width / 2 + 9, 0).unwrap().symbol(),
+            "e"
+        );
+
+        // Content outside of the pane is discarded.
+        let pane_width = size.width / 2;
+        backend
+            .pane(left)
+            .draw(std::iter::once((
+                pane_width,
+                1,
+                &ratatui::buffer::Cell::new("X"),
+            )))
+            .unwrap();
+        assert_eq!(backend.get_cell(pane_width, 1).unwrap().symbol(), " ");
+
+        // A wide character at the edge of a pane would spill into the next one.
+        backend
+            .pane(left)
+            .draw(std::iter::once((
+                pane_width - 1,
+                0,
+                &ratatui::buffer::Cell::new("\u{4e16}"),
+            )))
+            .unwrap();
+        backend.pane(left).flush().unwrap();
+        assert_eq!(backend.get_cell(pane_width - 1, 0).unwrap().symbol(), " ");
+        assert_eq!(backend.get_cell(pane_width, 0).unwrap().symbol(), "R");
+
+        // Redrawing a pane with the same content doesn't repaint anything.
+        backend.begin_batch();
+        {
+            let mut terminal = Terminal::new(backend.pane(right)).unwrap();
+            terminal.clear().unwrap();
+            terminal
+                .draw(|f| {
+                    // Keep the cursor where it is, so it doesn't need to be repainted.
+                    f.set_cursor_position(f.area().as_position());
+                    f.render_widget(Paragraph::new("Right pane"), f.area());
+                })
+                .unwrap();
+        }
+        assert!(!backend.dirty_rows.contains(&true));
+        backend.end_batch().unwrap();
+
+        // Clearing a pane leaves the other pane alone, once it's flushed.
+        backend.pane(left).clear().unwrap();
+        assert_eq!(backend.get_cell(0, 0).unwrap().symbol(), "L");
+        backend.pane(left).flush().unwrap();
+        assert_eq!(backend.get_cell(0, 0).unwrap().symbol(), " ");
+        assert_eq!(backend.get_cell(size.width / 2, 0).unwrap().symbol(), "R");
+
+        let mut pane = backend.pane(right);
+        pane.set_cursor_position(Position::new(2, 1)).unwrap();
+        assert_eq!(pane.get_cursor_position().unwrap(), Position::new(2, 1));
+        assert_eq!(
+            backend.get_cursor_position().unwrap(),
+            Position::new(size.width / 2 + 2, 1)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn min_frame_interval() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            192,
+            48,
+            |b| b.with_min_frame_interval(std::time::Duration::from_secs(3600)),
+        );
+
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("first"), f.area()))
+            .unwrap();
+        let first = surface_pixels(terminal.backend());
+
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("second"), f.area()))
+            .unwrap();
+        assert!(
+            surface_pixels(terminal.backend()) == first,
+            "Flush inside the interval rendered"
+        );
+
+        // Once the interval has elapsed, the skipped changes are rendered.
+        terminal.backend_mut().last_flush = None;
+        terminal.backend_mut().flush().unwrap();
+        assert!(
+            surface_pixels(terminal.backend()) != first,
+            "Skipped changes weren't rendered"
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn draw_image() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            192,
+            72,
+            |b| b,
+        );
+
+        let red = [255, 0, 0, 255];
+        let green = [0, 255, 0, 255];
+        let blue = [0, 0, 255, 255];
+        let black = [0, 0, 0, 255];
+        let image = [red, green, blue, black].concat();
+        let area = Rect::new(2, 0, 4, 2);
+        terminal
+            .backend_mut()
+            .draw_image(area, &image, 2, 2, SampleMode::Nearest);
+
+        let draw = |terminal: &mut Terminal<_>, text: &'static str| {
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new(text), f.area());
+                    f.render_widget(Paragraph::new("X").white(), Rect::new(5, 1, 1, 1));
+                })
+                .unwrap();
+        };
+
+        let cell = terminal.backend().cell_size();
+        let pixel = |pixels: &[Rgba<u8>], x: u16, y: u16| {
+            let x = x as usize * cell.width as usize + cell.width as usize / 2;
+            let y = y as usize * cell.height as usize + cell.height as usize / 2;
+            pixels[y * 192 + x].0
+        };
+        let check = |pixels: &[Rgba<u8>]| {
+            assert_eq!(pixel(pixels, 2, 0), red);
+            assert_eq!(pixel(pixels, 5, 0), green);
+            assert_eq!(pixel(pixels, 2, 1), blue);
+            // Glyphs are drawn over the image.
+            let x = 5 * cell.width as usize;
+            let y = cell.height as usize;
+            assert!((y..y + cell.height as usize).any(|y| {
+                pixels[y * 192 + x..][..cell.width as usize]
+                    .iter()
+                    .any(|px| px.0 != black)
+            }));
+            // Cells outside of the image are untouched.
+            assert_eq!(pixel(pixels, 7, 0), [255, 255, 255, 255]);
+        };
+
+        draw(&mut terminal, "ab");
+        check(&surface_pixels(terminal.backend()));
+
+        // Redrawing a cell in a row the image covers redraws the whole image.
+        draw(&mut terminal, "cb");
+        check(&surface_pixels(terminal.backend()));
+
+        terminal.backend_mut().clear_images();
+        terminal.backend_mut().flush().unwrap();
+        assert_eq!(
+            pixel(&surface_pixels(terminal.backend()), 2, 0),
+            [255, 255, 255, 255]
+        );
+    }
+
+    #[cfg(feature = "kitty-graphics")]
+    #[test]
+    #[serial]
+    fn kitty_graphics() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            192,
+            72,
+            |b| b,
+        );
+
+        let cell = terminal.backend().cell_size();
+        let pixel = |pixels: &[Rgba<u8>], x: u16, y: u16| {
+            let x = x as usize * cell.width as usize + cell.width as usize / 2;
+            let y = y as usize * cell.height as usize + cell.height as usize / 2;
+            pixels[y * 192 + x].0
+        };
+
+        // A single red pixel, stretched over 3x2 cells. The escape is consumed
+        // rather than drawn as text.
+        terminal
+            .draw(|f| {
+                f.buffer_mut()[(2, 0)].set_symbol("\x1b_Ga=T,f=32,s=1,v=1,c=3,r=2;/wAA/w==\x1b\\");
+            })
+            .unwrap();
+        let pixels = surface_pixels(terminal.backend());
+        for (x, y) in [(2, 0), (4, 0), (2, 1), (4, 1)] {
+            assert_eq!(pixel(&pixels, x, y), [255, 0, 0, 255]);
+        }
+        assert_eq!(pixel(&pixels, 5, 0), [255, 255, 255, 255]);
+
+        // Overwriting the cell holding the escape removes the image.
+        terminal
+            .draw(|f| {
+                f.buffer_mut()[(2, 0)].set_symbol(" ");
+            })
+            .unwrap();
+        assert_eq!(
+            pixel(&surface_pixels(terminal.backend()), 3, 1),
+            [255, 255, 255, 255]
+        );
+    }
+
+    #[cfg(feature = "sixel")]
+    #[test]
+    #[serial]
+    fn sixel() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            192,
+            72,
+            |b| b,
+        );
+
+        // A band of red one pixel wider than a cell covers two cells, without
+        // being stretched to fill the second.
+        let cell = terminal.backend().cell_size();
+        let escape = format!("\x1bPq#1;2;100;0;0!{}~\x1b\\", cell.width + 1);
+        terminal
+            .draw(|f| {
+                f.buffer_mut()[(1, 1)].set_symbol(&escape);
+            })
+            .unwrap();
+
+        let pixels = surface_pixels(terminal.backend());
+        let pixel = |x: usize, y: usize| {
+            pixels[(cell.height as usize + y) * 192 + cell.width as usize + x].0
+        };
+        assert_eq!(pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(cell.width as usize, 5), [255, 0, 0, 255]);
+        assert_eq!(pixel(cell.width as usize + 1, 0), [255, 255, 255, 255]);
+        assert_eq!(pixel(0, 6), [255, 255, 255, 255]);
+    }
+
+    #[test]
+    #[serial]
+    fn cell_shader() {
+        fn shader(cell: &ratatui::buffer::Cell, x: u16, _y: u16) -> CellOverride {
+            match (x, cell.symbol()) {
+                (1, _) => CellOverride {
+                    bg: Some(crate::Rgba::new(255, 0, 0, 255)),
+                    ..Default::default()
+                },
+                (_, "█") => CellOverride {
+                    fg: Some(crate::Rgba::new(0, 0, 255, 255)),
+                    ..Default::default()
+                },
+                _ => CellOverride::default(),
+            }
+        }
+
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            192,
+            24,
+            |b| b.with_cell_shader(shader),
+        );
+
+        terminal
+            .draw(|f| f.render_widget(Paragraph::new("   █"), f.area()))
+            .unwrap();
+
+        let cell = terminal.backend().cell_size();
+        let pixels = surface_pixels(terminal.backend());
+        let pixel = |x: usize| {
+            pixels
+                [cell.height as usize / 2 * 192 + x * cell.width as usize + cell.width as usize / 2]
+                .0
+        };
+        assert_eq!(pixel(0), [255, 255, 255, 255]);
+        assert_eq!(pixel(1), [255, 0, 0, 255]);
+        assert_eq!(pixel(2), [255, 255, 255, 255]);
+        assert_eq!(pixel(3), [0, 0, 255, 255]);
+    }
+
+    #[test]
+    #[serial]
+    fn integer_scaling() {
+        let mut backend = futures_lite::future::block_on(
+            Builder::<DefaultPostProcessor>::from_font(
+                Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                    .expect("Invalid font file"),
+            )
+            .with_integer_scaling(true)
+            .with_viewport(Viewport::Shrink {
+                width: 3,
+                height: 5,
+            })
+            .with_width_and_height(Dimensions {
+                width: NonZeroU32::new(203).unwrap(),
+                height: NonZeroU32::new(77).unwrap(),
+            })
+            .build_headless(),
+        )
+        .unwrap();
+
+        fn check(
+            backend: &WgpuBackend<'static, 'static, DefaultPostProcessor, HeadlessSurface>,
+            width: u32,
+            height: u32,
+        ) {
+            let cell = backend.cell_size();
+            let (cell_width, cell_height) = (cell.width as u32, cell.height as u32);
+            let size = backend.size().unwrap();
+            assert_eq!(size.width as u32, (width - 3) / cell_width);
+            assert_eq!(size.height as u32, (height - 5) / cell_height);
+            assert_eq!(
+                (backend.surface_config.width, backend.surface_config.height),
+                (
+                    size.width as u32 * cell_width + 3,
+                    size.height as u32 * cell_height + 5
+                )
+            );
+        }
+
+        let cell = backend.cell_size();
+        let (cell_width, cell_height) = (cell.width as u32, cell.height as u32);
+        check(&backend, 203, 77);
+
+        backend.resize(331, 101);
+        check(&backend, 331, 101);
+
+        // Sizes which snap to the current size are ignored.
+        backend.resize(331 + cell_width - 1 - (331 - 3) % cell_width, 101);
+        check(&backend, 331, 101);
+
+        // Sizes smaller than a cell still hold one cell.
+        backend.resize(4, 6);
+        assert_eq!(backend.size().unwrap(), Size::new(1, 1));
+        assert_eq!(
+            (backend.surface_config.width, backend.surface_config.height),
+            (cell_width + 3, cell_height + 5)
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn scale_factor() {
+        let build = |size: u32, scale: f32| {
+            Terminal::new(
+                futures_lite::future::block_on(
+                    Builder::<DefaultPostProcessor>::from_font(
+                        Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                            .expect("Invalid font file"),
+                    )
+                    .with_font_size_px(size)
+                    .with_line_height_px(size + size / 6)
+                    .with_scale_factor(scale)
+                    .with_width_and_height(Dimensions {
+                        width: NonZeroU32::new(256).unwrap(),
+                        height: NonZeroU32::new(112).unwrap(),
+                    })
+                    .build_headless(),
+                )
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        let draw = |terminal: &mut Terminal<_>| {
+            terminal
+                .draw(|f| f.render_widget(Paragraph::new("Hello"), f.area()))
+                .unwrap();
+            surface_pixels(terminal.backend())
+        };
+
+        let mut scaled = build(12, 2.0);
+        let mut unscaled = build(24, 1.0);
+        assert_eq!(scaled.backend().cell_size().height, 28);
+        assert_eq!(scaled.backend().cell_size(), unscaled.backend().cell_size());
+        assert_eq!(draw(&mut scaled), draw(&mut unscaled));
+
+        // Changing the scale factor resizes the grid to fit the new cell size.
+        let mut small = build(12, 1.0);
+        scaled.backend_mut().set_scale_factor(1.0);
+        assert_eq!(scaled.backend().scale_factor(), 1.0);
+        assert_eq!(scaled.backend().cell_size(), small.backend().cell_size());
+        assert_eq!(
+            scaled.backend().size().unwrap(),
+            small.backend().size().unwrap()
+        );
+        assert_eq!(draw(&mut scaled), draw(&mut small));
+    }
+
+    #[test]
+    #[serial]
+    fn alternate_screen() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            512,
+            72,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                let block = Block::bordered();
+                let area = block.inner(f.area());
+                f.render_widget(block, f.area());
+                f.render_widget(Paragraph::new("ABCDEFGHIJKLMNOPQRSTUVWXYZ"), area);
+            })
+            .unwrap();
+        let main_text = terminal.backend().get_text();
+
+        terminal.backend_mut().enter_alternate_screen();
+        assert!(terminal.backend().is_alternate_screen());
+
+        terminal
+            .draw(|f| {
+                f.render_widget(Paragraph::new("Alternate"), f.area());
+            })
+            .unwrap();
+
+        let alternate_text = terminal.backend().get_text();
+        assert!(alternate_text.starts_with("Alternate"));
+        assert!(!alternate_text.contains("ABCDEFGHIJKLMNOPQRSTUVWXYZ"));
+
+        terminal.backend_mut().leave_alternate_screen();
+        assert!(!terminal.backend().is_alternate_screen());
+        assert_eq!(terminal.backend().get_text(), main_text);
+
+        terminal.backend_mut().flush().unwrap();
+
+        assert_golden(
+            &surface_pixels(terminal.backend()),
+            include_bytes!("goldens/a_z.png"),
         );
-        queue.submit(Some(encoder.finish()));
     }
 
     #[test]
     #[serial]
-    fn a_z() {
+    fn arabic() {
         let mut terminal = Terminal::new(
             futures_lite::future::block_on(
                 Builder::<DefaultPostProcessor>::from_font(
@@ -1437,7 +8183,7 @@ mod tests {
                         .expect("Invalid font file"),
                 )
                 .with_width_and_height(Dimensions {
-                    width: NonZeroU32::new(512).unwrap(),
+                    width: NonZeroU32::new(256).unwrap(),
                     height: NonZeroU32::new(72).unwrap(),
                 })
                 .build_headless(),
@@ -1451,7 +8197,7 @@ mod tests {
                 let block = Block::bordered();
                 let area = block.inner(f.area());
                 f.render_widget(block, f.area());
-                f.render_widget(Paragraph::new("ABCDEFGHIJKLMNOPQRSTUVWXYZ"), area);
+                f.render_widget(Paragraph::new("مرحبا بالعالم"), area);
             })
             .unwrap();
 
@@ -1476,7 +8222,7 @@ mod tests {
                 ImageBuffer::<Rgba<u8>, _>::from_raw(surface.width, surface.height, data).unwrap();
 
             let pixels = image.pixels().copied().collect::<Vec<_>>();
-            let golden = load_from_memory(include_bytes!("goldens/a_z.png")).unwrap();
+            let golden = load_from_memory(include_bytes!("goldens/arabic.png")).unwrap();
             let golden_pixels = golden.pixels().map(|(_, _, px)| px).collect::<Vec<_>>();
 
             assert!(
@@ -1490,15 +8236,14 @@ mod tests {
 
     #[test]
     #[serial]
-    fn arabic() {
+    fn really_wide() {
         let mut terminal = Terminal::new(
             futures_lite::future::block_on(
                 Builder::<DefaultPostProcessor>::from_font(
-                    Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
-                        .expect("Invalid font file"),
+                    Font::new(include_bytes!("fonts/Fairfax.ttf")).expect("Invalid font file"),
                 )
                 .with_width_and_height(Dimensions {
-                    width: NonZeroU32::new(256).unwrap(),
+                    width: NonZeroU32::new(512).unwrap(),
                     height: NonZeroU32::new(72).unwrap(),
                 })
                 .build_headless(),
@@ -1512,7 +8257,7 @@ mod tests {
                 let block = Block::bordered();
                 let area = block.inner(f.area());
                 f.render_widget(block, f.area());
-                f.render_widget(Paragraph::new("مرحبا بالعالم"), area);
+                f.render_widget(Paragraph::new("Ｈｅｌｌｏ, ｗｏｒｌｄ!"), area);
             })
             .unwrap();
 
@@ -1537,7 +8282,7 @@ mod tests {
                 ImageBuffer::<Rgba<u8>, _>::from_raw(surface.width, surface.height, data).unwrap();
 
             let pixels = image.pixels().copied().collect::<Vec<_>>();
-            let golden = load_from_memory(include_bytes!("goldens/arabic.png")).unwrap();
+            let golden = load_from_memory(include_bytes!("goldens/really_wide.png")).unwrap();
             let golden_pixels = golden.pixels().map(|(_, _, px)| px).collect::<Vec<_>>();
 
             assert!(
@@ -1551,28 +8296,22 @@ mod tests {
 
     #[test]
     #[serial]
-    fn really_wide() {
-        let mut terminal = Terminal::new(
-            futures_lite::future::block_on(
-                Builder::<DefaultPostProcessor>::from_font(
-                    Font::new(include_bytes!("fonts/Fairfax.ttf")).expect("Invalid font file"),
-                )
-                .with_width_and_height(Dimensions {
-                    width: NonZeroU32::new(512).unwrap(),
-                    height: NonZeroU32::new(72).unwrap(),
-                })
-                .build_headless(),
-            )
-            .unwrap(),
-        )
-        .unwrap();
+    fn combining_last_column() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            72,
+            |b| b,
+        );
 
         terminal
             .draw(|f| {
-                let block = Block::bordered();
-                let area = block.inner(f.area());
-                f.render_widget(block, f.area());
-                f.render_widget(Paragraph::new("Ｈｅｌｌｏ, ｗｏｒｌｄ!"), area);
+                let area = f.area();
+                f.render_widget(Paragraph::new("abcdefghi\nabcdefghi\nabcdefghi"), area);
+                let buffer = f.buffer_mut();
+                buffer[(area.width - 1, 0)].set_symbol("e\u{301}");
+                buffer[(area.width - 1, 1)].set_symbol("e\u{301}\u{301}\u{301}");
+                buffer[(area.width - 1, 2)].set_symbol("\u{4E2D}\u{301}");
             })
             .unwrap();
 
@@ -1596,17 +8335,302 @@ mod tests {
             let image =
                 ImageBuffer::<Rgba<u8>, _>::from_raw(surface.width, surface.height, data).unwrap();
 
-            let pixels = image.pixels().copied().collect::<Vec<_>>();
-            let golden = load_from_memory(include_bytes!("goldens/really_wide.png")).unwrap();
-            let golden_pixels = golden.pixels().map(|(_, _, px)| px).collect::<Vec<_>>();
+            assert_golden(
+                &image.pixels().copied().collect::<Vec<_>>(),
+                include_bytes!("goldens/combining_last_column.png"),
+            );
+        }
+
+        surface.buffer.as_ref().unwrap().unmap();
+    }
+
+    fn fairfax_hello_world(antialias: bool) -> Vec<Rgba<u8>> {
+        let mut terminal = headless_terminal(include_bytes!("fonts/Fairfax.ttf"), 256, 54, |b| {
+            b.with_font_size_px(18).with_glyph_antialiasing(antialias)
+        });
+
+        terminal
+            .draw(|f| {
+                let block = Block::bordered();
+                let area = block.inner(f.area());
+                f.render_widget(block, f.area());
+                f.render_widget(Paragraph::new("Hello, world!"), area);
+            })
+            .unwrap();
+
+        surface_pixels(terminal.backend())
+    }
+
+    #[test]
+    #[serial]
+    fn fairfax_antialiased() {
+        assert_golden(
+            &fairfax_hello_world(true),
+            include_bytes!("goldens/fairfax_antialiased.png"),
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn fairfax_aliased() {
+        assert_golden(
+            &fairfax_hello_world(false),
+            include_bytes!("goldens/fairfax_aliased.png"),
+        );
+    }
+
+    fn cascadia_small_hello_world(hinting: HintingMode) -> Vec<Rgba<u8>> {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            128,
+            36,
+            |b| b.with_font_size_px(12).with_hinting(hinting),
+        );
+
+        terminal
+            .draw(|f| {
+                let block = Block::bordered();
+                let area = block.inner(f.area());
+                f.render_widget(block, f.area());
+                f.render_widget(Paragraph::new("Hello, world!"), area);
+            })
+            .unwrap();
+
+        surface_pixels(terminal.backend())
+    }
+
+    #[test]
+    #[serial]
+    fn hinting_none() {
+        assert_golden(
+            &cascadia_small_hello_world(HintingMode::None),
+            include_bytes!("goldens/hinting_none.png"),
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn hinting_vertical() {
+        assert_golden(
+            &cascadia_small_hello_world(HintingMode::Vertical),
+            include_bytes!("goldens/hinting_vertical.png"),
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn hinting_full() {
+        let pixels = cascadia_small_hello_world(HintingMode::Full);
+        assert_golden(&pixels, include_bytes!("goldens/hinting_full.png"));
+
+        // Full hinting also snaps horizontally, so it shouldn't match vertical
+        // hinting.
+        let vertical = load_from_memory(include_bytes!("goldens/hinting_vertical.png")).unwrap();
+        assert!(vertical.pixels().map(|(_, _, px)| px).ne(pixels));
+    }
+
+    #[test]
+    #[serial]
+    fn instance_descriptor() {
+        let build = |backends: Backends| {
+            futures_lite::future::block_on(
+                Builder::<DefaultPostProcessor>::from_font(
+                    Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                        .expect("Invalid font file"),
+                )
+                .with_instance_descriptor(InstanceDescriptor {
+                    backends,
+                    flags: InstanceFlags::empty(),
+                    ..Default::default()
+                })
+                .with_width_and_height(Dimensions {
+                    width: NonZeroU32::new(256).unwrap(),
+                    height: NonZeroU32::new(48).unwrap(),
+                })
+                .build_headless(),
+            )
+        };
+
+        let adapter = futures_lite::future::block_on(
+            wgpu::Instance::default().request_adapter(&Default::default()),
+        )
+        .unwrap();
+        let expected = adapter.get_info().backend;
+
+        // The adapter has to come from one of the requested backends.
+        let backend = build(Backends::from(expected)).unwrap();
+        assert_eq!(backend.adapter_info().unwrap().backend, expected);
+
+        assert!(build(Backends::empty()).is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn panel_shadow() {
+        let mut terminal = Terminal::new(
+            futures_lite::future::block_on(
+                Builder::<PanelShadowPostProcessor>::from_font(
+                    Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                        .expect("Invalid font file"),
+                )
+                .with_width_and_height(Dimensions {
+                    width: NonZeroU32::new(256).unwrap(),
+                    height: NonZeroU32::new(144).unwrap(),
+                })
+                .build_headless(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let panel = Rect::new(2, 1, 14, 4);
+        let backend = terminal.backend_mut();
+        let cell_size = backend.cell_size();
+        backend.post_processor_mut().set_panels(cell_size, &[panel]);
+
+        // Only the panel casts a shadow. The text outside of it shouldn't.
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Block::new().style(Style::new().bg(Color::Rgb(96, 104, 120))),
+                    f.area(),
+                );
+                f.render_widget(
+                    Paragraph::new("Hello")
+                        .block(Block::bordered())
+                        .style(Style::new().fg(Color::Black).bg(Color::Rgb(220, 220, 210))),
+                    panel,
+                );
+                f.render_widget(
+                    Paragraph::new("World").style(Style::new().fg(Color::White)),
+                    Rect::new(2, 5, 5, 1),
+                );
+            })
+            .unwrap();
+
+        assert_golden(
+            &surface_pixels(terminal.backend()),
+            include_bytes!("goldens/panel_shadow.png"),
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn crt() {
+        let mut terminal = Terminal::new(
+            futures_lite::future::block_on(
+                Builder::<CrtPostProcessor>::from_font_and_user_data(
+                    Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                        .expect("Invalid font file"),
+                    CrtConfig {
+                        curvature: 0.1,
+                        scanline_intensity: 0.5,
+                        vignette: 0.4,
+                    },
+                )
+                .with_width_and_height(Dimensions {
+                    width: NonZeroU32::new(256).unwrap(),
+                    height: NonZeroU32::new(144).unwrap(),
+                })
+                .build_headless(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Block::new().style(Style::new().bg(Color::Rgb(40, 80, 140))),
+                    f.area(),
+                );
+                f.render_widget(
+                    Paragraph::new("Hello, CRT")
+                        .block(Block::bordered())
+                        .style(Style::new().fg(Color::Rgb(120, 255, 120))),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        assert_golden(
+            &surface_pixels(terminal.backend()),
+            include_bytes!("goldens/crt.png"),
+        );
+
+        let backend = terminal.backend_mut();
+        assert!(!backend.post_processor().needs_update());
+
+        backend
+            .post_processor_mut()
+            .set_config(CrtConfig::default());
+        assert!(backend.post_processor().needs_update());
+
+        backend.flush().unwrap();
+        assert!(!backend.post_processor().needs_update());
+    }
+
+    #[test]
+    #[serial]
+    fn post_processor_srgb() {
+        fn render<P: PostProcessor>(user_data: P::UserData, format: TextureFormat) -> Vec<u8> {
+            let mut terminal = Terminal::new(
+                futures_lite::future::block_on(
+                    Builder::<P>::from_font_and_user_data(
+                        Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                            .expect("Invalid font file"),
+                        user_data,
+                    )
+                    .with_width_and_height(Dimensions {
+                        width: NonZeroU32::new(128).unwrap(),
+                        height: NonZeroU32::new(48).unwrap(),
+                    })
+                    .build_headless_with_format(format),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+
+            terminal
+                .draw(|f| {
+                    f.render_widget(
+                        Paragraph::new("CRT").style(
+                            Style::new()
+                                .fg(Color::Rgb(200, 10, 120))
+                                .bg(Color::Rgb(40, 80, 140)),
+                        ),
+                        f.area(),
+                    );
+                })
+                .unwrap();
+
+            terminal.backend().read_rgba()
+        }
 
+        fn assert_matches(linear: Vec<u8>, srgb: Vec<u8>) {
+            assert_eq!(linear.len(), srgb.len());
             assert!(
-                pixels == golden_pixels,
-                "Rendered image differs from golden"
+                linear.iter().zip(&srgb).all(|(l, s)| l.abs_diff(*s) <= 1),
+                "sRGB output differs from linear output"
             );
         }
 
-        surface.buffer.as_ref().unwrap().unmap();
+        // With every effect disabled, the sRGB surface should encode back to
+        // exactly what the linear surface shows.
+        let crt = CrtConfig {
+            curvature: 0.0,
+            scanline_intensity: 0.0,
+            vignette: 0.0,
+        };
+        assert_matches(
+            render::<CrtPostProcessor>(crt, TextureFormat::Rgba8Unorm),
+            render::<CrtPostProcessor>(crt, TextureFormat::Rgba8UnormSrgb),
+        );
+
+        assert_matches(
+            render::<PanelShadowPostProcessor>(Default::default(), TextureFormat::Rgba8Unorm),
+            render::<PanelShadowPostProcessor>(Default::default(), TextureFormat::Rgba8UnormSrgb),
+        );
     }
 
     #[test]
@@ -1900,6 +8924,142 @@ mod tests {
         surface.buffer.as_ref().unwrap().unmap();
     }
 
+    #[test]
+    #[serial]
+    fn prealloc_grid() {
+        let font =
+            Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf")).expect("Invalid font file");
+        let cell_width = Fonts::new(font.clone(), 24).min_width_px();
+
+        let mut terminal = Terminal::new(
+            futures_lite::future::block_on(
+                Builder::<DefaultPostProcessor>::from_font(font)
+                    .with_width_and_height(Dimensions {
+                        width: NonZeroU32::new(cell_width * 10).unwrap(),
+                        height: NonZeroU32::new(24 * 3).unwrap(),
+                    })
+                    .with_prealloc_grid(10, 3)
+                    .build_headless(),
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let capacities = |backend: &WgpuBackend<'_, '_, DefaultPostProcessor, HeadlessSurface>| {
+            (
+                backend.cells.capacity(),
+                backend.rendered.capacity(),
+                backend.sourced.capacity(),
+                backend.dirty_rows.capacity(),
+            )
+        };
+        let initial = capacities(terminal.backend());
+
+        for frame in 0..3 {
+            terminal
+                .draw(|f| {
+                    f.render_widget(Paragraph::new(format!("Frame {frame}")), f.area());
+                })
+                .unwrap();
+        }
+
+        assert_eq!(terminal.backend().cells.len(), 30);
+        assert_eq!(capacities(terminal.backend()), initial);
+    }
+
+    #[test]
+    #[serial]
+    fn char_cell_width() {
+        let backend = futures_lite::future::block_on(
+            Builder::<DefaultPostProcessor>::from_font(
+                Font::new(include_bytes!("fonts/CascadiaMono-Regular.ttf"))
+                    .expect("Invalid font file"),
+            )
+            .with_width_and_height(Dimensions {
+                width: NonZeroU32::new(256).unwrap(),
+                height: NonZeroU32::new(48).unwrap(),
+            })
+            .build_headless(),
+        )
+        .unwrap();
+
+        assert_eq!(backend.char_cell_width('a'), 1);
+        assert_eq!(backend.char_cell_width('Ｈ'), 2);
+        assert_eq!(backend.char_cell_width('😀'), 2);
+        assert_eq!(backend.char_cell_width('\u{301}'), 1);
+        assert_eq!(backend.char_cell_width('\u{7}'), 1);
+    }
+
+    #[test]
+    #[serial]
+    fn svg_export() {
+        let mut terminal = headless_terminal(
+            include_bytes!("fonts/CascadiaMono-Regular.ttf"),
+            256,
+            72,
+            |b| b,
+        );
+
+        terminal
+            .draw(|f| {
+                f.render_widget(
+                    Paragraph::new(vec![
+                        Line::from("A<B".bold().red().on_blue()),
+                        Line::from("\u{627}\u{628}"),
+                    ]),
+                    f.area(),
+                );
+            })
+            .unwrap();
+
+        // Drawing every cell of a buffer also draws the cells hidden behind its wide
+        // characters.
+        let size = terminal.size().unwrap();
+        let mut row = Buffer::empty(Rect::new(0, 2, size.width, 1));
+        row.set_string(0, 2, "\u{4e16}\u{754c}!", Style::default());
+        terminal
+            .backend_mut()
+            .draw(
+                row.content
+                    .iter()
+                    .enumerate()
+                    .map(|(x, cell)| (x as u16, 2, cell)),
+            )
+            .unwrap();
+
+        let svg = terminal.backend().to_svg();
+
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.ends_with("</svg>\n"));
+        // The cells covered by the two wide characters don't get their own rects.
+        assert_eq!(
+            svg.matches("<rect ").count(),
+            size.width as usize * size.height as usize - 2
+        );
+        assert!(svg.contains("<rect x=\"0\" y=\"0\" width=\"12\" height=\"24\" fill=\"#0000ff\"/>"));
+        assert!(
+            svg.contains("fill=\"#ff0000\" font-weight=\"bold\" xml:space=\"preserve\">A</text>")
+        );
+        assert!(svg.contains(">&lt;</text>"));
+
+        // The right-to-left run should be laid out in visual order, so the first
+        // logical character ends up to the right of the second.
+        let alef = svg.find(">\u{627}</text>").unwrap();
+        let beh = svg.find(">\u{628}</text>").unwrap();
+        assert!(beh < alef);
+
+        // Wide characters span two columns, and the rest of the row stays on the grid.
+        assert!(svg.contains("<rect x=\"0\" y=\"48\" width=\"24\" height=\"24\""));
+        assert!(svg.contains("<rect x=\"24\" y=\"48\" width=\"24\" height=\"24\""));
+        assert!(svg.contains("<rect x=\"48\" y=\"48\" width=\"12\" height=\"24\""));
+        assert!(svg.contains("<text x=\"48\" y=\""));
+        let last_column = (size.width as u32 - 1) * 12;
+        assert!(svg.contains(&format!(
+            "<rect x=\"{last_column}\" y=\"48\" width=\"12\" height=\"24\""
+        )));
+        assert!(!svg.contains(&format!("<rect x=\"{}\"", size.width as u32 * 12)));
+    }
+
     #[test]
     #[serial]
     fn rgb_conversion() {
@@ -2011,7 +9171,8 @@ mod tests {
                 ImageBuffer::<Rgba<u8>, _>::from_raw(surface.width, surface.height, data).unwrap();
 
             let pixels = image.pixels().copied().collect::<Vec<_>>();
-            let golden = load_from_memory(include_bytes!("goldens/srgb_conversion.png")).unwrap();
+            // sRGB surfaces should look identical to linear ones.
+            let golden = load_from_memory(include_bytes!("goldens/rgb_conversion.png")).unwrap();
             let golden_pixels = golden.pixels().map(|(_, _, px)| px).collect::<Vec<_>>();
 
             assert!(
@@ -2042,6 +9203,8 @@ mod tests {
             &mut image,
             raster,
             Entry::Cached(CacheRect {
+                page: 0,
+                epoch: 0,
                 x: 0,
                 y: 0,
                 width: golden.width(),
@@ -2085,6 +9248,8 @@ mod tests {
             &mut image,
             raster,
             Entry::Cached(CacheRect {
+                page: 0,
+                epoch: 0,
                 x: 0,
                 y: 0,
                 width: 1,
@@ -2120,6 +9285,8 @@ mod tests {
             &mut image,
             raster,
             Entry::Cached(CacheRect {
+                page: 0,
+                epoch: 0,
                 x: 0,
                 y: 0,
                 width: 4,
@@ -2168,6 +9335,8 @@ mod tests {
             &mut image,
             raster,
             Entry::Cached(CacheRect {
+                page: 0,
+                epoch: 0,
                 x: 0,
                 y: 0,
                 width: 8,
@@ -2226,6 +9395,8 @@ mod tests {
             &mut image,
             raster,
             Entry::Cached(CacheRect {
+                page: 0,
+                epoch: 0,
                 x: 0,
                 y: 0,
                 width: 6,
@@ -2282,6 +9453,8 @@ mod tests {
             &mut image,
             raster,
             Entry::Cached(CacheRect {
+                page: 0,
+                epoch: 0,
                 x: 0,
                 y: 0,
                 width: 6,
@@ -2350,6 +9523,8 @@ mod tests {
             &mut image,
             raster,
             Entry::Cached(CacheRect {
+                page: 0,
+                epoch: 0,
                 x: 0,
                 y: 0,
                 width: 1,
@@ -2388,6 +9563,8 @@ mod tests {
             &mut image,
             raster,
             Entry::Cached(CacheRect {
+                page: 0,
+                epoch: 0,
                 x: 0,
                 y: 0,
                 width: 2,
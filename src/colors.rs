@@ -1,5 +1,143 @@
 pub(crate) type Rgb = [u8; 3];
 
+/// A color with an explicit alpha channel. Used with
+/// [`WgpuBackend::print_rgba`](crate::WgpuBackend::print_rgba) to bypass
+/// [`ratatui::style::Color`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rgba {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub a: u8,
+}
+
+impl Rgba {
+    /// Create a new color from its red, green, blue, and alpha components.
+    pub const fn new(r: u8, g: u8, b: u8, a: u8) -> Self {
+        Self { r, g, b, a }
+    }
+}
+
+/// The colors used for ratatui's named and indexed colors. Use this with
+/// [`Builder::with_palette`](crate::Builder::with_palette) to theme an
+/// application (e.g. Solarized) without remapping colors at the ratatui layer.
+///
+/// The default palette uses the [SVG color keywords] for named colors and the
+/// standard xterm table for indexed colors. Note that indexed colors 0-15 are
+/// looked up in [`Palette::indexed`], not [`Palette::named`].
+///
+/// [SVG color keywords]: https://www.w3.org/TR/SVG11/types.html#ColorKeywords
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    /// The colors for the 16 named colors, in the order of their ANSI color
+    /// codes: [`Black`], [`Red`], [`Green`], [`Yellow`], [`Blue`],
+    /// [`Magenta`], [`Cyan`], [`Gray`], [`DarkGray`], [`LightRed`],
+    /// [`LightGreen`], [`LightYellow`], [`LightBlue`], [`LightMagenta`],
+    /// [`LightCyan`], [`White`].
+    ///
+    /// [`Black`]: ratatui::style::Color::Black
+    /// [`Red`]: ratatui::style::Color::Red
+    /// [`Green`]: ratatui::style::Color::Green
+    /// [`Yellow`]: ratatui::style::Color::Yellow
+    /// [`Blue`]: ratatui::style::Color::Blue
+    /// [`Magenta`]: ratatui::style::Color::Magenta
+    /// [`Cyan`]: ratatui::style::Color::Cyan
+    /// [`Gray`]: ratatui::style::Color::Gray
+    /// [`DarkGray`]: ratatui::style::Color::DarkGray
+    /// [`LightRed`]: ratatui::style::Color::LightRed
+    /// [`LightGreen`]: ratatui::style::Color::LightGreen
+    /// [`LightYellow`]: ratatui::style::Color::LightYellow
+    /// [`LightBlue`]: ratatui::style::Color::LightBlue
+    /// [`LightMagenta`]: ratatui::style::Color::LightMagenta
+    /// [`LightCyan`]: ratatui::style::Color::LightCyan
+    /// [`White`]: ratatui::style::Color::White
+    pub named: [[u8; 3]; 16],
+    /// The colors for [`Color::Indexed`](ratatui::style::Color::Indexed).
+    pub indexed: [[u8; 3]; 256],
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            named: [
+                named::BLACK,
+                named::RED,
+                named::GREEN,
+                named::YELLOW,
+                named::BLUE,
+                named::MAGENTA,
+                named::CYAN,
+                named::GRAY,
+                named::DARKGRAY,
+                named::LIGHTRED,
+                named::LIGHTGREEN,
+                named::LIGHTYELLOW,
+                named::LIGHTBLUE,
+                named::LIGHTMAGENTA,
+                named::LIGHTCYAN,
+                named::WHITE,
+            ],
+            indexed: ANSI_TO_RGB,
+        }
+    }
+}
+
+/// The relative luminance of an sRGB color, as defined by WCAG 2.
+/// <https://www.w3.org/TR/WCAG21/#dfn-relative-luminance>
+pub(crate) fn relative_luminance([r, g, b]: Rgb) -> f32 {
+    let linear = |c: u8| {
+        let c = c as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+
+    0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+}
+
+/// The WCAG 2 contrast ratio between two colors, from 1.0 (identical
+/// luminance) to 21.0 (black on white).
+/// <https://www.w3.org/TR/WCAG21/#dfn-contrast-ratio>
+pub(crate) fn contrast_ratio(a: Rgb, b: Rgb) -> f32 {
+    let a = relative_luminance(a);
+    let b = relative_luminance(b);
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// Adjust `fg` so that it has at least `min_ratio` contrast against `bg`. The
+/// foreground is mixed towards black or white, whichever contrasts more with
+/// the background, by the smallest amount which meets the ratio. If neither
+/// can meet it, the more contrasting of the two is returned.
+pub(crate) fn ensure_contrast(fg: Rgb, bg: Rgb, min_ratio: f32) -> Rgb {
+    if contrast_ratio(fg, bg) >= min_ratio {
+        return fg;
+    }
+
+    let target = if contrast_ratio(named::WHITE, bg) >= contrast_ratio(named::BLACK, bg) {
+        named::WHITE
+    } else {
+        named::BLACK
+    };
+
+    let mix = |t: f32| {
+        [0, 1, 2].map(|c| (fg[c] as f32 + (target[c] as f32 - fg[c] as f32) * t).round() as u8)
+    };
+
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..16 {
+        let t = (low + high) / 2.0;
+        if contrast_ratio(mix(t), bg) >= min_ratio {
+            high = t;
+        } else {
+            low = t;
+        }
+    }
+
+    mix(high)
+}
+
 /// <https://www.w3.org/TR/SVG11/types.html#ColorKeywords>
 pub(crate) mod named {
     use crate::colors::Rgb;
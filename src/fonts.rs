@@ -1,14 +1,15 @@
-use std::hash::{
-    BuildHasher,
-    Hasher,
-    RandomState,
-};
+use std::hash::Hasher;
 
 use ratatui::{
     buffer::Cell,
     style::Modifier,
 };
-use rustybuzz::Face;
+use rustybuzz::{
+    ttf_parser::name_id,
+    Face,
+};
+
+use crate::utils::glyph_cache::Fnv1a;
 
 /// A Font which can be used for rendering.
 #[derive(Clone)]
@@ -22,7 +23,8 @@ impl<'a> Font<'a> {
     /// Create a new Font from data. Returns [`None`] if the font cannot
     /// be parsed.
     pub fn new(data: &'a [u8]) -> Option<Self> {
-        let mut hasher = RandomState::new().build_hasher();
+        // The id is stable between runs so that glyphs can be cached on disk.
+        let mut hasher = Fnv1a::default();
         hasher.write(data);
 
         Face::from_slice(data, 0).map(|font| {
@@ -43,16 +45,83 @@ impl Font<'_> {
         self.id
     }
 
-    pub(crate) fn font(&self) -> &Face {
+    pub(crate) fn font(&self) -> &Face<'_> {
         &self.font
     }
 
+    pub(crate) fn family_name(&self) -> Option<String> {
+        self.font
+            .names()
+            .into_iter()
+            .filter(|name| name.name_id == name_id::FAMILY)
+            .find_map(|name| name.to_string())
+    }
+
     pub(crate) fn char_width(&self, height_px: u32) -> u32 {
         let scale = height_px as f32 / self.font.height() as f32;
         (self.advance * scale) as u32
     }
 }
 
+/// Read-only information about a font in a [`Fonts`] collection. See
+/// [`Fonts::iter`].
+#[derive(Clone, Copy)]
+pub struct FontInfo<'f, 'a> {
+    font: &'f Font<'a>,
+    height_px: u32,
+    last_resort: bool,
+}
+
+impl FontInfo<'_, '_> {
+    /// The family name of the font, if it has one.
+    pub fn family_name(&self) -> Option<String> {
+        self.font.family_name()
+    }
+
+    /// Whether the font is marked as bold.
+    pub fn is_bold(&self) -> bool {
+        self.font.font().is_bold()
+    }
+
+    /// Whether the font is marked as italic.
+    pub fn is_italic(&self) -> bool {
+        self.font.font().is_italic()
+    }
+
+    /// Whether the font is marked as monospaced.
+    pub fn is_monospaced(&self) -> bool {
+        self.font.font().is_monospaced()
+    }
+
+    /// Whether this is the last-resort font the collection was created with,
+    /// rather than a fallback added later.
+    pub fn is_last_resort(&self) -> bool {
+        self.last_resort
+    }
+
+    /// The number of font units per em.
+    pub fn units_per_em(&self) -> i32 {
+        self.font.font().units_per_em()
+    }
+
+    /// The ascender in font units.
+    pub fn ascender(&self) -> i16 {
+        self.font.font().ascender()
+    }
+
+    /// The descender in font units. This is typically negative.
+    pub fn descender(&self) -> i16 {
+        self.font.font().descender()
+    }
+
+    /// The width in pixels of a single cell in this font at the collection's
+    /// current height. The narrowest of these across all fonts determines the
+    /// width of a cell in the grid.
+    pub fn cell_width_px(&self) -> u32 {
+        self.font.char_width(self.height_px)
+    }
+}
+
 /// A collection of fonts to use for rendering. Supports font fallback.
 ///
 /// It is recommended, but not required, that all fonts have the same/very
@@ -61,6 +130,8 @@ impl Font<'_> {
 pub struct Fonts<'a> {
     char_width: u32,
     char_height: u32,
+    line_height: Option<u32>,
+    width_padding: u32,
 
     last_resort: Font<'a>,
 
@@ -82,6 +153,8 @@ impl<'a> Fonts<'a> {
         Self {
             char_width: font.char_width(size_px),
             char_height: size_px,
+            line_height: None,
+            width_padding: 0,
             last_resort: font,
             regular: vec![],
             bold: vec![],
@@ -113,6 +186,41 @@ impl<'a> Fonts<'a> {
             .unwrap_or_default()
     }
 
+    /// Iterate over every font in this collection. The last-resort font comes
+    /// first, followed by the regular, bold, italic, and bold italic fonts,
+    /// each in their fallback order.
+    pub fn iter(&self) -> impl Iterator<Item = FontInfo<'_, 'a>> + '_ {
+        std::iter::once((&self.last_resort, true))
+            .chain(
+                self.regular
+                    .iter()
+                    .chain(self.bold.iter())
+                    .chain(self.italic.iter())
+                    .chain(self.bold_italic.iter())
+                    .map(|font| (font, false)),
+            )
+            .map(|(font, last_resort)| FontInfo {
+                font,
+                height_px: self.char_height,
+                last_resort,
+            })
+    }
+
+    /// Find the font which will be used to render the specified character with
+    /// the specified style, following the same fallback order as rendering.
+    /// Only the bold and italic modifiers affect the choice of font. Returns
+    /// `None` if no font in this collection has a glyph for the character.
+    pub fn resolve(&self, ch: char, style: Modifier) -> Option<FontInfo<'_, 'a>> {
+        let (font, _, _) = self.font_for_symbol(ch.encode_utf8(&mut [0; 4]), style);
+        font.font().glyph_index(ch)?;
+
+        Some(FontInfo {
+            font,
+            height_px: self.char_height,
+            last_resort: std::ptr::eq(font, &self.last_resort),
+        })
+    }
+
     /// Change the height of all fonts in this collection to the specified
     /// height in pixels.
     pub fn set_size_px(&mut self, height_px: u32) {
@@ -128,6 +236,15 @@ impl<'a> Fonts<'a> {
             .unwrap_or_default();
     }
 
+    /// Replace the last-resort font, which renders any character none of the
+    /// other fonts can, and whose missing glyph is drawn for characters which
+    /// no font can render. Returns the previous last-resort font.
+    pub fn set_last_resort(&mut self, font: Font<'a>) -> Font<'a> {
+        let previous = std::mem::replace(&mut self.last_resort, font);
+        self.set_size_px(self.char_height);
+        previous
+    }
+
     /// Add a collection of fonts for various styles. They will automatically be
     /// added to the appropriate fallback font list based on the font's
     /// bold/italic properties. Note that this will automatically organize fonts
@@ -222,14 +339,63 @@ impl<'a> Fonts<'a> {
         self.char_width
     }
 
+    /// The width (in pixels) of a cell, which is the minimum width across all
+    /// fonts plus any padding.
+    pub(crate) fn cell_width_px(&self) -> u32 {
+        self.char_width + self.width_padding
+    }
+
+    pub(crate) fn width_padding_px(&self) -> u32 {
+        self.width_padding
+    }
+
+    pub(crate) fn set_width_padding_px(&mut self, padding: u32) {
+        self.width_padding = padding;
+    }
+
+    /// The height (in pixels) of a cell, which is never less than the height
+    /// of the fonts.
+    pub(crate) fn cell_height_px(&self) -> u32 {
+        self.line_height.map_or(self.char_height, |line_height| {
+            line_height.max(self.char_height)
+        })
+    }
+
+    /// The number of pixels of padding above glyphs in cells taller than the
+    /// fonts.
+    pub(crate) fn line_padding_top(&self) -> u32 {
+        (self.cell_height_px() - self.char_height) / 2
+    }
+
+    pub(crate) fn line_height_px(&self) -> Option<u32> {
+        self.line_height
+    }
+
+    pub(crate) fn set_line_height_px(&mut self, line_height: Option<u32>) {
+        self.line_height = line_height;
+    }
+
+    pub(crate) fn last_resort(&self) -> &Font<'a> {
+        &self.last_resort
+    }
+
+    /// The ids of every font in this collection.
+    pub(crate) fn ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.iter().map(|info| info.font.id())
+    }
+
     pub(crate) fn count(&self) -> usize {
         1 + self.bold.len() + self.italic.len() + self.bold_italic.len() + self.regular.len()
     }
 
-    pub(crate) fn font_for_cell(&self, cell: &Cell) -> (&Font, bool, bool) {
-        if cell.modifier.contains(Modifier::BOLD | Modifier::ITALIC) {
+    pub(crate) fn font_for_cell(&self, cell: &Cell) -> (&Font<'_>, bool, bool) {
+        self.font_for_symbol(cell.symbol(), cell.modifier)
+    }
+
+    fn font_for_symbol(&self, symbol: &str, modifier: Modifier) -> (&Font<'a>, bool, bool) {
+        if modifier.contains(Modifier::BOLD | Modifier::ITALIC) {
             self.select_font(
-                cell.symbol(),
+                symbol,
                 self.bold_italic
                     .iter()
                     .map(|f| (f, false, false))
@@ -239,9 +405,9 @@ impl<'a> Fonts<'a> {
                 true,
                 true,
             )
-        } else if cell.modifier.contains(Modifier::BOLD) {
+        } else if modifier.contains(Modifier::BOLD) {
             self.select_font(
-                cell.symbol(),
+                symbol,
                 self.bold
                     .iter()
                     .map(|f| (f, false, false))
@@ -249,9 +415,9 @@ impl<'a> Fonts<'a> {
                 true,
                 false,
             )
-        } else if cell.modifier.contains(Modifier::ITALIC) {
+        } else if modifier.contains(Modifier::ITALIC) {
             self.select_font(
-                cell.symbol(),
+                symbol,
                 self.italic
                     .iter()
                     .map(|f| (f, false, false))
@@ -261,7 +427,7 @@ impl<'a> Fonts<'a> {
             )
         } else {
             self.select_font(
-                cell.symbol(),
+                symbol,
                 self.regular.iter().map(|f| (f, false, false)),
                 false,
                 false,
@@ -323,3 +489,129 @@ impl<'a> Fonts<'a> {
             .unwrap_or(u32::MAX)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use ratatui::style::Modifier;
+
+    use crate::{
+        Font,
+        Fonts,
+    };
+
+    #[test]
+    fn iter() {
+        let mut fonts = Fonts::new(
+            Font::new(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/backend/fonts/CascadiaMono-Regular.ttf"
+            )))
+            .unwrap(),
+            24,
+        );
+        fonts.add_fonts([Font::new(include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/backend/fonts/Fairfax.ttf"
+        )))
+        .unwrap()]);
+
+        let infos = fonts.iter().collect::<Vec<_>>();
+        assert_eq!(infos.len(), 2);
+
+        assert!(infos[0].is_last_resort());
+        assert_eq!(infos[0].family_name().as_deref(), Some("Cascadia Mono"));
+        assert_eq!(infos[0].cell_width_px(), 12);
+        assert!(!infos[0].is_bold());
+        assert!(!infos[0].is_italic());
+        assert!(infos[0].ascender() > 0);
+        assert!(infos[0].descender() < 0);
+
+        assert!(!infos[1].is_last_resort());
+        assert_eq!(infos[1].family_name().as_deref(), Some("Fairfax"));
+        assert_eq!(
+            infos.iter().map(|info| info.cell_width_px()).min(),
+            Some(fonts.min_width_px())
+        );
+    }
+
+    #[test]
+    fn resolve() {
+        let mut fonts = Fonts::new(
+            Font::new(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/backend/fonts/CascadiaMono-Regular.ttf"
+            )))
+            .unwrap(),
+            24,
+        );
+        fonts.add_bold_fonts([Font::new(include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/backend/fonts/Fairfax.ttf"
+        )))
+        .unwrap()]);
+
+        let family = |ch, style| {
+            fonts
+                .resolve(ch, style)
+                .map(|info| (info.family_name().unwrap(), info.is_last_resort()))
+        };
+
+        assert_eq!(
+            family('A', Modifier::empty()),
+            Some(("Cascadia Mono".to_string(), true))
+        );
+        assert_eq!(
+            family('A', Modifier::BOLD),
+            Some(("Fairfax".to_string(), false))
+        );
+        assert_eq!(
+            family('A', Modifier::BOLD | Modifier::UNDERLINED),
+            Some(("Fairfax".to_string(), false))
+        );
+        assert_eq!(
+            family('A', Modifier::ITALIC),
+            Some(("Cascadia Mono".to_string(), true))
+        );
+        assert_eq!(family('\u{0F40}', Modifier::empty()), None);
+    }
+
+    #[test]
+    fn set_last_resort() {
+        let mut fonts = Fonts::new(
+            Font::new(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/backend/fonts/Fairfax.ttf"
+            )))
+            .unwrap(),
+            24,
+        );
+        fonts.add_bold_fonts([Font::new(include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/src/backend/fonts/Fairfax.ttf"
+        )))
+        .unwrap()]);
+
+        let previous = fonts.set_last_resort(
+            Font::new(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/backend/fonts/CascadiaMono-Regular.ttf"
+            )))
+            .unwrap(),
+        );
+        assert_eq!(previous.family_name().as_deref(), Some("Fairfax"));
+
+        let infos = fonts.iter().collect::<Vec<_>>();
+        assert_eq!(infos.len(), 2);
+        assert_eq!(infos[0].family_name().as_deref(), Some("Cascadia Mono"));
+        assert_eq!(
+            infos.iter().map(|info| info.cell_width_px()).min(),
+            Some(fonts.min_width_px())
+        );
+        assert_eq!(
+            fonts
+                .resolve('A', Modifier::empty())
+                .map(|info| info.is_last_resort()),
+            Some(true)
+        );
+    }
+}
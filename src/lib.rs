@@ -12,10 +12,7 @@
 //! Here's a short example using winit on native with the default post processor
 //! implementation:
 //! ```
-//! # use std::{
-//! #     num::NonZeroU32,
-//! #     sync::Arc,
-//! # };
+//! # use std::sync::Arc;
 //! #
 //! # use chrono::Local;
 //! # use ratatui::{
@@ -55,17 +52,12 @@
 //!
 //!         self.backend = Some(
 //!             Terminal::new(
-//!                 futures_lite::future::block_on(
-//!                     Builder::from_font(
-//!                         Font::new(include_bytes!("backend/fonts/CascadiaMono-Regular.ttf"))
-//!                             .unwrap(),
-//!                     )
-//!                     .with_width_and_height(Dimensions {
-//!                         width: NonZeroU32::new(size.width).unwrap(),
-//!                         height: NonZeroU32::new(size.height).unwrap(),
-//!                     })
-//!                     .build_with_target(self.window.as_ref().unwrap().clone()),
+//!                 Builder::from_font(
+//!                     Font::new(include_bytes!("backend/fonts/CascadiaMono-Regular.ttf"))
+//!                         .unwrap(),
 //!                 )
+//!                 .with_width_and_height(Dimensions::from_physical(size.width, size.height))
+//!                 .build_with_target_blocking(self.window.as_ref().unwrap().clone())
 //!                 .unwrap(),
 //!             )
 //!             .unwrap(),
@@ -107,18 +99,52 @@
 //! }
 //! ```
 //!
+//! # Image Escapes
+//! With the `kitty-graphics` or `sixel` features enabled, cells drawn with a
+//! symbol holding an image escape display the image using
+//! [`WgpuBackend::draw_image`] instead of drawing the escape as text. The image
+//! is placed at the cell holding the escape, and removed once that cell is
+//! drawn over. Images are always drawn beneath text.
+//!
+//! ## Kitty Graphics
+//! The `kitty-graphics` feature reads [kitty graphics protocol] escapes. A
+//! cell may hold several escapes, and a transmission split into chunks with
+//! `m=1` may be spread across several cells or draws.
+//!
+//! Supported:
+//! - `a=T` (transmit and display) with `t=d` (direct transmission) and `f=32`
+//!   (rgba) or `f=24` (rgb) data. `s` and `v` give the size of the image in
+//!   pixels, and `c` and `r` the number of columns and rows to display it over.
+//!   Without `c` or `r`, the image covers as many cells as its pixels fill.
+//! - `a=d` with `d=a` or `d=A` (delete all images).
+//!
+//! Everything else is ignored, including transmitting or placing images
+//! separately (`a=t`, `a=p`), queries (`a=q`), png data (`f=100`), compressed
+//! data (`o=z`), transmission through files or shared memory, image and
+//! placement ids, source rectangles, pixel offsets, and any delete target other
+//! than all images. The protocol's responses are never sent.
+//!
+//! [kitty graphics protocol]: https://sw.kovidgoyal.net/kitty/graphics-protocol/
+//!
+//! ## Sixel
+//! The `sixel` feature reads sixel images, with each cell holding a complete
+//! `DCS q` escape. The image is the size given by its raster attributes, or the
+//! size of the pixels it sets if that's larger, and covers as many cells as it
+//! fills without being stretched. Color registers may be defined in rgb or hls,
+//! and start out with the VT340's palette.
+//!
+//! Pixels which aren't set are transparent, whatever background mode the
+//! escape selects, and the pixel aspect ratio is always 1:1.
+//!
 //! # Limitations
-//! 1. No cursor rendering.
-//!     - The location of the cursor is tracked, and operations using it should
-//!       behave as expected, but the cursor is not rendered to the screen.
-//! 2. Attempting to render more unique (utf8 character * BOLD|ITALIC)
-//!    characters than can fit in the cache in a single draw call will cause
-//!    incorrect rendering. This is ~3750 characters at the default font size
-//!    with most fonts. If you need more than this, file a bug and I'll do the
-//!    work to make rendering handle an unbounded number of unique characters.
-//!    To put that in perspective, rendering every printable ascii character in
-//!    every combination of styles would take (95 * 4) 380 cache entries or ~10%
-//!    of the cache.
+//! 1. The glyph cache holds ~3750 unique (utf8 character * BOLD|ITALIC)
+//!    characters per page at the default font size with most fonts, and grows
+//!    up to [`Builder::with_max_atlas_pages`] pages. If a single frame needs
+//!    more unique characters than that, the frame is drawn in several batches
+//!    with the cache refilled between them. This renders correctly, but is
+//!    slower than a single draw. To put that in perspective, rendering every
+//!    printable ascii character in every combination of styles would take (95 *
+//!    4) 380 cache entries or ~10% of a single page.
 
 pub(crate) mod backend;
 pub(crate) mod colors;
@@ -150,6 +176,29 @@ pub enum Error {
     /// couldn't be loaded.
     #[error("Failed to get default Surface configuration from wgpu.")]
     SurfaceConfigurationRequestFailed,
+    /// Reading pixels back from the gpu failed because the staging buffer
+    /// couldn't be mapped.
+    #[error("{0}")]
+    BufferMapFailed(wgpu::BufferAsyncError),
+    /// Backend creation failed because the requested text atlas is larger
+    /// than the device supports.
+    #[error("Text atlas size {width}x{height} exceeds the device limit of {max}.")]
+    AtlasTooLarge {
+        /// The requested atlas width.
+        width: u32,
+        /// The requested atlas height.
+        height: u32,
+        /// The device's `max_texture_dimension_2d`.
+        max: u32,
+    },
+    /// Backend creation failed because some of the characters required by
+    /// [`Builder::with_required_coverage`](crate::Builder::with_required_coverage)
+    /// aren't covered by any of the fonts, including fallbacks.
+    #[error("No font can render the required characters {missing:?}.")]
+    InsufficientFontCoverage {
+        /// The required characters which no font can render.
+        missing: Vec<char>,
+    },
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
@@ -161,14 +210,39 @@ type RandomState = std::hash::RandomState;
 
 pub use backend::{
     builder::Builder,
+    pane::{
+        Pane,
+        PaneId,
+    },
     wgpu_backend::WgpuBackend,
+    BlendMode,
+    CellOverride,
+    CellShader,
+    CursorStyle,
+    DimMode,
     Dimensions,
+    EmojiMode,
+    FrameStats,
+    GradientStop,
+    HeadlessSurface,
+    HintingMode,
+    MetricLines,
     PostProcessor,
     RenderSurface,
     RenderTexture,
+    ResizePolicy,
+    Rounding,
+    SampleMode,
+    TextureTarget,
+    UnderlineStyle,
     Viewport,
 };
+pub use colors::{
+    Palette,
+    Rgba,
+};
 pub use fonts::{
     Font,
+    FontInfo,
     Fonts,
 };
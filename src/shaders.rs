@@ -5,7 +5,6 @@ use std::{
 
 use wgpu::{
     self,
-    include_wgsl,
     AddressMode,
     BindGroupDescriptor,
     BindGroupEntry,
@@ -40,6 +39,8 @@ use wgpu::{
     Sampler,
     SamplerBindingType,
     SamplerDescriptor,
+    ShaderModuleDescriptor,
+    ShaderSource,
     ShaderStages,
     StoreOp,
     TextureSampleType,
@@ -49,16 +50,32 @@ use wgpu::{
 
 use crate::backend::PostProcessor;
 
+mod chain;
+mod crt;
+mod panel_shadow;
+
+pub use chain::Chain;
+pub use crt::{
+    CrtConfig,
+    CrtPostProcessor,
+};
+pub use panel_shadow::{
+    PanelShadowConfig,
+    PanelShadowPostProcessor,
+    MAX_PANELS,
+};
+
 #[repr(C)]
 #[derive(bytemuck::Pod, bytemuck::Zeroable, Debug, Clone, Copy)]
 struct Uniforms {
     screen_size: [f32; 2],
-    use_srgb: u32,
-    _pad0: [u32; 5],
+    _pad0: [u32; 6],
 }
 
 /// The default post-processor. Used when you don't want to perform any custom
 /// shading on the output. This just blits the composited text to the surface.
+/// Colors are converted for sRGB surfaces, so the output looks the same
+/// regardless of whether the surface format is sRGB.
 pub struct DefaultPostProcessor {
     uniforms: Buffer,
     bindings: BindGroupLayout,
@@ -126,7 +143,16 @@ impl PostProcessor for DefaultPostProcessor {
             ],
         });
 
-        let shader = device.create_shader_module(include_wgsl!("shaders/blit.wgsl"));
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("blit.wgsl"),
+            source: ShaderSource::Wgsl(
+                concat!(
+                    include_str!("shaders/srgb.wgsl"),
+                    include_str!("shaders/blit.wgsl")
+                )
+                .into(),
+            ),
+        });
 
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Text Blit Layout"),
@@ -151,7 +177,11 @@ impl PostProcessor for DefaultPostProcessor {
             multisample: MultisampleState::default(),
             fragment: Some(FragmentState {
                 module: &shader,
-                entry_point: Some("fs_main"),
+                entry_point: Some(if surface_config.format.is_srgb() {
+                    "fs_main_srgb"
+                } else {
+                    "fs_main"
+                }),
                 compilation_options: PipelineCompilationOptions::default(),
                 targets: &[Some(ColorTargetState {
                     format: surface_config.format,
@@ -217,8 +247,7 @@ impl PostProcessor for DefaultPostProcessor {
                 .unwrap();
             uniforms.copy_from_slice(bytemuck::bytes_of(&Uniforms {
                 screen_size: [surface_config.width as f32, surface_config.height as f32],
-                use_srgb: u32::from(surface_config.format.is_srgb()),
-                _pad0: [0; 5],
+                _pad0: [0; 6],
             }));
         }
 
@@ -19,8 +19,8 @@ var Sampler: sampler;
 
 struct Uniforms {
     screen_size: vec2<f32>,
-    use_srgb: u32,
-    _pad1: vec3<u32>,
+    _pad0: vec2<u32>,
+    _pad1: vec4<u32>,
 }
 
 @group(0) @binding(2)
@@ -29,7 +29,12 @@ var<uniform> uniforms: Uniforms;
 @fragment
 fn fs_main(@builtin(position) gl_Position: vec4<f32>) -> FragmentOutput {
     let uv = gl_Position.xy / uniforms.screen_size;
-    let factor = select(2.2, 1.0, uniforms.use_srgb == 0);
+    return FragmentOutput(textureSample(Texture, Sampler, uv));
+}
 
-    return FragmentOutput(pow(textureSample(Texture, Sampler, uv), vec4(vec3(factor), 1.0)));
-}
\ No newline at end of file
+@fragment
+fn fs_main_srgb(@builtin(position) gl_Position: vec4<f32>) -> FragmentOutput {
+    let uv = gl_Position.xy / uniforms.screen_size;
+    let color = textureSample(Texture, Sampler, uv);
+    return FragmentOutput(vec4(srgb_to_linear(color.rgb), color.a));
+}
@@ -0,0 +1,146 @@
+use wgpu::{
+    self,
+    Extent3d,
+    SurfaceConfiguration,
+    TextureDescriptor,
+    TextureDimension,
+    TextureUsages,
+    TextureView,
+    TextureViewDescriptor,
+};
+
+use crate::backend::PostProcessor;
+
+/// A post-processor which runs two other post-processors one after the other.
+/// `A` processes the composited text into an intermediate texture the size of
+/// the surface, and `B` processes that texture onto the surface. Chains can be
+/// nested to combine more than two effects, e.g. `Chain<A, Chain<B, C>>`.
+///
+/// The intermediate texture uses the non-sRGB variant of the surface format,
+/// so `B` receives the output of `A` with the same encoding as the composited
+/// text.
+pub struct Chain<A: PostProcessor, B: PostProcessor> {
+    first: A,
+    second: B,
+
+    intermediate_config: SurfaceConfiguration,
+    intermediate_view: TextureView,
+}
+
+impl<A: PostProcessor, B: PostProcessor> Chain<A, B> {
+    /// Get the post-processor which runs first.
+    pub fn first(&self) -> &A {
+        &self.first
+    }
+
+    /// Get the post-processor which runs first mutably.
+    pub fn first_mut(&mut self) -> &mut A {
+        &mut self.first
+    }
+
+    /// Get the post-processor which runs second.
+    pub fn second(&self) -> &B {
+        &self.second
+    }
+
+    /// Get the post-processor which runs second mutably.
+    pub fn second_mut(&mut self) -> &mut B {
+        &mut self.second
+    }
+}
+
+impl<A: PostProcessor, B: PostProcessor> PostProcessor for Chain<A, B> {
+    type UserData = (A::UserData, B::UserData);
+
+    fn compile(
+        device: &wgpu::Device,
+        text_view: &wgpu::TextureView,
+        surface_config: &wgpu::SurfaceConfiguration,
+        (first, second): Self::UserData,
+    ) -> Self {
+        let intermediate_config = build_intermediate_config(surface_config);
+        let intermediate_view = build_intermediate_target(device, &intermediate_config);
+
+        Self {
+            first: A::compile(device, text_view, &intermediate_config, first),
+            second: B::compile(device, &intermediate_view, surface_config, second),
+            intermediate_config,
+            intermediate_view,
+        }
+    }
+
+    fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        text_view: &wgpu::TextureView,
+        surface_config: &wgpu::SurfaceConfiguration,
+    ) {
+        self.intermediate_config = build_intermediate_config(surface_config);
+        self.intermediate_view = build_intermediate_target(device, &self.intermediate_config);
+
+        self.first
+            .resize(device, text_view, &self.intermediate_config);
+        self.second
+            .resize(device, &self.intermediate_view, surface_config);
+    }
+
+    fn process(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        text_view: &wgpu::TextureView,
+        surface_config: &wgpu::SurfaceConfiguration,
+        surface_view: &wgpu::TextureView,
+    ) {
+        self.first.process(
+            encoder,
+            queue,
+            text_view,
+            &self.intermediate_config,
+            &self.intermediate_view,
+        );
+        self.second.process(
+            encoder,
+            queue,
+            &self.intermediate_view,
+            surface_config,
+            surface_view,
+        );
+    }
+
+    fn needs_update(&self) -> bool {
+        self.first.needs_update() || self.second.needs_update()
+    }
+
+    fn set_pointer(&mut self, position: Option<(f32, f32)>) {
+        self.first.set_pointer(position);
+        self.second.set_pointer(position);
+    }
+}
+
+fn build_intermediate_config(surface_config: &SurfaceConfiguration) -> SurfaceConfiguration {
+    SurfaceConfiguration {
+        format: surface_config.format.remove_srgb_suffix(),
+        view_formats: vec![],
+        ..surface_config.clone()
+    }
+}
+
+fn build_intermediate_target(device: &wgpu::Device, config: &SurfaceConfiguration) -> TextureView {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some("Post Process Chain Intermediate"),
+        size: Extent3d {
+            width: config.width.max(1),
+            height: config.height.max(1),
+            depth_or_array_layers: 1,
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: config.format,
+        usage: TextureUsages::TEXTURE_BINDING | TextureUsages::RENDER_ATTACHMENT,
+        view_formats: &[],
+    });
+
+    texture.create_view(&TextureViewDescriptor::default())
+}
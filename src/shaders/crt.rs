@@ -0,0 +1,304 @@
+use std::{
+    mem::size_of,
+    num::NonZeroU64,
+};
+
+use wgpu::{
+    self,
+    AddressMode,
+    BindGroupLayout,
+    BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry,
+    BindingType,
+    Buffer,
+    BufferBindingType,
+    BufferDescriptor,
+    BufferUsages,
+    Color,
+    ColorTargetState,
+    ColorWrites,
+    FilterMode,
+    FragmentState,
+    LoadOp,
+    MultisampleState,
+    Operations,
+    PipelineCompilationOptions,
+    PipelineLayoutDescriptor,
+    PrimitiveState,
+    PrimitiveTopology,
+    RenderBundle,
+    RenderPassColorAttachment,
+    RenderPassDescriptor,
+    RenderPipeline,
+    RenderPipelineDescriptor,
+    Sampler,
+    SamplerBindingType,
+    SamplerDescriptor,
+    ShaderModuleDescriptor,
+    ShaderSource,
+    ShaderStages,
+    StoreOp,
+    TextureSampleType,
+    TextureViewDimension,
+    VertexState,
+};
+
+use super::build_blitter;
+use crate::backend::PostProcessor;
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Debug, Clone, Copy)]
+struct Uniforms {
+    screen_size: [f32; 2],
+    use_srgb: u32,
+    curvature: f32,
+    scanline_intensity: f32,
+    vignette: f32,
+    _pad0: [u32; 2],
+}
+
+/// Configuration for a [`CrtPostProcessor`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CrtConfig {
+    /// How strongly the screen bulges outwards, from 0.0 (flat) upwards.
+    /// Content pushed past the edges of the surface is clipped. Defaults to
+    /// 0.08.
+    pub curvature: f32,
+    /// How much every other row of pixels is darkened, from 0.0 (no
+    /// scanlines) to 1.0 (fully black). Defaults to 0.25.
+    pub scanline_intensity: f32,
+    /// How much the corners of the screen are darkened, from 0.0 (no
+    /// vignette) to 1.0. Defaults to 0.3.
+    pub vignette: f32,
+}
+
+impl Default for CrtConfig {
+    fn default() -> Self {
+        Self {
+            curvature: 0.08,
+            scanline_intensity: 0.25,
+            vignette: 0.3,
+        }
+    }
+}
+
+/// A post-processor which imitates an old CRT monitor. The composited text is
+/// warped with barrel distortion, darkened towards the corners, and overlaid
+/// with scanlines.
+///
+/// Changing the configuration with [`CrtPostProcessor::set_config`] causes the
+/// next flush to re-render the screen even if no text changed, so the
+/// parameters can be animated by updating them every frame.
+pub struct CrtPostProcessor {
+    config: CrtConfig,
+    config_changed: bool,
+
+    uniforms: Buffer,
+    bindings: BindGroupLayout,
+    sampler: Sampler,
+    pipeline: RenderPipeline,
+
+    blitter: RenderBundle,
+}
+
+impl CrtPostProcessor {
+    /// Get the current CRT configuration.
+    pub fn config(&self) -> CrtConfig {
+        self.config
+    }
+
+    /// Change the CRT configuration. This takes effect on the next flush.
+    pub fn set_config(&mut self, config: CrtConfig) {
+        if config != self.config {
+            self.config = config;
+            self.config_changed = true;
+        }
+    }
+}
+
+impl PostProcessor for CrtPostProcessor {
+    type UserData = CrtConfig;
+
+    fn compile(
+        device: &wgpu::Device,
+        text_view: &wgpu::TextureView,
+        surface_config: &wgpu::SurfaceConfiguration,
+        user_data: Self::UserData,
+    ) -> Self {
+        let uniforms = device.create_buffer(&BufferDescriptor {
+            label: Some("CRT Uniforms"),
+            size: size_of::<Uniforms>() as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("CRT Bindings Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: NonZeroU64::new(size_of::<Uniforms>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("crt.wgsl"),
+            source: ShaderSource::Wgsl(
+                concat!(include_str!("srgb.wgsl"), include_str!("crt.wgsl")).into(),
+            ),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("CRT Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("CRT Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format: surface_config.format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+            cache: None,
+        });
+
+        let blitter = build_blitter(
+            device,
+            &layout,
+            text_view,
+            &sampler,
+            &uniforms,
+            surface_config,
+            &pipeline,
+        );
+
+        Self {
+            config: user_data,
+            config_changed: false,
+            uniforms,
+            bindings: layout,
+            sampler,
+            pipeline,
+            blitter,
+        }
+    }
+
+    fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        text_view: &wgpu::TextureView,
+        surface_config: &wgpu::SurfaceConfiguration,
+    ) {
+        self.blitter = build_blitter(
+            device,
+            &self.bindings,
+            text_view,
+            &self.sampler,
+            &self.uniforms,
+            surface_config,
+            &self.pipeline,
+        );
+    }
+
+    fn process(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        _text_view: &wgpu::TextureView,
+        surface_config: &wgpu::SurfaceConfiguration,
+        surface_view: &wgpu::TextureView,
+    ) {
+        {
+            let mut uniforms = queue
+                .write_buffer_with(
+                    &self.uniforms,
+                    0,
+                    NonZeroU64::new(size_of::<Uniforms>() as u64).unwrap(),
+                )
+                .unwrap();
+            uniforms.copy_from_slice(bytemuck::bytes_of(&Uniforms {
+                screen_size: [surface_config.width as f32, surface_config.height as f32],
+                use_srgb: u32::from(surface_config.format.is_srgb()),
+                curvature: self.config.curvature.max(0.0),
+                scanline_intensity: self.config.scanline_intensity.clamp(0.0, 1.0),
+                vignette: self.config.vignette.clamp(0.0, 1.0),
+                _pad0: [0; 2],
+            }));
+        }
+
+        self.config_changed = false;
+
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("CRT Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: surface_view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::TRANSPARENT),
+                    store: StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+
+        pass.execute_bundles(Some(&self.blitter));
+    }
+
+    fn needs_update(&self) -> bool {
+        self.config_changed
+    }
+}
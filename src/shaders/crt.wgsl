@@ -0,0 +1,59 @@
+struct VertexOutput {
+    @builtin(position) gl_Position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) Index: u32) -> VertexOutput {
+    let vertex = vec2(f32((Index << 1) & 2), f32(Index & 2));
+    return VertexOutput(vec4(vertex * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0));
+}
+
+struct FragmentOutput {
+    @location(0) FragColor: vec4<f32>,
+}
+
+@group(0) @binding(0) 
+var Texture: texture_2d<f32>;
+@group(0) @binding(1) 
+var Sampler: sampler;
+
+struct Uniforms {
+    screen_size: vec2<f32>,
+    use_srgb: u32,
+    curvature: f32,
+    scanline_intensity: f32,
+    vignette: f32,
+    _pad0: u32,
+    _pad1: u32,
+}
+
+@group(0) @binding(2)
+var<uniform> uniforms: Uniforms;
+
+const PI: f32 = 3.14159265;
+
+@fragment
+fn fs_main(@builtin(position) gl_Position: vec4<f32>) -> FragmentOutput {
+    // Work in [-1, 1] so the distortion is symmetric around the center of the
+    // screen. Points further from the center are pushed further out.
+    let centered = gl_Position.xy / uniforms.screen_size * 2.0 - 1.0;
+    let warped = centered * (1.0 + uniforms.curvature * dot(centered, centered));
+    let uv = warped * 0.5 + 0.5;
+
+    if any(uv < vec2(0.0)) || any(uv > vec2(1.0)) {
+        return FragmentOutput(vec4(0.0, 0.0, 0.0, 1.0));
+    }
+
+    let color = textureSampleLevel(Texture, Sampler, uv, 0.0);
+
+    // Pixel centers sit at half-integer rows, so this alternates between 1 and
+    // 0 on every other row of the source image.
+    let row = uv.y * uniforms.screen_size.y;
+    let scanline = 1.0 - uniforms.scanline_intensity * (0.5 - 0.5 * sin(row * PI));
+
+    let vignette = 1.0 - uniforms.vignette * clamp(dot(warped, warped) * 0.5, 0.0, 1.0);
+
+    let shaded = color.rgb * scanline * vignette;
+
+    return FragmentOutput(vec4(select(shaded, srgb_to_linear(shaded), uniforms.use_srgb != 0), color.a));
+}
@@ -0,0 +1,326 @@
+use std::{
+    mem::size_of,
+    num::NonZeroU64,
+};
+
+use ratatui::layout::{
+    Rect,
+    Size,
+};
+use wgpu::{
+    self,
+    AddressMode,
+    BindGroupLayout,
+    BindGroupLayoutDescriptor,
+    BindGroupLayoutEntry,
+    BindingType,
+    Buffer,
+    BufferBindingType,
+    BufferDescriptor,
+    BufferUsages,
+    Color,
+    ColorTargetState,
+    ColorWrites,
+    FilterMode,
+    FragmentState,
+    LoadOp,
+    MultisampleState,
+    Operations,
+    PipelineCompilationOptions,
+    PipelineLayoutDescriptor,
+    PrimitiveState,
+    PrimitiveTopology,
+    RenderBundle,
+    RenderPassColorAttachment,
+    RenderPassDescriptor,
+    RenderPipeline,
+    RenderPipelineDescriptor,
+    Sampler,
+    SamplerBindingType,
+    SamplerDescriptor,
+    ShaderModuleDescriptor,
+    ShaderSource,
+    ShaderStages,
+    StoreOp,
+    TextureSampleType,
+    TextureViewDimension,
+    VertexState,
+};
+
+use super::build_blitter;
+use crate::backend::PostProcessor;
+
+#[repr(C)]
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Debug, Clone, Copy)]
+struct Uniforms {
+    screen_size: [f32; 2],
+    use_srgb: u32,
+    radius: u32,
+    intensity: f32,
+    panel_count: u32,
+    _pad0: [u32; 2],
+    panels: [[f32; 4]; MAX_PANELS],
+}
+
+/// The most panels a [`PanelShadowPostProcessor`] casts shadows from. See
+/// [`PanelShadowPostProcessor::set_panels`].
+pub const MAX_PANELS: usize = 16;
+
+/// Configuration for a [`PanelShadowPostProcessor`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PanelShadowConfig {
+    /// How far, in pixels, a shadow extends from the edge casting it. Defaults
+    /// to 6.
+    pub radius: u32,
+    /// How much the shadow darkens the pixels under it, from 0.0 (no shadow)
+    /// to 1.0 (fully black at the edge). Defaults to 0.35.
+    pub intensity: f32,
+}
+
+impl Default for PanelShadowConfig {
+    fn default() -> Self {
+        Self {
+            radius: 6,
+            intensity: 0.35,
+        }
+    }
+}
+
+/// A post-processor which fakes a soft shadow under panels. Pixels just below
+/// and to the right of each panel set with
+/// [`PanelShadowPostProcessor::set_panels`] are darkened, giving bordered
+/// blocks a subtle sense of depth. Nothing inside a panel is shaded, so the
+/// borders and text drawn in it are left crisp. Otherwise this composites to
+/// the surface just like the
+/// [`DefaultPostProcessor`](super::DefaultPostProcessor).
+pub struct PanelShadowPostProcessor {
+    config: PanelShadowConfig,
+    panels: Vec<[f32; 4]>,
+
+    uniforms: Buffer,
+    bindings: BindGroupLayout,
+    sampler: Sampler,
+    pipeline: RenderPipeline,
+
+    blitter: RenderBundle,
+}
+
+impl PanelShadowPostProcessor {
+    /// Get the current shadow configuration.
+    pub fn config(&self) -> PanelShadowConfig {
+        self.config
+    }
+
+    /// Change the shadow configuration. This takes effect the next time the
+    /// screen is rendered.
+    pub fn set_config(&mut self, config: PanelShadowConfig) {
+        self.config = config;
+    }
+
+    /// Set the areas, in cells, which cast shadows. `cell_size` should be
+    /// [`WgpuBackend::cell_size`](crate::WgpuBackend::cell_size). Only the
+    /// first [`MAX_PANELS`] areas are used, and nothing casts a shadow until
+    /// this is called. Areas are positioned from the top left of the
+    /// composited text, so they are offset by the padding around the grid
+    /// when using [`Viewport::Centered`](crate::Viewport::Centered). This
+    /// takes effect the next time the screen is rendered.
+    pub fn set_panels(&mut self, cell_size: Size, panels: &[Rect]) {
+        let (width, height) = (f32::from(cell_size.width), f32::from(cell_size.height));
+        self.panels = panels
+            .iter()
+            .take(MAX_PANELS)
+            .map(|area| {
+                [
+                    f32::from(area.left()) * width,
+                    f32::from(area.top()) * height,
+                    f32::from(area.right()) * width,
+                    f32::from(area.bottom()) * height,
+                ]
+            })
+            .collect();
+    }
+}
+
+impl PostProcessor for PanelShadowPostProcessor {
+    type UserData = PanelShadowConfig;
+
+    fn compile(
+        device: &wgpu::Device,
+        text_view: &wgpu::TextureView,
+        surface_config: &wgpu::SurfaceConfiguration,
+        user_data: Self::UserData,
+    ) -> Self {
+        let uniforms = device.create_buffer(&BufferDescriptor {
+            label: Some("Panel Shadow Uniforms"),
+            size: size_of::<Uniforms>() as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::UNIFORM,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Panel Shadow Bindings Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: NonZeroU64::new(size_of::<Uniforms>() as u64),
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let shader = device.create_shader_module(ShaderModuleDescriptor {
+            label: Some("panel_shadow.wgsl"),
+            source: ShaderSource::Wgsl(
+                concat!(include_str!("srgb.wgsl"), include_str!("panel_shadow.wgsl")).into(),
+            ),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Panel Shadow Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Panel Shadow Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: Some("vs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                buffers: &[],
+            },
+            primitive: PrimitiveState {
+                topology: PrimitiveTopology::TriangleStrip,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: Some("fs_main"),
+                compilation_options: PipelineCompilationOptions::default(),
+                targets: &[Some(ColorTargetState {
+                    format: surface_config.format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            multiview: None,
+            cache: None,
+        });
+
+        let blitter = build_blitter(
+            device,
+            &layout,
+            text_view,
+            &sampler,
+            &uniforms,
+            surface_config,
+            &pipeline,
+        );
+
+        Self {
+            config: user_data,
+            panels: vec![],
+            uniforms,
+            bindings: layout,
+            sampler,
+            pipeline,
+            blitter,
+        }
+    }
+
+    fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        text_view: &wgpu::TextureView,
+        surface_config: &wgpu::SurfaceConfiguration,
+    ) {
+        self.blitter = build_blitter(
+            device,
+            &self.bindings,
+            text_view,
+            &self.sampler,
+            &self.uniforms,
+            surface_config,
+            &self.pipeline,
+        );
+    }
+
+    fn process(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        _text_view: &wgpu::TextureView,
+        surface_config: &wgpu::SurfaceConfiguration,
+        surface_view: &wgpu::TextureView,
+    ) {
+        {
+            let mut uniforms = queue
+                .write_buffer_with(
+                    &self.uniforms,
+                    0,
+                    NonZeroU64::new(size_of::<Uniforms>() as u64).unwrap(),
+                )
+                .unwrap();
+            uniforms.copy_from_slice(bytemuck::bytes_of(&Uniforms {
+                screen_size: [surface_config.width as f32, surface_config.height as f32],
+                use_srgb: u32::from(surface_config.format.is_srgb()),
+                radius: self.config.radius,
+                intensity: self.config.intensity.clamp(0.0, 1.0),
+                panel_count: self.panels.len() as u32,
+                _pad0: [0; 2],
+                panels: std::array::from_fn(|idx| {
+                    self.panels.get(idx).copied().unwrap_or_default()
+                }),
+            }));
+        }
+
+        let mut pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Panel Shadow Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: surface_view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Clear(Color::TRANSPARENT),
+                    store: StoreOp::Store,
+                },
+            })],
+            ..Default::default()
+        });
+
+        pass.execute_bundles(Some(&self.blitter));
+    }
+}
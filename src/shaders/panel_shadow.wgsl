@@ -0,0 +1,78 @@
+struct VertexOutput {
+    @builtin(position) gl_Position: vec4<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) Index: u32) -> VertexOutput {
+    let vertex = vec2(f32((Index << 1) & 2), f32(Index & 2));
+    return VertexOutput(vec4(vertex * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0));
+}
+
+struct FragmentOutput {
+    @location(0) FragColor: vec4<f32>,
+}
+
+@group(0) @binding(0) 
+var Texture: texture_2d<f32>;
+@group(0) @binding(1) 
+var Sampler: sampler;
+
+struct Uniforms {
+    screen_size: vec2<f32>,
+    use_srgb: u32,
+    radius: u32,
+    intensity: f32,
+    panel_count: u32,
+    _pad0: u32,
+    _pad1: u32,
+    // The left, top, right, and bottom edges of each panel in pixels of the
+    // composited text.
+    panels: array<vec4<f32>, 16>,
+}
+
+@group(0) @binding(2)
+var<uniform> uniforms: Uniforms;
+
+fn in_panel(position: vec2<f32>) -> bool {
+    for (var idx = 0u; idx < min(uniforms.panel_count, 16u); idx++) {
+        let panel = uniforms.panels[idx];
+        if all(position >= panel.xy) && all(position < panel.zw) {
+            return true;
+        }
+    }
+    return false;
+}
+
+fn occluder(position: vec2<f32>, offset: vec2<f32>) -> f32 {
+    return select(0.0, 1.0, in_panel(position + offset));
+}
+
+@fragment
+fn fs_main(@builtin(position) gl_Position: vec4<f32>) -> FragmentOutput {
+    let uv = gl_Position.xy / uniforms.screen_size;
+    let center = textureSampleLevel(Texture, Sampler, uv, 0.0);
+
+    // Panels are positioned in pixels of the composited text, which may be
+    // stretched to fit the surface.
+    let position = floor(uv * vec2<f32>(textureDimensions(Texture))) + 0.5;
+
+    // The light comes from the top left, so any panel above or to the left of
+    // this pixel casts a shadow onto it. Closer panels cast darker shadows.
+    // Nothing inside a panel is shaded, so its borders and text stay crisp.
+    let lit = in_panel(position);
+    var occlusion = 0.0;
+    var total = 0.0;
+    for (var step = 1u; step <= uniforms.radius; step++) {
+        let weight = f32(uniforms.radius + 1u - step) / f32(uniforms.radius);
+        let distance = f32(step);
+        occlusion += weight * occluder(position, vec2(-distance, -distance));
+        occlusion += weight * occluder(position, vec2(-distance, 0.0));
+        occlusion += weight * occluder(position, vec2(0.0, -distance));
+        total += 3.0 * weight;
+    }
+
+    let shade = select(1.0 - uniforms.intensity * (occlusion / max(total, 1.0)), 1.0, lit);
+    let color = center.rgb * shade;
+
+    return FragmentOutput(vec4(select(color, srgb_to_linear(color), uniforms.use_srgb != 0), center.a));
+}
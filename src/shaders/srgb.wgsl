@@ -0,0 +1,6 @@
+// The composited text is stored as sRGB encoded values in a linear texture. sRGB
+// surfaces encode what is written to them, so the text is decoded first to avoid
+// encoding it twice.
+fn srgb_to_linear(color: vec3<f32>) -> vec3<f32> {
+    return select(pow((color + 0.055) / 1.055, vec3(2.4)), color / 12.92, color <= vec3(0.04045));
+}
@@ -0,0 +1,441 @@
+//! Rasterized glyphs persisted to disk between runs. See
+//! [`Builder::with_glyph_disk_cache`](crate::Builder::with_glyph_disk_cache).
+
+use std::{
+    collections::HashMap,
+    fs::{
+        self,
+        OpenOptions,
+    },
+    hash::Hasher,
+    io::{
+        BufWriter,
+        Write,
+    },
+    path::PathBuf,
+};
+
+use ratatui::style::Modifier;
+
+use crate::{
+    backend::HintingMode,
+    utils::text_atlas::Key,
+    RandomState,
+};
+
+const MAGIC: &[u8; 4] = b"RWGC";
+/// Bump this whenever the layout of the file changes. Changes to how glyphs
+/// are rasterized are covered by the crate version, which is part of the
+/// settings each file is named for.
+const VERSION: u32 = 1;
+/// The most glyphs kept for each set of settings, both in memory and on disk.
+pub(crate) const MAX_GLYPHS: usize = 8192;
+/// The most files for other settings kept alongside the current one. The least
+/// recently written are deleted first.
+const MAX_OTHER_FILES: usize = 4;
+
+/// 64 bit FNV-1a, for hashes which are stored on disk. Unlike
+/// [`DefaultHasher`](std::collections::hash_map::DefaultHasher), its output is
+/// the same for every Rust release. Only [`Hasher::write`] is stable, since the
+/// other methods hash integers in native byte order.
+pub(crate) struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv1a {
+    fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 = (self.0 ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// A glyph as it was rasterized: its key in the atlas, and whether it was
+/// given synthetic italics and bold.
+#[derive(Debug, Hash, PartialEq, Eq, Clone, Copy)]
+pub(crate) struct DiskKey {
+    pub(crate) key: Key,
+    pub(crate) fake_italic: bool,
+    pub(crate) fake_bold: bool,
+}
+
+/// The pixels of a rasterized glyph, and whether it's drawn in its own colors.
+pub(crate) type Glyph = (Vec<u32>, bool);
+
+pub(crate) struct GlyphDiskCache {
+    dir: PathBuf,
+    /// Identifies the settings the loaded glyphs were rasterized with. Each set
+    /// of settings is stored in its own file.
+    settings: Option<u64>,
+    glyphs: HashMap<DiskKey, Glyph, RandomState>,
+    /// Glyphs added since the file was last written to.
+    pending: Vec<DiskKey>,
+}
+
+impl GlyphDiskCache {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        Self {
+            dir,
+            settings: None,
+            glyphs: HashMap::default(),
+            pending: vec![],
+        }
+    }
+
+    /// The settings the loaded glyphs were rasterized with, if any have been
+    /// loaded.
+    pub(crate) fn settings(&self) -> Option<u64> {
+        self.settings
+    }
+
+    fn path(&self, settings: u64) -> PathBuf {
+        self.dir.join(format!("glyphs-{settings:016x}.bin"))
+    }
+
+    /// Replace the loaded glyphs with those rasterized with `settings`,
+    /// writing out any which are pending first. Glyphs from fonts for which
+    /// `live_font` returns false are dropped from the file, so it doesn't keep
+    /// growing as fonts change, and so are the files for all but the most
+    /// recently written other settings.
+    pub(crate) fn load(&mut self, settings: u64, live_font: impl Fn(u64) -> bool) {
+        self.save();
+        self.settings = Some(settings);
+        self.glyphs.clear();
+        self.prune(settings);
+
+        let path = self.path(settings);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+            Err(err) => {
+                warn!("Failed to read glyph cache {}: {err}", path.display());
+                return;
+            }
+        };
+
+        let mut reader = Reader(&data);
+        if reader.take(4) != Some(MAGIC) || reader.u32() != Some(VERSION) {
+            warn!(
+                "Discarding glyph cache {} from another version",
+                path.display()
+            );
+            self.rewrite();
+            return;
+        }
+
+        let mut stale = false;
+        while !reader.0.is_empty() {
+            let Some((key, glyph)) = reader.entry() else {
+                warn!(
+                    "Discarding truncated glyph cache entries in {}",
+                    path.display()
+                );
+                stale = true;
+                break;
+            };
+
+            if live_font(key.key.font) && self.glyphs.len() < MAX_GLYPHS {
+                self.glyphs.insert(key, glyph);
+            } else {
+                stale = true;
+            }
+        }
+
+        if stale {
+            self.rewrite();
+        }
+    }
+
+    pub(crate) fn get(&self, key: &DiskKey) -> Option<&Glyph> {
+        self.glyphs.get(key)
+    }
+
+    pub(crate) fn glyphs(&self) -> impl Iterator<Item = (&DiskKey, &Glyph)> {
+        self.glyphs.iter()
+    }
+
+    /// Add a glyph, which is written to disk by the next call to
+    /// [`GlyphDiskCache::save`]. Glyphs past the first [`MAX_GLYPHS`] aren't
+    /// cached.
+    pub(crate) fn insert(&mut self, key: DiskKey, glyph: Glyph) {
+        if self.glyphs.len() >= MAX_GLYPHS && !self.glyphs.contains_key(&key) {
+            return;
+        }
+
+        if self.glyphs.insert(key, glyph).is_none() {
+            self.pending.push(key);
+        }
+    }
+
+    /// Delete the files for settings other than `settings`, except for the
+    /// [`MAX_OTHER_FILES`] most recently written.
+    fn prune(&self, settings: u64) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return;
+        };
+
+        let current = self.path(settings);
+        let mut others = entries
+            .filter_map(Result::ok)
+            .map(|entry| entry.path())
+            .filter(|path| {
+                *path != current
+                    && path
+                        .file_name()
+                        .and_then(|name| name.to_str())
+                        .is_some_and(|name| name.starts_with("glyphs-") && name.ends_with(".bin"))
+            })
+            .filter_map(|path| Some((fs::metadata(&path).ok()?.modified().ok()?, path)))
+            .collect::<Vec<_>>();
+        others.sort_by(|(a, _), (b, _)| b.cmp(a));
+
+        for (_, path) in others.into_iter().skip(MAX_OTHER_FILES) {
+            if let Err(err) = fs::remove_file(&path) {
+                warn!("Failed to remove glyph cache {}: {err}", path.display());
+            }
+        }
+    }
+
+    /// Append any glyphs added since the last save to the file for the current
+    /// settings.
+    pub(crate) fn save(&mut self) {
+        let (Some(settings), false) = (self.settings, self.pending.is_empty()) else {
+            return;
+        };
+
+        let path = self.path(settings);
+        let pending = std::mem::take(&mut self.pending);
+        let result = fs::create_dir_all(&self.dir).and_then(|_| {
+            let file = OpenOptions::new().create(true).append(true).open(&path)?;
+            let new = file.metadata()?.len() == 0;
+            let mut writer = BufWriter::new(file);
+            if new {
+                writer.write_all(MAGIC)?;
+                writer.write_all(&VERSION.to_le_bytes())?;
+            }
+            for key in pending {
+                write_entry(&mut writer, &key, &self.glyphs[&key])?;
+            }
+            writer.flush()
+        });
+
+        if let Err(err) = result {
+            warn!("Failed to write glyph cache {}: {err}", path.display());
+        }
+    }
+
+    /// Replace the file for the current settings with the loaded glyphs.
+    fn rewrite(&mut self) {
+        let Some(settings) = self.settings else {
+            return;
+        };
+
+        let path = self.path(settings);
+        if let Err(err) = fs::remove_file(&path) {
+            warn!("Failed to remove glyph cache {}: {err}", path.display());
+            return;
+        }
+        self.pending = self.glyphs.keys().copied().collect();
+        self.save();
+    }
+}
+
+fn write_entry(
+    writer: &mut impl Write,
+    key: &DiskKey,
+    (pixels, color): &Glyph,
+) -> std::io::Result<()> {
+    let hinting = match key.key.hinting {
+        HintingMode::None => 0u8,
+        HintingMode::Vertical => 1,
+        HintingMode::Full => 2,
+    };
+    let flags = key.fake_italic as u8 | (key.fake_bold as u8) << 1 | (*color as u8) << 2;
+
+    writer.write_all(&key.key.font.to_le_bytes())?;
+    writer.write_all(&key.key.glyph.to_le_bytes())?;
+    writer.write_all(&key.key.style.bits().to_le_bytes())?;
+    writer.write_all(&key.key.width.to_le_bytes())?;
+    writer.write_all(&[hinting, flags])?;
+    writer.write_all(&(pixels.len() as u32).to_le_bytes())?;
+    for pixel in pixels {
+        writer.write_all(&pixel.to_le_bytes())?;
+    }
+    Ok(())
+}
+
+struct Reader<'d>(&'d [u8]);
+
+impl<'d> Reader<'d> {
+    fn take(&mut self, len: usize) -> Option<&'d [u8]> {
+        let taken = self.0.get(..len)?;
+        self.0 = &self.0[len..];
+        Some(taken)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn entry(&mut self) -> Option<(DiskKey, Glyph)> {
+        let font = u64::from_le_bytes(self.take(8)?.try_into().unwrap());
+        let glyph = self.u32()?;
+        let style = u16::from_le_bytes(self.take(2)?.try_into().unwrap());
+        let width = self.u32()?;
+        let [hinting, flags] = self.take(2)?.try_into().unwrap();
+        let len = self.u32()? as usize;
+        let pixels = self
+            .take(len.checked_mul(4)?)?
+            .chunks_exact(4)
+            .map(|px| u32::from_le_bytes(px.try_into().unwrap()))
+            .collect();
+
+        let key = Key {
+            style: Modifier::from_bits_truncate(style),
+            glyph,
+            font,
+            width,
+            hinting: match hinting {
+                0 => HintingMode::None,
+                1 => HintingMode::Vertical,
+                2 => HintingMode::Full,
+                _ => return None,
+            },
+        };
+        Some((
+            DiskKey {
+                key,
+                fake_italic: flags & 1 != 0,
+                fake_bold: flags & 2 != 0,
+            },
+            (pixels, flags & 4 != 0),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(glyph: u32, font: u64) -> DiskKey {
+        DiskKey {
+            key: Key {
+                style: Modifier::BOLD,
+                glyph,
+                font,
+                width: 2,
+                hinting: HintingMode::Vertical,
+            },
+            fake_italic: true,
+            fake_bold: false,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ratatui-wgpu-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn round_trip() {
+        let dir = temp_dir("glyph-cache-round-trip");
+
+        let mut cache = GlyphDiskCache::new(dir.clone());
+        cache.load(1, |_| true);
+        cache.insert(key(1, 10), (vec![1, 2], false));
+        cache.save();
+        cache.insert(key(2, 20), (vec![3, u32::MAX], true));
+        cache.save();
+
+        let mut cache = GlyphDiskCache::new(dir.clone());
+        cache.load(1, |_| true);
+        assert_eq!(cache.get(&key(1, 10)), Some(&(vec![1, 2], false)));
+        assert_eq!(cache.get(&key(2, 20)), Some(&(vec![3, u32::MAX], true)));
+
+        // Other settings have their own file.
+        cache.load(2, |_| true);
+        assert_eq!(cache.glyphs().count(), 0);
+
+        // Glyphs from fonts which are gone are dropped from the file.
+        cache.load(1, |font| font == 20);
+        assert_eq!(cache.glyphs().count(), 1);
+        let mut cache = GlyphDiskCache::new(dir.clone());
+        cache.load(1, |_| true);
+        assert_eq!(cache.get(&key(1, 10)), None);
+        assert_eq!(cache.get(&key(2, 20)), Some(&(vec![3, u32::MAX], true)));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn other_version() {
+        let dir = temp_dir("glyph-cache-other-version");
+
+        let mut cache = GlyphDiskCache::new(dir.clone());
+        cache.load(1, |_| true);
+        cache.insert(key(1, 10), (vec![1, 2], false));
+        cache.save();
+
+        let path = cache.path(1);
+        let mut data = fs::read(&path).unwrap();
+        data[4..8].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        fs::write(&path, data).unwrap();
+
+        let mut cache = GlyphDiskCache::new(dir.clone());
+        cache.load(1, |_| true);
+        assert_eq!(cache.glyphs().count(), 0);
+        assert!(!path.exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn fnv1a() {
+        let hash = |data: &[u8]| {
+            let mut hasher = Fnv1a::default();
+            hasher.write(data);
+            hasher.finish()
+        };
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(hash(b"foobar"), 0x8594_4171_f739_67e8);
+    }
+
+    #[test]
+    fn limits() {
+        let dir = temp_dir("glyph-cache-limits");
+
+        let mut cache = GlyphDiskCache::new(dir.clone());
+        for settings in 0..MAX_OTHER_FILES as u64 + 3 {
+            cache.load(settings, |_| true);
+            cache.insert(key(1, 10), (vec![1], false));
+            cache.save();
+        }
+        cache.load(100, |_| true);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), MAX_OTHER_FILES);
+        assert!(!cache.path(0).exists());
+        assert!(cache.path(MAX_OTHER_FILES as u64 + 2).exists());
+
+        for glyph in 0..MAX_GLYPHS as u32 + 10 {
+            cache.insert(key(glyph, 10), (vec![glyph], false));
+        }
+        assert_eq!(cache.glyphs().count(), MAX_GLYPHS);
+        cache.save();
+
+        let mut cache = GlyphDiskCache::new(dir.clone());
+        cache.load(100, |_| true);
+        assert_eq!(cache.glyphs().count(), MAX_GLYPHS);
+        assert_eq!(cache.get(&key(MAX_GLYPHS as u32, 10)), None);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
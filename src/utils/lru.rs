@@ -1,6 +1,9 @@
 use std::{
     fmt::Debug,
-    hash::Hash,
+    hash::{
+        BuildHasher,
+        Hash,
+    },
 };
 
 use indexmap::IndexMap;
@@ -14,21 +17,27 @@ struct Entry<Value> {
 }
 
 #[derive(Debug)]
-pub(crate) struct Lru<Key, Value> {
-    queue: IndexMap<Key, Entry<Value>, RandomState>,
+pub(crate) struct Lru<Key, Value, Hasher = RandomState> {
+    queue: IndexMap<Key, Entry<Value>, Hasher>,
     age: u64,
 }
 
 impl<Key, Value> Default for Lru<Key, Value> {
     fn default() -> Self {
+        Self::with_hasher(RandomState::default())
+    }
+}
+
+impl<Key, Value, Hasher> Lru<Key, Value, Hasher> {
+    pub(crate) fn with_hasher(hasher: Hasher) -> Self {
         Self {
-            queue: Default::default(),
+            queue: IndexMap::with_hasher(hasher),
             age: u64::MAX,
         }
     }
 }
 
-impl<Key: Hash + Eq, Value> Lru<Key, Value> {
+impl<Key: Hash + Eq, Value, Hasher: BuildHasher> Lru<Key, Value, Hasher> {
     pub(crate) fn clear(&mut self) {
         self.queue.clear();
         self.age = u64::MAX;
@@ -85,7 +94,12 @@ impl<Key: Hash + Eq, Value> Lru<Key, Value> {
         &self.queue[index].value
     }
 
+    #[cfg(test)]
     pub(crate) fn get(&mut self, key: &Key) -> Option<&Value> {
+        self.get_mut(key).map(|value| &*value)
+    }
+
+    pub(crate) fn get_mut(&mut self, key: &Key) -> Option<&mut Value> {
         if self.age == 0 {
             self.age = u64::MAX;
             self.re_index(0);
@@ -97,12 +111,17 @@ impl<Key: Hash + Eq, Value> Lru<Key, Value> {
 
             index = self.bubble_down(index);
 
-            Some(&self.queue[index].value)
+            Some(&mut self.queue[index].value)
         } else {
             None
         }
     }
 
+    /// Get the least recently used value without removing it.
+    pub(crate) fn peek(&self) -> Option<&Value> {
+        self.queue.first().map(|(_, entry)| &entry.value)
+    }
+
     pub(crate) fn pop(&mut self) -> Option<(Key, Value)> {
         self.pop_internal().map(|(key, entry)| (key, entry.value))
     }
@@ -111,6 +130,10 @@ impl<Key: Hash + Eq, Value> Lru<Key, Value> {
         self.queue.len()
     }
 
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.queue.reserve(additional);
+    }
+
     fn pop_internal(&mut self) -> Option<(Key, Entry<Value>)> {
         if self.queue.is_empty() {
             return None;
@@ -7,6 +7,7 @@ use raqote::{
     IntRect,
     Path,
     PathBuilder,
+    PathOp,
     Point,
     SolidSource,
     Source,
@@ -18,6 +19,9 @@ use rustybuzz::{
     Face,
 };
 
+use crate::backend::HintingMode;
+
+pub(crate) mod glyph_cache;
 pub(crate) mod lru;
 pub(crate) mod plan_cache;
 pub(crate) mod text_atlas;
@@ -38,6 +42,50 @@ impl Outline {
     pub(crate) fn finish(self) -> Path {
         self.path.finish()
     }
+
+    /// Finish the outline, snapping its points to the pixel grid according to
+    /// `hinting`. `scale` and the offsets map font units to pixels, with y
+    /// increasing downwards in pixel space.
+    pub(crate) fn finish_hinted(
+        self,
+        hinting: HintingMode,
+        scale: f32,
+        x_offset: f32,
+        y_offset: f32,
+    ) -> Path {
+        let mut path = self.path.finish();
+        if hinting == HintingMode::None {
+            return path;
+        }
+
+        let snap = |point: Point| {
+            let x = if hinting == HintingMode::Full {
+                ((point.x * scale + x_offset).round() - x_offset) / scale
+            } else {
+                point.x
+            };
+            let y = (y_offset - (y_offset - point.y * scale).round()) / scale;
+            Point::new(x, y)
+        };
+
+        for op in path.ops.iter_mut() {
+            match op {
+                PathOp::MoveTo(p) | PathOp::LineTo(p) => *p = snap(*p),
+                PathOp::QuadTo(c, p) => {
+                    *c = snap(*c);
+                    *p = snap(*p);
+                }
+                PathOp::CubicTo(c1, c2, p) => {
+                    *c1 = snap(*c1);
+                    *c2 = snap(*c2);
+                    *p = snap(*p);
+                }
+                PathOp::Close => {}
+            }
+        }
+
+        path
+    }
 }
 
 impl rustybuzz::ttf_parser::OutlineBuilder for Outline {
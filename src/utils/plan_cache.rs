@@ -1,3 +1,5 @@
+use std::hash::BuildHasher;
+
 use rustybuzz::{
     Direction,
     Script,
@@ -8,6 +10,7 @@ use rustybuzz::{
 use crate::{
     utils::lru::Lru,
     Font,
+    RandomState,
 };
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -17,19 +20,33 @@ struct Key {
     script: Script,
 }
 
-pub(crate) struct PlanCache {
-    lru: Lru<Key, ShapePlan>,
+pub(crate) struct PlanCache<H = RandomState> {
+    lru: Lru<Key, ShapePlan, H>,
     capacity: usize,
 }
 
-impl PlanCache {
-    pub(crate) fn new(capacity: usize) -> Self {
+impl<H: BuildHasher> PlanCache<H> {
+    pub(crate) fn new(capacity: usize, hasher: H) -> Self {
         Self {
-            lru: Lru::default(),
+            lru: Lru::with_hasher(hasher),
             capacity: capacity + 1,
         }
     }
 
+    /// Change the number of plans the cache holds, evicting the least recently
+    /// used plans if it shrinks.
+    pub(crate) fn resize(&mut self, capacity: usize) {
+        self.capacity = capacity + 1;
+        while self.lru.len() >= self.capacity {
+            self.lru.pop();
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity - 1
+    }
+
     pub(crate) fn get(&mut self, font: &Font, buffer: &mut UnicodeBuffer) -> &ShapePlan {
         buffer.guess_segment_properties();
         let key = Key {
@@ -3,6 +3,7 @@ use std::ops::Deref;
 use ratatui::style::Modifier;
 
 use crate::{
+    backend::HintingMode,
     utils::lru::Lru,
     Fonts,
 };
@@ -12,10 +13,17 @@ pub(crate) struct Key {
     pub(crate) style: Modifier,
     pub(crate) glyph: u32,
     pub(crate) font: u64,
+    pub(crate) width: u32,
+    pub(crate) hinting: HintingMode,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub(crate) struct CacheRect {
+    /// The layer of the atlas texture array this entry is stored in.
+    pub(crate) page: u32,
+    /// The batch this entry was last known to be valid in. Entries from
+    /// earlier batches may have been overwritten.
+    pub(crate) epoch: u64,
     pub(crate) x: u32,
     pub(crate) y: u32,
     pub(crate) width: u32,
@@ -45,7 +53,8 @@ impl Deref for Entry {
 
 #[derive(Debug)]
 pub(crate) struct Atlas {
-    lru: Lru<Key, CacheRect>,
+    /// Map from key -> (entry, frame the entry was last used in).
+    lru: Lru<Key, (CacheRect, u64)>,
     width: u32,
     height: u32,
 
@@ -54,14 +63,29 @@ pub(crate) struct Atlas {
 
     next_entry: u32,
     max_entries: u32,
+
+    /// The number of pages currently in use. Pages are only added when every
+    /// entry in the atlas is in use by the current frame.
+    pages: u32,
+    max_pages: u32,
+
+    /// Incremented whenever a single frame needs more glyphs than the atlas
+    /// can hold. Entries from each epoch are drawn before the next epoch's
+    /// entries are uploaded over them.
+    epoch: u64,
+
+    frame: u64,
+    /// Entries from previous frames which were evicted to make room for new
+    /// entries since the last call to [`Atlas::take_evicted`].
+    evicted: Vec<CacheRect>,
 }
 
 impl Atlas {
-    pub(crate) fn new(fonts: &Fonts, width: u32, height: u32) -> Self {
+    pub(crate) fn new(fonts: &Fonts, width: u32, height: u32, max_pages: u32) -> Self {
         let entry_width = fonts.min_width_px() * 2;
-        let entry_height = fonts.height_px();
+        let entry_height = fonts.cell_height_px();
         let max_entries = (width / entry_width) * (height / entry_height);
-        debug!("Atlas with WxH {entry_width}x{entry_height} can hold {max_entries}");
+        debug!("Atlas with WxH {entry_width}x{entry_height} can hold {max_entries} per page");
 
         Atlas {
             lru: Lru::default(),
@@ -71,14 +95,19 @@ impl Atlas {
             entry_height,
             next_entry: 0,
             max_entries,
+            pages: 1,
+            max_pages: max_pages.max(1),
+            epoch: 0,
+            frame: 0,
+            evicted: vec![],
         }
     }
 
     pub(crate) fn match_fonts(&mut self, fonts: &Fonts) {
         self.clear();
         self.entry_width = fonts.min_width_px() * 2;
-        self.entry_height = fonts.height_px();
-        self.max_entries = (self.width / self.entry_width) * (self.height / self.entry_height);
+        self.entry_height = fonts.cell_height_px();
+        self.max_entries = self.entries_per_page() * self.pages;
 
         debug!(
             "Atlas with WxH {}x{} can hold {}",
@@ -86,13 +115,73 @@ impl Atlas {
         );
     }
 
-    fn clear(&mut self) {
+    /// The number of entries currently cached.
+    pub(crate) fn len(&self) -> usize {
+        self.lru.len()
+    }
+
+    /// The number of entries which fit in the pages currently in use.
+    pub(crate) fn capacity(&self) -> usize {
+        self.max_entries as usize
+    }
+
+    /// The fraction of the entries the atlas can hold at its largest which
+    /// have been allocated, between 0 and 1.
+    pub(crate) fn usage(&self) -> f32 {
+        let max_entries = self.entries_per_page() * self.max_pages;
+        if max_entries == 0 {
+            return 1.0;
+        }
+        self.next_entry as f32 / max_entries as f32
+    }
+
+    /// The current epoch. Entries from earlier epochs may have been
+    /// overwritten since they were returned.
+    pub(crate) fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// The number of pages the atlas texture must have to hold every entry.
+    pub(crate) fn pages(&self) -> u32 {
+        self.pages
+    }
+
+    /// Reserve space for at least `additional` entries, up to the number of
+    /// entries which can fit in the atlas.
+    pub(crate) fn reserve(&mut self, additional: usize) {
+        self.lru
+            .reserve(additional.min(self.max_entries as usize - self.lru.len()));
+    }
+
+    /// Drop every entry, keeping the pages currently in use.
+    pub(crate) fn clear(&mut self) {
         self.lru.clear();
         self.next_entry = 0;
+        self.evicted.clear();
+    }
+
+    /// Start a new frame. Entries used during the current frame are never
+    /// reported as evicted, since anything referencing them is being drawn
+    /// again anyways.
+    pub(crate) fn next_frame(&mut self) {
+        self.frame += 1;
+    }
+
+    /// Take the entries evicted since the last call to this function. Anything
+    /// still referencing these entries needs to be rasterized again.
+    pub(crate) fn take_evicted(&mut self) -> Vec<CacheRect> {
+        std::mem::take(&mut self.evicted)
     }
 
     pub(crate) fn try_get(&mut self, key: &Key) -> Option<Entry> {
-        self.lru.get(key).copied().map(Entry::Cached)
+        let frame = self.frame;
+        let epoch = self.epoch;
+        self.lru.get_mut(key).map(|(rect, last_used)| {
+            *last_used = frame;
+            // Anything still in the atlas is valid until the next overflow.
+            rect.epoch = epoch;
+            Entry::Cached(*rect)
+        })
     }
 
     pub(crate) fn get(&mut self, key: &Key, width: u32, height: u32) -> Entry {
@@ -107,23 +196,57 @@ impl Atlas {
         );
 
         self.try_get(key).unwrap_or_else(|| {
+            if self.next_entry == self.max_entries
+                && self.lru.peek().is_some_and(|(rect, last_used)| {
+                    *last_used == self.frame && rect.epoch == self.epoch
+                })
+            {
+                // Everything in the atlas is needed for this frame, so evicting anything
+                // would corrupt glyphs which are about to be drawn. Add a page if there's
+                // room, and otherwise start a new epoch so the glyphs cached so far can
+                // be drawn before they're replaced.
+                if self.pages < self.max_pages {
+                    self.max_entries += self.entries_per_page();
+                    self.pages += 1;
+                    debug!("Atlas grew to {} pages", self.pages);
+                } else {
+                    self.epoch += 1;
+                    debug!("Atlas overflowed, starting epoch {}", self.epoch);
+                }
+            }
+
             let rect = if self.next_entry == self.max_entries {
-                self.lru.pop().expect("Atlas has zero max entries!").1
+                let (_, (rect, last_used)) = self.lru.pop().expect("Atlas has zero max entries!");
+                if last_used != self.frame {
+                    self.evicted.push(rect);
+                }
+                CacheRect {
+                    epoch: self.epoch,
+                    ..rect
+                }
             } else {
                 let entry = self.next_entry;
                 self.next_entry += 1;
                 self.slot_to_rect(entry, width)
             };
 
-            self.lru.insert(*key, rect);
+            self.lru.insert(*key, (rect, self.frame));
             Entry::Uncached(rect)
         })
     }
 
+    fn entries_per_page(&self) -> u32 {
+        (self.width / self.entry_width) * (self.height / self.entry_height)
+    }
+
     fn slot_to_rect(&self, slot: u32, width: u32) -> CacheRect {
+        let page = slot / self.entries_per_page();
+        let slot = slot % self.entries_per_page();
         let x = slot % (self.width / self.entry_width) * self.entry_width;
         let y = slot / (self.width / self.entry_width) * self.entry_height;
         CacheRect {
+            page,
+            epoch: self.epoch,
             x,
             y,
             width,
@@ -143,6 +266,7 @@ mod tests {
         },
         Font,
         Fonts,
+        HintingMode,
     };
 
     #[test]
@@ -155,7 +279,7 @@ mod tests {
             .unwrap(),
             24,
         );
-        let mut atlas = Atlas::new(&fonts, 24, 24);
+        let mut atlas = Atlas::new(&fonts, 24, 24, 1);
 
         for idx in 0..atlas.max_entries {
             atlas.get(
@@ -163,6 +287,8 @@ mod tests {
                     style: Modifier::default(),
                     glyph: idx as _,
                     font: idx as _,
+                    width: 12,
+                    hinting: HintingMode::None,
                 },
                 12,
                 24,
@@ -173,6 +299,8 @@ mod tests {
             style: Modifier::default(),
             glyph: u32::MAX,
             font: u32::MAX as _,
+            width: 12,
+            hinting: HintingMode::None,
         };
 
         let last_inserted = atlas.get(&last_key, 12, 24);
@@ -180,4 +308,155 @@ mod tests {
 
         assert_eq!(*last_inserted, *post_insertion);
     }
+
+    #[test]
+    fn evicted() {
+        let fonts = Fonts::new(
+            Font::new(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/backend/fonts/Fairfax.ttf"
+            )))
+            .unwrap(),
+            24,
+        );
+        let mut atlas = Atlas::new(&fonts, 96, 96, 1);
+        let key = |idx: u32| Key {
+            style: Modifier::default(),
+            glyph: idx,
+            font: idx as _,
+            width: 12,
+            hinting: HintingMode::None,
+        };
+
+        let rects = (0..atlas.max_entries)
+            .map(|idx| *atlas.get(&key(idx), 12, 24))
+            .collect::<Vec<_>>();
+
+        // Replacing an entry used in the current frame isn't reported.
+        atlas.get(&key(atlas.max_entries), 12, 24);
+        assert!(atlas.take_evicted().is_empty());
+
+        atlas.next_frame();
+        atlas.get(&key(atlas.max_entries + 1), 12, 24);
+        assert_eq!(atlas.take_evicted(), vec![rects[1]]);
+        assert!(atlas.take_evicted().is_empty());
+    }
+
+    #[test]
+    fn pages() {
+        let fonts = Fonts::new(
+            Font::new(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/backend/fonts/Fairfax.ttf"
+            )))
+            .unwrap(),
+            24,
+        );
+        let mut atlas = Atlas::new(&fonts, 96, 96, 2);
+        let key = |idx: u32| Key {
+            style: Modifier::default(),
+            glyph: idx,
+            font: idx as _,
+            width: 12,
+            hinting: HintingMode::None,
+        };
+
+        let per_page = atlas.max_entries;
+        for idx in 0..per_page {
+            assert_eq!(atlas.get(&key(idx), 12, 24).page, 0);
+        }
+        assert_eq!(atlas.pages(), 1);
+
+        // The first page is entirely in use by this frame, so a new page is added.
+        let rect = *atlas.get(&key(per_page), 12, 24);
+        assert_eq!(atlas.pages(), 2);
+        assert_eq!((rect.page, rect.x, rect.y), (1, 0, 0));
+
+        for idx in per_page + 1..per_page * 2 {
+            assert_eq!(atlas.get(&key(idx), 12, 24).page, 1);
+        }
+
+        // Out of pages, so the least recently used entry is replaced.
+        atlas.next_frame();
+        let rect = *atlas.get(&key(per_page * 2), 12, 24);
+        assert_eq!(atlas.pages(), 2);
+        assert_eq!((rect.page, rect.x, rect.y), (0, 0, 0));
+    }
+
+    #[test]
+    fn overflow() {
+        let fonts = Fonts::new(
+            Font::new(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/backend/fonts/Fairfax.ttf"
+            )))
+            .unwrap(),
+            24,
+        );
+        let mut atlas = Atlas::new(&fonts, 96, 96, 1);
+        let key = |idx: u32| Key {
+            style: Modifier::default(),
+            glyph: idx,
+            font: idx as _,
+            width: 12,
+            hinting: HintingMode::None,
+        };
+
+        let max_entries = atlas.max_entries;
+        for idx in 0..max_entries {
+            assert_eq!(atlas.get(&key(idx), 12, 24).epoch, 0);
+        }
+
+        // Every entry is in use by this frame, so a new epoch starts and the
+        // entries are replaced in order.
+        let rect = *atlas.get(&key(max_entries), 12, 24);
+        assert_eq!(atlas.epoch(), 1);
+        assert_eq!((rect.epoch, rect.x, rect.y), (1, 0, 0));
+
+        // Entries which haven't been replaced are still valid in the new epoch.
+        assert_eq!(atlas.get(&key(max_entries - 1), 12, 24).epoch, 1);
+        assert!(atlas.get(&key(max_entries - 1), 12, 24).cached());
+
+        // Replacing the rest of the previous epoch's entries doesn't start another.
+        for idx in max_entries + 1..max_entries * 2 - 1 {
+            atlas.get(&key(idx), 12, 24);
+        }
+        assert_eq!(atlas.epoch(), 1);
+    }
+
+    #[test]
+    fn usage() {
+        let fonts = Fonts::new(
+            Font::new(include_bytes!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/src/backend/fonts/Fairfax.ttf"
+            )))
+            .unwrap(),
+            24,
+        );
+        let mut atlas = Atlas::new(&fonts, 96, 96, 2);
+        let key = |idx: u32| Key {
+            style: Modifier::default(),
+            glyph: idx,
+            font: idx as _,
+            width: 12,
+            hinting: HintingMode::None,
+        };
+
+        assert_eq!(atlas.usage(), 0.0);
+
+        let per_page = atlas.max_entries;
+        for idx in 0..per_page {
+            atlas.get(&key(idx), 12, 24);
+        }
+        assert_eq!(atlas.usage(), 0.5);
+
+        // Looking up cached entries doesn't allocate anything.
+        atlas.get(&key(0), 12, 24);
+        assert_eq!(atlas.usage(), 0.5);
+
+        atlas.clear();
+        assert_eq!(atlas.usage(), 0.0);
+        assert!(!atlas.get(&key(0), 12, 24).cached());
+    }
 }